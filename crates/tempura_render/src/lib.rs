@@ -1,3 +1,5 @@
+mod render_graph;
 mod renderer;
 
+pub use render_graph::*;
 pub use renderer::*;
@@ -0,0 +1,782 @@
+/// Format of a render graph texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureFormat {
+    Rgba8Unorm,
+    Rgba8Srgb,
+    Bgra8Unorm,
+    Bgra8Srgb,
+    Rgba16Float,
+    Rgba32Float,
+    R32Float,
+    Depth32Float,
+}
+
+impl TextureFormat {
+    pub fn is_depth(&self) -> bool {
+        matches!(self, TextureFormat::Depth32Float)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+}
+
+/// Texture declared in a `RenderGraph`, valid for that graph only.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+impl TextureHandle {
+    /// Index of the texture in the order it was declared.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// How a pass uses a texture, which decides its layout and the synchronization around it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureAccess {
+    ColorAttachment,
+    DepthStencilAttachment,
+    /// Read through a sampler by any shader stage.
+    Sampled,
+    /// Read or written with image load/store by any shader stage.
+    Storage,
+    TransferSrc,
+    TransferDst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClearValue {
+    Color([f32; 4]),
+    DepthStencil { depth: f32, stencil: u32 },
+}
+
+/// What an attachment holds when a pass starts rendering to it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadOp {
+    /// Keeps the contents written by earlier passes, which makes the pass read the texture.
+    Load,
+    Clear(ClearValue),
+    DontCare,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attachment {
+    pub texture: TextureHandle,
+    pub load_op: LoadOp,
+}
+
+/// Synchronization needed before a pass accesses a texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureTransition {
+    pub texture: TextureHandle,
+    /// Last access to the texture, `None` for its first access in this graph.
+    pub before: Option<TextureAccess>,
+    pub after: TextureAccess,
+    /// Whether the previous contents can be thrown away, including what other textures
+    /// sharing its memory left there.
+    pub discard: bool,
+}
+
+/// Texture allocated by the backend for a graph, used by the kept passes of `lifetime`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransientTexture {
+    pub texture: TextureHandle,
+    pub desc: TextureDesc,
+    /// Every access of the texture, for the backend to pick the usages.
+    pub accesses: Vec<TextureAccess>,
+    /// Indices into the kept passes of the first and the last pass using the texture.
+    /// Transient textures whose lifetimes don't overlap can share memory.
+    pub lifetime: (usize, usize),
+}
+
+struct TextureNode {
+    name: String,
+    desc: TextureDesc,
+    imported: bool,
+}
+
+#[derive(Clone, Copy)]
+struct PassAccess {
+    texture: TextureHandle,
+    access: TextureAccess,
+    read: bool,
+    write: bool,
+}
+
+/// Declares the textures a pass reads and writes, in the setup closure of
+/// `RenderGraph::add_pass`.
+#[derive(Default)]
+pub struct PassBuilder {
+    accesses: Vec<PassAccess>,
+    color_attachments: Vec<Attachment>,
+    depth_attachment: Option<Attachment>,
+    side_effect: bool,
+}
+
+impl PassBuilder {
+    /// Renders to `texture` as the next color attachment.
+    pub fn color_attachment(&mut self, texture: TextureHandle, load_op: LoadOp) {
+        self.add_access(
+            texture,
+            TextureAccess::ColorAttachment,
+            load_op == LoadOp::Load,
+            true,
+        );
+        self.color_attachments.push(Attachment { texture, load_op });
+    }
+
+    /// Renders to `texture` as the depth attachment, replacing any previous one.
+    pub fn depth_attachment(&mut self, texture: TextureHandle, load_op: LoadOp) {
+        self.add_access(
+            texture,
+            TextureAccess::DepthStencilAttachment,
+            load_op == LoadOp::Load,
+            true,
+        );
+        self.depth_attachment = Some(Attachment { texture, load_op });
+    }
+
+    /// Reads `texture` written by an earlier pass. Attachments are declared with
+    /// `color_attachment` and `depth_attachment` instead.
+    pub fn read(&mut self, texture: TextureHandle, access: TextureAccess) {
+        self.add_access(texture, access, true, false);
+    }
+
+    /// Writes `texture` outside of attachments, e.g. as a storage image or a copy destination.
+    pub fn write(&mut self, texture: TextureHandle, access: TextureAccess) {
+        self.add_access(texture, access, false, true);
+    }
+
+    /// Keeps the pass even if nothing reads what it writes to graph textures, e.g. for a pass
+    /// writing buffers, which the graph doesn't track.
+    pub fn side_effect(&mut self) {
+        self.side_effect = true;
+    }
+
+    fn add_access(
+        &mut self,
+        texture: TextureHandle,
+        access: TextureAccess,
+        read: bool,
+        write: bool,
+    ) {
+        match self
+            .accesses
+            .iter_mut()
+            .find(|a| a.texture == texture && a.access == access)
+        {
+            Some(a) => {
+                a.read |= read;
+                a.write |= write;
+            }
+            None => self.accesses.push(PassAccess {
+                texture,
+                access,
+                read,
+                write,
+            }),
+        }
+    }
+}
+
+struct PassNode<'g, E: ?Sized> {
+    name: String,
+    builder: PassBuilder,
+    execute: Box<dyn FnOnce(&E) + 'g>,
+}
+
+/// Frame described as passes declaring the textures they read and write.
+/// Compiling it culls the passes whose results aren't used, computes the lifetimes of the
+/// transient textures, for the backend to let the ones that don't overlap share memory, and
+/// the transitions before each pass. A backend then executes it with its
+/// command encoder `E`, in the order the passes were added.
+/// Imported textures, such as the swapchain image, outlive the graph, so the passes writing
+/// them are always kept.
+pub struct RenderGraph<'g, E: ?Sized> {
+    textures: Vec<TextureNode>,
+    passes: Vec<PassNode<'g, E>>,
+}
+
+impl<'g, E: ?Sized> Default for RenderGraph<'g, E> {
+    fn default() -> Self {
+        RenderGraph {
+            textures: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+}
+
+impl<'g, E: ?Sized> RenderGraph<'g, E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a texture allocated by the backend for this graph only.
+    pub fn create_texture(&mut self, name: &str, desc: TextureDesc) -> TextureHandle {
+        self.add_texture(name, desc, false)
+    }
+
+    /// Declares a texture provided by the backend, whose contents outlive the graph.
+    pub fn import_texture(&mut self, name: &str, desc: TextureDesc) -> TextureHandle {
+        self.add_texture(name, desc, true)
+    }
+
+    /// Adds a pass declaring its textures in `setup`, recorded by `execute` when the graph is
+    /// executed. Passes must be added after the passes writing what they read.
+    pub fn add_pass<S, F>(&mut self, name: &str, setup: S, execute: F)
+    where
+        S: FnOnce(&mut PassBuilder),
+        F: FnOnce(&E) + 'g,
+    {
+        let mut builder = PassBuilder::default();
+        setup(&mut builder);
+        self.passes.push(PassNode {
+            name: name.to_owned(),
+            builder,
+            execute: Box::new(execute),
+        });
+    }
+
+    pub fn compile(self) -> Result<CompiledRenderGraph<'g, E>, String> {
+        self.validate()?;
+        let kept = self.cull();
+
+        // Lifetimes of the textures as indices into the kept passes.
+        let mut lifetimes = vec![None::<(usize, usize)>; self.textures.len()];
+        let kept_passes = self
+            .passes
+            .iter()
+            .zip(&kept)
+            .filter(|(_, &kept)| kept)
+            .map(|(pass, _)| pass);
+        for (index, pass) in kept_passes.enumerate() {
+            for access in &pass.builder.accesses {
+                let lifetime = &mut lifetimes[access.texture.0];
+                *lifetime = Some(lifetime.map_or((index, index), |(first, _)| (first, index)));
+            }
+        }
+
+        let mut transient_textures = (0..self.textures.len())
+            .filter(|&texture| !self.textures[texture].imported)
+            .filter_map(|texture| {
+                lifetimes[texture].map(|lifetime| TransientTexture {
+                    texture: TextureHandle(texture),
+                    desc: self.textures[texture].desc,
+                    accesses: Vec::new(),
+                    lifetime,
+                })
+            })
+            .collect::<Vec<_>>();
+        transient_textures.sort_by_key(|transient| transient.lifetime.0);
+        let mut texture_transient = vec![None; self.textures.len()];
+        for (index, transient) in transient_textures.iter().enumerate() {
+            texture_transient[transient.texture.0] = Some(index);
+        }
+
+        // The last access of each texture.
+        let mut texture_states = vec![None::<(TextureAccess, bool)>; self.textures.len()];
+        let mut passes = Vec::new();
+        let mut culled_passes = Vec::new();
+        for (pass, kept) in self.passes.into_iter().zip(kept) {
+            if !kept {
+                culled_passes.push(pass.name);
+                continue;
+            }
+            let mut transitions = Vec::new();
+            for access in &pass.builder.accesses {
+                let texture = access.texture.0;
+                if let Some(transient) = texture_transient[texture] {
+                    let accesses = &mut transient_textures[transient].accesses;
+                    if !accesses.contains(&access.access) {
+                        accesses.push(access.access);
+                    }
+                }
+                let transition = match texture_states[texture] {
+                    None => Some(TextureTransition {
+                        texture: access.texture,
+                        before: None,
+                        after: access.access,
+                        discard: !access.read,
+                    }),
+                    Some((before, before_write)) => (before != access.access
+                        || before_write
+                        || access.write)
+                        .then_some(TextureTransition {
+                            texture: access.texture,
+                            before: Some(before),
+                            after: access.access,
+                            discard: false,
+                        }),
+                };
+                transitions.extend(transition);
+                texture_states[texture] = Some((access.access, access.write));
+            }
+            passes.push(CompiledPass {
+                name: pass.name,
+                accesses: pass
+                    .builder
+                    .accesses
+                    .iter()
+                    .map(|access| (access.texture, access.access))
+                    .collect(),
+                transitions,
+                color_attachments: pass.builder.color_attachments,
+                depth_attachment: pass.builder.depth_attachment,
+                execute: pass.execute,
+            });
+        }
+
+        Ok(CompiledRenderGraph {
+            resources: RenderGraphResources {
+                textures: self.textures,
+                transient_textures,
+                final_accesses: texture_states
+                    .iter()
+                    .map(|state| state.map(|(access, _)| access))
+                    .collect(),
+                culled_passes,
+            },
+            passes,
+        })
+    }
+
+    fn add_texture(&mut self, name: &str, desc: TextureDesc, imported: bool) -> TextureHandle {
+        self.textures.push(TextureNode {
+            name: name.to_owned(),
+            desc,
+            imported,
+        });
+        TextureHandle(self.textures.len() - 1)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut written = self
+            .textures
+            .iter()
+            .map(|texture| texture.imported)
+            .collect::<Vec<_>>();
+        for pass in &self.passes {
+            let builder = &pass.builder;
+            for (index, access) in builder.accesses.iter().enumerate() {
+                let texture = self.textures.get(access.texture.0).ok_or_else(|| {
+                    format!("Pass {} uses a texture of another graph.", pass.name)
+                })?;
+                if builder.accesses[..index]
+                    .iter()
+                    .any(|a| a.texture == access.texture)
+                {
+                    return Err(format!(
+                        "Pass {} uses texture {} with more than one access.",
+                        pass.name, texture.name
+                    ));
+                }
+                let is_attachment = builder
+                    .color_attachments
+                    .iter()
+                    .chain(&builder.depth_attachment)
+                    .any(|attachment| attachment.texture == access.texture);
+                let attachment_access = matches!(
+                    access.access,
+                    TextureAccess::ColorAttachment | TextureAccess::DepthStencilAttachment
+                );
+                if attachment_access && !is_attachment {
+                    return Err(format!(
+                        "Pass {} reads or writes texture {} as an attachment without declaring it as one.",
+                        pass.name, texture.name
+                    ));
+                }
+                if access.read && !written[access.texture.0] {
+                    return Err(format!(
+                        "Pass {} reads texture {} before any pass writes it.",
+                        pass.name, texture.name
+                    ));
+                }
+            }
+            for attachment in &builder.color_attachments {
+                let texture = &self.textures[attachment.texture.0];
+                if texture.desc.format.is_depth() {
+                    return Err(format!(
+                        "Pass {} uses depth texture {} as a color attachment.",
+                        pass.name, texture.name
+                    ));
+                }
+            }
+            if let Some(attachment) = &builder.depth_attachment {
+                let texture = &self.textures[attachment.texture.0];
+                if !texture.desc.format.is_depth() {
+                    return Err(format!(
+                        "Pass {} uses color texture {} as the depth attachment.",
+                        pass.name, texture.name
+                    ));
+                }
+            }
+            let mut extents = builder
+                .color_attachments
+                .iter()
+                .chain(&builder.depth_attachment)
+                .map(|attachment| {
+                    let desc = &self.textures[attachment.texture.0].desc;
+                    (desc.width, desc.height)
+                });
+            if let Some(extent) = extents.next() {
+                if extents.any(|e| e != extent) {
+                    return Err(format!(
+                        "Attachments of pass {} have different sizes.",
+                        pass.name
+                    ));
+                }
+            }
+            for access in builder.accesses.iter().filter(|access| access.write) {
+                written[access.texture.0] = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the passes backwards from the imported textures, keeping the passes that write
+    /// what a kept pass reads.
+    fn cull(&self) -> Vec<bool> {
+        let mut needed = self
+            .textures
+            .iter()
+            .map(|texture| texture.imported)
+            .collect::<Vec<_>>();
+        let mut kept = vec![false; self.passes.len()];
+        for (index, pass) in self.passes.iter().enumerate().rev() {
+            let accesses = &pass.builder.accesses;
+            kept[index] = pass.builder.side_effect
+                || accesses
+                    .iter()
+                    .any(|access| access.write && needed[access.texture.0]);
+            if !kept[index] {
+                continue;
+            }
+            // Earlier contents of a texture overwritten without being read are not needed.
+            for access in accesses
+                .iter()
+                .filter(|access| access.write && !access.read)
+            {
+                needed[access.texture.0] = false;
+            }
+            for access in accesses.iter().filter(|access| access.read) {
+                needed[access.texture.0] = true;
+            }
+        }
+        kept
+    }
+}
+
+/// Textures of a compiled graph and the lifetimes of the transient ones.
+pub struct RenderGraphResources {
+    textures: Vec<TextureNode>,
+    transient_textures: Vec<TransientTexture>,
+    final_accesses: Vec<Option<TextureAccess>>,
+    culled_passes: Vec<String>,
+}
+
+impl RenderGraphResources {
+    /// Every texture declared in the graph, in declaration order.
+    pub fn textures(&self) -> impl Iterator<Item = TextureHandle> {
+        (0..self.textures.len()).map(TextureHandle)
+    }
+
+    pub fn texture_name(&self, texture: TextureHandle) -> &str {
+        &self.textures[texture.0].name
+    }
+
+    pub fn texture_desc(&self, texture: TextureHandle) -> TextureDesc {
+        self.textures[texture.0].desc
+    }
+
+    pub fn is_imported(&self, texture: TextureHandle) -> bool {
+        self.textures[texture.0].imported
+    }
+
+    /// Transient textures used by the kept passes, which the backend allocates, in the order
+    /// of their first use.
+    pub fn transient_textures(&self) -> &[TransientTexture] {
+        &self.transient_textures
+    }
+
+    /// Access of the last kept pass using `texture`, which an imported texture is left in.
+    pub fn final_access(&self, texture: TextureHandle) -> Option<TextureAccess> {
+        self.final_accesses[texture.0]
+    }
+
+    /// Names of the passes culled because nothing used their results.
+    pub fn culled_passes(&self) -> &[String] {
+        &self.culled_passes
+    }
+}
+
+/// Pass kept by `RenderGraph::compile`, with the transitions to make before it.
+pub struct CompiledPass<'g, E: ?Sized> {
+    name: String,
+    accesses: Vec<(TextureHandle, TextureAccess)>,
+    transitions: Vec<TextureTransition>,
+    color_attachments: Vec<Attachment>,
+    depth_attachment: Option<Attachment>,
+    execute: Box<dyn FnOnce(&E) + 'g>,
+}
+
+impl<'g, E: ?Sized> CompiledPass<'g, E> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Every texture the pass uses, with how it uses it.
+    pub fn accesses(&self) -> &[(TextureHandle, TextureAccess)] {
+        &self.accesses
+    }
+
+    pub fn transitions(&self) -> &[TextureTransition] {
+        &self.transitions
+    }
+
+    pub fn color_attachments(&self) -> &[Attachment] {
+        &self.color_attachments
+    }
+
+    pub fn depth_attachment(&self) -> Option<&Attachment> {
+        self.depth_attachment.as_ref()
+    }
+
+    /// Whether the backend renders the pass inside a render pass over its attachments.
+    pub fn has_attachments(&self) -> bool {
+        !self.color_attachments.is_empty() || self.depth_attachment.is_some()
+    }
+
+    /// Records the pass with the backend's encoder.
+    pub fn execute(self, encoder: &E) {
+        (self.execute)(encoder)
+    }
+}
+
+pub struct CompiledRenderGraph<'g, E: ?Sized> {
+    resources: RenderGraphResources,
+    passes: Vec<CompiledPass<'g, E>>,
+}
+
+impl<'g, E: ?Sized> CompiledRenderGraph<'g, E> {
+    pub fn resources(&self) -> &RenderGraphResources {
+        &self.resources
+    }
+
+    /// Kept passes, in execution order.
+    pub fn passes(&self) -> &[CompiledPass<'g, E>] {
+        &self.passes
+    }
+
+    pub fn into_parts(self) -> (RenderGraphResources, Vec<CompiledPass<'g, E>>) {
+        (self.resources, self.passes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESC: TextureDesc = TextureDesc {
+        width: 64,
+        height: 64,
+        format: TextureFormat::Rgba8Unorm,
+    };
+
+    fn pass_names<'a>(graph: &'a CompiledRenderGraph<'_, ()>) -> Vec<&'a str> {
+        graph.passes().iter().map(|pass| pass.name()).collect()
+    }
+
+    fn clear() -> LoadOp {
+        LoadOp::Clear(ClearValue::Color([0.0; 4]))
+    }
+
+    #[test]
+    fn culls_passes_whose_results_are_unused() {
+        let mut graph = RenderGraph::<()>::new();
+        let backbuffer = graph.import_texture("backbuffer", DESC);
+        let shadow = graph.create_texture("shadow", DESC);
+        let unused = graph.create_texture("unused", DESC);
+        graph.add_pass(
+            "shadow",
+            |pass| pass.color_attachment(shadow, clear()),
+            |_| {},
+        );
+        graph.add_pass(
+            "unused",
+            |pass| pass.color_attachment(unused, clear()),
+            |_| {},
+        );
+        graph.add_pass(
+            "readback",
+            |pass| {
+                pass.read(unused, TextureAccess::TransferSrc);
+                pass.side_effect();
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "main",
+            |pass| {
+                pass.read(shadow, TextureAccess::Sampled);
+                pass.color_attachment(backbuffer, clear());
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "overwritten",
+            |pass| pass.write(shadow, TextureAccess::Storage),
+            |_| {},
+        );
+
+        let graph = graph.compile().unwrap();
+        assert_eq!(pass_names(&graph), ["shadow", "unused", "readback", "main"]);
+        assert_eq!(graph.resources().culled_passes(), ["overwritten"]);
+    }
+
+    #[test]
+    fn culls_every_pass_when_nothing_writes_an_imported_texture() {
+        let mut graph = RenderGraph::<()>::new();
+        graph.import_texture("backbuffer", DESC);
+        let color = graph.create_texture("color", DESC);
+        graph.add_pass("draw", |pass| pass.color_attachment(color, clear()), |_| {});
+
+        let graph = graph.compile().unwrap();
+        assert!(graph.passes().is_empty());
+        assert_eq!(graph.resources().culled_passes(), ["draw"]);
+        assert!(graph.resources().transient_textures().is_empty());
+    }
+
+    #[test]
+    fn computes_the_lifetimes_of_transient_textures() {
+        let mut graph = RenderGraph::<()>::new();
+        let backbuffer = graph.import_texture("backbuffer", DESC);
+        let depth_desc = TextureDesc {
+            format: TextureFormat::Depth32Float,
+            ..DESC
+        };
+        let depth = graph.create_texture("depth", depth_desc);
+        let a = graph.create_texture("a", DESC);
+        let b = graph.create_texture("b", DESC);
+        let c = graph.create_texture("c", DESC);
+        graph.add_pass("write a", |pass| pass.color_attachment(a, clear()), |_| {});
+        graph.add_pass(
+            "a to b",
+            |pass| {
+                pass.read(a, TextureAccess::Sampled);
+                pass.color_attachment(b, clear());
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "b to c",
+            |pass| {
+                pass.read(b, TextureAccess::Sampled);
+                pass.color_attachment(c, clear());
+                pass.depth_attachment(depth, LoadOp::DontCare);
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "c to backbuffer",
+            |pass| {
+                pass.read(c, TextureAccess::Sampled);
+                pass.color_attachment(backbuffer, clear());
+            },
+            |_| {},
+        );
+
+        let graph = graph.compile().unwrap();
+        let lifetimes = graph
+            .resources()
+            .transient_textures()
+            .iter()
+            .map(|transient| (transient.texture, transient.desc, transient.lifetime))
+            .collect::<Vec<_>>();
+        // In the order of the first uses then of creation, without the imported backbuffer.
+        assert_eq!(
+            lifetimes,
+            [
+                (a, DESC, (0, 1)),
+                (b, DESC, (1, 2)),
+                (depth, depth_desc, (2, 2)),
+                (c, DESC, (2, 3)),
+            ]
+        );
+        assert_eq!(
+            graph.resources().transient_textures()[0].accesses,
+            [TextureAccess::ColorAttachment, TextureAccess::Sampled]
+        );
+        // `c` starts by discarding whatever was in its memory.
+        assert!(graph.passes()[2]
+            .transitions()
+            .contains(&TextureTransition {
+                texture: c,
+                before: None,
+                after: TextureAccess::ColorAttachment,
+                discard: true,
+            }));
+        assert_eq!(
+            graph.passes()[3].accesses(),
+            [
+                (c, TextureAccess::Sampled),
+                (backbuffer, TextureAccess::ColorAttachment)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_reading_a_texture_written_by_a_later_pass() {
+        let mut graph = RenderGraph::<()>::new();
+        let backbuffer = graph.import_texture("backbuffer", DESC);
+        let history = graph.create_texture("history", DESC);
+        graph.add_pass(
+            "resolve",
+            |pass| {
+                pass.read(history, TextureAccess::Sampled);
+                pass.color_attachment(backbuffer, clear());
+            },
+            |_| {},
+        );
+        graph.add_pass(
+            "store",
+            |pass| pass.color_attachment(history, clear()),
+            |_| {},
+        );
+
+        assert_eq!(
+            graph.compile().err(),
+            Some("Pass resolve reads texture history before any pass writes it.".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_attachments_of_different_sizes() {
+        let mut graph = RenderGraph::<()>::new();
+        let backbuffer = graph.import_texture("backbuffer", DESC);
+        let depth = graph.create_texture(
+            "depth",
+            TextureDesc {
+                width: 32,
+                format: TextureFormat::Depth32Float,
+                ..DESC
+            },
+        );
+        graph.add_pass(
+            "main",
+            |pass| {
+                pass.color_attachment(backbuffer, clear());
+                pass.depth_attachment(depth, clear());
+            },
+            |_| {},
+        );
+
+        assert_eq!(
+            graph.compile().err(),
+            Some("Attachments of pass main have different sizes.".to_owned())
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
 
@@ -10,33 +10,60 @@ pub trait Renderer {
     fn render(&self, swapchain: &Self::Swapchain);
 
     fn create_swapchain(
-        self: &Rc<Self>,
+        self: &Arc<Self>,
         display_handle: &RawDisplayHandle,
         window_handle: &RawWindowHandle,
-        window_size: &Rc<dyn WindowSizeProvider>,
+        window_size: &Arc<dyn WindowSizeProvider>,
     ) -> Self::Swapchain;
 
     fn create_shader(
-        self: &Rc<Self>,
-        vertex_shader_code: &Vec<u8>,
-        fragment_shader_code: &Vec<u8>,
+        self: &Arc<Self>,
+        vertex_shader: &ShaderSource,
+        fragment_shader: &ShaderSource,
     ) -> Self::Shader;
 
-    fn create_material(self: &Rc<Self>, shader: &Rc<Self::Shader>) -> Self::Material;
+    /// `specialization_constants` set the shader's specialization constants by name.
+    fn create_material(
+        self: &Arc<Self>,
+        shader: &Arc<Self::Shader>,
+        specialization_constants: &[(&str, SpecializationValue)],
+    ) -> Self::Material;
 }
 
 pub trait Swapchain {
     type RenderTarget;
 }
 
+/// Shader code of one stage, translated by the backend to its own representation.
+#[derive(Clone, Copy, Debug)]
+pub enum ShaderSource<'a> {
+    SpirV(&'a [u8]),
+    /// WGSL module and the name of the stage's entry point in it.
+    Wgsl {
+        source: &'a str,
+        entry_point: &'a str,
+    },
+    /// GLSL without includes. The entry point is `main`.
+    Glsl(&'a str),
+}
+
+/// Value of a specialization constant. It must match the type declared in the shader.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpecializationValue {
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+}
+
 pub trait Shader {}
 pub trait RenderTarget {}
 
-pub trait WindowSizeProvider {
+pub trait WindowSizeProvider: Send + Sync {
     fn window_size(&self) -> (u32, u32);
 }
 
 pub trait Material {
     type Shader;
-    fn shader(&self) -> Rc<Self::Shader>;
+    fn shader(&self) -> Arc<Self::Shader>;
 }
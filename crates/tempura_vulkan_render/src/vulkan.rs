@@ -1,11 +1,66 @@
+mod bindless;
+mod buffer;
+mod command_encoder;
+mod compute;
+mod descriptor;
+mod descriptor_allocator;
+mod frame;
+mod image;
+mod indirect;
+mod instance_buffer;
 mod material;
+mod pipeline_cache;
+mod query;
+mod queue;
+mod registry;
+mod render_graph;
 mod render_target;
 mod renderer;
+mod rendering;
+mod resource_state;
+mod sampler;
 mod shader;
+mod shader_compiler;
+mod shader_variants;
+mod shader_watcher;
+mod specialization;
+mod storage_buffer;
 mod swapchain;
+mod synchronization;
+mod timeline;
+mod transient_uniform;
+mod upload;
+mod vertex_layout;
 
+pub use ash::vk;
+pub use bindless::{BindlessBinding, BindlessIndex, BINDLESS_SET};
+pub use buffer::{Buffer, MemoryLocation};
+pub use command_encoder::{CommandEncoder, GpuTimerScope, QueryScope};
+pub use compute::{ComputePipeline, ComputeShader};
+pub use image::StorageImage;
+pub use indirect::{DrawIndexedIndirectCommand, DrawIndirectCommand};
+pub use instance_buffer::{InstanceBuffer, InstanceData};
 pub use material::Material;
+pub use query::{GpuTiming, OcclusionResult, PipelineStatistics};
+pub use queue::{QueueSubmission, QueueType};
+pub use registry::{
+    BufferHandle, Handle, MaterialHandle, RegisteredResource, ResourceLeak, ShaderHandle,
+    StorageImageHandle,
+};
 pub use render_target::VulkanRenderTarget;
-pub use renderer::Renderer;
+pub use renderer::{Renderer, RendererOptions};
+pub use rendering::AttachmentFormats;
+pub use resource_state::ResourceAccess;
+pub use sampler::Sampler;
 pub use shader::Shader;
+pub use shader_compiler::{
+    compile_shader_file, compile_shader_source, ShaderCompileError, ShaderCompileOptions,
+    ShaderLanguage, ShaderStage,
+};
+pub use shader_variants::{ShaderVariantKey, ShaderVariants};
+pub use shader_watcher::{ShaderReload, ShaderWatcher};
+pub use storage_buffer::StorageBuffer;
 pub use swapchain::VulkanSwapchain;
+pub use transient_uniform::TransientUniform;
+pub use upload::UploadHandle;
+pub use vertex_layout::{VertexAttribute, VertexBinding, VertexLayout};
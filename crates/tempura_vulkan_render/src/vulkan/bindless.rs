@@ -0,0 +1,367 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use ash::{prelude::VkResult, vk, Device, Instance};
+
+/// Descriptor set shaders declare the bindless table at, the last one every device can bind.
+/// Pipelines that declare it get the global table there instead of a set of their own.
+pub const BINDLESS_SET: u32 = 3;
+/// Slots of each binding of the table, fewer if the device limits are lower.
+const BINDLESS_CAPACITY: u32 = 16384;
+
+/// Binding of the bindless table, declared by shaders as an unbounded array, e.g.
+/// `layout(set = 3, binding = 1) uniform texture2D textures[];`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BindlessBinding {
+    /// Storage buffers at binding 0.
+    StorageBuffer,
+    /// Storage images sampled as textures at binding 1, in the `GENERAL` layout.
+    SampledImage,
+    /// Storage images at binding 2, in the `GENERAL` layout.
+    StorageImage,
+    /// Samplers at binding 3.
+    Sampler,
+}
+
+impl BindlessBinding {
+    const ALL: [BindlessBinding; 4] = [
+        BindlessBinding::StorageBuffer,
+        BindlessBinding::SampledImage,
+        BindlessBinding::StorageImage,
+        BindlessBinding::Sampler,
+    ];
+
+    /// Binding number in the bindless set.
+    pub fn binding(self) -> u32 {
+        self as u32
+    }
+
+    pub(crate) fn descriptor_type(self) -> vk::DescriptorType {
+        match self {
+            BindlessBinding::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
+            BindlessBinding::SampledImage => vk::DescriptorType::SAMPLED_IMAGE,
+            BindlessBinding::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+            BindlessBinding::Sampler => vk::DescriptorType::SAMPLER,
+        }
+    }
+
+    pub(crate) fn from_binding(binding: u32) -> Option<Self> {
+        BindlessBinding::ALL.get(binding as usize).copied()
+    }
+}
+
+/// Slot of a resource in the bindless table. Shaders index the array of `binding` with
+/// `index`, which materials typically get through push constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BindlessIndex {
+    pub binding: BindlessBinding,
+    pub index: u32,
+}
+
+/// Last submissions of the graphics and compute queues, the queues that bind the table.
+pub(crate) type QueueProgress = [u64; 2];
+
+/// Slots of one binding. Freed slots are reused once the submissions made before they were
+/// freed complete, so that no pending command buffer sees a slot change.
+#[derive(Default)]
+struct BindingSlots {
+    capacity: u32,
+    next: u32,
+    free: Vec<u32>,
+    retired: VecDeque<(QueueProgress, u32)>,
+}
+
+impl BindingSlots {
+    fn new(capacity: u32) -> Self {
+        BindingSlots {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    /// Free slot, or `None` if every slot is used or waits for pending submissions.
+    fn allocate(&mut self, completed: QueueProgress) -> Option<u32> {
+        while let Some(&(submissions, index)) = self.retired.front() {
+            if submissions[0] > completed[0] || submissions[1] > completed[1] {
+                break;
+            }
+            self.retired.pop_front();
+            self.free.push(index);
+        }
+        self.free.pop().or_else(|| {
+            (self.next < self.capacity).then(|| {
+                self.next += 1;
+                self.next - 1
+            })
+        })
+    }
+
+    /// Frees `index` once the submissions up to `submitted` complete.
+    fn retire(&mut self, index: u32, submitted: QueueProgress) {
+        self.retired.push_back((submitted, index));
+    }
+}
+
+#[derive(Default)]
+struct BindlessSlots {
+    bindings: [BindingSlots; 4],
+    /// Slots by binding and raw handle of the resource, so that a resource keeps one index.
+    indices: HashMap<(BindlessBinding, u64), u32>,
+}
+
+/// Global descriptor set of the bindless model, bound by every pipeline that declares
+/// `BINDLESS_SET`. Slots are written with update-after-bind, so resources are added while
+/// frames are recorded and in flight.
+pub(crate) struct BindlessTable {
+    pub(crate) layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    pub(crate) set: vk::DescriptorSet,
+    /// Also held while writing the set, whose updates are externally synchronized.
+    slots: Mutex<BindlessSlots>,
+}
+
+impl BindlessTable {
+    pub(crate) fn new(
+        instance: &Instance,
+        pdevice: &vk::PhysicalDevice,
+        device: &Device,
+    ) -> VkResult<Self> {
+        let capacities = get_capacities(instance, pdevice);
+        unsafe {
+            let layout_bindings = BindlessBinding::ALL
+                .iter()
+                .zip(capacities)
+                .map(|(binding, capacity)| {
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(binding.binding())
+                        .descriptor_type(binding.descriptor_type())
+                        .descriptor_count(capacity)
+                        .stage_flags(vk::ShaderStageFlags::ALL)
+                        .build()
+                })
+                .collect::<Vec<_>>();
+            let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+                | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+                4];
+            let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+                .binding_flags(&binding_flags)
+                .build();
+            let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+                .bindings(&layout_bindings)
+                .push_next(&mut binding_flags_info)
+                .build();
+            let layout = device.create_descriptor_set_layout(&layout_create_info, None)?;
+
+            let pool_sizes = layout_bindings
+                .iter()
+                .map(|binding| vk::DescriptorPoolSize {
+                    ty: binding.descriptor_type,
+                    descriptor_count: binding.descriptor_count,
+                })
+                .collect::<Vec<_>>();
+            let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+                .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+                .max_sets(1)
+                .pool_sizes(&pool_sizes)
+                .build();
+            let pool = match device.create_descriptor_pool(&pool_create_info, None) {
+                Ok(pool) => pool,
+                Err(e) => {
+                    device.destroy_descriptor_set_layout(layout, None);
+                    return Err(e);
+                }
+            };
+            let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&[layout])
+                .build();
+            let set = match device.allocate_descriptor_sets(&allocate_info) {
+                Ok(sets) => sets[0],
+                Err(e) => {
+                    device.destroy_descriptor_pool(pool, None);
+                    device.destroy_descriptor_set_layout(layout, None);
+                    return Err(e);
+                }
+            };
+
+            let mut slots = BindlessSlots::default();
+            for (binding_slots, capacity) in slots.bindings.iter_mut().zip(capacities) {
+                *binding_slots = BindingSlots::new(capacity);
+            }
+            Ok(BindlessTable {
+                layout,
+                pool,
+                set,
+                slots: Mutex::new(slots),
+            })
+        }
+    }
+
+    /// Index of the resource of `handle` in `binding`, writing it to a new slot with `write`
+    /// unless it has one already.
+    /// Panics if the binding is full.
+    pub(crate) fn add(
+        &self,
+        device: &Device,
+        binding: BindlessBinding,
+        handle: u64,
+        completed: QueueProgress,
+        write: vk::WriteDescriptorSetBuilder<'_>,
+    ) -> BindlessIndex {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some(&index) = slots.indices.get(&(binding, handle)) {
+            return BindlessIndex { binding, index };
+        }
+        let index = slots.bindings[binding as usize]
+            .allocate(completed)
+            .unwrap_or_else(|| panic!("The bindless {:?} binding is full.", binding));
+        let write = write
+            .dst_set(self.set)
+            .dst_binding(binding.binding())
+            .dst_array_element(index)
+            .descriptor_type(binding.descriptor_type())
+            .build();
+        unsafe {
+            device.update_descriptor_sets(&[write], &[]);
+        }
+        slots.indices.insert((binding, handle), index);
+        BindlessIndex { binding, index }
+    }
+
+    /// Frees the slot of `index`, once the submissions up to `submitted` complete.
+    /// Does nothing if the slot is already free.
+    pub(crate) fn remove(&self, index: BindlessIndex, submitted: QueueProgress) {
+        let mut slots = self.slots.lock().unwrap();
+        let before = slots.indices.len();
+        slots
+            .indices
+            .retain(|&(binding, _), &mut i| binding != index.binding || i != index.index);
+        if slots.indices.len() != before {
+            slots.bindings[index.binding as usize].retire(index.index, submitted);
+        }
+    }
+
+    /// Frees the slots of a resource being destroyed, in every binding.
+    pub(crate) fn forget(&self, handle: u64, submitted: QueueProgress) {
+        let mut slots = self.slots.lock().unwrap();
+        let BindlessSlots { bindings, indices } = &mut *slots;
+        indices.retain(|&(binding, h), &mut index| {
+            if h == handle {
+                bindings[binding as usize].retire(index, submitted);
+            }
+            h != handle
+        });
+    }
+
+    /// The GPU must be done with the set.
+    pub(crate) fn destroy(&self, device: &Device) {
+        unsafe {
+            // The set is freed together with the pool.
+            device.destroy_descriptor_pool(self.pool, None);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+/// Whether the device supports the descriptor indexing features the bindless table uses.
+/// It must support Vulkan 1.2.
+pub(crate) fn is_bindless_supported(instance: &Instance, pdevice: &vk::PhysicalDevice) -> bool {
+    unsafe {
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut vulkan12_features)
+            .build();
+        instance.get_physical_device_features2(*pdevice, &mut features);
+        [
+            vulkan12_features.descriptor_indexing,
+            vulkan12_features.runtime_descriptor_array,
+            vulkan12_features.descriptor_binding_partially_bound,
+            vulkan12_features.descriptor_binding_update_unused_while_pending,
+            vulkan12_features.descriptor_binding_storage_buffer_update_after_bind,
+            vulkan12_features.descriptor_binding_sampled_image_update_after_bind,
+            vulkan12_features.descriptor_binding_storage_image_update_after_bind,
+            vulkan12_features.shader_storage_buffer_array_non_uniform_indexing,
+            vulkan12_features.shader_sampled_image_array_non_uniform_indexing,
+            vulkan12_features.shader_storage_image_array_non_uniform_indexing,
+        ]
+        .iter()
+        .all(|&supported| supported == vk::TRUE)
+    }
+}
+
+/// Enables the features checked by `is_bindless_supported`.
+pub(crate) fn enable_bindless_features(
+    features: vk::PhysicalDeviceVulkan12FeaturesBuilder,
+) -> vk::PhysicalDeviceVulkan12FeaturesBuilder {
+    features
+        .descriptor_indexing(true)
+        .runtime_descriptor_array(true)
+        .descriptor_binding_partially_bound(true)
+        .descriptor_binding_update_unused_while_pending(true)
+        .descriptor_binding_storage_buffer_update_after_bind(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+        .descriptor_binding_storage_image_update_after_bind(true)
+        .shader_storage_buffer_array_non_uniform_indexing(true)
+        .shader_sampled_image_array_non_uniform_indexing(true)
+        .shader_storage_image_array_non_uniform_indexing(true)
+}
+
+/// Slots of each binding, within the update-after-bind limits of the device.
+fn get_capacities(instance: &Instance, pdevice: &vk::PhysicalDevice) -> [u32; 4] {
+    let mut vulkan12_properties = vk::PhysicalDeviceVulkan12Properties::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder()
+        .push_next(&mut vulkan12_properties)
+        .build();
+    unsafe {
+        instance.get_physical_device_properties2(*pdevice, &mut properties);
+    }
+    let p = &vulkan12_properties;
+    [
+        p.max_per_stage_descriptor_update_after_bind_storage_buffers
+            .min(p.max_descriptor_set_update_after_bind_storage_buffers),
+        p.max_per_stage_descriptor_update_after_bind_sampled_images
+            .min(p.max_descriptor_set_update_after_bind_sampled_images),
+        p.max_per_stage_descriptor_update_after_bind_storage_images
+            .min(p.max_descriptor_set_update_after_bind_storage_images),
+        p.max_per_stage_descriptor_update_after_bind_samplers
+            .min(p.max_descriptor_set_update_after_bind_samplers),
+    ]
+    .map(|limit| limit.min(BINDLESS_CAPACITY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_a_freed_slot_once_both_queues_complete_their_submissions() {
+        let mut slots = BindingSlots::new(4);
+        assert_eq!(slots.allocate([0, 0]), Some(0));
+        assert_eq!(slots.allocate([0, 0]), Some(1));
+        // Freed after the 3rd graphics and the 5th compute submissions.
+        slots.retire(0, [3, 5]);
+
+        assert_eq!(slots.allocate([3, 4]), Some(2));
+        assert_eq!(slots.allocate([2, 5]), Some(3));
+        assert_eq!(slots.allocate([2, 5]), None);
+        assert_eq!(slots.allocate([3, 5]), Some(0));
+    }
+
+    #[test]
+    fn reports_a_full_binding() {
+        let mut slots = BindingSlots::new(2);
+        assert_eq!(slots.allocate([0, 0]), Some(0));
+        assert_eq!(slots.allocate([0, 0]), Some(1));
+        assert_eq!(slots.allocate([u64::MAX; 2]), None);
+
+        slots.retire(1, [1, 0]);
+        slots.retire(0, [2, 0]);
+        assert_eq!(slots.allocate([1, 0]), Some(1));
+        assert_eq!(slots.allocate([1, 0]), None);
+        assert_eq!(slots.allocate([2, 0]), Some(0));
+    }
+}
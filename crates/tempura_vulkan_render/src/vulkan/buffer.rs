@@ -0,0 +1,220 @@
+use std::{
+    ffi::c_void,
+    ptr,
+    sync::{Arc, Mutex},
+};
+
+use ash::{prelude::VkResult, vk};
+
+use super::{resource_state::TrackedResource, Renderer};
+
+/// Where the memory of a resource lives, and how the CPU can access it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryLocation {
+    /// Device local memory, not accessible by the CPU.
+    GpuOnly,
+    /// Host visible memory written by the CPU and read by the GPU.
+    CpuToGpu,
+    /// Host visible memory written by the GPU and read back by the CPU.
+    GpuToCpu,
+}
+
+impl MemoryLocation {
+    /// Memory properties in order of preference.
+    fn memory_properties(&self) -> &'static [vk::MemoryPropertyFlags] {
+        const HOST_COHERENT: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::from_raw(
+            vk::MemoryPropertyFlags::HOST_VISIBLE.as_raw()
+                | vk::MemoryPropertyFlags::HOST_COHERENT.as_raw(),
+        );
+        const HOST_CACHED: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::from_raw(
+            HOST_COHERENT.as_raw() | vk::MemoryPropertyFlags::HOST_CACHED.as_raw(),
+        );
+        match self {
+            MemoryLocation::GpuOnly => &[vk::MemoryPropertyFlags::DEVICE_LOCAL],
+            MemoryLocation::CpuToGpu => &[HOST_COHERENT],
+            MemoryLocation::GpuToCpu => &[HOST_CACHED, HOST_COHERENT],
+        }
+    }
+}
+
+/// Allocates memory for `requirements` with the first of the preferred properties available.
+pub(crate) fn allocate_memory(
+    renderer: &Renderer,
+    requirements: &vk::MemoryRequirements,
+    location: MemoryLocation,
+) -> VkResult<vk::DeviceMemory> {
+    let properties = unsafe {
+        renderer
+            .instance
+            .get_physical_device_memory_properties(renderer.physical_device)
+    };
+    let memory_types = &properties.memory_types[..properties.memory_type_count as usize];
+    let memory_type_index = location
+        .memory_properties()
+        .iter()
+        .find_map(|&flags| {
+            memory_types
+                .iter()
+                .enumerate()
+                .position(|(index, memory_type)| {
+                    requirements.memory_type_bits & (1 << index) != 0
+                        && memory_type.property_flags.contains(flags)
+                })
+        })
+        .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)?;
+
+    let allocate_info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(requirements.size)
+        .memory_type_index(memory_type_index as u32)
+        .build();
+    unsafe { renderer.device.allocate_memory(&allocate_info, None) }
+}
+
+/// GPU buffer with its own memory allocation.
+/// Host visible buffers stay mapped for their whole lifetime.
+pub struct Buffer {
+    renderer: Arc<Renderer>,
+    pub(crate) buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+    mapped: *mut c_void,
+    /// Held while the CPU accesses the mapped memory.
+    host_access: Mutex<()>,
+}
+
+// The mapped memory is only accessed under `host_access`.
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
+
+impl Buffer {
+    pub(crate) fn new(
+        renderer: &Arc<Renderer>,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> VkResult<Self> {
+        unsafe {
+            let create_info = vk::BufferCreateInfo::builder()
+                .size(size)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build();
+            let buffer = renderer.device.create_buffer(&create_info, None)?;
+            let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+            let memory = match allocate_memory(renderer, &requirements, location) {
+                Ok(memory) => memory,
+                Err(e) => {
+                    renderer.device.destroy_buffer(buffer, None);
+                    return Err(e);
+                }
+            };
+            let mapped = match bind_and_map(renderer, buffer, memory, location) {
+                Ok(mapped) => mapped,
+                Err(e) => {
+                    renderer.device.destroy_buffer(buffer, None);
+                    renderer.device.free_memory(memory, None);
+                    return Err(e);
+                }
+            };
+
+            Ok(Buffer {
+                renderer: renderer.clone(),
+                buffer,
+                memory,
+                size,
+                usage,
+                mapped,
+                host_access: Mutex::new(()),
+            })
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn usage(&self) -> vk::BufferUsageFlags {
+        self.usage
+    }
+
+    /// Whether the CPU can `write` and `read` the buffer.
+    pub fn is_mapped(&self) -> bool {
+        !self.mapped.is_null()
+    }
+
+    /// Copies `data` into the buffer at `offset` bytes.
+    /// The GPU must not be using the written range.
+    /// Panics if the buffer isn't host visible or the range is out of the buffer.
+    pub fn write<T: bytemuck::Pod>(&self, offset: u64, data: &[T]) {
+        let bytes = bytemuck::cast_slice::<T, u8>(data);
+        self.check_mapped_range(offset, bytes.len() as u64);
+        let _host_access = self.host_access.lock().unwrap();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                (self.mapped as *mut u8).add(offset as usize),
+                bytes.len(),
+            );
+        }
+    }
+
+    /// Reads `count` elements from the buffer at `offset` bytes.
+    /// The GPU must have finished writing the range.
+    /// Panics if the buffer isn't host visible or the range is out of the buffer.
+    pub fn read<T: bytemuck::Pod>(&self, offset: u64, count: usize) -> Vec<T> {
+        let mut data = vec![T::zeroed(); count];
+        let bytes = bytemuck::cast_slice_mut::<T, u8>(&mut data);
+        self.check_mapped_range(offset, bytes.len() as u64);
+        let _host_access = self.host_access.lock().unwrap();
+        unsafe {
+            ptr::copy_nonoverlapping(
+                (self.mapped as *const u8).add(offset as usize),
+                bytes.as_mut_ptr(),
+                bytes.len(),
+            );
+        }
+        data
+    }
+
+    fn check_mapped_range(&self, offset: u64, size: u64) {
+        assert!(self.is_mapped(), "Buffer is not host visible.");
+        assert!(
+            offset.checked_add(size).is_some_and(|end| end <= self.size),
+            "Range {}..{} is out of the buffer of {} bytes.",
+            offset,
+            offset.saturating_add(size),
+            self.size
+        );
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        self.renderer
+            .forget_resource(TrackedResource::Buffer(self.buffer));
+        unsafe {
+            self.renderer.device.destroy_buffer(self.buffer, None);
+            self.renderer.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Binds `memory` to `buffer`, and maps it unless it is GPU only.
+pub(crate) fn bind_and_map(
+    renderer: &Renderer,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    location: MemoryLocation,
+) -> VkResult<*mut c_void> {
+    unsafe {
+        renderer.device.bind_buffer_memory(buffer, memory, 0)?;
+        if location == MemoryLocation::GpuOnly {
+            Ok(ptr::null_mut())
+        } else {
+            renderer
+                .device
+                .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+        }
+    }
+}
@@ -0,0 +1,1435 @@
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+    sync::Arc,
+    thread,
+};
+
+use ash::{extensions::khr::DrawIndirectCount, vk};
+use tempura_render as tr;
+
+use super::{
+    descriptor::PipelineDescriptors,
+    frame::Frame,
+    material::MaterialPipeline,
+    query::FrameQueries,
+    queue::{record_ownership_transfer, PendingAcquire},
+    render_graph::GraphPassTextures,
+    rendering::begin_secondary,
+    resource_state::{record_transitions, AccessScope, ResourceUse, TrackedResource},
+    Buffer, ComputePipeline, DrawIndexedIndirectCommand, DrawIndirectCommand, Material, QueueType,
+    Renderer, ResourceAccess, ShaderStage, StorageImage, TransientUniform, VulkanSwapchain,
+};
+
+/// Records commands into the command buffer of the current frame, or of a submission to the
+/// compute or transfer queue.
+/// It is passed to the closure of `Renderer::render_frame_with` and `Renderer::execute`,
+/// outside of any render pass, and to the closure of `Renderer::render_with` inside the
+/// swapchain pass. Draws are recorded in `swapchain_pass`, dispatches and barriers outside it.
+///
+/// The encoder tracks the buffers and storage images its commands use, including the resources
+/// bound to materials and compute pipelines, and inserts the barriers a new use needs.
+/// Barriers can't be recorded inside render passes, so a resource used by draws in a way that
+/// needs one is declared with `use_buffer` or `use_storage_image` before the pass.
+/// A buffer used by another queue next is handed over with `release_buffer`.
+/// Draws of the swapchain pass can be recorded on several threads with
+/// `swapchain_pass_parallel`.
+pub struct CommandEncoder<'a> {
+    renderer: &'a Renderer,
+    command_buffer: vk::CommandBuffer,
+    /// Frame the commands are submitted with, which encoders of other queues don't have.
+    frame: Option<&'a Frame>,
+    /// Whether the command buffer is a secondary one of a parallel pass.
+    secondary: bool,
+    queue: QueueType,
+    swapchain: Option<&'a VulkanSwapchain>,
+    in_render_pass: Cell<bool>,
+    swapchain_pass_recorded: Cell<bool>,
+    /// Pipeline of the last bound material, kept alive until the encoder is dropped.
+    bound_material: RefCell<Option<Arc<MaterialPipeline>>>,
+    bound_compute_pipeline: RefCell<Option<BoundComputePipeline>>,
+    /// Textures of the render graph pass being recorded, if any.
+    graph_pass: RefCell<Option<GraphPassTextures>>,
+}
+
+/// What push constants and transient descriptor sets need to know about the bound compute
+/// pipeline.
+struct BoundComputePipeline {
+    descriptors: Arc<PipelineDescriptors>,
+    pipeline_layout: vk::PipelineLayout,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    /// Uses of the bound resources, tracked by each dispatch.
+    resource_uses: Vec<(TrackedResource, ResourceUse)>,
+}
+
+impl<'a> CommandEncoder<'a> {
+    pub(crate) fn new(
+        renderer: &'a Renderer,
+        frame: &'a Frame,
+        swapchain: Option<&'a VulkanSwapchain>,
+    ) -> Self {
+        CommandEncoder {
+            renderer,
+            command_buffer: frame.command_buffer,
+            frame: Some(frame),
+            secondary: false,
+            queue: QueueType::Graphics,
+            swapchain,
+            in_render_pass: Cell::new(false),
+            swapchain_pass_recorded: Cell::new(false),
+            bound_material: RefCell::new(None),
+            bound_compute_pipeline: RefCell::new(None),
+            graph_pass: RefCell::new(None),
+        }
+    }
+
+    /// Encoder of a command buffer of the compute or transfer queue.
+    pub(crate) fn new_async(
+        renderer: &'a Renderer,
+        command_buffer: vk::CommandBuffer,
+        queue: QueueType,
+    ) -> Self {
+        CommandEncoder {
+            renderer,
+            command_buffer,
+            frame: None,
+            secondary: false,
+            queue,
+            swapchain: None,
+            in_render_pass: Cell::new(false),
+            swapchain_pass_recorded: Cell::new(false),
+            bound_material: RefCell::new(None),
+            bound_compute_pipeline: RefCell::new(None),
+            graph_pass: RefCell::new(None),
+        }
+    }
+
+    /// Encoder of a secondary command buffer continuing a render pass of the graphics queue.
+    fn new_secondary(
+        renderer: &'a Renderer,
+        frame: &'a Frame,
+        command_buffer: vk::CommandBuffer,
+    ) -> Self {
+        CommandEncoder {
+            renderer,
+            command_buffer,
+            frame: Some(frame),
+            secondary: true,
+            queue: QueueType::Graphics,
+            swapchain: None,
+            in_render_pass: Cell::new(true),
+            swapchain_pass_recorded: Cell::new(false),
+            bound_material: RefCell::new(None),
+            bound_compute_pipeline: RefCell::new(None),
+            graph_pass: RefCell::new(None),
+        }
+    }
+
+    /// Queue the recorded commands are submitted to.
+    pub fn queue(&self) -> QueueType {
+        self.queue
+    }
+
+    /// Value the recorded commands will be submitted with on `queue`, to check with
+    /// `Renderer::is_submission_complete` before reusing or reading back what they write.
+    pub fn submission(&self) -> u64 {
+        self.renderer.timeline_of(self.queue).last_submitted() + 1
+    }
+
+    pub(crate) fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    fn queries(&self) -> &FrameQueries {
+        &self
+            .frame
+            .filter(|_| !self.secondary)
+            .expect("GPU queries are only recorded in the primary command buffers of frames.")
+            .queries
+    }
+
+    pub(crate) fn swapchain_pass_recorded(&self) -> bool {
+        self.swapchain_pass_recorded.get()
+    }
+
+    /// Records `record` inside the render pass of the swapchain image, whose viewport and
+    /// scissor cover the whole image.
+    /// Panics if the encoder has no swapchain or the pass was already recorded.
+    pub fn swapchain_pass<F>(&self, record: F)
+    where
+        F: FnOnce(&CommandEncoder),
+    {
+        let swapchain = self
+            .swapchain
+            .expect("The encoder has no swapchain to render to.");
+        assert!(
+            !self.swapchain_pass_recorded.replace(true),
+            "The swapchain pass is recorded once per frame."
+        );
+        let clear_values = [vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.5, 1.0],
+            },
+        }];
+        swapchain.begin_render_pass(
+            &clear_values,
+            &self.command_buffer(),
+            vk::SubpassContents::INLINE,
+        );
+        self.in_render_pass.set(true);
+        record(self);
+        self.in_render_pass.set(false);
+        swapchain.end_render_pass(&self.command_buffer());
+    }
+
+    /// Same as `swapchain_pass`, recording the draws on `jobs` threads, e.g. one per part of
+    /// a scene. Each thread calls `record` with its job index and an encoder of a secondary
+    /// command buffer, allocated from a command pool of its own. The secondary command buffers
+    /// execute in the order of the jobs.
+    /// The encoders of the jobs start with nothing bound, and can't record GPU queries.
+    /// Panics under the conditions of `swapchain_pass`, or if a job panics.
+    pub fn swapchain_pass_parallel<F>(&self, jobs: usize, record: F)
+    where
+        F: Fn(usize, &CommandEncoder) + Sync,
+    {
+        let swapchain = self
+            .swapchain
+            .expect("The encoder has no swapchain to render to.");
+        let frame = self
+            .frame
+            .expect("Secondary command buffers are only recorded in frames.");
+        assert!(
+            !self.swapchain_pass_recorded.replace(true),
+            "The swapchain pass is recorded once per frame."
+        );
+        let renderer = self.renderer;
+        let device = &renderer.device;
+        let inheritance = swapchain.pass_inheritance();
+        let mut pools = frame
+            .take_thread_pools(
+                device,
+                renderer.queue_family_index(QueueType::Graphics),
+                jobs,
+            )
+            .expect("Create command pool failed.");
+        let command_buffers = run_jobs(&mut pools, |job, pool| {
+            let command_buffer = pool
+                .next_secondary(device)
+                .expect("Allocate command buffers failed.");
+            begin_secondary(device, command_buffer, &inheritance)
+                .expect("Begin commandbuffer failed.");
+            record(
+                job,
+                &CommandEncoder::new_secondary(renderer, frame, command_buffer),
+            );
+            unsafe {
+                device
+                    .end_command_buffer(command_buffer)
+                    .expect("End commandbuffer failed.");
+            }
+            command_buffer
+        });
+        frame.return_thread_pools(pools);
+        let command_buffers = command_buffers
+            .unwrap_or_else(|_| panic!("Recording a job of the swapchain pass failed."));
+
+        let clear_values = [vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.5, 1.0],
+            },
+        }];
+        swapchain.begin_render_pass(
+            &clear_values,
+            &self.command_buffer(),
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+        );
+        if !command_buffers.is_empty() {
+            unsafe {
+                device.cmd_execute_commands(self.command_buffer(), &command_buffers);
+            }
+        }
+        swapchain.end_render_pass(&self.command_buffer());
+    }
+
+    /// Records `record` inside a render pass begun by the caller, e.g. a render graph pass.
+    pub(crate) fn render_pass_scope<F>(&self, record: F)
+    where
+        F: FnOnce(),
+    {
+        self.in_render_pass.set(true);
+        record();
+        self.in_render_pass.set(false);
+    }
+
+    /// Records `record` as the render graph pass using `textures`, which it can bind.
+    pub(crate) fn graph_pass_scope<F>(&self, textures: GraphPassTextures, record: F)
+    where
+        F: FnOnce(),
+    {
+        *self.graph_pass.borrow_mut() = Some(textures);
+        record();
+        *self.graph_pass.borrow_mut() = None;
+    }
+
+    /// Records that the following commands use `buffer` with `access`, inserting the barrier
+    /// it needs after the previous uses. Commands of the encoder do this themselves, except
+    /// for draws in a render pass and for `HostRead` before `Buffer::read`.
+    /// Panics if the encoder is in a render pass.
+    pub fn use_buffer(&self, buffer: &Buffer, access: ResourceAccess) {
+        assert!(
+            !self.in_render_pass.get(),
+            "Barriers must be outside of render passes."
+        );
+        self.use_resources(&[(
+            TrackedResource::Buffer(buffer.buffer),
+            access.resource_use(vk::ImageLayout::UNDEFINED),
+        )]);
+    }
+
+    /// Same as `use_buffer` for a storage image, which stays in the `GENERAL` layout.
+    pub fn use_storage_image(&self, image: &StorageImage, access: ResourceAccess) {
+        assert!(
+            !self.in_render_pass.get(),
+            "Barriers must be outside of render passes."
+        );
+        self.use_resources(&[(
+            TrackedResource::Image(image.image),
+            access.resource_use(vk::ImageLayout::GENERAL),
+        )]);
+    }
+
+    /// Hands `buffer` over to the next command buffer of queue `to`, which waits for this
+    /// submission. The encoder must not use the buffer afterwards. Between queue families,
+    /// this releases the ownership of the buffer, which the other queue acquires.
+    /// Panics if `to` is the queue of the encoder or the encoder is in a render pass.
+    pub fn release_buffer(&self, buffer: &Buffer, to: QueueType) {
+        assert!(
+            to != self.queue,
+            "The buffer is released to the queue of the encoder."
+        );
+        assert!(
+            !self.in_render_pass.get(),
+            "Barriers must be outside of render passes."
+        );
+        let src = self
+            .renderer
+            .resource_states
+            .lock()
+            .unwrap()
+            .hand_over(TrackedResource::Buffer(buffer.buffer));
+        let src_family = self.renderer.queue_family_index(self.queue);
+        let dst_family = self.renderer.queue_family_index(to);
+        let families = (src_family != dst_family).then_some((src_family, dst_family));
+        if let Some(families) = families {
+            record_ownership_transfer(
+                &self.renderer.synchronization,
+                self.command_buffer(),
+                TrackedResource::Buffer(buffer.buffer),
+                families,
+                src,
+                AccessScope::default(),
+            );
+        }
+        self.renderer
+            .pending_acquires
+            .lock()
+            .unwrap()
+            .push(PendingAcquire {
+                resource: TrackedResource::Buffer(buffer.buffer),
+                src_queue: self.queue,
+                dst_queue: to,
+                submission: self.submission(),
+                families,
+            });
+    }
+
+    /// Records `uses` of resources, with the barriers they need outside of render passes.
+    /// Panics in a render pass if a use needs a barrier.
+    fn use_resources(&self, uses: &[(TrackedResource, ResourceUse)]) {
+        let mut states = self.renderer.resource_states.lock().unwrap();
+        if self.in_render_pass.get() {
+            for (resource, resource_use) in uses {
+                assert!(
+                    states.use_in_render_pass(*resource, resource_use),
+                    "{:?} needs a barrier for {:?}, which can't be recorded in a render pass. \
+                     Declare the use with `use_buffer` or `use_storage_image` before the pass.",
+                    resource,
+                    resource_use.scope
+                );
+            }
+            return;
+        }
+        let transitions = uses
+            .iter()
+            .filter_map(|(resource, resource_use)| states.transition(*resource, resource_use))
+            .collect::<Vec<_>>();
+        record_transitions(
+            &self.renderer.synchronization,
+            self.command_buffer(),
+            &transitions,
+        );
+    }
+
+    /// Binds a set with the layout of `set` of the bound pipeline, with the render graph
+    /// `textures` written to their sampled or storage image bindings, like
+    /// `bind_transient_buffers`. In a pass with attachments, the pipeline is the bound
+    /// material, otherwise the bound compute pipeline. The current pass must read a sampled
+    /// texture as `Sampled`, and read or write a storage one as `Storage`. Shaders sample
+    /// them with samplers of their own, e.g. from the bindless table.
+    /// Panics outside of render graph passes, if no pipeline is bound, or if the pass or the
+    /// shader doesn't declare a texture that way.
+    pub fn bind_graph_textures(&self, set: u32, textures: &[(u32, tr::TextureHandle)]) {
+        let frame = self
+            .frame
+            .expect("Transient descriptor sets are only allocated in frames.");
+        let images = {
+            let graph_pass = self.graph_pass.borrow();
+            let graph_pass = graph_pass
+                .as_ref()
+                .expect("Graph textures are only bound in render graph passes.");
+            textures
+                .iter()
+                .map(|&(binding, texture)| {
+                    let (descriptor_type, image, view) = graph_pass.image(texture)?;
+                    Ok((binding, descriptor_type, image, view))
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .unwrap_or_else(|e| panic!("{}", e))
+        };
+        let bind = |descriptors: &PipelineDescriptors,
+                    bind_point: vk::PipelineBindPoint,
+                    pipeline_layout: vk::PipelineLayout| {
+            let (descriptor_set, uses) = descriptors
+                .transient_image_set(
+                    &mut frame.transient_descriptors.lock().unwrap(),
+                    set,
+                    &images,
+                )
+                .unwrap_or_else(|e| panic!("{}", e));
+            self.use_resources(&uses);
+            let dynamic_offsets = vec![0; descriptors.dynamic_descriptor_count(set..set + 1)];
+            unsafe {
+                self.renderer.device.cmd_bind_descriptor_sets(
+                    self.command_buffer(),
+                    bind_point,
+                    pipeline_layout,
+                    set,
+                    &[descriptor_set],
+                    &dynamic_offsets,
+                );
+            }
+        };
+        if self.in_render_pass.get() {
+            let bound = self.bound_material.borrow();
+            let pipeline = bound
+                .as_ref()
+                .expect("A material must be bound before binding graph textures.");
+            bind(
+                &pipeline.descriptors.lock().unwrap(),
+                vk::PipelineBindPoint::GRAPHICS,
+                *pipeline.pipeline_layout.lock().unwrap(),
+            );
+        } else {
+            let bound = self.bound_compute_pipeline.borrow();
+            let pipeline = bound
+                .as_ref()
+                .expect("A compute pipeline must be bound before binding graph textures.");
+            bind(
+                &pipeline.descriptors,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.pipeline_layout,
+            );
+        }
+    }
+
+    /// Binds the pipeline of `material` and its descriptor sets for the following draws.
+    pub fn bind_material(&self, material: &Material) {
+        let descriptors = material.pipeline.descriptors.lock().unwrap();
+        self.use_resources(&descriptors.resource_uses());
+        unsafe {
+            self.renderer.device.cmd_bind_pipeline(
+                self.command_buffer(),
+                vk::PipelineBindPoint::GRAPHICS,
+                *material.pipeline.pipeline.lock().unwrap(),
+            );
+        }
+        descriptors.bind(
+            self.command_buffer(),
+            vk::PipelineBindPoint::GRAPHICS,
+            *material.pipeline.pipeline_layout.lock().unwrap(),
+        );
+        *self.bound_material.borrow_mut() = Some(material.pipeline.clone());
+    }
+
+    /// Binds a set with the layout of `set` of the bound material for the following draws,
+    /// with `buffers` written to their bindings, e.g. for per-object data, leaving the set of
+    /// the material untouched. The set is allocated from the transient descriptor pools of
+    /// the frame, which are reset when the frame is reused.
+    /// Panics outside of frames, if no material is bound or if the shader doesn't declare
+    /// uniform or storage buffers at those bindings.
+    pub fn bind_transient_buffers(&self, set: u32, buffers: &[(u32, &Buffer)]) {
+        let frame = self
+            .frame
+            .expect("Transient descriptor sets are only allocated in frames.");
+        let bound = self.bound_material.borrow();
+        let pipeline = bound
+            .as_ref()
+            .expect("A material must be bound before binding transient buffers.");
+        let descriptors = pipeline.descriptors.lock().unwrap();
+        let (descriptor_set, uses) = descriptors
+            .transient_set(
+                &mut frame.transient_descriptors.lock().unwrap(),
+                set,
+                buffers,
+            )
+            .unwrap_or_else(|e| panic!("{}", e));
+        self.use_resources(&uses);
+        let dynamic_offsets = vec![0; descriptors.dynamic_descriptor_count(set..set + 1)];
+        unsafe {
+            self.renderer.device.cmd_bind_descriptor_sets(
+                self.command_buffer(),
+                vk::PipelineBindPoint::GRAPHICS,
+                *pipeline.pipeline_layout.lock().unwrap(),
+                set,
+                &[descriptor_set],
+                &dynamic_offsets,
+            );
+        }
+    }
+
+    /// Copies `data` to the transient uniform memory of the frame, e.g. the constants of a
+    /// camera or an object, to be bound with `bind_transient_uniforms`. Its layout must match
+    /// the uniform block of the shader. The memory is reused once the frame completes.
+    /// Panics outside of frames, or if `T` is larger than the transient uniform range, which
+    /// is 64 KiB unless the device limit is lower.
+    pub fn transient_uniform<T: bytemuck::Pod>(&self, data: &T) -> TransientUniform {
+        let frame = self
+            .frame
+            .expect("Transient uniforms are only allocated in frames.");
+        frame
+            .transient_uniforms
+            .lock()
+            .unwrap()
+            .allocate(self.renderer, bytemuck::bytes_of(data))
+            .expect("Create transient uniform buffer failed.")
+    }
+
+    /// Binds `uniforms` of this frame to the uniform buffers of `set` of the bound material
+    /// for the following draws, at their dynamic offsets. Every binding of the set must be
+    /// given one. The descriptor set is allocated once per frame for the same uniform memory,
+    /// so binding the uniforms of the next draw only changes the offsets.
+    /// Panics outside of frames, if no material is bound or if the bindings of the set aren't
+    /// exactly the given uniform buffers.
+    pub fn bind_transient_uniforms(&self, set: u32, uniforms: &[(u32, TransientUniform)]) {
+        let frame = self
+            .frame
+            .expect("Transient uniforms are only bound in frames.");
+        let bound = self.bound_material.borrow();
+        let pipeline = bound
+            .as_ref()
+            .expect("A material must be bound before binding transient uniforms.");
+        let (descriptor_set, dynamic_offsets) = pipeline
+            .descriptors
+            .lock()
+            .unwrap()
+            .transient_uniform_set(
+                &mut frame.transient_descriptors.lock().unwrap(),
+                &mut frame.transient_uniforms.lock().unwrap(),
+                set,
+                uniforms,
+            )
+            .unwrap_or_else(|e| panic!("{}", e));
+        unsafe {
+            self.renderer.device.cmd_bind_descriptor_sets(
+                self.command_buffer(),
+                vk::PipelineBindPoint::GRAPHICS,
+                *pipeline.pipeline_layout.lock().unwrap(),
+                set,
+                &[descriptor_set],
+                &dynamic_offsets,
+            );
+        }
+    }
+
+    pub fn draw(&self, vertex_count: u32, first_vertex: u32) {
+        self.draw_instanced(vertex_count, 1, first_vertex, 0);
+    }
+
+    /// Draws `instance_count` instances, advancing the per-instance vertex bindings from
+    /// `first_instance`.
+    pub fn draw_instanced(
+        &self,
+        vertex_count: u32,
+        instance_count: u32,
+        first_vertex: u32,
+        first_instance: u32,
+    ) {
+        assert!(self.in_render_pass.get(), "Draws must be in a render pass.");
+        unsafe {
+            self.renderer.device.cmd_draw(
+                self.command_buffer(),
+                vertex_count,
+                instance_count,
+                first_vertex,
+                first_instance,
+            );
+        }
+    }
+
+    /// Binds `(buffer, offset)` pairs to the vertex bindings from `first_binding`, for the
+    /// following draws. Panics if a buffer lacks `VERTEX_BUFFER` usage.
+    pub fn bind_vertex_buffers(&self, first_binding: u32, buffers: &[(&Buffer, u64)]) {
+        assert!(
+            buffers
+                .iter()
+                .all(|(buffer, _)| buffer.usage().contains(vk::BufferUsageFlags::VERTEX_BUFFER)),
+            "A buffer lacks VERTEX_BUFFER usage."
+        );
+        self.use_resources(
+            &buffers
+                .iter()
+                .map(|(buffer, _)| {
+                    (
+                        TrackedResource::Buffer(buffer.buffer),
+                        ResourceAccess::VertexRead.resource_use(vk::ImageLayout::UNDEFINED),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+        let (handles, offsets): (Vec<_>, Vec<_>) = buffers
+            .iter()
+            .map(|(buffer, offset)| (buffer.buffer, *offset))
+            .unzip();
+        unsafe {
+            self.renderer.device.cmd_bind_vertex_buffers(
+                self.command_buffer(),
+                first_binding,
+                &handles,
+                &offsets,
+            );
+        }
+    }
+
+    /// Binds `buffer` at `offset` as the index buffer of the following indexed draws.
+    /// Panics if the buffer lacks `INDEX_BUFFER` usage.
+    pub fn bind_index_buffer(&self, buffer: &Buffer, offset: u64, index_type: vk::IndexType) {
+        assert!(
+            buffer.usage().contains(vk::BufferUsageFlags::INDEX_BUFFER),
+            "The buffer lacks INDEX_BUFFER usage."
+        );
+        self.use_buffer_with(buffer, ResourceAccess::IndexRead);
+        unsafe {
+            self.renderer.device.cmd_bind_index_buffer(
+                self.command_buffer(),
+                buffer.buffer,
+                offset,
+                index_type,
+            );
+        }
+    }
+
+    /// Draws `index_count` indices of the bound index buffer from `first_index`, adding
+    /// `vertex_offset` to each index.
+    pub fn draw_indexed(&self, index_count: u32, first_index: u32, vertex_offset: i32) {
+        self.draw_indexed_instanced(index_count, 1, first_index, vertex_offset, 0);
+    }
+
+    /// Same as `draw_instanced` with the bound index buffer.
+    pub fn draw_indexed_instanced(
+        &self,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    ) {
+        assert!(self.in_render_pass.get(), "Draws must be in a render pass.");
+        unsafe {
+            self.renderer.device.cmd_draw_indexed(
+                self.command_buffer(),
+                index_count,
+                instance_count,
+                first_index,
+                vertex_offset,
+                first_instance,
+            );
+        }
+    }
+
+    /// Records `draw_count` draws whose arguments the GPU reads from `buffer` at `offset`,
+    /// as consecutive `DrawIndirectCommand`s. The buffer needs `INDIRECT_BUFFER` usage.
+    /// More than one draw needs the `multiDrawIndirect` device feature.
+    pub fn draw_indirect(&self, buffer: &Buffer, offset: u64, draw_count: u32) {
+        let stride = mem::size_of::<DrawIndirectCommand>() as u32;
+        self.check_draw_indirect(buffer, offset, draw_count, stride);
+        unsafe {
+            self.renderer.device.cmd_draw_indirect(
+                self.command_buffer(),
+                buffer.buffer,
+                offset,
+                draw_count,
+                stride,
+            );
+        }
+    }
+
+    /// Same as `draw_indirect` with `DrawIndexedIndirectCommand`s, using the bound index buffer.
+    pub fn draw_indexed_indirect(&self, buffer: &Buffer, offset: u64, draw_count: u32) {
+        let stride = mem::size_of::<DrawIndexedIndirectCommand>() as u32;
+        self.check_draw_indirect(buffer, offset, draw_count, stride);
+        unsafe {
+            self.renderer.device.cmd_draw_indexed_indirect(
+                self.command_buffer(),
+                buffer.buffer,
+                offset,
+                draw_count,
+                stride,
+            );
+        }
+    }
+
+    /// Same as `draw_indirect`, with the number of draws read by the GPU as a `u32` from
+    /// `count_buffer` at `count_offset` and clamped to `max_draw_count`, e.g. the counter of
+    /// a culling compute shader. `count_buffer` needs `INDIRECT_BUFFER` usage.
+    /// Panics if `Renderer::supports_draw_indirect_count` is false.
+    pub fn draw_indirect_count(
+        &self,
+        buffer: &Buffer,
+        offset: u64,
+        count_buffer: &Buffer,
+        count_offset: u64,
+        max_draw_count: u32,
+    ) {
+        let stride = mem::size_of::<DrawIndirectCommand>() as u32;
+        let loader = self.check_draw_indirect_count(
+            buffer,
+            offset,
+            count_buffer,
+            count_offset,
+            max_draw_count,
+            stride,
+        );
+        unsafe {
+            loader.cmd_draw_indirect_count(
+                self.command_buffer(),
+                buffer.buffer,
+                offset,
+                count_buffer.buffer,
+                count_offset,
+                max_draw_count,
+                stride,
+            );
+        }
+    }
+
+    /// Same as `draw_indirect_count` with `DrawIndexedIndirectCommand`s, using the bound index
+    /// buffer.
+    pub fn draw_indexed_indirect_count(
+        &self,
+        buffer: &Buffer,
+        offset: u64,
+        count_buffer: &Buffer,
+        count_offset: u64,
+        max_draw_count: u32,
+    ) {
+        let stride = mem::size_of::<DrawIndexedIndirectCommand>() as u32;
+        let loader = self.check_draw_indirect_count(
+            buffer,
+            offset,
+            count_buffer,
+            count_offset,
+            max_draw_count,
+            stride,
+        );
+        unsafe {
+            loader.cmd_draw_indexed_indirect_count(
+                self.command_buffer(),
+                buffer.buffer,
+                offset,
+                count_buffer.buffer,
+                count_offset,
+                max_draw_count,
+                stride,
+            );
+        }
+    }
+
+    fn check_draw_indirect(&self, buffer: &Buffer, offset: u64, draw_count: u32, stride: u32) {
+        assert!(
+            draw_count <= 1 || self.renderer.enabled_features.multi_draw_indirect == vk::TRUE,
+            "The device doesn't support multiple draws per indirect draw."
+        );
+        self.check_draw_commands(buffer, offset, draw_count, stride);
+    }
+
+    /// Checks of indirect draws and of indirect count draws, which don't need
+    /// `multiDrawIndirect` for several draws.
+    fn check_draw_commands(&self, buffer: &Buffer, offset: u64, draw_count: u32, stride: u32) {
+        assert!(self.in_render_pass.get(), "Draws must be in a render pass.");
+        self.check_indirect_buffer(buffer, offset, draw_count as u64 * stride as u64);
+    }
+
+    /// Checks that `size` bytes of indirect commands at `offset` are within `buffer`.
+    fn check_indirect_buffer(&self, buffer: &Buffer, offset: u64, size: u64) {
+        check_indirect_range(buffer.usage(), buffer.size(), offset, size);
+        self.use_buffer_with(buffer, ResourceAccess::IndirectRead);
+    }
+
+    fn check_draw_indirect_count(
+        &self,
+        buffer: &Buffer,
+        offset: u64,
+        count_buffer: &Buffer,
+        count_offset: u64,
+        max_draw_count: u32,
+        stride: u32,
+    ) -> &DrawIndirectCount {
+        let loader = self
+            .renderer
+            .draw_indirect_count_loader
+            .as_ref()
+            .expect("The device doesn't support VK_KHR_draw_indirect_count.");
+        self.check_draw_commands(buffer, offset, max_draw_count, stride);
+        self.check_indirect_buffer(count_buffer, count_offset, mem::size_of::<u32>() as u64);
+        loader
+    }
+
+    /// Binds `pipeline` and its descriptor sets for the following dispatches.
+    pub fn bind_compute_pipeline(&self, pipeline: &ComputePipeline) {
+        unsafe {
+            self.renderer.device.cmd_bind_pipeline(
+                self.command_buffer(),
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.pipeline,
+            );
+        }
+        pipeline.descriptors.bind(
+            self.command_buffer(),
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline.pipeline_layout,
+        );
+        *self.bound_compute_pipeline.borrow_mut() = Some(BoundComputePipeline {
+            descriptors: pipeline.descriptors.clone(),
+            pipeline_layout: pipeline.pipeline_layout,
+            push_constant_ranges: pipeline.push_constant_ranges(),
+            resource_uses: pipeline.descriptors.resource_uses(),
+        });
+    }
+
+    /// Dispatches the bound compute pipeline over `group_count_x * y * z` workgroups.
+    /// Panics if no compute pipeline is bound or the encoder is in a render pass.
+    pub fn dispatch(&self, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        self.check_dispatch();
+        unsafe {
+            self.renderer.device.cmd_dispatch(
+                self.command_buffer(),
+                group_count_x,
+                group_count_y,
+                group_count_z,
+            );
+        }
+    }
+
+    /// Dispatches with the workgroup counts read by the GPU from `buffer` at `offset`,
+    /// laid out like `vk::DispatchIndirectCommand`. The buffer needs `INDIRECT_BUFFER` usage.
+    /// Panics if `offset` isn't a multiple of 4 or the command is out of the buffer.
+    pub fn dispatch_indirect(&self, buffer: &Buffer, offset: u64) {
+        self.check_dispatch();
+        self.check_indirect_buffer(
+            buffer,
+            offset,
+            mem::size_of::<vk::DispatchIndirectCommand>() as u64,
+        );
+        unsafe {
+            self.renderer.device.cmd_dispatch_indirect(
+                self.command_buffer(),
+                buffer.buffer,
+                offset,
+            );
+        }
+    }
+
+    fn check_dispatch(&self) {
+        assert!(
+            self.queue != QueueType::Transfer,
+            "Dispatches can't be recorded for the transfer queue."
+        );
+        assert!(
+            !self.in_render_pass.get(),
+            "Dispatches must be outside of render passes."
+        );
+        let bound_compute_pipeline = self.bound_compute_pipeline.borrow();
+        let bound_compute_pipeline = bound_compute_pipeline
+            .as_ref()
+            .expect("A compute pipeline must be bound before dispatching.");
+        self.use_resources(&bound_compute_pipeline.resource_uses);
+    }
+
+    /// Records a use of `buffer` by a command of the encoder.
+    fn use_buffer_with(&self, buffer: &Buffer, access: ResourceAccess) {
+        self.use_resources(&[(
+            TrackedResource::Buffer(buffer.buffer),
+            access.resource_use(vk::ImageLayout::UNDEFINED),
+        )]);
+    }
+
+    /// Makes the memory written by the commands in `src_stage` before the barrier available to
+    /// the commands in `dst_stage` after it, e.g. from `COMPUTE_SHADER` to `VERTEX_SHADER`.
+    /// Panics if the encoder is in a render pass.
+    pub fn memory_barrier(
+        &self,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+    ) {
+        assert!(
+            !self.in_render_pass.get(),
+            "Barriers must be outside of render passes."
+        );
+        // The stages have the same bits in both flag types.
+        let src_stage = vk::PipelineStageFlags2::from_raw(src_stage.as_raw().into());
+        let dst_stage = vk::PipelineStageFlags2::from_raw(dst_stage.as_raw().into());
+        let barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(src_stage)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(dst_stage)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
+            .build();
+        self.renderer
+            .synchronization
+            .pipeline_barrier(self.command_buffer(), &[barrier], &[], &[]);
+        self.renderer
+            .resource_states
+            .lock()
+            .unwrap()
+            .memory_barrier(src_stage, dst_stage);
+    }
+
+    /// Copies `size` bytes from `src` at `src_offset` to `dst` at `dst_offset`.
+    /// `src` needs `TRANSFER_SRC` usage and `dst` `TRANSFER_DST` usage.
+    /// Panics if the encoder is in a render pass or a range is out of its buffer.
+    pub fn copy_buffer(
+        &self,
+        src: &Buffer,
+        src_offset: u64,
+        dst: &Buffer,
+        dst_offset: u64,
+        size: u64,
+    ) {
+        check_buffer_range(src, src_offset, size, vk::BufferUsageFlags::TRANSFER_SRC);
+        check_buffer_range(dst, dst_offset, size, vk::BufferUsageFlags::TRANSFER_DST);
+        assert!(
+            !self.in_render_pass.get(),
+            "Copies must be outside of render passes."
+        );
+        self.use_buffer_with(src, ResourceAccess::TransferRead);
+        self.use_buffer_with(dst, ResourceAccess::TransferWrite);
+        let region = vk::BufferCopy {
+            src_offset,
+            dst_offset,
+            size,
+        };
+        unsafe {
+            self.renderer.device.cmd_copy_buffer(
+                self.command_buffer(),
+                src.buffer,
+                dst.buffer,
+                &[region],
+            );
+        }
+    }
+
+    /// Fills `size` bytes of `dst` at `offset` with copies of `data`, e.g. to clear counters.
+    /// `dst` needs `TRANSFER_DST` usage, and `offset` and `size` must be multiples of 4.
+    /// Panics if the encoder is in a render pass or the range is out of the buffer.
+    pub fn fill_buffer(&self, dst: &Buffer, offset: u64, size: u64, data: u32) {
+        check_buffer_range(dst, offset, size, vk::BufferUsageFlags::TRANSFER_DST);
+        assert!(
+            offset.is_multiple_of(4) && size.is_multiple_of(4),
+            "Fill offset and size must be multiples of 4."
+        );
+        assert!(
+            !self.in_render_pass.get(),
+            "Fills must be outside of render passes."
+        );
+        self.use_buffer_with(dst, ResourceAccess::TransferWrite);
+        unsafe {
+            self.renderer.device.cmd_fill_buffer(
+                self.command_buffer(),
+                dst.buffer,
+                offset,
+                size,
+                data,
+            );
+        }
+    }
+
+    /// Updates push constants at `offset` with `bytes`, in the layout of the bound compute
+    /// pipeline for `ShaderStage::Compute`, otherwise of the bound material.
+    /// The other stages whose push constant blocks overlap the bytes are updated too,
+    /// as Vulkan requires, with one update per range of bytes covered by the same stages.
+    /// Panics if nothing is bound, if `offset` or the length isn't a multiple of 4,
+    /// or if the bytes aren't within the push constant block of `stage`.
+    pub fn set_push_constants(&self, stage: ShaderStage, offset: u32, bytes: &[u8]) {
+        assert!(
+            offset.is_multiple_of(4) && bytes.len().is_multiple_of(4),
+            "Push constant offset and size must be multiples of 4."
+        );
+        let (pipeline_layout, ranges) = if stage == ShaderStage::Compute {
+            let bound = self.bound_compute_pipeline.borrow();
+            let pipeline = bound
+                .as_ref()
+                .expect("A compute pipeline must be bound before setting push constants.");
+            (
+                pipeline.pipeline_layout,
+                pipeline.push_constant_ranges.clone(),
+            )
+        } else {
+            let bound = self.bound_material.borrow();
+            let pipeline = bound
+                .as_ref()
+                .expect("A material must be bound before setting push constants.");
+            let ranges = pipeline.push_constant_ranges.lock().unwrap().clone();
+            let pipeline_layout = *pipeline.pipeline_layout.lock().unwrap();
+            (pipeline_layout, ranges)
+        };
+        let updates = push_constant_updates(&ranges, stage.vk_flags(), offset, bytes.len())
+            .unwrap_or_else(|| {
+                panic!(
+                    "{:?} stage has no push constants at offset {} for {} bytes.",
+                    stage,
+                    offset,
+                    bytes.len()
+                )
+            });
+        for update in updates {
+            let start = (update.offset - offset) as usize;
+            unsafe {
+                self.renderer.device.cmd_push_constants(
+                    self.command_buffer(),
+                    pipeline_layout,
+                    update.stage_flags,
+                    update.offset,
+                    &bytes[start..start + update.size as usize],
+                );
+            }
+        }
+    }
+
+    /// Same as `set_push_constants` with the bytes of `value`,
+    /// whose layout must match the block declared in the shader.
+    pub fn set_push_constants_typed<T: bytemuck::Pod>(
+        &self,
+        stage: ShaderStage,
+        offset: u32,
+        value: &T,
+    ) {
+        self.set_push_constants(stage, offset, bytemuck::bytes_of(value));
+    }
+
+    /// Measures the GPU time of the commands recorded while the returned scope is alive.
+    /// The result is available from `Renderer::gpu_timings` once the frame has completed.
+    /// Panics for the compute and transfer queues, as are the other queries.
+    #[must_use = "the timer ends when the scope is dropped"]
+    pub fn gpu_timer(&self, name: &str) -> GpuTimerScope<'_> {
+        let query =
+            self.queries()
+                .begin_timestamp(&self.renderer.device, self.command_buffer(), name);
+        GpuTimerScope {
+            encoder: self,
+            query,
+        }
+    }
+
+    /// Counts pipeline statistics of the draws and dispatches recorded while the returned scope
+    /// is alive. The result is available from `Renderer::pipeline_statistics` once the frame has
+    /// completed. The scope is ignored if the device lacks `pipelineStatisticsQuery`.
+    /// Panics if another pipeline statistics scope is alive, as queries of a type don't nest.
+    #[must_use = "the query ends when the scope is dropped"]
+    pub fn pipeline_statistics_query(&self, name: &str) -> QueryScope<'_> {
+        let query = self.queries().begin_pipeline_statistics(
+            &self.renderer.device,
+            self.command_buffer(),
+            name,
+        );
+        QueryScope {
+            encoder: self,
+            query,
+        }
+    }
+
+    /// Counts the samples passing the depth and stencil tests while the returned scope is alive.
+    /// The result is available from `Renderer::occlusion_results` once the frame has completed.
+    /// Without `precise`, or if the device lacks `occlusionQueryPrecise`, the result is only
+    /// guaranteed to be non-zero when some samples passed.
+    /// Panics if another occlusion scope is alive, as queries of a type don't nest.
+    #[must_use = "the query ends when the scope is dropped"]
+    pub fn occlusion_query(&self, name: &str, precise: bool) -> QueryScope<'_> {
+        let flags = if precise && self.renderer.query_capabilities.occlusion_query_precise {
+            vk::QueryControlFlags::PRECISE
+        } else {
+            vk::QueryControlFlags::empty()
+        };
+        let query = self.queries().begin_occlusion(
+            &self.renderer.device,
+            self.command_buffer(),
+            name,
+            flags,
+        );
+        QueryScope {
+            encoder: self,
+            query,
+        }
+    }
+}
+
+pub struct GpuTimerScope<'a> {
+    encoder: &'a CommandEncoder<'a>,
+    query: Option<u32>,
+}
+
+impl Drop for GpuTimerScope<'_> {
+    fn drop(&mut self) {
+        if let Some(query) = self.query {
+            self.encoder.queries().end_timestamp(
+                &self.encoder.renderer.device,
+                self.encoder.command_buffer(),
+                query,
+            );
+        }
+    }
+}
+
+pub struct QueryScope<'a> {
+    encoder: &'a CommandEncoder<'a>,
+    query: Option<(vk::QueryPool, u32)>,
+}
+
+impl Drop for QueryScope<'_> {
+    fn drop(&mut self) {
+        if let Some(query) = self.query {
+            self.encoder.queries().end_query(
+                &self.encoder.renderer.device,
+                self.encoder.command_buffer(),
+                query,
+            );
+        }
+    }
+}
+
+fn check_buffer_range(buffer: &Buffer, offset: u64, size: u64, usage: vk::BufferUsageFlags) {
+    check_range(buffer.usage(), buffer.size(), offset, size, usage);
+}
+
+/// Checks that `size` bytes at `offset` are within a buffer of `buffer_size` bytes with
+/// `buffer_usage`, which includes `usage`.
+fn check_range(
+    buffer_usage: vk::BufferUsageFlags,
+    buffer_size: u64,
+    offset: u64,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+) {
+    assert!(
+        buffer_usage.contains(usage),
+        "The buffer lacks {:?} usage.",
+        usage
+    );
+    assert!(
+        offset
+            .checked_add(size)
+            .is_some_and(|end| end <= buffer_size),
+        "Range {}..{} is out of the buffer of {} bytes.",
+        offset,
+        offset.saturating_add(size),
+        buffer_size
+    );
+}
+
+/// Checks that `size` bytes of indirect commands or counts at `offset` are within an indirect
+/// buffer, at an offset that is a multiple of 4.
+fn check_indirect_range(
+    buffer_usage: vk::BufferUsageFlags,
+    buffer_size: u64,
+    offset: u64,
+    size: u64,
+) {
+    assert!(
+        offset.is_multiple_of(4),
+        "Indirect offset must be a multiple of 4."
+    );
+    check_range(
+        buffer_usage,
+        buffer_size,
+        offset,
+        size,
+        vk::BufferUsageFlags::INDIRECT_BUFFER,
+    );
+}
+
+fn range_end(range: &vk::PushConstantRange) -> u64 {
+    u64::from(range.offset) + u64::from(range.size)
+}
+
+/// Disjoint ranges splitting the bytes of the push constant blocks of `ranges`, in offset
+/// order, each with the stages whose blocks cover all of it.
+fn push_constant_segments(ranges: &[vk::PushConstantRange]) -> Vec<vk::PushConstantRange> {
+    let mut bounds = ranges
+        .iter()
+        .flat_map(|range| [u64::from(range.offset), range_end(range)])
+        .collect::<Vec<_>>();
+    bounds.sort_unstable();
+    bounds.dedup();
+    let mut segments = Vec::<vk::PushConstantRange>::new();
+    for bound in bounds.windows(2) {
+        let stage_flags = ranges
+            .iter()
+            .filter(|range| u64::from(range.offset) <= bound[0] && bound[1] <= range_end(range))
+            .fold(vk::ShaderStageFlags::empty(), |flags, range| {
+                flags | range.stage_flags
+            });
+        if stage_flags.is_empty() {
+            continue;
+        }
+        match segments.last_mut() {
+            Some(last) if last.stage_flags == stage_flags && range_end(last) == bound[0] => {
+                last.size += (bound[1] - bound[0]) as u32;
+            }
+            _ => segments.push(vk::PushConstantRange {
+                stage_flags,
+                offset: bound[0] as u32,
+                size: (bound[1] - bound[0]) as u32,
+            }),
+        }
+    }
+    segments
+}
+
+/// Updates of `len` bytes at `offset` for `stage`, one per segment of the bytes, with the stages
+/// whose blocks cover the segment. Vulkan requires updating every stage whose block overlaps
+/// the bytes of an update, and each of them to cover all of them. `None` unless the bytes are
+/// within the block of `stage`, including when their end overflows.
+fn push_constant_updates(
+    ranges: &[vk::PushConstantRange],
+    stage: vk::ShaderStageFlags,
+    offset: u32,
+    len: usize,
+) -> Option<Vec<vk::PushConstantRange>> {
+    let start = u64::from(offset);
+    let end = u64::from(offset.checked_add(u32::try_from(len).ok()?)?);
+    let within_stage = ranges.iter().any(|range| {
+        range.stage_flags.contains(stage)
+            && u64::from(range.offset) <= start
+            && end <= range_end(range)
+    });
+    if !within_stage {
+        return None;
+    }
+    let updates = push_constant_segments(ranges)
+        .into_iter()
+        .filter_map(|segment| {
+            let update_start = start.max(u64::from(segment.offset));
+            let update_end = end.min(range_end(&segment));
+            (update_start < update_end).then(|| vk::PushConstantRange {
+                stage_flags: segment.stage_flags,
+                offset: update_start as u32,
+                size: (update_end - update_start) as u32,
+            })
+        })
+        .collect();
+    Some(updates)
+}
+
+/// Runs `job` on a thread of its own for each of `pools`, with the index and pool of the job,
+/// and returns the results in job order, or the panic of a job.
+fn run_jobs<P, T, F>(pools: &mut [P], job: F) -> thread::Result<Vec<T>>
+where
+    P: Send,
+    T: Send,
+    F: Fn(usize, &mut P) -> T + Sync,
+{
+    let job = &job;
+    thread::scope(|scope| {
+        let threads = pools
+            .iter_mut()
+            .enumerate()
+            .map(|(index, pool)| scope.spawn(move || job(index, pool)))
+            .collect::<Vec<_>>();
+        threads.into_iter().map(|thread| thread.join()).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    const VERTEX: vk::ShaderStageFlags = vk::ShaderStageFlags::VERTEX;
+    const FRAGMENT: vk::ShaderStageFlags = vk::ShaderStageFlags::FRAGMENT;
+
+    fn range(stage_flags: vk::ShaderStageFlags, offset: u32, size: u32) -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags,
+            offset,
+            size,
+        }
+    }
+
+    fn tuple(range: vk::PushConstantRange) -> (vk::ShaderStageFlags, u32, u32) {
+        (range.stage_flags, range.offset, range.size)
+    }
+
+    fn segments(ranges: &[vk::PushConstantRange]) -> Vec<(vk::ShaderStageFlags, u32, u32)> {
+        push_constant_segments(ranges)
+            .into_iter()
+            .map(tuple)
+            .collect()
+    }
+
+    fn updates(
+        ranges: &[vk::PushConstantRange],
+        stage: vk::ShaderStageFlags,
+        offset: u32,
+        len: usize,
+    ) -> Option<Vec<(vk::ShaderStageFlags, u32, u32)>> {
+        push_constant_updates(ranges, stage, offset, len)
+            .map(|updates| updates.into_iter().map(tuple).collect())
+    }
+
+    #[test]
+    fn updates_the_stages_overlapping_the_bytes() {
+        let ranges = [range(VERTEX, 0, 64), range(FRAGMENT, 64, 16)];
+        assert_eq!(updates(&ranges, VERTEX, 0, 64), Some(vec![(VERTEX, 0, 64)]));
+        assert_eq!(
+            updates(&ranges, FRAGMENT, 64, 16),
+            Some(vec![(FRAGMENT, 64, 16)])
+        );
+
+        let ranges = [range(VERTEX, 0, 64), range(FRAGMENT, 48, 32)];
+        assert_eq!(
+            updates(&ranges, VERTEX, 48, 16),
+            Some(vec![(VERTEX | FRAGMENT, 48, 16)])
+        );
+        assert_eq!(updates(&ranges, VERTEX, 0, 16), Some(vec![(VERTEX, 0, 16)]));
+    }
+
+    #[test]
+    fn splits_updates_of_partly_overlapping_blocks() {
+        let ranges = [range(VERTEX, 0, 64), range(FRAGMENT, 48, 32)];
+        assert_eq!(
+            segments(&ranges),
+            [
+                (VERTEX, 0, 48),
+                (VERTEX | FRAGMENT, 48, 16),
+                (FRAGMENT, 64, 16)
+            ]
+        );
+        // The fragment block doesn't cover 32..48, so those bytes only update the vertex stage.
+        assert_eq!(
+            updates(&ranges, VERTEX, 32, 32),
+            Some(vec![(VERTEX, 32, 16), (VERTEX | FRAGMENT, 48, 16)])
+        );
+        assert_eq!(
+            updates(&ranges, FRAGMENT, 48, 32),
+            Some(vec![(VERTEX | FRAGMENT, 48, 16), (FRAGMENT, 64, 16)])
+        );
+    }
+
+    #[test]
+    fn merges_segments_covered_by_the_same_stages() {
+        let ranges = [
+            range(VERTEX, 0, 32),
+            range(FRAGMENT, 0, 16),
+            range(FRAGMENT, 16, 16),
+        ];
+        assert_eq!(segments(&ranges), [(VERTEX | FRAGMENT, 0, 32)]);
+        let ranges = [range(VERTEX, 0, 16), range(FRAGMENT, 32, 16)];
+        assert_eq!(segments(&ranges), [(VERTEX, 0, 16), (FRAGMENT, 32, 16)]);
+    }
+
+    #[test]
+    fn rejects_bytes_outside_the_block_of_the_stage() {
+        let ranges = [range(VERTEX, 0, 64), range(FRAGMENT, 64, 16)];
+        assert_eq!(updates(&ranges, VERTEX, 60, 8), None);
+        assert_eq!(updates(&ranges, FRAGMENT, 0, 4), None);
+        assert_eq!(updates(&[], VERTEX, 0, 4), None);
+    }
+
+    #[test]
+    fn rejects_ends_that_overflow() {
+        let ranges = [range(VERTEX, 0, u32::MAX)];
+        assert_eq!(updates(&ranges, VERTEX, u32::MAX - 3, 8), None);
+        assert_eq!(updates(&ranges, VERTEX, 4, u32::MAX as usize + 1), None);
+        assert_eq!(
+            updates(&ranges, VERTEX, u32::MAX - 3, 3),
+            Some(vec![(VERTEX, u32::MAX - 3, 3)])
+        );
+    }
+
+    const INDIRECT: vk::BufferUsageFlags = vk::BufferUsageFlags::INDIRECT_BUFFER;
+    const DRAW_SIZE: u64 = mem::size_of::<DrawIndirectCommand>() as u64;
+
+    #[test]
+    fn accepts_indirect_commands_within_the_buffer() {
+        check_indirect_range(INDIRECT, 4 * DRAW_SIZE, 0, 4 * DRAW_SIZE);
+        check_indirect_range(INDIRECT, 4 * DRAW_SIZE, 2 * DRAW_SIZE, 2 * DRAW_SIZE);
+        check_indirect_range(INDIRECT | vk::BufferUsageFlags::STORAGE_BUFFER, 8, 4, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Indirect offset must be a multiple of 4.")]
+    fn rejects_unaligned_indirect_offsets() {
+        check_indirect_range(INDIRECT, 4 * DRAW_SIZE, 2, DRAW_SIZE);
+    }
+
+    #[test]
+    #[should_panic(expected = "Range 32..80 is out of the buffer of 64 bytes.")]
+    fn rejects_indirect_commands_past_the_end() {
+        check_indirect_range(INDIRECT, 4 * DRAW_SIZE, 2 * DRAW_SIZE, 3 * DRAW_SIZE);
+    }
+
+    #[test]
+    #[should_panic(expected = "is out of the buffer of 64 bytes.")]
+    fn rejects_indirect_ranges_that_overflow() {
+        check_indirect_range(INDIRECT, 4 * DRAW_SIZE, u64::MAX - 3, DRAW_SIZE);
+    }
+
+    #[test]
+    #[should_panic(expected = "The buffer lacks INDIRECT_BUFFER usage.")]
+    fn rejects_buffers_without_indirect_usage() {
+        check_indirect_range(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            4 * DRAW_SIZE,
+            0,
+            DRAW_SIZE,
+        );
+    }
+
+    #[test]
+    fn runs_jobs_on_threads_of_their_own_and_keeps_their_order() {
+        let mut pools = vec![Vec::new(); 4];
+        let results = run_jobs(&mut pools, |job, pool: &mut Vec<thread::ThreadId>| {
+            pool.push(thread::current().id());
+            job * 10
+        })
+        .unwrap();
+        assert_eq!(results, [0, 10, 20, 30]);
+        let threads = pools.concat();
+        assert_eq!(threads.len(), 4);
+        assert_eq!(threads.iter().collect::<HashSet<_>>().len(), 4);
+        assert!(!threads.contains(&thread::current().id()));
+    }
+
+    #[test]
+    fn reports_the_panic_of_a_job() {
+        let mut pools = vec![(); 3];
+        let result = run_jobs(&mut pools, |job, _| {
+            assert!(job != 1, "Job 1 failed.");
+            job
+        });
+        assert!(result.is_err());
+        assert!(run_jobs(&mut [] as &mut [()], |job, _| job)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+use std::{mem, sync::Arc};
+
+use ash::vk;
+use tempura_render as tr;
+
+use super::{
+    descriptor::PipelineDescriptors,
+    shader::ShaderStageModule,
+    specialization::{validate_specialization_values, SpecializationData},
+    Buffer, Renderer, ShaderStage, StorageBuffer, StorageImage,
+};
+
+pub struct ComputeShader {
+    renderer: Arc<Renderer>,
+    pub(crate) module: ShaderStageModule,
+}
+
+impl ComputeShader {
+    pub(crate) fn new(renderer: &Arc<Renderer>, source: &tr::ShaderSource) -> Self {
+        let module = ShaderStageModule::new(renderer, source, ShaderStage::Compute)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        ComputeShader {
+            renderer: renderer.clone(),
+            module,
+        }
+    }
+
+    /// Push constant range reflected from the shader, if it declares a block.
+    pub fn push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        self.module.push_constant_range
+    }
+}
+
+impl Drop for ComputeShader {
+    fn drop(&mut self) {
+        self.module.destroy(&self.renderer);
+    }
+}
+
+/// Compute counterpart of `Material`: a compute shader specialized into a pipeline,
+/// with the resources bound to its descriptors.
+pub struct ComputePipeline {
+    renderer: Arc<Renderer>,
+    shader: Arc<ComputeShader>,
+    pub(crate) descriptors: Arc<PipelineDescriptors>,
+    pub(crate) pipeline_layout: vk::PipelineLayout,
+    pub(crate) pipeline: vk::Pipeline,
+}
+
+impl ComputePipeline {
+    /// Panics if a specialization constant isn't declared by the shader or has another type.
+    pub(crate) fn new(
+        renderer: &Arc<Renderer>,
+        shader: &Arc<ComputeShader>,
+        specialization_constants: &[(&str, tr::SpecializationValue)],
+    ) -> Self {
+        let specialization_constants = specialization_constants
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value))
+            .collect::<Vec<_>>();
+        let descriptors = PipelineDescriptors::new(renderer, &[&shader.module.descriptor_bindings])
+            .unwrap_or_else(|e| panic!("Create compute pipeline descriptors failed. {}", e));
+        let (pipeline_layout, pipeline) = create_pipeline(
+            renderer,
+            shader,
+            &descriptors.set_layouts,
+            &specialization_constants,
+        )
+        .unwrap_or_else(|e| panic!("create_compute_pipelines failed. {}", e));
+
+        ComputePipeline {
+            renderer: renderer.clone(),
+            shader: shader.clone(),
+            descriptors: Arc::new(descriptors),
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn shader(&self) -> Arc<ComputeShader> {
+        self.shader.clone()
+    }
+
+    /// Binds `buffer` to the storage or uniform buffer declared at `set` and `binding`.
+    /// The pipeline must not be in use by a frame in flight.
+    /// Panics if the shader doesn't declare a buffer there.
+    pub fn bind_buffer(&self, set: u32, binding: u32, buffer: &Arc<Buffer>) {
+        self.descriptors
+            .bind_buffer(set, binding, buffer)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Same as `bind_buffer`, also checking that `T` matches the element stride of the
+    /// runtime array the storage buffer block ends with.
+    pub fn bind_storage_buffer<T: bytemuck::Pod>(
+        &self,
+        set: u32,
+        binding: u32,
+        buffer: &StorageBuffer<T>,
+    ) {
+        self.descriptors
+            .bind_storage_buffer(set, binding, buffer.buffer(), mem::size_of::<T>())
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Binds `image` to the storage image declared at `set` and `binding`.
+    /// The pipeline must not be in use by a frame in flight.
+    /// Panics if the shader doesn't declare a storage image there.
+    pub fn bind_storage_image(&self, set: u32, binding: u32, image: &Arc<StorageImage>) {
+        self.descriptors
+            .bind_storage_image(set, binding, image)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    pub(crate) fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.shader.push_constant_range().into_iter().collect()
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.renderer.device.destroy_pipeline(self.pipeline, None);
+            self.renderer
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+fn create_pipeline(
+    renderer: &Renderer,
+    shader: &ComputeShader,
+    set_layouts: &[vk::DescriptorSetLayout],
+    specialization_constants: &[(String, tr::SpecializationValue)],
+) -> Result<(vk::PipelineLayout, vk::Pipeline), String> {
+    validate_specialization_values(
+        &[&shader.module.specialization_constants],
+        specialization_constants,
+    )?;
+    let specialization = SpecializationData::new(
+        &shader.module.specialization_constants,
+        specialization_constants,
+    );
+    let specialization_info = specialization.info();
+    let push_constant_ranges = shader.push_constant_range().into_iter().collect::<Vec<_>>();
+    unsafe {
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(&push_constant_ranges)
+            .build();
+        let pipeline_layout = renderer
+            .device
+            .create_pipeline_layout(&pipeline_layout_create_info, None)
+            .map_err(|r| r.to_string())?;
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader.module.module)
+            .name(shader.module.entry_point.as_c_str())
+            .specialization_info(&specialization_info)
+            .build();
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(pipeline_layout)
+            .build();
+        let pipeline = renderer
+            .device
+            .create_compute_pipelines(renderer.pipeline_cache, &[pipeline_info], None)
+            .map_err(|(_, r)| {
+                renderer
+                    .device
+                    .destroy_pipeline_layout(pipeline_layout, None);
+                r.to_string()
+            })?;
+
+        Ok((pipeline_layout, pipeline[0]))
+    }
+}
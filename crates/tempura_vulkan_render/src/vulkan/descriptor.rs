@@ -0,0 +1,849 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use ash::vk;
+use rspirv_reflect::{
+    rspirv::dr::{Instruction, Operand},
+    spirv::{Decoration, Op},
+    BindingCount, Reflection,
+};
+
+use super::{
+    bindless::{BindlessBinding, BINDLESS_SET},
+    descriptor_allocator::{pool_sizes, DescriptorAllocator},
+    resource_state::{shader_stages, AccessScope, ResourceUse, TrackedResource},
+    transient_uniform::{TransientUniform, TransientUniforms},
+    Buffer, Renderer, StorageImage,
+};
+
+/// Resource written to a descriptor, kept alive as long as the descriptor refers to it.
+enum BoundResource {
+    Buffer(Arc<Buffer>),
+    StorageImage(Arc<StorageImage>),
+}
+
+/// Descriptor binding declared by the stages of a pipeline.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DescriptorBinding {
+    pub(crate) set: u32,
+    pub(crate) binding: u32,
+    pub(crate) descriptor_type: vk::DescriptorType,
+    /// 0 for an unbounded array, which only the bindless set may declare.
+    pub(crate) count: u32,
+    pub(crate) stage_flags: vk::ShaderStageFlags,
+    /// Array stride of the runtime array ending a storage buffer block, if it has one.
+    pub(crate) element_stride: Option<u32>,
+}
+
+/// Finds the descriptor bindings a stage declares.
+pub(crate) fn reflect_descriptor_bindings(
+    reflect: &Reflection,
+    stage_flags: vk::ShaderStageFlags,
+) -> Result<Vec<DescriptorBinding>, String> {
+    let sets = reflect
+        .get_descriptor_sets()
+        .map_err(|e| format!("Descriptor set reflection failed. {}", e))?;
+    let mut bindings = Vec::new();
+    for (&set, set_bindings) in &sets {
+        for (&binding, info) in set_bindings {
+            let count = match info.binding_count {
+                BindingCount::One => 1,
+                BindingCount::StaticSized(count) => count as u32,
+                BindingCount::Unbounded => 0,
+            };
+            bindings.push(DescriptorBinding {
+                set,
+                binding,
+                descriptor_type: vk::DescriptorType::from_raw(info.ty.0 as i32),
+                count,
+                stage_flags,
+                element_stride: reflect_element_stride(reflect, set, binding),
+            });
+        }
+    }
+    Ok(bindings)
+}
+
+/// Finds the array stride of the runtime array a buffer block at `set` and `binding` ends with,
+/// which is the size of one element as the shader sees it.
+fn reflect_element_stride(reflect: &Reflection, set: u32, binding: u32) -> Option<u32> {
+    let module = &reflect.0;
+    let decoration = |target: u32, decoration: Decoration| {
+        module
+            .annotations
+            .iter()
+            .find_map(|instruction| match instruction.operands.as_slice() {
+                [Operand::IdRef(id), Operand::Decoration(d), Operand::LiteralBit32(value), ..]
+                    if *id == target && *d == decoration =>
+                {
+                    Some(*value)
+                }
+                _ => None,
+            })
+    };
+    let definition = |id: u32| -> Option<&Instruction> {
+        module
+            .types_global_values
+            .iter()
+            .find(|instruction| instruction.result_id == Some(id))
+    };
+
+    let variable = module.types_global_values.iter().find(|instruction| {
+        instruction.class.opcode == Op::Variable
+            && instruction.result_id.is_some_and(|id| {
+                decoration(id, Decoration::DescriptorSet) == Some(set)
+                    && decoration(id, Decoration::Binding) == Some(binding)
+            })
+    })?;
+    let pointer = definition(variable.result_type?)?;
+    let Some(Operand::IdRef(block_type)) = pointer.operands.get(1) else {
+        return None;
+    };
+    let block = definition(*block_type)?;
+    if block.class.opcode != Op::TypeStruct {
+        return None;
+    }
+    let Some(Operand::IdRef(last_member_type)) = block.operands.last() else {
+        return None;
+    };
+    let last_member = definition(*last_member_type)?;
+    if last_member.class.opcode != Op::TypeRuntimeArray {
+        return None;
+    }
+    decoration(last_member.result_id?, Decoration::ArrayStride)
+}
+
+/// Checks that `declared` is a storage buffer whose runtime array, if any, has elements of
+/// `element_size` bytes.
+fn check_storage_buffer_elements(
+    declared: &DescriptorBinding,
+    element_size: usize,
+) -> Result<(), String> {
+    if declared.descriptor_type != vk::DescriptorType::STORAGE_BUFFER {
+        return Err(format!(
+            "Descriptor set {} binding {} is {:?}, not a storage buffer.",
+            declared.set, declared.binding, declared.descriptor_type
+        ));
+    }
+    match declared.element_stride {
+        Some(stride) if stride as usize != element_size => Err(format!(
+            "Descriptor set {} binding {} has elements of {} bytes, but {} is given.",
+            declared.set, declared.binding, stride, element_size
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Descriptor set layouts reflected from the stages of a pipeline, shared through the layout
+/// cache of the renderer, and one descriptor set per layout to bind resources to, allocated
+/// from its persistent descriptor pools.
+/// If the stages declare `BINDLESS_SET`, the layout and set there are the ones of the
+/// bindless table of the renderer.
+pub(crate) struct PipelineDescriptors {
+    renderer: Arc<Renderer>,
+    pub(crate) set_layouts: Vec<vk::DescriptorSetLayout>,
+    /// Pool the sets were allocated from.
+    pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+    /// Set of the bindless table, if the stages declare it.
+    bindless_set: Option<vk::DescriptorSet>,
+    bindings: Vec<DescriptorBinding>,
+    resources: Mutex<HashMap<(u32, u32), BoundResource>>,
+}
+
+impl PipelineDescriptors {
+    /// Merges the bindings of the stages, which must agree on the type of a shared binding.
+    /// Sets are numbered contiguously, so an unused set number gets an empty layout.
+    /// Uniform buffers are dynamic, so that draws can bind transient uniforms at dynamic
+    /// offsets. The sets of the pipeline bind them at offset 0.
+    pub(crate) fn new(
+        renderer: &Arc<Renderer>,
+        stages: &[&[DescriptorBinding]],
+    ) -> Result<Self, String> {
+        let mut bindings = Vec::<DescriptorBinding>::new();
+        for reflected in stages.iter().flat_map(|bindings| bindings.iter()) {
+            match bindings.iter_mut().find(|binding| {
+                binding.set == reflected.set && binding.binding == reflected.binding
+            }) {
+                Some(binding) if binding.descriptor_type != reflected.descriptor_type => {
+                    return Err(format!(
+                        "Descriptor set {} binding {} is declared with different types.",
+                        reflected.set, reflected.binding
+                    ));
+                }
+                Some(binding) => binding.stage_flags |= reflected.stage_flags,
+                None => bindings.push(*reflected),
+            }
+        }
+
+        let set_count = bindings
+            .iter()
+            .map(|binding| binding.set + 1)
+            .max()
+            .unwrap_or(0);
+        let bindless = match &renderer.bindless {
+            Some(table) if bindings.iter().any(|binding| binding.set == BINDLESS_SET) => {
+                validate_bindless_bindings(&bindings)?;
+                bindings.retain(|binding| binding.set != BINDLESS_SET);
+                Some(table)
+            }
+            _ => None,
+        };
+        if let Some(binding) = bindings.iter().find(|binding| binding.count == 0) {
+            return Err(format!(
+                "Descriptor set {} binding {} is an unbounded array, which only the bindless set {} supports.",
+                binding.set, binding.binding, BINDLESS_SET
+            ));
+        }
+
+        for binding in &mut bindings {
+            if binding.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER {
+                binding.descriptor_type = vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC;
+            }
+        }
+
+        let mut descriptors = PipelineDescriptors {
+            renderer: renderer.clone(),
+            set_layouts: Vec::new(),
+            pool: vk::DescriptorPool::null(),
+            sets: Vec::new(),
+            bindless_set: bindless.map(|table| table.set),
+            bindings,
+            resources: Mutex::new(HashMap::new()),
+        };
+        {
+            let mut layout_cache = renderer.descriptor_layouts.lock().unwrap();
+            for set in 0..set_count {
+                let layout = match bindless {
+                    Some(table) if set == BINDLESS_SET => table.layout,
+                    _ => layout_cache
+                        .get(&renderer.device, &descriptors.layout_bindings(set))
+                        .map_err(|r| r.to_string())?,
+                };
+                descriptors.set_layouts.push(layout);
+            }
+        }
+        let owned_layouts = descriptors.owned_set_layouts().collect::<Vec<_>>();
+        if owned_layouts.is_empty() {
+            return Ok(descriptors);
+        }
+        let pool_sizes = pool_sizes(&descriptors.layout_bindings_of(0..set_count));
+        let (pool, mut sets) = renderer
+            .descriptor_sets
+            .lock()
+            .unwrap()
+            .allocate(&renderer.device, &owned_layouts, &pool_sizes)
+            .map_err(|r| r.to_string())?;
+        if let Some(set) = descriptors.bindless_set {
+            sets.insert(BINDLESS_SET as usize, set);
+        }
+        descriptors.pool = pool;
+        descriptors.sets = sets;
+        Ok(descriptors)
+    }
+
+    /// Layout bindings of `set`.
+    fn layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.layout_bindings_of(set..set + 1)
+    }
+
+    fn layout_bindings_of(&self, sets: Range<u32>) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.bindings
+            .iter()
+            .filter(|binding| sets.contains(&binding.set))
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .descriptor_count(binding.count)
+                    .stage_flags(binding.stage_flags)
+                    .build()
+            })
+            .collect()
+    }
+
+    /// Layouts of the sets allocated for the pipeline, without the one of the bindless table.
+    fn owned_set_layouts(&self) -> impl Iterator<Item = vk::DescriptorSetLayout> + '_ {
+        self.set_layouts
+            .iter()
+            .enumerate()
+            .filter(|&(set, _)| self.bindless_set.is_none() || set as u32 != BINDLESS_SET)
+            .map(|(_, &layout)| layout)
+    }
+
+    /// Binds the sets for the pipelines of `pipeline_layout` at `bind_point`.
+    pub(crate) fn bind(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        if self.sets.is_empty() {
+            return;
+        }
+        let dynamic_offsets = vec![0; self.dynamic_descriptor_count(0..self.sets.len() as u32)];
+        unsafe {
+            self.renderer.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                bind_point,
+                pipeline_layout,
+                0,
+                &self.sets,
+                &dynamic_offsets,
+            );
+        }
+    }
+
+    /// Number of dynamic offsets binding `sets` takes.
+    pub(crate) fn dynamic_descriptor_count(&self, sets: Range<u32>) -> usize {
+        self.bindings
+            .iter()
+            .filter(|binding| {
+                sets.contains(&binding.set)
+                    && binding.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            })
+            .map(|binding| binding.count as usize)
+            .sum()
+    }
+
+    /// Writes `buffer` to a storage buffer binding, checking that elements of `element_size`
+    /// bytes match the stride of the runtime array the block ends with, if any.
+    pub(crate) fn bind_storage_buffer(
+        &self,
+        set: u32,
+        binding: u32,
+        buffer: &Arc<Buffer>,
+        element_size: usize,
+    ) -> Result<(), String> {
+        check_storage_buffer_elements(self.binding(set, binding)?, element_size)?;
+        self.bind_buffer(set, binding, buffer)
+    }
+
+    /// Writes `buffer` to a uniform or storage buffer binding.
+    pub(crate) fn bind_buffer(
+        &self,
+        set: u32,
+        binding: u32,
+        buffer: &Arc<Buffer>,
+    ) -> Result<(), String> {
+        let descriptor_type = self.buffer_descriptor_type(set, binding)?;
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build();
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.sets[set as usize])
+            .dst_binding(binding)
+            .descriptor_type(descriptor_type)
+            .buffer_info(&[buffer_info])
+            .build();
+        unsafe {
+            self.renderer.device.update_descriptor_sets(&[write], &[]);
+        }
+        self.resources
+            .lock()
+            .unwrap()
+            .insert((set, binding), BoundResource::Buffer(buffer.clone()));
+        Ok(())
+    }
+
+    /// Writes `image` to a storage image binding.
+    pub(crate) fn bind_storage_image(
+        &self,
+        set: u32,
+        binding: u32,
+        image: &Arc<StorageImage>,
+    ) -> Result<(), String> {
+        let descriptor_type = self.descriptor_type(set, binding)?;
+        if descriptor_type != vk::DescriptorType::STORAGE_IMAGE {
+            return Err(format!(
+                "Descriptor set {} binding {} is {:?}, not a storage image.",
+                set, binding, descriptor_type
+            ));
+        }
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_view(image.view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .build();
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.sets[set as usize])
+            .dst_binding(binding)
+            .descriptor_type(descriptor_type)
+            .image_info(&[image_info])
+            .build();
+        unsafe {
+            self.renderer.device.update_descriptor_sets(&[write], &[]);
+        }
+        self.resources
+            .lock()
+            .unwrap()
+            .insert((set, binding), BoundResource::StorageImage(image.clone()));
+        Ok(())
+    }
+
+    /// Allocates a set with the layout of `set` from the transient `allocator`, writing
+    /// `buffers` to their uniform or storage buffer bindings, and returns it with how the
+    /// shaders use the buffers. Bindings without a buffer are left unwritten.
+    pub(crate) fn transient_set(
+        &self,
+        allocator: &mut DescriptorAllocator,
+        set: u32,
+        buffers: &[(u32, &Buffer)],
+    ) -> Result<(vk::DescriptorSet, Vec<(TrackedResource, ResourceUse)>), String> {
+        let declared = buffers
+            .iter()
+            .map(|&(binding, _)| {
+                self.buffer_descriptor_type(set, binding)?;
+                self.binding(set, binding)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let layout = *self
+            .set_layouts
+            .get(set as usize)
+            .ok_or_else(|| format!("Descriptor set {} is not declared.", set))?;
+        let (_, sets) = allocator
+            .allocate(
+                &self.renderer.device,
+                &[layout],
+                &pool_sizes(&self.layout_bindings(set)),
+            )
+            .map_err(|r| r.to_string())?;
+        let buffer_infos = buffers
+            .iter()
+            .map(|(_, buffer)| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer.buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+        let writes = declared
+            .iter()
+            .zip(&buffer_infos)
+            .map(|(binding, buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(sets[0])
+                    .dst_binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            self.renderer.device.update_descriptor_sets(&writes, &[]);
+        }
+        let uses = declared
+            .iter()
+            .zip(buffers)
+            .map(|(binding, (_, buffer))| {
+                descriptor_use(binding, TrackedResource::Buffer(buffer.buffer))
+            })
+            .collect();
+        Ok((sets[0], uses))
+    }
+
+    /// Allocates a set with the layout of `set` from the transient `allocator`, writing
+    /// `images` to their bindings of the given sampled or storage image type, and returns it
+    /// with how the shaders use the images. Bindings without an image are left unwritten.
+    pub(crate) fn transient_image_set(
+        &self,
+        allocator: &mut DescriptorAllocator,
+        set: u32,
+        images: &[(u32, vk::DescriptorType, vk::Image, vk::ImageView)],
+    ) -> Result<(vk::DescriptorSet, Vec<(TrackedResource, ResourceUse)>), String> {
+        let declared = images
+            .iter()
+            .map(|&(binding, descriptor_type, _, _)| {
+                let declared = self.binding(set, binding)?;
+                if declared.descriptor_type != descriptor_type {
+                    return Err(format!(
+                        "Descriptor set {} binding {} is {:?}, not {:?}.",
+                        set, binding, declared.descriptor_type, descriptor_type
+                    ));
+                }
+                Ok(declared)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let layout = *self
+            .set_layouts
+            .get(set as usize)
+            .ok_or_else(|| format!("Descriptor set {} is not declared.", set))?;
+        let (_, sets) = allocator
+            .allocate(
+                &self.renderer.device,
+                &[layout],
+                &pool_sizes(&self.layout_bindings(set)),
+            )
+            .map_err(|r| r.to_string())?;
+        let uses = declared
+            .iter()
+            .zip(images)
+            .map(|(binding, &(_, _, image, _))| {
+                descriptor_use(binding, TrackedResource::Image(image))
+            })
+            .collect::<Vec<_>>();
+        let image_infos = images
+            .iter()
+            .zip(&uses)
+            .map(|(&(_, _, _, view), (_, image_use))| {
+                [vk::DescriptorImageInfo::builder()
+                    .image_view(view)
+                    .image_layout(image_use.layout)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+        let writes = declared
+            .iter()
+            .zip(&image_infos)
+            .map(|(binding, image_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(sets[0])
+                    .dst_binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .image_info(image_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            self.renderer.device.update_descriptor_sets(&writes, &[]);
+        }
+        Ok((sets[0], uses))
+    }
+
+    /// Descriptor set with the layout of `set` whose uniform buffers are the chunks of
+    /// `uniforms`, with the dynamic offsets to bind it with in the order of the bindings.
+    /// Every binding of the set must be given a uniform. Sets are allocated from the transient
+    /// `allocator` once per frame for the same chunks.
+    pub(crate) fn transient_uniform_set(
+        &self,
+        allocator: &mut DescriptorAllocator,
+        transient_uniforms: &mut TransientUniforms,
+        set: u32,
+        uniforms: &[(u32, TransientUniform)],
+    ) -> Result<(vk::DescriptorSet, Vec<u32>), String> {
+        let mut uniforms = uniforms.to_vec();
+        uniforms.sort_by_key(|&(binding, _)| binding);
+        for &(binding, _) in &uniforms {
+            let declared = self.binding(set, binding)?;
+            if declared.descriptor_type != vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+                || declared.count != 1
+            {
+                return Err(format!(
+                    "Descriptor set {} binding {} is not a single uniform buffer.",
+                    set, binding
+                ));
+            }
+        }
+        let declared = self.layout_bindings(set);
+        if declared.len() != uniforms.len()
+            || declared
+                .iter()
+                .any(|d| !uniforms.iter().any(|&(binding, _)| binding == d.binding))
+        {
+            return Err(format!(
+                "Every binding of descriptor set {} needs one transient uniform.",
+                set
+            ));
+        }
+
+        let layout = self.set_layouts[set as usize];
+        let key = (
+            layout,
+            uniforms
+                .iter()
+                .map(|&(binding, uniform)| (binding, uniform.buffer))
+                .collect::<Vec<_>>(),
+        );
+        let offsets = uniforms.iter().map(|(_, uniform)| uniform.offset).collect();
+        if let Some(&descriptor_set) = transient_uniforms.sets.get(&key) {
+            return Ok((descriptor_set, offsets));
+        }
+        let (_, sets) = allocator
+            .allocate(&self.renderer.device, &[layout], &pool_sizes(&declared))
+            .map_err(|r| r.to_string())?;
+        let buffer_infos = uniforms
+            .iter()
+            .map(|(_, uniform)| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(uniform.buffer)
+                    .offset(0)
+                    .range(transient_uniforms.range)
+                    .build()]
+            })
+            .collect::<Vec<_>>();
+        let writes = uniforms
+            .iter()
+            .zip(&buffer_infos)
+            .map(|(&(binding, _), buffer_info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(sets[0])
+                    .dst_binding(binding)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                    .buffer_info(buffer_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            self.renderer.device.update_descriptor_sets(&writes, &[]);
+        }
+        transient_uniforms.sets.insert(key, sets[0]);
+        Ok((sets[0], offsets))
+    }
+
+    /// Writes the resources bound to `other` to the same bindings of `self`, for a pipeline
+    /// rebuilt from a reloaded shader. Returns the errors of the bindings that don't match.
+    pub(crate) fn rebind_from(&self, other: &PipelineDescriptors) -> Vec<String> {
+        other
+            .resources
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(&(set, binding), resource)| {
+                match resource {
+                    BoundResource::Buffer(buffer) => self.bind_buffer(set, binding, buffer),
+                    BoundResource::StorageImage(image) => {
+                        self.bind_storage_image(set, binding, image)
+                    }
+                }
+                .err()
+            })
+            .collect()
+    }
+
+    /// How the shaders use the bound resources. Storage bindings count as writes, as
+    /// reflection doesn't tell whether the shader writes them.
+    pub(crate) fn resource_uses(&self) -> Vec<(TrackedResource, ResourceUse)> {
+        self.resources
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(&(set, binding), resource)| {
+                let declared = self.binding(set, binding).ok()?;
+                let resource = match resource {
+                    BoundResource::Buffer(buffer) => TrackedResource::Buffer(buffer.buffer),
+                    BoundResource::StorageImage(image) => TrackedResource::Image(image.image),
+                };
+                Some(descriptor_use(declared, resource))
+            })
+            .collect()
+    }
+
+    /// Type of a uniform or storage buffer binding.
+    fn buffer_descriptor_type(&self, set: u32, binding: u32) -> Result<vk::DescriptorType, String> {
+        let descriptor_type = self.descriptor_type(set, binding)?;
+        if descriptor_type != vk::DescriptorType::STORAGE_BUFFER
+            && descriptor_type != vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+        {
+            return Err(format!(
+                "Descriptor set {} binding {} is {:?}, not a buffer.",
+                set, binding, descriptor_type
+            ));
+        }
+        Ok(descriptor_type)
+    }
+
+    fn descriptor_type(&self, set: u32, binding: u32) -> Result<vk::DescriptorType, String> {
+        self.binding(set, binding)
+            .map(|binding| binding.descriptor_type)
+    }
+
+    fn binding(&self, set: u32, binding: u32) -> Result<&DescriptorBinding, String> {
+        if self.bindless_set.is_some() && set == BINDLESS_SET {
+            return Err(format!(
+                "Descriptor set {} is the bindless table, whose resources are added with `Renderer::bindless_*`.",
+                set
+            ));
+        }
+        self.bindings
+            .iter()
+            .find(|b| b.set == set && b.binding == binding)
+            .ok_or_else(|| {
+                format!(
+                    "Descriptor set {} binding {} is not declared.",
+                    set, binding
+                )
+            })
+    }
+}
+
+impl Drop for PipelineDescriptors {
+    fn drop(&mut self) {
+        // The layouts stay in the cache for other pipelines.
+        let sets = self
+            .sets
+            .iter()
+            .copied()
+            .filter(|&set| Some(set) != self.bindless_set)
+            .collect::<Vec<_>>();
+        if !sets.is_empty() {
+            self.renderer.descriptor_sets.lock().unwrap().free(
+                &self.renderer.device,
+                self.pool,
+                &sets,
+            );
+        }
+    }
+}
+
+/// How the shaders use `resource` written to `declared`.
+pub(crate) fn descriptor_use(
+    declared: &DescriptorBinding,
+    resource: TrackedResource,
+) -> (TrackedResource, ResourceUse) {
+    let (layout, access, write) = match declared.descriptor_type {
+        vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC => (
+            vk::ImageLayout::GENERAL,
+            vk::AccessFlags2::UNIFORM_READ,
+            false,
+        ),
+        vk::DescriptorType::SAMPLED_IMAGE => (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+            false,
+        ),
+        _ => (
+            vk::ImageLayout::GENERAL,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            true,
+        ),
+    };
+    (
+        resource,
+        ResourceUse {
+            layout,
+            scope: AccessScope {
+                stages: shader_stages(declared.stage_flags),
+                access,
+            },
+            write,
+        },
+    )
+}
+
+/// Checks that the bindings a pipeline declares at `BINDLESS_SET` match the bindless table.
+fn validate_bindless_bindings(bindings: &[DescriptorBinding]) -> Result<(), String> {
+    for binding in bindings
+        .iter()
+        .filter(|binding| binding.set == BINDLESS_SET)
+    {
+        match BindlessBinding::from_binding(binding.binding) {
+            Some(bindless) if bindless.descriptor_type() == binding.descriptor_type => {}
+            Some(bindless) => {
+                return Err(format!(
+                    "Bindless binding {} is declared as {:?}, but it holds {:?}.",
+                    binding.binding,
+                    binding.descriptor_type,
+                    bindless.descriptor_type()
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "Bindless binding {} is not a binding of the bindless table.",
+                    binding.binding
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vulkan::{compile_shader_source, ShaderCompileOptions, ShaderLanguage, ShaderStage};
+
+    fn reflect_compute(source: &str) -> Vec<DescriptorBinding> {
+        let code = compile_shader_source(
+            source,
+            None,
+            ShaderLanguage::Glsl,
+            ShaderStage::Compute,
+            &ShaderCompileOptions::default(),
+        )
+        .unwrap();
+        let reflect = Reflection::new_from_spirv(&code).unwrap();
+        let mut bindings =
+            reflect_descriptor_bindings(&reflect, vk::ShaderStageFlags::COMPUTE).unwrap();
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        bindings
+    }
+
+    #[test]
+    fn reflects_the_element_stride_of_runtime_arrays() {
+        let bindings = reflect_compute(
+            "#version 450
+            layout(local_size_x = 64) in;
+            struct Particle {
+                vec3 position;
+                float mass;
+                vec3 velocity;
+            };
+            layout(std430, set = 0, binding = 0) buffer Particles {
+                uint count;
+                Particle particles[];
+            };
+            layout(std430, set = 0, binding = 1) buffer Values {
+                float values[];
+            };
+            layout(std430, set = 1, binding = 0) buffer Totals {
+                vec4 total;
+            };
+            void main() {
+                particles[gl_GlobalInvocationID.x].mass = values[0] + float(count) + total.x;
+            }
+            ",
+        );
+        let reflected = bindings
+            .iter()
+            .map(|b| (b.set, b.binding, b.descriptor_type, b.element_stride))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reflected,
+            [
+                (0, 0, vk::DescriptorType::STORAGE_BUFFER, Some(32)),
+                (0, 1, vk::DescriptorType::STORAGE_BUFFER, Some(4)),
+                (1, 0, vk::DescriptorType::STORAGE_BUFFER, None),
+            ]
+        );
+    }
+
+    fn binding(
+        descriptor_type: vk::DescriptorType,
+        element_stride: Option<u32>,
+    ) -> DescriptorBinding {
+        DescriptorBinding {
+            set: 0,
+            binding: 2,
+            descriptor_type,
+            count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            element_stride,
+        }
+    }
+
+    #[test]
+    fn checks_elements_against_the_declared_stride() {
+        let storage = vk::DescriptorType::STORAGE_BUFFER;
+        assert!(check_storage_buffer_elements(&binding(storage, Some(32)), 32).is_ok());
+        assert!(check_storage_buffer_elements(&binding(storage, None), 12).is_ok());
+        assert_eq!(
+            check_storage_buffer_elements(&binding(storage, Some(32)), 28),
+            Err("Descriptor set 0 binding 2 has elements of 32 bytes, but 28 is given.".to_owned())
+        );
+        assert_eq!(
+            check_storage_buffer_elements(
+                &binding(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, None),
+                16
+            ),
+            Err(
+                "Descriptor set 0 binding 2 is UNIFORM_BUFFER_DYNAMIC, not a storage buffer."
+                    .to_owned()
+            )
+        );
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+
+use ash::{prelude::VkResult, vk, Device};
+
+/// Descriptors of each type a pool holds per set it can allocate.
+const POOL_RATIOS: [(vk::DescriptorType, u32); 7] = [
+    (vk::DescriptorType::UNIFORM_BUFFER, 1),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 2),
+    (vk::DescriptorType::STORAGE_BUFFER, 2),
+    (vk::DescriptorType::STORAGE_IMAGE, 1),
+    (vk::DescriptorType::SAMPLED_IMAGE, 2),
+    (vk::DescriptorType::SAMPLER, 1),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2),
+];
+/// Sets of the first pool of an allocator. Each new pool holds twice as many, up to
+/// `MAX_POOL_SETS`.
+const INITIAL_POOL_SETS: u32 = 64;
+const MAX_POOL_SETS: u32 = 4096;
+
+/// Allocates descriptor sets from a growing list of pools. When the current pool runs out,
+/// the next one is used, or created larger than the last.
+///
+/// Persistent allocators free their sets one by one, for the sets of materials and compute
+/// pipelines. Transient ones are reset as a whole, for the sets of one frame.
+pub(crate) struct DescriptorAllocator {
+    /// Whether sets are freed with `free` rather than by `reset`.
+    persistent: bool,
+    /// Pools in the order they are tried in. The ones before `current` are full.
+    pools: Vec<vk::DescriptorPool>,
+    current: usize,
+    next_pool_sets: u32,
+}
+
+impl DescriptorAllocator {
+    pub(crate) fn new(persistent: bool) -> Self {
+        DescriptorAllocator {
+            persistent,
+            pools: Vec::new(),
+            current: 0,
+            next_pool_sets: INITIAL_POOL_SETS,
+        }
+    }
+
+    /// Allocates one set per layout from one pool, returned with the sets so that
+    /// persistent sets can be freed to it. `pool_sizes` are the descriptors the layouts
+    /// hold together, which a new pool holds at least.
+    pub(crate) fn allocate(
+        &mut self,
+        device: &Device,
+        layouts: &[vk::DescriptorSetLayout],
+        pool_sizes: &[vk::DescriptorPoolSize],
+    ) -> VkResult<(vk::DescriptorPool, Vec<vk::DescriptorSet>)> {
+        let persistent = self.persistent;
+        self.allocate_with(
+            layouts.len() as u32,
+            |pool| allocate_sets(device, pool, layouts),
+            |max_sets| create_pool(device, persistent, max_sets, pool_sizes),
+        )
+    }
+
+    /// Tries the pools from the current one with `allocate`, then a pool of at least
+    /// `set_count` sets from `create`.
+    fn allocate_with<T>(
+        &mut self,
+        set_count: u32,
+        mut allocate: impl FnMut(vk::DescriptorPool) -> VkResult<T>,
+        create: impl FnOnce(u32) -> VkResult<vk::DescriptorPool>,
+    ) -> VkResult<(vk::DescriptorPool, T)> {
+        while self.current < self.pools.len() {
+            let pool = self.pools[self.current];
+            match allocate(pool) {
+                Ok(sets) => return Ok((pool, sets)),
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                    self.current += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let pool = create(self.next_pool_sets.max(set_count))?;
+        self.next_pool_sets = (self.next_pool_sets * 2).min(MAX_POOL_SETS);
+        self.pools.push(pool);
+        allocate(pool).map(|sets| (pool, sets))
+    }
+
+    /// Frees sets of a persistent allocator to the pool they were allocated from.
+    pub(crate) fn free(
+        &mut self,
+        device: &Device,
+        pool: vk::DescriptorPool,
+        sets: &[vk::DescriptorSet],
+    ) {
+        assert!(
+            self.persistent,
+            "Transient descriptor sets are freed by reset."
+        );
+        unsafe {
+            device
+                .free_descriptor_sets(pool, sets)
+                .expect("Free descriptor sets failed.");
+        }
+        self.reclaim(pool);
+    }
+
+    /// A full pool has room again after a free, so it moves back among the ones tried.
+    fn reclaim(&mut self, pool: vk::DescriptorPool) {
+        if let Some(index) = self.pools[..self.current].iter().position(|&p| p == pool) {
+            self.current -= 1;
+            self.pools.swap(index, self.current);
+        }
+    }
+
+    /// Frees every set of a transient allocator, whose sets the GPU must be done with.
+    pub(crate) fn reset(&mut self, device: &Device) -> VkResult<()> {
+        self.reset_with(|pool| unsafe {
+            device.reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+        })
+    }
+
+    /// Resets the pools allocated from since the last reset with `reset_pool`. The ones after
+    /// the current pool are still empty.
+    fn reset_with(
+        &mut self,
+        mut reset_pool: impl FnMut(vk::DescriptorPool) -> VkResult<()>,
+    ) -> VkResult<()> {
+        for &pool in &self.pools[..self.pools.len().min(self.current + 1)] {
+            reset_pool(pool)?;
+        }
+        self.current = 0;
+        Ok(())
+    }
+
+    /// The GPU must be done with the sets.
+    pub(crate) fn destroy(&self, device: &Device) {
+        for &pool in &self.pools {
+            unsafe {
+                device.destroy_descriptor_pool(pool, None);
+            }
+        }
+    }
+}
+
+fn create_pool(
+    device: &Device,
+    persistent: bool,
+    max_sets: u32,
+    pool_sizes: &[vk::DescriptorPoolSize],
+) -> VkResult<vk::DescriptorPool> {
+    let sizes = pool_descriptor_counts(max_sets, pool_sizes);
+    let flags = if persistent {
+        vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
+    } else {
+        vk::DescriptorPoolCreateFlags::empty()
+    };
+    let create_info = vk::DescriptorPoolCreateInfo::builder()
+        .flags(flags)
+        .max_sets(max_sets)
+        .pool_sizes(&sizes)
+        .build();
+    unsafe { device.create_descriptor_pool(&create_info, None) }
+}
+
+/// Descriptors of a pool of `max_sets` sets: `POOL_RATIOS` per set, and at least
+/// `pool_sizes`.
+fn pool_descriptor_counts(
+    max_sets: u32,
+    pool_sizes: &[vk::DescriptorPoolSize],
+) -> Vec<vk::DescriptorPoolSize> {
+    let mut sizes = POOL_RATIOS
+        .iter()
+        .map(|&(ty, ratio)| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: ratio * max_sets,
+        })
+        .collect::<Vec<_>>();
+    for required in pool_sizes {
+        match sizes.iter_mut().find(|size| size.ty == required.ty) {
+            Some(size) => {
+                size.descriptor_count = size.descriptor_count.max(required.descriptor_count)
+            }
+            None => sizes.push(*required),
+        }
+    }
+    sizes
+}
+
+fn allocate_sets(
+    device: &Device,
+    pool: vk::DescriptorPool,
+    layouts: &[vk::DescriptorSetLayout],
+) -> VkResult<Vec<vk::DescriptorSet>> {
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(layouts)
+        .build();
+    unsafe { device.allocate_descriptor_sets(&allocate_info) }
+}
+
+/// Descriptors of each type in `bindings`, to size pools with.
+pub(crate) fn pool_sizes<'a>(
+    bindings: impl IntoIterator<Item = &'a vk::DescriptorSetLayoutBinding>,
+) -> Vec<vk::DescriptorPoolSize> {
+    let mut sizes = Vec::<vk::DescriptorPoolSize>::new();
+    for binding in bindings {
+        match sizes
+            .iter_mut()
+            .find(|size| size.ty == binding.descriptor_type)
+        {
+            Some(size) => size.descriptor_count += binding.descriptor_count,
+            None => sizes.push(vk::DescriptorPoolSize {
+                ty: binding.descriptor_type,
+                descriptor_count: binding.descriptor_count,
+            }),
+        }
+    }
+    sizes
+}
+
+/// Bindings of a layout, in the order of their binding numbers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct LayoutKey(Vec<(u32, vk::DescriptorType, u32, vk::ShaderStageFlags)>);
+
+impl LayoutKey {
+    fn new(bindings: &[vk::DescriptorSetLayoutBinding]) -> Self {
+        let mut key = bindings
+            .iter()
+            .map(|b| {
+                (
+                    b.binding,
+                    b.descriptor_type,
+                    b.descriptor_count,
+                    b.stage_flags,
+                )
+            })
+            .collect::<Vec<_>>();
+        key.sort_by_key(|&(binding, ..)| binding);
+        LayoutKey(key)
+    }
+}
+
+/// Descriptor set layouts shared by the pipelines declaring the same bindings, so that
+/// shaders agreeing on a set get compatible layouts. They live as long as the renderer.
+#[derive(Default)]
+pub(crate) struct DescriptorLayoutCache {
+    layouts: HashMap<LayoutKey, vk::DescriptorSetLayout>,
+}
+
+impl DescriptorLayoutCache {
+    pub(crate) fn get(
+        &mut self,
+        device: &Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> VkResult<vk::DescriptorSetLayout> {
+        let key = LayoutKey::new(bindings);
+        if let Some(&layout) = self.layouts.get(&key) {
+            return Ok(layout);
+        }
+        let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(bindings)
+            .build();
+        let layout = unsafe { device.create_descriptor_set_layout(&create_info, None)? };
+        self.layouts.insert(key, layout);
+        Ok(layout)
+    }
+
+    /// No pipeline or set may use the layouts anymore.
+    pub(crate) fn destroy(&self, device: &Device) {
+        for &layout in self.layouts.values() {
+            unsafe {
+                device.destroy_descriptor_set_layout(layout, None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    fn pool(raw: u64) -> vk::DescriptorPool {
+        vk::DescriptorPool::from_raw(raw)
+    }
+
+    /// Allocates from pools with room for `capacity` allocations each, creating pools
+    /// numbered from 1 and recording their sizes.
+    fn allocate(
+        allocator: &mut DescriptorAllocator,
+        used: &mut HashMap<vk::DescriptorPool, u32>,
+        created: &mut Vec<u32>,
+        capacity: u32,
+        full_error: vk::Result,
+    ) -> vk::DescriptorPool {
+        let next_pool = pool(created.len() as u64 + 1);
+        let (allocated_from, ()) = allocator
+            .allocate_with(
+                1,
+                |pool| {
+                    let used = used.entry(pool).or_default();
+                    if *used == capacity {
+                        return Err(full_error);
+                    }
+                    *used += 1;
+                    Ok(())
+                },
+                |max_sets| {
+                    created.push(max_sets);
+                    Ok(next_pool)
+                },
+            )
+            .unwrap();
+        allocated_from
+    }
+
+    #[test]
+    fn moves_to_the_next_pool_when_one_is_full() {
+        for full_error in [
+            vk::Result::ERROR_OUT_OF_POOL_MEMORY,
+            vk::Result::ERROR_FRAGMENTED_POOL,
+        ] {
+            let mut allocator = DescriptorAllocator::new(false);
+            let mut used = HashMap::new();
+            let mut created = Vec::new();
+            let pools = (0..5)
+                .map(|_| allocate(&mut allocator, &mut used, &mut created, 2, full_error))
+                .collect::<Vec<_>>();
+            assert_eq!(pools, [pool(1), pool(1), pool(2), pool(2), pool(3)]);
+            assert_eq!(allocator.current, 2);
+        }
+    }
+
+    #[test]
+    fn fails_on_other_errors() {
+        let mut allocator = DescriptorAllocator::new(false);
+        allocator
+            .allocate_with(1, |_| Ok(()), |_| Ok(pool(1)))
+            .unwrap();
+        let result = allocator.allocate_with::<()>(
+            1,
+            |_| Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY),
+            |_| panic!("No pool is created for other errors."),
+        );
+        assert_eq!(result, Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY));
+        assert_eq!(allocator.pools.len(), 1);
+    }
+
+    #[test]
+    fn doubles_pool_sizes_up_to_the_maximum() {
+        let mut allocator = DescriptorAllocator::new(false);
+        let mut used = HashMap::new();
+        let mut created = Vec::new();
+        for _ in 0..10 {
+            allocate(
+                &mut allocator,
+                &mut used,
+                &mut created,
+                1,
+                vk::Result::ERROR_OUT_OF_POOL_MEMORY,
+            );
+        }
+        assert_eq!(
+            created,
+            [64, 128, 256, 512, 1024, 2048, 4096, 4096, 4096, 4096]
+        );
+        assert_eq!(*created.iter().max().unwrap(), MAX_POOL_SETS);
+
+        // A pool holds at least the sets allocated together.
+        let mut allocator = DescriptorAllocator::new(false);
+        let mut max_sets = 0;
+        allocator
+            .allocate_with(
+                100,
+                |_| Ok(()),
+                |sets| {
+                    max_sets = sets;
+                    Ok(pool(1))
+                },
+            )
+            .unwrap();
+        assert_eq!(max_sets, 100);
+    }
+
+    #[test]
+    fn sizes_pools_for_the_required_descriptors() {
+        let required = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1000,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::INPUT_ATTACHMENT,
+                descriptor_count: 3,
+            },
+        ];
+        let sizes = pool_descriptor_counts(64, &required);
+        let count = |ty| {
+            sizes
+                .iter()
+                .find(|size| size.ty == ty)
+                .map(|size| size.descriptor_count)
+        };
+        assert_eq!(count(vk::DescriptorType::UNIFORM_BUFFER), Some(64));
+        assert_eq!(count(vk::DescriptorType::SAMPLED_IMAGE), Some(128));
+        assert_eq!(count(vk::DescriptorType::STORAGE_BUFFER), Some(1000));
+        assert_eq!(count(vk::DescriptorType::INPUT_ATTACHMENT), Some(3));
+    }
+
+    #[test]
+    fn resets_the_pools_used_since_the_last_reset() {
+        let mut allocator = DescriptorAllocator::new(false);
+        let mut used = HashMap::new();
+        let mut created = Vec::new();
+        for _ in 0..6 {
+            allocate(
+                &mut allocator,
+                &mut used,
+                &mut created,
+                2,
+                vk::Result::ERROR_OUT_OF_POOL_MEMORY,
+            );
+        }
+        let mut reset = Vec::new();
+        allocator
+            .reset_with(|pool| {
+                reset.push(pool);
+                used.remove(&pool);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(reset, [pool(1), pool(2), pool(3)]);
+        assert_eq!(allocator.current, 0);
+
+        // The next frame reuses the pools from the first one, without creating any.
+        for expected in [pool(1), pool(1), pool(2)] {
+            let allocated_from = allocate(
+                &mut allocator,
+                &mut used,
+                &mut created,
+                2,
+                vk::Result::ERROR_OUT_OF_POOL_MEMORY,
+            );
+            assert_eq!(allocated_from, expected);
+        }
+        assert_eq!(created.len(), 3);
+
+        // Pools after the current one are still empty.
+        reset.clear();
+        allocator
+            .reset_with(|pool| {
+                reset.push(pool);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(reset, [pool(1), pool(2)]);
+    }
+
+    #[test]
+    fn retries_full_pools_that_sets_were_freed_to() {
+        let mut allocator = DescriptorAllocator::new(true);
+        let mut used = HashMap::new();
+        let mut created = Vec::new();
+        for _ in 0..5 {
+            allocate(
+                &mut allocator,
+                &mut used,
+                &mut created,
+                2,
+                vk::Result::ERROR_OUT_OF_POOL_MEMORY,
+            );
+        }
+        assert_eq!(allocator.current, 2);
+
+        *used.get_mut(&pool(1)).unwrap() -= 1;
+        allocator.reclaim(pool(1));
+        assert_eq!(allocator.current, 1);
+        assert_eq!(
+            allocate(
+                &mut allocator,
+                &mut used,
+                &mut created,
+                2,
+                vk::Result::ERROR_OUT_OF_POOL_MEMORY,
+            ),
+            pool(1)
+        );
+
+        // Pools still tried stay in place.
+        allocator.reclaim(pool(3));
+        assert_eq!(allocator.current, 1);
+    }
+
+    fn binding(
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(1)
+            .stage_flags(stage_flags)
+            .build()
+    }
+
+    #[test]
+    fn keys_layouts_by_their_bindings_in_any_order() {
+        let uniform = binding(
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::ShaderStageFlags::VERTEX,
+        );
+        let texture = binding(
+            1,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vk::ShaderStageFlags::FRAGMENT,
+        );
+        assert_eq!(
+            LayoutKey::new(&[uniform, texture]),
+            LayoutKey::new(&[texture, uniform])
+        );
+
+        let fragment_uniform = binding(
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::ShaderStageFlags::FRAGMENT,
+        );
+        assert_ne!(
+            LayoutKey::new(&[uniform, texture]),
+            LayoutKey::new(&[fragment_uniform, texture])
+        );
+        let mut textures = texture;
+        textures.descriptor_count = 4;
+        assert_ne!(
+            LayoutKey::new(&[uniform, texture]),
+            LayoutKey::new(&[uniform, textures])
+        );
+        let storage = binding(
+            0,
+            vk::DescriptorType::STORAGE_BUFFER,
+            vk::ShaderStageFlags::VERTEX,
+        );
+        assert_ne!(
+            LayoutKey::new(&[uniform, texture]),
+            LayoutKey::new(&[storage, texture])
+        );
+        assert_ne!(
+            LayoutKey::new(&[uniform, texture]),
+            LayoutKey::new(&[uniform])
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::{atomic::AtomicU64, Mutex};
+
+use ash::{prelude::VkResult, vk, Device};
+
+use super::{
+    descriptor_allocator::DescriptorAllocator,
+    query::{FrameQueries, QueryCapabilities},
+    render_graph::GraphFrameResources,
+    transient_uniform::TransientUniforms,
+};
+
+pub(crate) const FRAMES_IN_FLIGHT: usize = 2;
+
+/// Objects used by one frame in flight.
+/// They are reused once the GPU completes `submission`.
+pub(crate) struct Frame {
+    pub(crate) command_buffer: vk::CommandBuffer,
+    /// Value of the last submission of `command_buffer`, 0 before the first one.
+    pub(crate) submission: AtomicU64,
+    pub(crate) present_semaphore: vk::Semaphore,
+    pub(crate) render_semaphore: vk::Semaphore,
+    pub(crate) queries: FrameQueries,
+    pub(crate) graph_resources: Mutex<GraphFrameResources>,
+    /// Pools of the threads recording secondary command buffers, one per job of a parallel
+    /// pass. They are reset when the frame is reused.
+    thread_pools: Mutex<Vec<ThreadCommandPool>>,
+    /// Pools of the descriptor sets allocated while recording the frame, reset when the
+    /// frame is reused.
+    pub(crate) transient_descriptors: Mutex<DescriptorAllocator>,
+    /// Constant data of the draws of the frame, reset together with the descriptor pools.
+    pub(crate) transient_uniforms: Mutex<TransientUniforms>,
+}
+
+impl Frame {
+    pub(crate) fn new(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        query_capabilities: &QueryCapabilities,
+        limits: &vk::PhysicalDeviceLimits,
+    ) -> VkResult<Self> {
+        unsafe {
+            let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+            let present_semaphore = device.create_semaphore(&semaphore_create_info, None)?;
+            let render_semaphore = device.create_semaphore(&semaphore_create_info, None)?;
+            let queries = FrameQueries::new(device, query_capabilities)?;
+
+            Ok(Frame {
+                command_buffer,
+                submission: AtomicU64::new(0),
+                present_semaphore,
+                render_semaphore,
+                queries,
+                graph_resources: Mutex::new(GraphFrameResources::default()),
+                thread_pools: Mutex::new(Vec::new()),
+                transient_descriptors: Mutex::new(DescriptorAllocator::new(false)),
+                transient_uniforms: Mutex::new(TransientUniforms::new(limits)),
+            })
+        }
+    }
+
+    /// Takes `count` thread pools out of the frame, creating the missing ones for
+    /// `queue_family_index`. They are given back with `return_thread_pools`.
+    pub(crate) fn take_thread_pools(
+        &self,
+        device: &Device,
+        queue_family_index: u32,
+        count: usize,
+    ) -> VkResult<Vec<ThreadCommandPool>> {
+        take_pools(&mut self.thread_pools.lock().unwrap(), count, || {
+            ThreadCommandPool::new(device, queue_family_index)
+        })
+    }
+
+    pub(crate) fn return_thread_pools(&self, pools: Vec<ThreadCommandPool>) {
+        self.thread_pools.lock().unwrap().extend(pools);
+    }
+
+    /// Resets the secondary command buffers, once the submission of the frame is complete.
+    pub(crate) fn reset_thread_pools(&self, device: &Device) -> VkResult<()> {
+        for pool in self.thread_pools.lock().unwrap().iter_mut() {
+            pool.reset(device)?;
+        }
+        Ok(())
+    }
+
+    /// The command buffer is freed together with the command pool.
+    pub(crate) fn destroy(&self, device: &Device) {
+        unsafe {
+            self.queries.destroy(device);
+            self.graph_resources.lock().unwrap().destroy(device);
+            self.thread_pools
+                .lock()
+                .unwrap()
+                .iter()
+                .for_each(|pool| device.destroy_command_pool(pool.pool, None));
+            self.transient_descriptors.lock().unwrap().destroy(device);
+            self.transient_uniforms.lock().unwrap().destroy(device);
+            device.destroy_semaphore(self.present_semaphore, None);
+            device.destroy_semaphore(self.render_semaphore, None);
+        }
+    }
+}
+
+/// Takes `count` pools out of `pools`, the last ones first, creating the missing ones.
+fn take_pools<P, F>(pools: &mut Vec<P>, count: usize, mut create: F) -> VkResult<Vec<P>>
+where
+    F: FnMut() -> VkResult<P>,
+{
+    while pools.len() < count {
+        pools.push(create()?);
+    }
+    let rest = pools.len() - count;
+    Ok(pools.drain(rest..).collect())
+}
+
+/// Command pool used by one recording thread at a time, with the secondary command buffers
+/// allocated from it.
+pub(crate) struct ThreadCommandPool {
+    pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    /// Number of command buffers recorded since the last reset.
+    used: usize,
+}
+
+impl ThreadCommandPool {
+    fn new(device: &Device, queue_family_index: u32) -> VkResult<Self> {
+        let create_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue_family_index)
+            .build();
+        let pool = unsafe { device.create_command_pool(&create_info, None)? };
+        Ok(ThreadCommandPool {
+            pool,
+            command_buffers: Vec::new(),
+            used: 0,
+        })
+    }
+
+    /// Secondary command buffer not recorded since the last reset.
+    pub(crate) fn next_secondary(&mut self, device: &Device) -> VkResult<vk::CommandBuffer> {
+        if self.used == self.command_buffers.len() {
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(self.pool)
+                .level(vk::CommandBufferLevel::SECONDARY)
+                .build();
+            let command_buffers = unsafe { device.allocate_command_buffers(&allocate_info)? };
+            self.command_buffers.extend(command_buffers);
+        }
+        self.used += 1;
+        Ok(self.command_buffers[self.used - 1])
+    }
+
+    fn reset(&mut self, device: &Device) -> VkResult<()> {
+        if self.used > 0 {
+            unsafe {
+                device.reset_command_pool(self.pool, vk::CommandPoolResetFlags::empty())?;
+            }
+            self.used = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_only_the_missing_thread_pools() {
+        let mut pools = vec![1, 2];
+        let mut created = 2;
+        let mut create = || {
+            created += 1;
+            Ok(created)
+        };
+        assert_eq!(take_pools(&mut pools, 1, &mut create), Ok(vec![2]));
+        assert_eq!(pools, [1]);
+        assert_eq!(take_pools(&mut pools, 3, &mut create), Ok(vec![1, 3, 4]));
+        assert!(pools.is_empty());
+        // Given back, they are reused by the next pass.
+        pools.extend([1, 3, 4]);
+        assert_eq!(take_pools(&mut pools, 3, &mut create), Ok(vec![1, 3, 4]));
+    }
+
+    #[test]
+    fn keeps_the_thread_pools_when_one_fails_to_be_created() {
+        let mut pools = vec![1];
+        assert_eq!(
+            take_pools(&mut pools, 3, || Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY)),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY)
+        );
+        assert_eq!(pools, [1]);
+    }
+}
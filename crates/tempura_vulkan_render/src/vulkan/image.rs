@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use ash::{prelude::VkResult, vk};
+
+use super::{
+    buffer::allocate_memory,
+    resource_state::{record_transitions, AccessScope, ResourceUse, TrackedResource},
+    MemoryLocation, Renderer,
+};
+
+/// 2D image that shaders read and write with `imageLoad`/`imageStore`.
+/// It stays in the `GENERAL` layout for its whole lifetime.
+pub struct StorageImage {
+    renderer: Arc<Renderer>,
+    pub(crate) image: vk::Image,
+    pub(crate) view: vk::ImageView,
+    memory: vk::DeviceMemory,
+    format: vk::Format,
+    extent: vk::Extent2D,
+}
+
+impl StorageImage {
+    pub(crate) fn new(
+        renderer: &Arc<Renderer>,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+    ) -> VkResult<Self> {
+        let extent = vk::Extent2D { width, height };
+        unsafe {
+            let create_info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(
+                    vk::ImageUsageFlags::STORAGE
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC
+                        | vk::ImageUsageFlags::TRANSFER_DST,
+                )
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build();
+            let image = renderer.device.create_image(&create_info, None)?;
+            let requirements = renderer.device.get_image_memory_requirements(image);
+            let memory = match allocate_memory(renderer, &requirements, MemoryLocation::GpuOnly) {
+                Ok(memory) => memory,
+                Err(e) => {
+                    renderer.device.destroy_image(image, None);
+                    return Err(e);
+                }
+            };
+            let view = match create_view(renderer, image, memory, format) {
+                Ok(view) => view,
+                Err(e) => {
+                    renderer.device.destroy_image(image, None);
+                    renderer.device.free_memory(memory, None);
+                    return Err(e);
+                }
+            };
+
+            renderer.resource_states.lock().unwrap().register_image(
+                image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageAspectFlags::COLOR,
+            );
+            renderer.submit_setup_commands(|command_buffer| {
+                let general = ResourceUse {
+                    layout: vk::ImageLayout::GENERAL,
+                    scope: AccessScope {
+                        stages: vk::PipelineStageFlags2::ALL_COMMANDS,
+                        access: vk::AccessFlags2::SHADER_STORAGE_READ
+                            | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    },
+                    write: true,
+                };
+                let transition = renderer
+                    .resource_states
+                    .lock()
+                    .unwrap()
+                    .transition(TrackedResource::Image(image), &general);
+                if let Some(transition) = transition {
+                    record_transitions(&renderer.synchronization, command_buffer, &[transition]);
+                }
+            });
+            // The setup commands are complete, so the first use waits for nothing.
+            renderer.resource_states.lock().unwrap().register_image(
+                image,
+                vk::ImageLayout::GENERAL,
+                vk::ImageAspectFlags::COLOR,
+            );
+
+            Ok(StorageImage {
+                renderer: renderer.clone(),
+                image,
+                view,
+                memory,
+                format,
+                extent,
+            })
+        }
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+}
+
+impl Drop for StorageImage {
+    fn drop(&mut self) {
+        self.renderer
+            .forget_resource(TrackedResource::Image(self.image));
+        unsafe {
+            self.renderer.device.destroy_image_view(self.view, None);
+            self.renderer.device.destroy_image(self.image, None);
+            self.renderer.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+fn color_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+/// Binds `memory` to `image` and creates a view of the whole image.
+fn create_view(
+    renderer: &Renderer,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    format: vk::Format,
+) -> VkResult<vk::ImageView> {
+    unsafe {
+        renderer.device.bind_image_memory(image, memory, 0)?;
+        let create_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(color_subresource_range())
+            .build();
+        renderer.device.create_image_view(&create_info, None)
+    }
+}
@@ -0,0 +1,118 @@
+use std::mem;
+
+use ash::vk;
+
+/// Arguments of one draw read by `CommandEncoder::draw_indirect`, laid out like
+/// `vk::DrawIndirectCommand` so that compute shaders can write them.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawIndirectCommand {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub first_vertex: u32,
+    pub first_instance: u32,
+}
+
+// Plain `u32`s without padding.
+unsafe impl bytemuck::Zeroable for DrawIndirectCommand {}
+unsafe impl bytemuck::Pod for DrawIndirectCommand {}
+
+impl From<DrawIndirectCommand> for vk::DrawIndirectCommand {
+    fn from(command: DrawIndirectCommand) -> Self {
+        vk::DrawIndirectCommand {
+            vertex_count: command.vertex_count,
+            instance_count: command.instance_count,
+            first_vertex: command.first_vertex,
+            first_instance: command.first_instance,
+        }
+    }
+}
+
+/// Arguments of one draw read by `CommandEncoder::draw_indexed_indirect`, laid out like
+/// `vk::DrawIndexedIndirectCommand` so that compute shaders can write them.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawIndexedIndirectCommand {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+}
+
+// Plain 32-bit integers without padding.
+unsafe impl bytemuck::Zeroable for DrawIndexedIndirectCommand {}
+unsafe impl bytemuck::Pod for DrawIndexedIndirectCommand {}
+
+impl From<DrawIndexedIndirectCommand> for vk::DrawIndexedIndirectCommand {
+    fn from(command: DrawIndexedIndirectCommand) -> Self {
+        vk::DrawIndexedIndirectCommand {
+            index_count: command.index_count,
+            instance_count: command.instance_count,
+            first_index: command.first_index,
+            vertex_offset: command.vertex_offset,
+            first_instance: command.first_instance,
+        }
+    }
+}
+
+const _: () =
+    assert!(mem::size_of::<DrawIndirectCommand>() == mem::size_of::<vk::DrawIndirectCommand>());
+const _: () = assert!(
+    mem::size_of::<DrawIndexedIndirectCommand>()
+        == mem::size_of::<vk::DrawIndexedIndirectCommand>()
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_draw_arguments_in_vulkan_order() {
+        let command = DrawIndirectCommand {
+            vertex_count: 3,
+            instance_count: 2,
+            first_vertex: 7,
+            first_instance: 1,
+        };
+        assert_eq!(bytemuck::cast::<_, [u32; 4]>(command), [3, 2, 7, 1]);
+
+        let vk_command = vk::DrawIndirectCommand::from(command);
+        assert_eq!(
+            (
+                vk_command.vertex_count,
+                vk_command.instance_count,
+                vk_command.first_vertex,
+                vk_command.first_instance
+            ),
+            (3, 2, 7, 1)
+        );
+    }
+
+    #[test]
+    fn lays_out_indexed_draw_arguments_in_vulkan_order() {
+        let command = DrawIndexedIndirectCommand {
+            index_count: 36,
+            instance_count: 4,
+            first_index: 6,
+            vertex_offset: -2,
+            first_instance: 5,
+        };
+        assert_eq!(
+            bytemuck::cast::<_, [u32; 5]>(command),
+            [36, 4, 6, -2i32 as u32, 5]
+        );
+
+        let vk_command = vk::DrawIndexedIndirectCommand::from(command);
+        assert_eq!(
+            (
+                vk_command.index_count,
+                vk_command.instance_count,
+                vk_command.first_index,
+                vk_command.vertex_offset,
+                vk_command.first_instance
+            ),
+            (36, 4, 6, -2, 5)
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::{marker::PhantomData, mem, sync::Arc};
+
+use ash::vk;
+
+use super::{Buffer, MemoryLocation, Renderer, VertexAttribute, VertexBinding};
+
+/// Transform and color of one instance, read by the vertex shader as four `vec4` columns
+/// followed by a `vec4`. SPIR-V from glslang can also read the columns as a `mat4`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InstanceData {
+    /// Column-major model matrix.
+    pub transform: [[f32; 4]; 4],
+    pub color: [f32; 4],
+}
+
+// Plain `f32`s without padding.
+unsafe impl bytemuck::Zeroable for InstanceData {}
+unsafe impl bytemuck::Pod for InstanceData {}
+
+impl Default for InstanceData {
+    /// Identity transform and white.
+    fn default() -> Self {
+        InstanceData {
+            transform: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+            color: [1.0; 4],
+        }
+    }
+}
+
+impl InstanceData {
+    /// Per-instance binding reading the transform at `first_location` to `first_location + 3`
+    /// and the color at `first_location + 4`.
+    pub fn vertex_binding(binding: u32, first_location: u32) -> VertexBinding {
+        let column_size = mem::size_of::<[f32; 4]>() as u32;
+        let attributes = (0..5)
+            .map(|index| VertexAttribute {
+                location: first_location + index,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: index * column_size,
+            })
+            .collect();
+        VertexBinding::per_instance(binding, mem::size_of::<Self>() as u32, attributes)
+    }
+}
+
+/// Vertex buffer holding per-instance data of `T`, such as `InstanceData`.
+pub struct InstanceBuffer<T> {
+    buffer: Arc<Buffer>,
+    len: usize,
+    _element: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> InstanceBuffer<T> {
+    /// The buffer has `VERTEX_BUFFER` and `TRANSFER_DST` usages, so that a `GpuOnly` buffer
+    /// can be filled by a copy.
+    pub(crate) fn new(renderer: &Arc<Renderer>, len: usize, location: MemoryLocation) -> Self {
+        let usage = vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST;
+        // Vulkan buffers can't be empty.
+        let size = (len.max(1) * mem::size_of::<T>()) as u64;
+        let buffer =
+            Buffer::new(renderer, size, usage, location).expect("Create instance buffer failed.");
+
+        InstanceBuffer {
+            buffer: Arc::new(buffer),
+            len,
+            _element: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Untyped buffer, for `CommandEncoder::bind_vertex_buffers` and copies.
+    pub fn buffer(&self) -> &Arc<Buffer> {
+        &self.buffer
+    }
+
+    /// Copies `instances` into the buffer starting at instance `first`.
+    /// The buffer must be host visible and the GPU must not be using the written instances.
+    pub fn write(&self, first: usize, instances: &[T]) {
+        self.buffer
+            .write((first * mem::size_of::<T>()) as u64, instances);
+    }
+}
@@ -1,111 +1,361 @@
-use std::{ffi::CString, rc::Rc};
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
 
 use ash::vk;
 
-use super::{Renderer, Shader};
+use super::{
+    descriptor::PipelineDescriptors,
+    specialization::{validate_specialization_values, SpecializationData},
+    AttachmentFormats, Buffer, Renderer, Shader, ShaderStage, StorageBuffer, StorageImage,
+    VertexLayout,
+};
 use tempura_render as tr;
 
 pub struct Material {
-    shader: Rc<Shader>,
-    pipeline_layout: vk::PipelineLayout,
-    pipeline: vk::Pipeline,
+    shader: Arc<Shader>,
+    keywords: Vec<String>,
+    pub(crate) pipeline: Arc<MaterialPipeline>,
+}
+
+/// Pipeline of a material, shared with its shader so that a shader reload can rebuild it.
+pub(crate) struct MaterialPipeline {
+    renderer: Arc<Renderer>,
+    specialization_constants: Vec<(String, tr::SpecializationValue)>,
+    vertex_layout: VertexLayout,
+    attachment_formats: AttachmentFormats,
+    pub(crate) pipeline_layout: Mutex<vk::PipelineLayout>,
+    pub(crate) pipeline: Mutex<vk::Pipeline>,
+    /// Ranges `pipeline_layout` was created with.
+    pub(crate) push_constant_ranges: Mutex<Vec<vk::PushConstantRange>>,
+    pub(crate) descriptors: Mutex<PipelineDescriptors>,
 }
 
 impl Material {
-    pub(crate) fn new(renderer: &Rc<Renderer>, shader: &Rc<Shader>) -> Self {
+    /// `keywords` are the ones `shader` was compiled with, when it is a variant.
+    /// Panics if a specialization constant isn't declared by the shader or has another type,
+    /// or if `vertex_layout` doesn't provide every input of the vertex shader.
+    /// The material renders to attachments of `attachment_formats`, with depth testing when
+    /// there is a depth attachment.
+    /// With tessellation stages, the material draws patches whose size is implied by the
+    /// tessellation mode: 3 vertices for `triangles`, 4 for `quads` and 2 for `isolines`.
+    pub(crate) fn new(
+        renderer: &Arc<Renderer>,
+        shader: &Arc<Shader>,
+        keywords: Vec<String>,
+        vertex_layout: VertexLayout,
+        attachment_formats: AttachmentFormats,
+        specialization_constants: &[(&str, tr::SpecializationValue)],
+    ) -> Self {
+        let specialization_constants = specialization_constants
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value))
+            .collect::<Vec<_>>();
+        let descriptors = create_descriptors(renderer, shader)
+            .unwrap_or_else(|e| panic!("Create material descriptors failed. {}", e));
+        let (pipeline_layout, pipeline) = create_pipeline(
+            renderer,
+            shader,
+            &descriptors.set_layouts,
+            &vertex_layout,
+            &attachment_formats,
+            &specialization_constants,
+        )
+        .unwrap_or_else(|e| panic!("create_graphics_pipeline failed. {}", e));
+        let pipeline = Arc::new(MaterialPipeline {
+            renderer: renderer.clone(),
+            specialization_constants,
+            vertex_layout,
+            attachment_formats,
+            pipeline_layout: Mutex::new(pipeline_layout),
+            pipeline: Mutex::new(pipeline),
+            push_constant_ranges: Mutex::new(shader.push_constant_ranges()),
+            descriptors: Mutex::new(descriptors),
+        });
+        shader.add_material_pipeline(&pipeline);
+
+        Material {
+            shader: shader.clone(),
+            keywords,
+            pipeline,
+        }
+    }
+
+    /// Keywords of the shader variant the material uses.
+    pub fn keywords(&self) -> &[String] {
+        &self.keywords
+    }
+
+    /// Binds `buffer` to the storage or uniform buffer declared at `set` and `binding`
+    /// by any stage. The material must not be in use by a frame in flight.
+    /// Panics if the shader doesn't declare a buffer there.
+    pub fn bind_buffer(&self, set: u32, binding: u32, buffer: &Arc<Buffer>) {
+        self.pipeline
+            .descriptors
+            .lock()
+            .unwrap()
+            .bind_buffer(set, binding, buffer)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Same as `bind_buffer`, also checking that `T` matches the element stride of the
+    /// runtime array the storage buffer block ends with.
+    pub fn bind_storage_buffer<T: bytemuck::Pod>(
+        &self,
+        set: u32,
+        binding: u32,
+        buffer: &StorageBuffer<T>,
+    ) {
+        self.pipeline
+            .descriptors
+            .lock()
+            .unwrap()
+            .bind_storage_buffer(set, binding, buffer.buffer(), mem::size_of::<T>())
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Binds `image` to the storage image declared at `set` and `binding` by any stage.
+    /// The material must not be in use by a frame in flight.
+    /// Panics if the shader doesn't declare a storage image there.
+    pub fn bind_storage_image(&self, set: u32, binding: u32, image: &Arc<StorageImage>) {
+        self.pipeline
+            .descriptors
+            .lock()
+            .unwrap()
+            .bind_storage_image(set, binding, image)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+}
+
+impl MaterialPipeline {
+    /// Recreates the pipeline from the current stages of `shader`, binding the resources
+    /// again to the bindings the new stages still declare. Returns the resources that could
+    /// not be bound again.
+    /// The old pipeline is kept if it fails. The GPU must not be using the old pipeline.
+    pub(crate) fn rebuild(&self, shader: &Shader) -> Result<Vec<String>, String> {
+        let descriptors = create_descriptors(&self.renderer, shader)?;
+        let (pipeline_layout, pipeline) = create_pipeline(
+            &self.renderer,
+            shader,
+            &descriptors.set_layouts,
+            &self.vertex_layout,
+            &self.attachment_formats,
+            &self.specialization_constants,
+        )?;
+        let rebind_errors = descriptors.rebind_from(&self.descriptors.lock().unwrap());
+        self.destroy();
+        *self.pipeline_layout.lock().unwrap() = pipeline_layout;
+        *self.pipeline.lock().unwrap() = pipeline;
+        *self.push_constant_ranges.lock().unwrap() = shader.push_constant_ranges();
+        *self.descriptors.lock().unwrap() = descriptors;
+        Ok(rebind_errors)
+    }
+
+    fn destroy(&self) {
         unsafe {
-            let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
-                .flags(vk::PipelineLayoutCreateFlags::empty())
-                .set_layouts(&[])
-                .push_constant_ranges(&[])
-                .build();
-            let pipeline_layout = renderer
+            self.renderer
                 .device
-                .create_pipeline_layout(&pipeline_layout_create_info, None)
-                .expect("create_pipeline_layout failed.");
-
-            let vertex_shader_entry_point = shader.vertex_shader_reflect.get_entry_point_name();
-            let vertex_shader_entry_point = CString::new(vertex_shader_entry_point).unwrap();
-            let stages = vk::PipelineShaderStageCreateInfo::builder()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .module(shader.vertex_shader)
-                .name(vertex_shader_entry_point.as_c_str())
-                .build();
-            let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder().build();
-            let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
-                .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-                .primitive_restart_enable(false)
-                .build();
-            let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
-                .depth_clamp_enable(false)
-                .rasterizer_discard_enable(false)
-                .polygon_mode(vk::PolygonMode::FILL)
-                .line_width(1.0)
-                .cull_mode(vk::CullModeFlags::NONE)
-                .front_face(vk::FrontFace::CLOCKWISE)
-                .depth_bias_enable(false)
-                .depth_bias_constant_factor(0.0)
-                .depth_bias_clamp(0.0)
-                .depth_bias_slope_factor(0.0)
-                .build();
-            let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
-                .sample_shading_enable(false)
-                .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-                .min_sample_shading(1.0)
-                .sample_mask(&[])
-                .alpha_to_coverage_enable(false)
-                .alpha_to_one_enable(false)
-                .build();
-            let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-                .viewports(&[vk::Viewport::builder().width(800.0).height(600.0).build()])
-                .scissors(&[vk::Rect2D::default()])
-                .build();
-            let pipeline_color_blend_attachment_state =
+                .destroy_pipeline(*self.pipeline.lock().unwrap(), None);
+            self.renderer
+                .device
+                .destroy_pipeline_layout(*self.pipeline_layout.lock().unwrap(), None);
+        }
+    }
+}
+
+impl Drop for MaterialPipeline {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
+
+fn create_descriptors(
+    renderer: &Arc<Renderer>,
+    shader: &Shader,
+) -> Result<PipelineDescriptors, String> {
+    let modules = shader.modules.lock().unwrap();
+    let stages = modules
+        .stages
+        .iter()
+        .map(|stage| stage.descriptor_bindings.as_slice())
+        .collect::<Vec<_>>();
+    PipelineDescriptors::new(renderer, &stages)
+}
+
+fn create_pipeline(
+    renderer: &Renderer,
+    shader: &Shader,
+    set_layouts: &[vk::DescriptorSetLayout],
+    vertex_layout: &VertexLayout,
+    attachment_formats: &AttachmentFormats,
+    specialization_constants: &[(String, tr::SpecializationValue)],
+) -> Result<(vk::PipelineLayout, vk::Pipeline), String> {
+    let modules = shader.modules.lock().unwrap();
+    if let Some(vertex) = modules.stage(ShaderStage::Vertex) {
+        vertex_layout.validate(&vertex.input_locations)?;
+    }
+    validate_specialization_values(
+        &modules
+            .stages
+            .iter()
+            .map(|stage| stage.specialization_constants.as_slice())
+            .collect::<Vec<_>>(),
+        specialization_constants,
+    )?;
+    let specializations = modules
+        .stages
+        .iter()
+        .map(|stage| {
+            SpecializationData::new(&stage.specialization_constants, specialization_constants)
+        })
+        .collect::<Vec<_>>();
+    let specialization_infos = specializations
+        .iter()
+        .map(SpecializationData::info)
+        .collect::<Vec<_>>();
+    let push_constant_ranges = modules.push_constant_ranges();
+    let render_pass = if renderer.dynamic_rendering {
+        vk::RenderPass::null()
+    } else {
+        renderer
+            .compatible_render_pass(attachment_formats)
+            .map_err(|r| r.to_string())?
+    };
+    unsafe {
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .flags(vk::PipelineLayoutCreateFlags::empty())
+            .set_layouts(set_layouts)
+            .push_constant_ranges(&push_constant_ranges)
+            .build();
+        let pipeline_layout = renderer
+            .device
+            .create_pipeline_layout(&pipeline_layout_create_info, None)
+            .map_err(|r| r.to_string())?;
+
+        let stages = modules
+            .stages
+            .iter()
+            .zip(&specialization_infos)
+            .map(|(stage, specialization_info)| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(stage.stage.vk_flags())
+                    .module(stage.module)
+                    .name(stage.entry_point.as_c_str())
+                    .specialization_info(specialization_info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let patch_control_points = modules.patch_control_points();
+        let topology = if patch_control_points.is_some() {
+            vk::PrimitiveTopology::PATCH_LIST
+        } else {
+            vk::PrimitiveTopology::TRIANGLE_LIST
+        };
+        let tessellation_state = vk::PipelineTessellationStateCreateInfo::builder()
+            .patch_control_points(patch_control_points.unwrap_or(0))
+            .build();
+        let vertex_binding_descriptions = vertex_layout.binding_descriptions();
+        let vertex_attribute_descriptions = vertex_layout.attribute_descriptions();
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&vertex_binding_descriptions)
+            .vertex_attribute_descriptions(&vertex_attribute_descriptions)
+            .build();
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(topology)
+            .primitive_restart_enable(false)
+            .build();
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::CLOCKWISE)
+            .depth_bias_enable(false)
+            .depth_bias_constant_factor(0.0)
+            .depth_bias_clamp(0.0)
+            .depth_bias_slope_factor(0.0)
+            .build();
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+            .min_sample_shading(1.0)
+            .sample_mask(&[])
+            .alpha_to_coverage_enable(false)
+            .alpha_to_one_enable(false)
+            .build();
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(&[vk::Viewport::builder().width(800.0).height(600.0).build()])
+            .scissors(&[vk::Rect2D::default()])
+            .build();
+        let color_blend_attachment_states = attachment_formats
+            .color
+            .iter()
+            .map(|_| {
                 vk::PipelineColorBlendAttachmentState::builder()
                     .color_write_mask(vk::ColorComponentFlags::RGBA)
                     .blend_enable(false)
-                    .build();
-            let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
-                .logic_op_enable(false)
-                .logic_op(vk::LogicOp::COPY)
-                .attachments(&[pipeline_color_blend_attachment_state])
-                .build();
-
-            let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
-                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
-                .build();
-
-            let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
-                .stages(&[stages])
-                .vertex_input_state(&vertex_input_state)
-                .input_assembly_state(&input_assembly_state)
-                .viewport_state(&viewport_state)
-                .rasterization_state(&rasterization_state)
-                .multisample_state(&multisample_state)
-                .color_blend_state(&color_blend_state)
-                .layout(pipeline_layout)
-                .render_pass(vk::RenderPass::null())
-                .dynamic_state(&dynamic_state)
-                .subpass(0)
-                .build();
-            let pipeline = renderer
-                .device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-                .expect("create_graphics_pipeline failed.");
-
-            Material {
-                shader: shader.clone(),
-                pipeline: pipeline[0],
-                pipeline_layout,
-            }
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .logic_op(vk::LogicOp::COPY)
+            .attachments(&color_blend_attachment_states)
+            .build();
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(true)
+            .depth_write_enable(true)
+            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .build();
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(&attachment_formats.color)
+            .depth_attachment_format(attachment_formats.depth.unwrap_or_default())
+            .build();
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+            .build();
+
+        let mut pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .tessellation_state(&tessellation_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .dynamic_state(&dynamic_state)
+            .subpass(0);
+        if attachment_formats.depth.is_some() {
+            pipeline_info = pipeline_info.depth_stencil_state(&depth_stencil_state);
         }
+        if renderer.dynamic_rendering {
+            pipeline_info = pipeline_info.push_next(&mut rendering_info);
+        }
+        let pipeline_info = pipeline_info.build();
+        let pipeline = renderer
+            .device
+            .create_graphics_pipelines(renderer.pipeline_cache, &[pipeline_info], None)
+            .map_err(|(_, r)| {
+                renderer
+                    .device
+                    .destroy_pipeline_layout(pipeline_layout, None);
+                r.to_string()
+            })?;
+
+        Ok((pipeline_layout, pipeline[0]))
     }
 }
 
 impl tr::Material for Material {
     type Shader = Shader;
 
-    fn shader(&self) -> std::rc::Rc<Self::Shader> {
+    fn shader(&self) -> Arc<Self::Shader> {
         self.shader.clone()
     }
 }
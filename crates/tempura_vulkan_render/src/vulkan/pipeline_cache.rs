@@ -0,0 +1,143 @@
+use std::{fs, io, path::Path};
+
+use ash::{prelude::VkResult, vk, Device};
+
+/// Size of `VkPipelineCacheHeaderVersionOne`.
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+pub(crate) fn create_pipeline_cache(
+    device: &Device,
+    initial_data: &[u8],
+) -> VkResult<vk::PipelineCache> {
+    unsafe {
+        let create_info = vk::PipelineCacheCreateInfo::builder()
+            .initial_data(initial_data)
+            .build();
+        device.create_pipeline_cache(&create_info, None)
+    }
+}
+
+/// Reads a pipeline cache file, rejecting data written by another device or driver version.
+pub(crate) fn read_pipeline_cache_file(
+    path: &Path,
+    properties: &vk::PhysicalDeviceProperties,
+) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if !is_compatible_header(&data, properties) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "pipeline cache was created by another device or driver",
+        ));
+    }
+    Ok(data)
+}
+
+pub(crate) fn write_pipeline_cache_file(
+    device: &Device,
+    pipeline_cache: vk::PipelineCache,
+    path: &Path,
+) -> io::Result<()> {
+    let data = unsafe {
+        device
+            .get_pipeline_cache_data(pipeline_cache)
+            .map_err(io::Error::other)?
+    };
+    fs::write(path, data)
+}
+
+fn is_compatible_header(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+    let read_u32 = |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+    let header_size = read_u32(0);
+    let header_version = read_u32(4);
+    let vendor_id = read_u32(8);
+    let device_id = read_u32(12);
+    let uuid = &data[16..HEADER_SIZE];
+
+    (HEADER_SIZE..=data.len()).contains(&(header_size as usize))
+        && header_version == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && vendor_id == properties.vendor_id
+        && device_id == properties.device_id
+        && uuid == properties.pipeline_cache_uuid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties() -> vk::PhysicalDeviceProperties {
+        vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2684,
+            pipeline_cache_uuid: [7; vk::UUID_SIZE],
+            ..Default::default()
+        }
+    }
+
+    fn header(properties: &vk::PhysicalDeviceProperties) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend((HEADER_SIZE as u32).to_ne_bytes());
+        data.extend((vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_ne_bytes());
+        data.extend(properties.vendor_id.to_ne_bytes());
+        data.extend(properties.device_id.to_ne_bytes());
+        data.extend(properties.pipeline_cache_uuid);
+        // Driver data after the header.
+        data.extend([1, 2, 3, 4]);
+        data
+    }
+
+    #[test]
+    fn accepts_headers_of_the_device() {
+        let properties = properties();
+        assert!(is_compatible_header(&header(&properties), &properties));
+        assert!(is_compatible_header(
+            &header(&properties)[..HEADER_SIZE],
+            &properties
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        let properties = properties();
+        let data = header(&properties);
+        assert!(!is_compatible_header(&[], &properties));
+        assert!(!is_compatible_header(&data[..8], &properties));
+        assert!(!is_compatible_header(&data[..HEADER_SIZE - 1], &properties));
+    }
+
+    #[test]
+    fn rejects_wrong_header_lengths_and_versions() {
+        let properties = properties();
+        let mut data = header(&properties);
+        data[..4].copy_from_slice(&(HEADER_SIZE as u32 - 4).to_ne_bytes());
+        assert!(!is_compatible_header(&data, &properties));
+        let past_the_data = data.len() as u32 + 1;
+        data[..4].copy_from_slice(&past_the_data.to_ne_bytes());
+        assert!(!is_compatible_header(&data, &properties));
+
+        let mut data = header(&properties);
+        data[4..8].copy_from_slice(&2u32.to_ne_bytes());
+        assert!(!is_compatible_header(&data, &properties));
+    }
+
+    #[test]
+    fn rejects_headers_of_other_devices_and_drivers() {
+        let properties = properties();
+        let data = header(&properties);
+        let other_vendor = vk::PhysicalDeviceProperties {
+            vendor_id: 0x1002,
+            ..properties
+        };
+        let other_device = vk::PhysicalDeviceProperties {
+            device_id: 0x2704,
+            ..properties
+        };
+        let mut other_driver = properties;
+        other_driver.pipeline_cache_uuid[15] = 8;
+        assert!(!is_compatible_header(&data, &other_vendor));
+        assert!(!is_compatible_header(&data, &other_device));
+        assert!(!is_compatible_header(&data, &other_driver));
+    }
+}
@@ -0,0 +1,504 @@
+use std::sync::Mutex;
+
+use ash::{prelude::VkResult, vk, Device};
+
+/// Maximum number of scopes of each query type per frame.
+const MAX_QUERY_SCOPES: u32 = 64;
+
+/// Statistics counted by `pipeline_statistics_query` scopes, in the order Vulkan writes them.
+const PIPELINE_STATISTIC_FLAGS: vk::QueryPipelineStatisticFlags =
+    vk::QueryPipelineStatisticFlags::from_raw(
+        vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+            | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+            | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+            | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw(),
+    );
+
+/// Resolved GPU duration of one `gpu_timer` scope.
+#[derive(Clone, Debug)]
+pub struct GpuTiming {
+    pub name: String,
+    pub milliseconds: f64,
+}
+
+/// Resolved counters of one `pipeline_statistics_query` scope.
+#[derive(Clone, Debug)]
+pub struct PipelineStatistics {
+    pub name: String,
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+/// Resolved result of one `occlusion_query` scope.
+#[derive(Clone, Debug)]
+pub struct OcclusionResult {
+    pub name: String,
+    pub samples_passed: u64,
+}
+
+/// Query support of the picked device and its graphics queue.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct QueryCapabilities {
+    pub(crate) timestamp_valid_bits: u32,
+    pub(crate) timestamp_period: f32,
+    pub(crate) pipeline_statistics_query: bool,
+    pub(crate) occlusion_query_precise: bool,
+}
+
+/// Names of the scopes of one query type recorded in a frame, in recording order.
+#[derive(Default)]
+struct QueryScopes {
+    names: Vec<String>,
+    /// Whether scopes are begun and ended queries, which Vulkan doesn't allow to nest within
+    /// the same query type, unlike timestamps.
+    exclusive: bool,
+    /// Scope of a begun query not ended yet.
+    open: Option<u32>,
+}
+
+impl QueryScopes {
+    fn new(exclusive: bool) -> Self {
+        QueryScopes {
+            exclusive,
+            ..Default::default()
+        }
+    }
+
+    /// Index of the new scope, or `None` if the frame has `MAX_QUERY_SCOPES` already.
+    /// Panics if the scopes are exclusive and another one is still open.
+    fn begin(&mut self, name: &str) -> Option<u32> {
+        if let Some(open) = self.open {
+            panic!(
+                "Query scope \"{}\" begins while \"{}\" of the same type is still open.",
+                name, self.names[open as usize]
+            );
+        }
+        if self.names.len() as u32 >= MAX_QUERY_SCOPES {
+            return None;
+        }
+        self.names.push(name.to_owned());
+        let scope = self.names.len() as u32 - 1;
+        if self.exclusive {
+            self.open = Some(scope);
+        }
+        Some(scope)
+    }
+
+    fn end(&mut self, scope: u32) {
+        if self.open == Some(scope) {
+            self.open = None;
+        }
+    }
+
+    /// Names of the scopes recorded since the last call.
+    fn take(&mut self) -> Vec<String> {
+        self.open = None;
+        std::mem::take(&mut self.names)
+    }
+}
+
+/// Query pool whose queries are handed out to named scopes, in recording order.
+/// An unsupported query type gets a null pool, and every scope of it is ignored.
+struct ScopedQueryPool {
+    pool: vk::QueryPool,
+    queries_per_scope: u32,
+    scopes: Mutex<QueryScopes>,
+}
+
+impl ScopedQueryPool {
+    fn new(
+        device: &Device,
+        supported: bool,
+        query_type: vk::QueryType,
+        pipeline_statistics: vk::QueryPipelineStatisticFlags,
+        queries_per_scope: u32,
+        exclusive: bool,
+    ) -> VkResult<Self> {
+        let pool = if supported {
+            let create_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(query_type)
+                .query_count(MAX_QUERY_SCOPES * queries_per_scope)
+                .pipeline_statistics(pipeline_statistics)
+                .build();
+            unsafe { device.create_query_pool(&create_info, None)? }
+        } else {
+            vk::QueryPool::null()
+        };
+
+        Ok(ScopedQueryPool {
+            pool,
+            queries_per_scope,
+            scopes: Mutex::new(QueryScopes::new(exclusive)),
+        })
+    }
+
+    /// Returns the first query index of the new scope.
+    fn allocate(&self, name: &str) -> Option<u32> {
+        if self.pool == vk::QueryPool::null() {
+            return None;
+        }
+        let scope = self.scopes.lock().unwrap().begin(name)?;
+        Some(scope * self.queries_per_scope)
+    }
+
+    /// Ends the scope of `query` from `allocate`.
+    fn end(&self, query: u32) {
+        self.scopes
+            .lock()
+            .unwrap()
+            .end(query / self.queries_per_scope);
+    }
+
+    /// Reads back the scopes recorded the last time this frame was used, without waiting.
+    /// `T` is the result layout of a single query.
+    fn take_results<T: Copy + Default>(&self, device: &Device) -> Option<(Vec<String>, Vec<T>)> {
+        let scopes = self.scopes.lock().unwrap().take();
+        if scopes.is_empty() {
+            return None;
+        }
+
+        let mut data = vec![T::default(); scopes.len() * self.queries_per_scope as usize];
+        unsafe {
+            device
+                .get_query_pool_results(
+                    self.pool,
+                    0,
+                    data.len() as u32,
+                    &mut data,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+                .ok()?;
+        }
+        Some((scopes, data))
+    }
+
+    fn reset(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        if self.pool == vk::QueryPool::null() {
+            return;
+        }
+        unsafe {
+            device.cmd_reset_query_pool(
+                command_buffer,
+                self.pool,
+                0,
+                MAX_QUERY_SCOPES * self.queries_per_scope,
+            );
+        }
+    }
+
+    fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_query_pool(self.pool, None);
+        }
+    }
+}
+
+/// Query pools of one frame in flight.
+pub(crate) struct FrameQueries {
+    timestamps: ScopedQueryPool,
+    pipeline_statistics: ScopedQueryPool,
+    occlusion: ScopedQueryPool,
+    timestamp_valid_mask: u64,
+    timestamp_period: f32,
+}
+
+impl FrameQueries {
+    pub(crate) fn new(device: &Device, capabilities: &QueryCapabilities) -> VkResult<Self> {
+        // Scope `i` writes its begin/end timestamps to queries `2 * i` and `2 * i + 1`.
+        let timestamps = ScopedQueryPool::new(
+            device,
+            capabilities.timestamp_valid_bits != 0,
+            vk::QueryType::TIMESTAMP,
+            vk::QueryPipelineStatisticFlags::empty(),
+            2,
+            false,
+        )?;
+        let pipeline_statistics = ScopedQueryPool::new(
+            device,
+            capabilities.pipeline_statistics_query,
+            vk::QueryType::PIPELINE_STATISTICS,
+            PIPELINE_STATISTIC_FLAGS,
+            1,
+            true,
+        )?;
+        let occlusion = ScopedQueryPool::new(
+            device,
+            true,
+            vk::QueryType::OCCLUSION,
+            vk::QueryPipelineStatisticFlags::empty(),
+            1,
+            true,
+        )?;
+        Ok(FrameQueries {
+            timestamps,
+            pipeline_statistics,
+            occlusion,
+            timestamp_valid_mask: timestamp_valid_mask(capabilities.timestamp_valid_bits),
+            timestamp_period: capabilities.timestamp_period,
+        })
+    }
+
+    /// Must be recorded outside of a render pass, before any scope.
+    pub(crate) fn reset(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        self.timestamps.reset(device, command_buffer);
+        self.pipeline_statistics.reset(device, command_buffer);
+        self.occlusion.reset(device, command_buffer);
+    }
+
+    /// Writes the begin timestamp and returns the query index of the scope.
+    pub(crate) fn begin_timestamp(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+    ) -> Option<u32> {
+        let query = self.timestamps.allocate(name)?;
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.timestamps.pool,
+                query,
+            );
+        }
+        Some(query)
+    }
+
+    pub(crate) fn end_timestamp(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        query: u32,
+    ) {
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.timestamps.pool,
+                query + 1,
+            );
+        }
+    }
+
+    /// Begins a pipeline statistics query and returns its pool and index.
+    /// Panics if another pipeline statistics query is still open.
+    pub(crate) fn begin_pipeline_statistics(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+    ) -> Option<(vk::QueryPool, u32)> {
+        let query = self.pipeline_statistics.allocate(name)?;
+        unsafe {
+            device.cmd_begin_query(
+                command_buffer,
+                self.pipeline_statistics.pool,
+                query,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+        Some((self.pipeline_statistics.pool, query))
+    }
+
+    /// Begins an occlusion query and returns its pool and index.
+    /// Panics if another occlusion query is still open.
+    pub(crate) fn begin_occlusion(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        name: &str,
+        flags: vk::QueryControlFlags,
+    ) -> Option<(vk::QueryPool, u32)> {
+        let query = self.occlusion.allocate(name)?;
+        unsafe {
+            device.cmd_begin_query(command_buffer, self.occlusion.pool, query, flags);
+        }
+        Some((self.occlusion.pool, query))
+    }
+
+    /// Ends a query from `begin_pipeline_statistics` or `begin_occlusion`.
+    pub(crate) fn end_query(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (pool, query): (vk::QueryPool, u32),
+    ) {
+        unsafe {
+            device.cmd_end_query(command_buffer, pool, query);
+        }
+        if pool == self.pipeline_statistics.pool {
+            self.pipeline_statistics.end(query);
+        } else {
+            self.occlusion.end(query);
+        }
+    }
+
+    /// The frame's submission must be complete.
+    pub(crate) fn resolve_timings(&self, device: &Device) -> Option<Vec<GpuTiming>> {
+        let (scopes, data) = self.timestamps.take_results::<u64>(device)?;
+        let timings = scopes
+            .into_iter()
+            .zip(data.chunks_exact(2))
+            .map(|(name, ticks)| GpuTiming {
+                name,
+                milliseconds: timestamp_milliseconds(
+                    ticks[0],
+                    ticks[1],
+                    self.timestamp_valid_mask,
+                    self.timestamp_period,
+                ),
+            })
+            .collect();
+        Some(timings)
+    }
+
+    /// The frame's submission must be complete.
+    pub(crate) fn resolve_pipeline_statistics(
+        &self,
+        device: &Device,
+    ) -> Option<Vec<PipelineStatistics>> {
+        let (scopes, data) = self.pipeline_statistics.take_results::<[u64; 7]>(device)?;
+        let statistics = scopes
+            .into_iter()
+            .zip(data)
+            .map(|(name, values)| PipelineStatistics {
+                name,
+                input_assembly_vertices: values[0],
+                input_assembly_primitives: values[1],
+                vertex_shader_invocations: values[2],
+                clipping_invocations: values[3],
+                clipping_primitives: values[4],
+                fragment_shader_invocations: values[5],
+                compute_shader_invocations: values[6],
+            })
+            .collect();
+        Some(statistics)
+    }
+
+    /// The frame's submission must be complete.
+    pub(crate) fn resolve_occlusion(&self, device: &Device) -> Option<Vec<OcclusionResult>> {
+        let (scopes, data) = self.occlusion.take_results::<u64>(device)?;
+        let results = scopes
+            .into_iter()
+            .zip(data)
+            .map(|(name, samples_passed)| OcclusionResult {
+                name,
+                samples_passed,
+            })
+            .collect();
+        Some(results)
+    }
+
+    pub(crate) fn destroy(&self, device: &Device) {
+        self.timestamps.destroy(device);
+        self.pipeline_statistics.destroy(device);
+        self.occlusion.destroy(device);
+    }
+}
+
+/// Mask of the bits a timestamp query writes, given `timestampValidBits`.
+fn timestamp_valid_mask(valid_bits: u32) -> u64 {
+    if valid_bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << valid_bits) - 1
+    }
+}
+
+/// Duration between two timestamps of `period` nanoseconds per tick. Timestamps wrap around
+/// at their valid bits, so `end` may be lower than `begin`.
+fn timestamp_milliseconds(begin: u64, end: u64, valid_mask: u64, period: f32) -> f64 {
+    let ticks = end.wrapping_sub(begin) & valid_mask;
+    ticks as f64 * period as f64 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_the_valid_bits_of_timestamps() {
+        assert_eq!(timestamp_valid_mask(0), 0);
+        assert_eq!(timestamp_valid_mask(36), 0xf_ffff_ffff);
+        assert_eq!(timestamp_valid_mask(64), u64::MAX);
+    }
+
+    #[test]
+    fn converts_ticks_to_milliseconds() {
+        assert_eq!(timestamp_milliseconds(1000, 3_001_000, u64::MAX, 1.0), 3.0);
+        assert_eq!(timestamp_milliseconds(0, 1_000_000, u64::MAX, 2.5), 2.5);
+        assert_eq!(timestamp_milliseconds(5, 5, u64::MAX, 1.0), 0.0);
+    }
+
+    #[test]
+    fn measures_timestamps_wrapping_around_their_valid_bits() {
+        let mask = timestamp_valid_mask(36);
+        assert_eq!(
+            timestamp_milliseconds(mask - 999_999, 1_000_000, mask, 1.0),
+            2.0
+        );
+        assert_eq!(
+            timestamp_milliseconds(u64::MAX, 999_999, u64::MAX, 1.0),
+            1.0
+        );
+    }
+
+    #[test]
+    fn numbers_scopes_in_recording_order() {
+        let mut scopes = QueryScopes::default();
+        assert_eq!(scopes.begin("shadows"), Some(0));
+        assert_eq!(scopes.begin("main"), Some(1));
+        assert_eq!(scopes.take(), ["shadows", "main"]);
+
+        // The next use of the frame numbers its scopes from 0 again.
+        assert!(scopes.take().is_empty());
+        assert_eq!(scopes.begin("main"), Some(0));
+    }
+
+    #[test]
+    fn allows_nested_timestamp_scopes() {
+        let mut scopes = QueryScopes::new(false);
+        assert_eq!(scopes.begin("frame"), Some(0));
+        assert_eq!(scopes.begin("shadows"), Some(1));
+    }
+
+    #[test]
+    fn allows_consecutive_exclusive_scopes() {
+        let mut scopes = QueryScopes::new(true);
+        assert_eq!(scopes.begin("opaque"), Some(0));
+        scopes.end(0);
+        assert_eq!(scopes.begin("transparent"), Some(1));
+        scopes.end(1);
+        assert_eq!(scopes.take(), ["opaque", "transparent"]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Query scope \"transparent\" begins while \"opaque\" of the same type is still open."
+    )]
+    fn rejects_nested_exclusive_scopes() {
+        let mut scopes = QueryScopes::new(true);
+        scopes.begin("opaque");
+        scopes.begin("transparent");
+    }
+
+    #[test]
+    fn ignores_scopes_beyond_the_maximum() {
+        let mut scopes = QueryScopes::new(true);
+        for scope in 0..MAX_QUERY_SCOPES {
+            assert_eq!(scopes.begin("pass"), Some(scope));
+            scopes.end(scope);
+        }
+        assert_eq!(scopes.begin("extra"), None);
+        assert_eq!(scopes.take().len(), MAX_QUERY_SCOPES as usize);
+        assert_eq!(scopes.begin("pass"), Some(0));
+    }
+}
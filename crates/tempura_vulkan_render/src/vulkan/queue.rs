@@ -0,0 +1,395 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use ash::{prelude::VkResult, vk, Device};
+
+use super::{
+    resource_state::{AccessScope, TrackedResource},
+    synchronization::Synchronization,
+    timeline::SubmissionTimeline,
+};
+
+/// Queue that commands are submitted to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QueueType {
+    /// Queue of the frames and `Renderer::execute`.
+    Graphics,
+    /// Queue of `Renderer::submit_compute`, running alongside the graphics queue.
+    Compute,
+    /// Queue of `Renderer::submit_transfer`, for uploads and copies.
+    Transfer,
+}
+
+/// Submission to a queue, numbered from 1 in the order of that queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueSubmission {
+    pub queue: QueueType,
+    pub value: u64,
+}
+
+/// Queue families of the device: the graphics family, and the families dedicated to compute
+/// and to transfers, if the device has them.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct QueueFamilies {
+    pub(crate) graphics: u32,
+    pub(crate) compute: Option<u32>,
+    pub(crate) transfer: Option<u32>,
+}
+
+impl QueueFamilies {
+    /// Picks the first graphics family, the first compute family without graphics and the
+    /// first transfer family with neither, from the `properties` of the device's families.
+    pub(crate) fn select(properties: &[vk::QueueFamilyProperties]) -> Option<QueueFamilies> {
+        let find = |include: vk::QueueFlags, exclude: vk::QueueFlags| {
+            properties
+                .iter()
+                .position(|prop| {
+                    prop.queue_flags.contains(include) && !prop.queue_flags.intersects(exclude)
+                })
+                .map(|index| index as u32)
+        };
+        Some(QueueFamilies {
+            graphics: find(vk::QueueFlags::GRAPHICS, vk::QueueFlags::empty())?,
+            compute: find(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS),
+            transfer: find(
+                vk::QueueFlags::TRANSFER,
+                vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+            ),
+        })
+    }
+
+    /// Distinct families, which get one queue each.
+    pub(crate) fn unique(&self) -> Vec<u32> {
+        let mut families = vec![self.graphics];
+        families.extend(self.compute);
+        families.extend(self.transfer);
+        families.dedup();
+        families
+    }
+}
+
+/// Compute or transfer queue with its own command pool and submissions.
+/// Without a dedicated family, it submits to the graphics queue.
+pub(crate) struct AsyncQueue {
+    pub(crate) family_index: u32,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    pub(crate) timeline: SubmissionTimeline,
+    /// Submitted command buffers, reused once their submission completes.
+    command_buffers: Mutex<VecDeque<(u64, vk::CommandBuffer)>>,
+    /// Held from `begin` to `submit`, as the command pool is used by one thread at a time.
+    pub(crate) recording: Mutex<()>,
+}
+
+impl AsyncQueue {
+    pub(crate) fn new(
+        device: &Device,
+        family_index: u32,
+        timeline_semaphore: bool,
+    ) -> VkResult<Self> {
+        unsafe {
+            let queue = device.get_device_queue(family_index, 0);
+            let create_info = vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(family_index)
+                .build();
+            let command_pool = device.create_command_pool(&create_info, None)?;
+            let timeline = match SubmissionTimeline::new(device, timeline_semaphore) {
+                Ok(timeline) => timeline,
+                Err(e) => {
+                    device.destroy_command_pool(command_pool, None);
+                    return Err(e);
+                }
+            };
+            Ok(AsyncQueue {
+                family_index,
+                queue,
+                command_pool,
+                timeline,
+                command_buffers: Mutex::new(VecDeque::new()),
+                recording: Mutex::new(()),
+            })
+        }
+    }
+
+    /// Begins a command buffer, reusing the oldest one if its submission is complete.
+    pub(crate) fn begin(&self, device: &Device) -> VkResult<vk::CommandBuffer> {
+        let completed = self.timeline.completed(device);
+        let reusable = {
+            let mut command_buffers = self.command_buffers.lock().unwrap();
+            match command_buffers.front() {
+                Some(&(submission, _)) if submission <= completed => command_buffers.pop_front(),
+                _ => None,
+            }
+        };
+        unsafe {
+            let command_buffer = match reusable {
+                Some((_, command_buffer)) => {
+                    device.reset_command_buffer(
+                        command_buffer,
+                        vk::CommandBufferResetFlags::RELEASE_RESOURCES,
+                    )?;
+                    command_buffer
+                }
+                None => {
+                    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                        .command_buffer_count(1)
+                        .command_pool(self.command_pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .build();
+                    device.allocate_command_buffers(&allocate_info)?[0]
+                }
+            };
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build();
+            device.begin_command_buffer(command_buffer, &begin_info)?;
+            Ok(command_buffer)
+        }
+    }
+
+    /// Ends and submits a command buffer from `begin`, returning the value of the submission.
+    pub(crate) fn submit(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        command_buffer: vk::CommandBuffer,
+        wait_semaphores: &[vk::SemaphoreSubmitInfo],
+    ) -> VkResult<u64> {
+        unsafe {
+            device.end_command_buffer(command_buffer)?;
+        }
+        let submission = self.timeline.submit(
+            device,
+            synchronization,
+            self.queue,
+            command_buffer,
+            wait_semaphores,
+            &[],
+        )?;
+        self.command_buffers
+            .lock()
+            .unwrap()
+            .push_back((submission, command_buffer));
+        Ok(submission)
+    }
+
+    /// The submissions must be completed. The command buffers are freed with the pool.
+    pub(crate) fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_command_pool(self.command_pool, None);
+        }
+        self.timeline.destroy(device);
+    }
+}
+
+/// Resource released by one queue, to be acquired by the next command buffer of another queue
+/// once the releasing submission is submitted.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PendingAcquire {
+    pub(crate) resource: TrackedResource,
+    pub(crate) src_queue: QueueType,
+    pub(crate) dst_queue: QueueType,
+    /// Value of the releasing submission on `src_queue`.
+    pub(crate) submission: u64,
+    /// Families of an ownership transfer, which the acquire barrier repeats. `None` if the
+    /// queues share a family, where waiting for the submission is enough.
+    pub(crate) families: Option<(u32, u32)>,
+}
+
+/// Takes the acquires of `pending` that the next command buffer of `queue` records: those
+/// whose releasing submission was submitted, as `last_submitted` tells for each queue.
+pub(crate) fn take_acquires<F>(
+    pending: &mut Vec<PendingAcquire>,
+    queue: QueueType,
+    last_submitted: F,
+) -> Vec<PendingAcquire>
+where
+    F: Fn(QueueType) -> u64,
+{
+    let (acquires, rest) = pending.drain(..).partition(|acquire: &PendingAcquire| {
+        acquire.dst_queue == queue && acquire.submission <= last_submitted(acquire.src_queue)
+    });
+    *pending = rest;
+    acquires
+}
+
+/// Last releasing submission of each queue in `acquires`, which the acquiring submission
+/// waits for.
+pub(crate) fn acquire_waits(acquires: &[PendingAcquire]) -> HashMap<QueueType, u64> {
+    let mut waits = HashMap::<QueueType, u64>::new();
+    for acquire in acquires {
+        let value = waits.entry(acquire.src_queue).or_default();
+        *value = (*value).max(acquire.submission);
+    }
+    waits
+}
+
+/// Records one side of an ownership transfer of `resource` between `families`: the release
+/// with the `src` accesses, or the acquire with the `dst` ones.
+/// Tracked images are storage images, which stay in the `GENERAL` layout.
+pub(crate) fn record_ownership_transfer(
+    synchronization: &Synchronization,
+    command_buffer: vk::CommandBuffer,
+    resource: TrackedResource,
+    families: (u32, u32),
+    src: AccessScope,
+    dst: AccessScope,
+) {
+    match resource {
+        TrackedResource::Buffer(buffer) => {
+            let buffer_barrier = vk::BufferMemoryBarrier2::builder()
+                .src_stage_mask(src.stages)
+                .src_access_mask(src.access)
+                .dst_stage_mask(dst.stages)
+                .dst_access_mask(dst.access)
+                .src_queue_family_index(families.0)
+                .dst_queue_family_index(families.1)
+                .buffer(buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build();
+            synchronization.pipeline_barrier(command_buffer, &[], &[buffer_barrier], &[]);
+        }
+        TrackedResource::Image(image) => {
+            let image_barrier = vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(src.stages)
+                .src_access_mask(src.access)
+                .dst_stage_mask(dst.stages)
+                .dst_access_mask(dst.access)
+                .old_layout(vk::ImageLayout::GENERAL)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(families.0)
+                .dst_queue_family_index(families.1)
+                .image(image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: vk::REMAINING_MIP_LEVELS,
+                    base_array_layer: 0,
+                    layer_count: vk::REMAINING_ARRAY_LAYERS,
+                })
+                .build();
+            synchronization.pipeline_barrier(command_buffer, &[], &[], &[image_barrier]);
+        }
+    }
+}
+
+/// Records the acquires of resources transferred to the queue of `command_buffer`.
+/// Later commands need no barrier, as the acquires cover all of them.
+pub(crate) fn record_acquires(
+    synchronization: &Synchronization,
+    command_buffer: vk::CommandBuffer,
+    acquires: &[PendingAcquire],
+) {
+    let dst = AccessScope {
+        stages: vk::PipelineStageFlags2::ALL_COMMANDS,
+        access: vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+    };
+    for acquire in acquires {
+        if let Some(families) = acquire.families {
+            record_ownership_transfer(
+                synchronization,
+                command_buffer,
+                acquire.resource,
+                families,
+                AccessScope::default(),
+                dst,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    fn family(queue_flags: vk::QueueFlags) -> vk::QueueFamilyProperties {
+        vk::QueueFamilyProperties {
+            queue_flags,
+            queue_count: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn selects_dedicated_compute_and_transfer_families() {
+        let all = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let families = QueueFamilies::select(&[
+            family(vk::QueueFlags::TRANSFER),
+            family(all),
+            family(vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER),
+        ])
+        .unwrap();
+        assert_eq!(
+            (families.graphics, families.compute, families.transfer),
+            (1, Some(2), Some(0))
+        );
+        assert_eq!(families.unique(), [1, 2, 0]);
+
+        // A device with one family submits everything to it.
+        let families = QueueFamilies::select(&[family(all)]).unwrap();
+        assert_eq!((families.compute, families.transfer), (None, None));
+        assert_eq!(families.unique(), [0]);
+
+        assert!(QueueFamilies::select(&[family(vk::QueueFlags::COMPUTE)]).is_none());
+    }
+
+    fn acquire(src_queue: QueueType, dst_queue: QueueType, submission: u64) -> PendingAcquire {
+        PendingAcquire {
+            resource: TrackedResource::Buffer(vk::Buffer::from_raw(submission)),
+            src_queue,
+            dst_queue,
+            submission,
+            families: None,
+        }
+    }
+
+    #[test]
+    fn takes_the_acquires_of_submitted_releases_to_a_queue() {
+        let mut pending = vec![
+            acquire(QueueType::Transfer, QueueType::Graphics, 1),
+            acquire(QueueType::Compute, QueueType::Graphics, 3),
+            acquire(QueueType::Transfer, QueueType::Compute, 2),
+            acquire(QueueType::Transfer, QueueType::Graphics, 4),
+        ];
+        let last_submitted = |queue| match queue {
+            QueueType::Transfer => 2,
+            _ => 3,
+        };
+
+        let acquires = take_acquires(&mut pending, QueueType::Graphics, last_submitted);
+        let submissions = |acquires: &[PendingAcquire]| {
+            acquires
+                .iter()
+                .map(|acquire| acquire.submission)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(submissions(&acquires), [1, 3]);
+        // The release of the fourth submission isn't submitted yet, and the third goes to
+        // another queue.
+        assert_eq!(submissions(&pending), [2, 4]);
+        assert_eq!(
+            acquire_waits(&acquires),
+            HashMap::from([(QueueType::Transfer, 1), (QueueType::Compute, 3)])
+        );
+    }
+
+    #[test]
+    fn waits_for_the_last_release_of_each_queue() {
+        let acquires = [
+            acquire(QueueType::Transfer, QueueType::Graphics, 5),
+            acquire(QueueType::Transfer, QueueType::Graphics, 2),
+            acquire(QueueType::Compute, QueueType::Graphics, 1),
+        ];
+        assert_eq!(
+            acquire_waits(&acquires),
+            HashMap::from([(QueueType::Transfer, 5), (QueueType::Compute, 1)])
+        );
+        assert!(acquire_waits(&[]).is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    any::Any,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use super::{Buffer, ComputePipeline, Material, Sampler, Shader, StorageImage};
+
+/// Resource that can be registered with `Renderer::register` and referenced by `Handle`.
+pub trait RegisteredResource: Any + Send + Sync {
+    /// Kind of the resource in leak reports.
+    const KIND: &'static str;
+}
+
+impl RegisteredResource for Buffer {
+    const KIND: &'static str = "buffer";
+}
+
+impl RegisteredResource for StorageImage {
+    const KIND: &'static str = "storage image";
+}
+
+impl RegisteredResource for Sampler {
+    const KIND: &'static str = "sampler";
+}
+
+impl RegisteredResource for Shader {
+    const KIND: &'static str = "shader";
+}
+
+impl RegisteredResource for Material {
+    const KIND: &'static str = "material";
+}
+
+impl RegisteredResource for ComputePipeline {
+    const KIND: &'static str = "compute pipeline";
+}
+
+/// Typed reference to a resource registered with `Renderer::register`, cheap to copy into
+/// plain data such as ECS components.
+/// The slot of an unregistered resource is reused with a new generation, so that stale
+/// handles resolve to nothing rather than to the new resource.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _resource: PhantomData<fn() -> T>,
+}
+
+pub type BufferHandle = Handle<Buffer>;
+pub type StorageImageHandle = Handle<StorageImage>;
+pub type ShaderHandle = Handle<Shader>;
+pub type MaterialHandle = Handle<Material>;
+
+impl<T> Handle<T> {
+    fn new(index: u32, generation: u32) -> Self {
+        Handle {
+            index,
+            generation,
+            _resource: PhantomData,
+        }
+    }
+
+    /// The handle packed into 64 bits, e.g. to serialize it with a scene.
+    pub fn to_raw(self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
+    }
+
+    /// Handle packed by `to_raw`. It resolves to nothing if it doesn't refer to a registered
+    /// resource of `T`.
+    pub fn from_raw(raw: u64) -> Self {
+        Handle::new(raw as u32, (raw >> 32) as u32)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_raw().hash(state);
+    }
+}
+
+impl<T: RegisteredResource> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle<{}>({}v{})", T::KIND, self.index, self.generation)
+    }
+}
+
+/// Resource still registered when `Renderer::unregister_all` ran.
+#[derive(Clone, Debug)]
+pub struct ResourceLeak {
+    pub kind: &'static str,
+    pub name: String,
+    pub index: u32,
+    pub generation: u32,
+}
+
+impl fmt::Display for ResourceLeak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} \"{}\" ({}v{}) was not unregistered.",
+            self.kind, self.name, self.index, self.generation
+        )
+    }
+}
+
+struct Entry {
+    resource: Arc<dyn Any + Send + Sync>,
+    kind: &'static str,
+    name: String,
+}
+
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+/// Slots of the registered resources of every kind. Handles are typed, so a slot holds any
+/// resource and lookups downcast it.
+#[derive(Default)]
+pub(crate) struct ResourceRegistry {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+impl ResourceRegistry {
+    pub(crate) fn insert<T: RegisteredResource>(
+        &mut self,
+        name: &str,
+        resource: Arc<T>,
+    ) -> Handle<T> {
+        let entry = Entry {
+            resource,
+            kind: T::KIND,
+            name: name.to_owned(),
+        };
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.entry = Some(entry);
+                Handle::new(index, slot.generation)
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entry: Some(entry),
+                });
+                Handle::new(self.slots.len() as u32 - 1, 0)
+            }
+        }
+    }
+
+    fn entry<T>(&self, handle: Handle<T>) -> Option<&Entry> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.entry.as_ref())
+    }
+
+    pub(crate) fn get<T: RegisteredResource>(&self, handle: Handle<T>) -> Option<Arc<T>> {
+        self.entry(handle)?.resource.clone().downcast::<T>().ok()
+    }
+
+    pub(crate) fn remove<T: RegisteredResource>(&mut self, handle: Handle<T>) -> Option<Arc<T>> {
+        let resource = self.get(handle)?;
+        let slot = &mut self.slots[handle.index as usize];
+        slot.entry = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        Some(resource)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// Removes every resource, returning them with their leak reports.
+    pub(crate) fn drain(&mut self) -> Vec<(ResourceLeak, Arc<dyn Any + Send + Sync>)> {
+        let mut drained = Vec::new();
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if let Some(entry) = slot.entry.take() {
+                drained.push((
+                    ResourceLeak {
+                        kind: entry.kind,
+                        name: entry.name,
+                        index: index as u32,
+                        generation: slot.generation,
+                    },
+                    entry.resource,
+                ));
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index as u32);
+            }
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestResource(u32);
+
+    impl RegisteredResource for TestResource {
+        const KIND: &'static str = "test resource";
+    }
+
+    struct OtherResource;
+
+    impl RegisteredResource for OtherResource {
+        const KIND: &'static str = "other resource";
+    }
+
+    #[test]
+    fn rejects_stale_handles() {
+        let mut registry = ResourceRegistry::default();
+        let handle = registry.insert("a", Arc::new(TestResource(1)));
+        assert_eq!(registry.get(handle).map(|resource| resource.0), Some(1));
+        assert!(registry.remove(handle).is_some());
+
+        assert!(registry.get(handle).is_none());
+        assert!(registry.remove(handle).is_none());
+        assert!(registry
+            .get(Handle::<TestResource>::from_raw(u64::MAX))
+            .is_none());
+        assert_eq!(registry.len(), 0);
+    }
+
+    #[test]
+    fn bumps_the_generation_of_reused_slots() {
+        let mut registry = ResourceRegistry::default();
+        let first = registry.insert("a", Arc::new(TestResource(1)));
+        registry.remove(first);
+        let second = registry.insert("b", Arc::new(TestResource(2)));
+
+        assert_eq!(second.index, first.index);
+        assert_eq!(second.generation, first.generation + 1);
+        assert_ne!(second, first);
+        assert!(registry.get(first).is_none());
+        assert_eq!(registry.get(second).map(|resource| resource.0), Some(2));
+        assert_eq!(Handle::<TestResource>::from_raw(second.to_raw()), second);
+    }
+
+    #[test]
+    fn rejects_handles_of_another_kind() {
+        let mut registry = ResourceRegistry::default();
+        let handle = registry.insert("a", Arc::new(TestResource(1)));
+        let other = Handle::<OtherResource>::from_raw(handle.to_raw());
+
+        assert!(registry.get(other).is_none());
+        assert!(registry.remove(other).is_none());
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn reports_the_resources_left_registered() {
+        let mut registry = ResourceRegistry::default();
+        let removed = registry.insert("removed", Arc::new(TestResource(1)));
+        let leaked = registry.insert("leaked", Arc::new(TestResource(2)));
+        registry.insert("other", Arc::new(OtherResource));
+        registry.remove(removed);
+
+        let leaks = registry.drain();
+        assert_eq!(leaks.len(), 2);
+        let (leak, resource) = &leaks[0];
+        assert_eq!(
+            (leak.kind, leak.name.as_str(), leak.index, leak.generation),
+            ("test resource", "leaked", leaked.index, leaked.generation)
+        );
+        assert!(resource.clone().downcast::<TestResource>().is_ok());
+        assert_eq!(
+            leak.to_string(),
+            format!(
+                "test resource \"leaked\" ({}v{}) was not unregistered.",
+                leaked.index, leaked.generation
+            )
+        );
+        assert_eq!(leaks[1].0.kind, "other resource");
+
+        assert_eq!(registry.len(), 0);
+        assert!(registry.get(leaked).is_none());
+        assert!(registry.drain().is_empty());
+    }
+}
@@ -0,0 +1,1135 @@
+use ash::{prelude::VkResult, vk, Device};
+use tempura_render as tr;
+
+use super::{
+    buffer::allocate_memory,
+    rendering::{begin_rendering, create_render_pass, set_viewport, PassAttachment},
+    resource_state::{
+        record_transitions, AccessScope, ResourceStates, ResourceUse, TrackedResource, Transition,
+    },
+    CommandEncoder, MemoryLocation, Renderer, VulkanSwapchain,
+};
+
+pub(crate) fn vk_format(format: tr::TextureFormat) -> vk::Format {
+    match format {
+        tr::TextureFormat::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+        tr::TextureFormat::Rgba8Srgb => vk::Format::R8G8B8A8_SRGB,
+        tr::TextureFormat::Bgra8Unorm => vk::Format::B8G8R8A8_UNORM,
+        tr::TextureFormat::Bgra8Srgb => vk::Format::B8G8R8A8_SRGB,
+        tr::TextureFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+        tr::TextureFormat::Rgba32Float => vk::Format::R32G32B32A32_SFLOAT,
+        tr::TextureFormat::R32Float => vk::Format::R32_SFLOAT,
+        tr::TextureFormat::Depth32Float => vk::Format::D32_SFLOAT,
+    }
+}
+
+pub(crate) fn texture_format(format: vk::Format) -> Option<tr::TextureFormat> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM => Some(tr::TextureFormat::Rgba8Unorm),
+        vk::Format::R8G8B8A8_SRGB => Some(tr::TextureFormat::Rgba8Srgb),
+        vk::Format::B8G8R8A8_UNORM => Some(tr::TextureFormat::Bgra8Unorm),
+        vk::Format::B8G8R8A8_SRGB => Some(tr::TextureFormat::Bgra8Srgb),
+        vk::Format::R16G16B16A16_SFLOAT => Some(tr::TextureFormat::Rgba16Float),
+        vk::Format::R32G32B32A32_SFLOAT => Some(tr::TextureFormat::Rgba32Float),
+        vk::Format::R32_SFLOAT => Some(tr::TextureFormat::R32Float),
+        vk::Format::D32_SFLOAT => Some(tr::TextureFormat::Depth32Float),
+        _ => None,
+    }
+}
+
+fn aspect_mask(format: tr::TextureFormat) -> vk::ImageAspectFlags {
+    if format.is_depth() {
+        vk::ImageAspectFlags::DEPTH
+    } else {
+        vk::ImageAspectFlags::COLOR
+    }
+}
+
+fn image_usage(access: tr::TextureAccess) -> vk::ImageUsageFlags {
+    match access {
+        tr::TextureAccess::ColorAttachment => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        tr::TextureAccess::DepthStencilAttachment => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        tr::TextureAccess::Sampled => vk::ImageUsageFlags::SAMPLED,
+        tr::TextureAccess::Storage => vk::ImageUsageFlags::STORAGE,
+        tr::TextureAccess::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+        tr::TextureAccess::TransferDst => vk::ImageUsageFlags::TRANSFER_DST,
+    }
+}
+
+/// How a pass uses a texture with `access`, for the resource state tracker.
+pub(crate) fn texture_use(access: tr::TextureAccess) -> ResourceUse {
+    const SHADER_STAGES: vk::PipelineStageFlags2 = vk::PipelineStageFlags2::from_raw(
+        vk::PipelineStageFlags2::VERTEX_SHADER.as_raw()
+            | vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+            | vk::PipelineStageFlags2::COMPUTE_SHADER.as_raw(),
+    );
+    let (layout, stages, access, write) = match access {
+        tr::TextureAccess::ColorAttachment => (
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            true,
+        ),
+        tr::TextureAccess::DepthStencilAttachment => (
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            true,
+        ),
+        tr::TextureAccess::Sampled => (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            SHADER_STAGES,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+            false,
+        ),
+        tr::TextureAccess::Storage => (
+            vk::ImageLayout::GENERAL,
+            SHADER_STAGES,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            true,
+        ),
+        tr::TextureAccess::TransferSrc => (
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_READ,
+            false,
+        ),
+        tr::TextureAccess::TransferDst => (
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            true,
+        ),
+    };
+    ResourceUse {
+        layout,
+        scope: AccessScope { stages, access },
+        write,
+    }
+}
+
+/// Use of the swapchain image by the presentation engine, which waits for the semaphore the
+/// frame signals.
+pub(crate) fn present_use() -> ResourceUse {
+    ResourceUse {
+        layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        scope: AccessScope::default(),
+        write: false,
+    }
+}
+
+fn load_op(load_op: tr::LoadOp) -> vk::AttachmentLoadOp {
+    match load_op {
+        tr::LoadOp::Load => vk::AttachmentLoadOp::LOAD,
+        tr::LoadOp::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+        tr::LoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+    }
+}
+
+fn clear_value(load_op: tr::LoadOp) -> vk::ClearValue {
+    match load_op {
+        tr::LoadOp::Clear(tr::ClearValue::Color(color)) => vk::ClearValue {
+            color: vk::ClearColorValue { float32: color },
+        },
+        tr::LoadOp::Clear(tr::ClearValue::DepthStencil { depth, stencil }) => vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+        },
+        _ => vk::ClearValue::default(),
+    }
+}
+
+/// Image of a transient texture, bound to an offset of the memory it shares with the textures
+/// whose lifetimes don't overlap its own.
+struct TransientImage {
+    image: vk::Image,
+    view: vk::ImageView,
+    format: tr::TextureFormat,
+}
+
+fn create_image(
+    device: &Device,
+    desc: tr::TextureDesc,
+    usage: vk::ImageUsageFlags,
+) -> VkResult<vk::Image> {
+    let create_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(vk_format(desc.format))
+        .extent(vk::Extent3D {
+            width: desc.width,
+            height: desc.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .build();
+    unsafe { device.create_image(&create_info, None) }
+}
+
+/// Binds `memory` at `offset` to `image` and creates a view of the whole image.
+fn create_view(
+    device: &Device,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    offset: u64,
+    format: tr::TextureFormat,
+) -> VkResult<vk::ImageView> {
+    unsafe {
+        device.bind_image_memory(image, memory, offset)?;
+        let create_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(vk_format(format))
+            .subresource_range(subresource_range(format))
+            .build();
+        device.create_image_view(&create_info, None)
+    }
+}
+
+fn subresource_range(format: tr::TextureFormat) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect_mask(format))
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+/// Memory a transient texture needs, and the kept passes using it.
+#[derive(Clone, Copy, Debug)]
+struct MemoryRequest {
+    size: u64,
+    alignment: u64,
+    memory_type_bits: u32,
+    lifetime: (usize, usize),
+}
+
+impl MemoryRequest {
+    fn overlaps(&self, other: &MemoryRequest) -> bool {
+        self.lifetime.0 <= other.lifetime.1 && other.lifetime.0 <= self.lifetime.1
+    }
+}
+
+/// Allocation shared by transient textures, of memory types all of them accept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SharedMemory {
+    size: u64,
+    memory_type_bits: u32,
+}
+
+/// Places each request at `(memory, offset)` in the shared allocations. A request takes the
+/// first allocation of a memory type it accepts, at the lowest offset where it doesn't overlap
+/// the requests placed before it whose lifetimes overlap its own.
+fn place_textures(requests: &[MemoryRequest]) -> (Vec<SharedMemory>, Vec<(usize, u64)>) {
+    let mut memories = Vec::<SharedMemory>::new();
+    let mut placements = Vec::<(usize, u64)>::new();
+    for request in requests {
+        let Some(memory) = memories
+            .iter()
+            .position(|memory| memory.memory_type_bits & request.memory_type_bits != 0)
+        else {
+            memories.push(SharedMemory {
+                size: request.size,
+                memory_type_bits: request.memory_type_bits,
+            });
+            placements.push((memories.len() - 1, 0));
+            continue;
+        };
+        let occupied = requests
+            .iter()
+            .zip(&placements)
+            .filter(|&(other, &(other_memory, _))| {
+                other_memory == memory && other.overlaps(request)
+            })
+            .map(|(other, &(_, offset))| (offset, offset + other.size))
+            .collect::<Vec<_>>();
+        let mut candidates = occupied
+            .iter()
+            .map(|&(_, end)| end.next_multiple_of(request.alignment))
+            .chain([0])
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+        let offset = candidates
+            .into_iter()
+            .find(|&offset| {
+                occupied
+                    .iter()
+                    .all(|&(start, end)| offset + request.size <= start || end <= offset)
+            })
+            .expect("No offset is past the occupied ranges.");
+        let shared = &mut memories[memory];
+        shared.size = shared.size.max(offset + request.size);
+        shared.memory_type_bits &= request.memory_type_bits;
+        placements.push((memory, offset));
+    }
+    (memories, placements)
+}
+
+/// For each request, the requests whose memory it takes over: those placed in the same
+/// allocation, at overlapping ranges, whose lifetimes end before its own begins.
+fn aliased_requests(requests: &[MemoryRequest], placements: &[(usize, u64)]) -> Vec<Vec<usize>> {
+    requests
+        .iter()
+        .zip(placements)
+        .map(|(request, &(memory, offset))| {
+            requests
+                .iter()
+                .zip(placements)
+                .enumerate()
+                .filter(|&(_, (other, &(other_memory, other_offset)))| {
+                    other_memory == memory
+                        && other.lifetime.1 < request.lifetime.0
+                        && other_offset < offset + request.size
+                        && offset < other_offset + other.size
+                })
+                .map(|(index, _)| index)
+                .collect()
+        })
+        .collect()
+}
+
+/// Render graph objects of a frame in flight, reused or destroyed once its submission is complete.
+/// Transient textures are kept for the next graph executed in the frame, so that a graph
+/// built the same way every frame allocates nothing after the first frames.
+#[derive(Default)]
+pub(crate) struct GraphFrameResources {
+    /// Descriptions, usages and lifetimes the transient images were created for.
+    texture_layout: Vec<(tr::TextureDesc, vk::ImageUsageFlags, (usize, usize))>,
+    images: Vec<TransientImage>,
+    /// For each image, the images whose memory it takes over.
+    aliased: Vec<Vec<vk::Image>>,
+    memories: Vec<vk::DeviceMemory>,
+    render_passes: Vec<vk::RenderPass>,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl GraphFrameResources {
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        self.destroy_pass_objects(device);
+        self.destroy_textures(device);
+    }
+
+    fn destroy_pass_objects(&mut self, device: &Device) {
+        unsafe {
+            self.framebuffers
+                .drain(..)
+                .for_each(|framebuffer| device.destroy_framebuffer(framebuffer, None));
+            self.render_passes
+                .drain(..)
+                .for_each(|render_pass| device.destroy_render_pass(render_pass, None));
+        }
+    }
+
+    fn destroy_textures(&mut self, device: &Device) {
+        self.texture_layout.clear();
+        self.aliased.clear();
+        unsafe {
+            for image in self.images.drain(..) {
+                device.destroy_image_view(image.view, None);
+                device.destroy_image(image.image, None);
+            }
+            self.memories
+                .drain(..)
+                .for_each(|memory| device.free_memory(memory, None));
+        }
+    }
+
+    /// Makes `images[i]` back `transient_textures[i]`, keeping the images of the previous
+    /// graph if its textures had the same descriptions, usages and lifetimes.
+    fn allocate_textures(
+        &mut self,
+        renderer: &Renderer,
+        transient_textures: &[tr::TransientTexture],
+    ) -> VkResult<()> {
+        let texture_layout = transient_textures
+            .iter()
+            .map(|transient| {
+                let usage = transient
+                    .accesses
+                    .iter()
+                    .fold(vk::ImageUsageFlags::empty(), |usage, &access| {
+                        usage | image_usage(access)
+                    });
+                (transient.desc, usage, transient.lifetime)
+            })
+            .collect::<Vec<_>>();
+        if texture_layout == self.texture_layout {
+            return Ok(());
+        }
+        {
+            let mut states = renderer.resource_states.lock().unwrap();
+            for image in &self.images {
+                states.forget(TrackedResource::Image(image.image));
+            }
+        }
+        self.destroy_textures(&renderer.device);
+        let result = self.create_textures(renderer, &texture_layout);
+        if result.is_err() {
+            self.destroy_textures(&renderer.device);
+        }
+        result
+    }
+
+    fn create_textures(
+        &mut self,
+        renderer: &Renderer,
+        texture_layout: &[(tr::TextureDesc, vk::ImageUsageFlags, (usize, usize))],
+    ) -> VkResult<()> {
+        let device = &renderer.device;
+        let mut images = Vec::new();
+        let mut requests = Vec::new();
+        for &(desc, usage, lifetime) in texture_layout {
+            let image = match create_image(device, desc, usage) {
+                Ok(image) => image,
+                Err(e) => {
+                    images
+                        .iter()
+                        .for_each(|&image| unsafe { device.destroy_image(image, None) });
+                    return Err(e);
+                }
+            };
+            images.push(image);
+            let requirements = unsafe { device.get_image_memory_requirements(image) };
+            requests.push(MemoryRequest {
+                size: requirements.size,
+                alignment: requirements.alignment,
+                memory_type_bits: requirements.memory_type_bits,
+                lifetime,
+            });
+        }
+        // Views are created as images are added, so that `destroy_textures` cleans up after
+        // a failure; the images without one yet are destroyed here.
+        let destroy_remaining = |from: usize| {
+            images[from..]
+                .iter()
+                .for_each(|&image| unsafe { device.destroy_image(image, None) });
+        };
+
+        let (memories, placements) = place_textures(&requests);
+        for memory in &memories {
+            let requirements = vk::MemoryRequirements {
+                size: memory.size,
+                alignment: 1,
+                memory_type_bits: memory.memory_type_bits,
+            };
+            match allocate_memory(renderer, &requirements, MemoryLocation::GpuOnly) {
+                Ok(memory) => self.memories.push(memory),
+                Err(e) => {
+                    destroy_remaining(0);
+                    return Err(e);
+                }
+            }
+        }
+        for (index, (&image, &(memory, offset))) in images.iter().zip(&placements).enumerate() {
+            let format = texture_layout[index].0.format;
+            let view = match create_view(device, image, self.memories[memory], offset, format) {
+                Ok(view) => view,
+                Err(e) => {
+                    destroy_remaining(index);
+                    return Err(e);
+                }
+            };
+            self.images.push(TransientImage {
+                image,
+                view,
+                format,
+            });
+        }
+        self.aliased = aliased_requests(&requests, &placements)
+            .into_iter()
+            .map(|aliased| aliased.into_iter().map(|index| images[index]).collect())
+            .collect();
+        self.texture_layout = texture_layout.to_vec();
+
+        let mut states = renderer.resource_states.lock().unwrap();
+        for image in &self.images {
+            states.register_image(
+                image.image,
+                vk::ImageLayout::UNDEFINED,
+                aspect_mask(image.format),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the only imported texture is the swapchain image of `backbuffer_desc`, and
+/// that the graph uses it in a way the swapchain supports.
+pub(crate) fn validate_backbuffer<E: ?Sized>(
+    graph: &tr::CompiledRenderGraph<'_, E>,
+    backbuffer_desc: tr::TextureDesc,
+    backbuffer: tr::TextureHandle,
+) -> Result<(), String> {
+    let resources = graph.resources();
+    if !resources.textures().any(|texture| texture == backbuffer)
+        || !resources.is_imported(backbuffer)
+    {
+        return Err("The backbuffer must be imported into the graph.".to_owned());
+    }
+    if let Some(texture) = resources
+        .textures()
+        .find(|&texture| texture != backbuffer && resources.is_imported(texture))
+    {
+        return Err(format!(
+            "Imported texture {} is not the backbuffer, which is the only one supported.",
+            resources.texture_name(texture)
+        ));
+    }
+    if resources.texture_desc(backbuffer) != backbuffer_desc {
+        return Err("The backbuffer description doesn't match the swapchain.".to_owned());
+    }
+    let transitions = graph.passes().iter().flat_map(|pass| pass.transitions());
+    for transition in transitions.filter(|transition| transition.texture == backbuffer) {
+        if transition.after != tr::TextureAccess::ColorAttachment {
+            return Err(format!(
+                "The backbuffer can only be a color attachment, not {:?}.",
+                transition.after
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a graph executed without a swapchain imports no texture.
+pub(crate) fn validate_offscreen<E: ?Sized>(
+    graph: &tr::CompiledRenderGraph<'_, E>,
+) -> Result<(), String> {
+    let resources = graph.resources();
+    match resources
+        .textures()
+        .find(|&texture| resources.is_imported(texture))
+    {
+        Some(texture) => Err(format!(
+            "Imported texture {} can't be used without a swapchain.",
+            resources.texture_name(texture)
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Image of a texture of the graph being recorded, with the images whose memory it takes
+/// over when its contents are discarded.
+#[derive(Clone, Debug)]
+struct GraphImage {
+    image: vk::Image,
+    view: vk::ImageView,
+    aliased: Vec<vk::Image>,
+}
+
+/// Records the uses of the textures of a pass in `states`, and returns the barriers needed
+/// before the pass. `images` holds the image of each texture the passes use.
+fn pass_transitions(
+    states: &mut ResourceStates,
+    transitions: &[tr::TextureTransition],
+    images: &[Option<GraphImage>],
+) -> Vec<Transition> {
+    transitions
+        .iter()
+        .filter_map(|transition| {
+            let image = images[transition.texture.index()]
+                .as_ref()
+                .expect("A texture used by a pass has no image.");
+            if transition.discard {
+                states.discard(image.image, &image.aliased);
+            }
+            states.transition(
+                TrackedResource::Image(image.image),
+                &texture_use(transition.after),
+            )
+        })
+        .collect()
+}
+
+/// Texture of the render graph pass being recorded, which its encoder can bind to shaders.
+#[derive(Clone, Debug)]
+pub(crate) struct GraphPassTexture {
+    texture: tr::TextureHandle,
+    name: String,
+    access: tr::TextureAccess,
+    image: vk::Image,
+    view: vk::ImageView,
+}
+
+/// Textures of the render graph pass being recorded.
+#[derive(Clone, Debug)]
+pub(crate) struct GraphPassTextures {
+    pub(crate) pass: String,
+    textures: Vec<GraphPassTexture>,
+}
+
+impl GraphPassTextures {
+    /// Descriptor type, image and view of `texture`, which the pass must read as `Sampled` or
+    /// read or write as `Storage`, the layouts its descriptors are written with.
+    pub(crate) fn image(
+        &self,
+        texture: tr::TextureHandle,
+    ) -> Result<(vk::DescriptorType, vk::Image, vk::ImageView), String> {
+        let used = self
+            .textures
+            .iter()
+            .find(|used| used.texture == texture)
+            .ok_or_else(|| {
+                format!(
+                    "Pass {} doesn't declare texture {} it binds.",
+                    self.pass,
+                    texture.index()
+                )
+            })?;
+        let descriptor_type = match used.access {
+            tr::TextureAccess::Sampled => vk::DescriptorType::SAMPLED_IMAGE,
+            tr::TextureAccess::Storage => vk::DescriptorType::STORAGE_IMAGE,
+            access => {
+                return Err(format!(
+                    "Pass {} uses texture {} as {:?}, which shaders can't access.",
+                    self.pass, used.name, access
+                ))
+            }
+        };
+        Ok((descriptor_type, used.image, used.view))
+    }
+}
+
+/// Records the passes of a compiled graph, with the barriers before each pass.
+/// With a `swapchain`, the backbuffer texture is its current image, the only imported texture,
+/// which is left ready to present. Without one, the graph imports no texture.
+pub(crate) fn record_render_graph<'g, 'r>(
+    renderer: &Renderer,
+    encoder: &CommandEncoder<'r>,
+    resources: &mut GraphFrameResources,
+    swapchain: Option<(&VulkanSwapchain, tr::TextureHandle)>,
+    graph: tr::CompiledRenderGraph<'g, CommandEncoder<'r>>,
+) {
+    let device = &renderer.device;
+    let command_buffer = encoder.command_buffer();
+    resources.destroy_pass_objects(device);
+    let (graph_resources, passes) = graph.into_parts();
+    resources
+        .allocate_textures(renderer, graph_resources.transient_textures())
+        .expect("Create render graph texture failed.");
+    let mut images = vec![None; graph_resources.textures().count()];
+    for ((transient, image), aliased) in graph_resources
+        .transient_textures()
+        .iter()
+        .zip(&resources.images)
+        .zip(&resources.aliased)
+    {
+        images[transient.texture.index()] = Some(GraphImage {
+            image: image.image,
+            view: image.view,
+            aliased: aliased.clone(),
+        });
+    }
+    if let Some((swapchain, backbuffer)) = swapchain {
+        let (image, view) = swapchain.current_image();
+        images[backbuffer.index()] = Some(GraphImage {
+            image,
+            view,
+            aliased: Vec::new(),
+        });
+    }
+    let image = |texture: tr::TextureHandle| {
+        images[texture.index()]
+            .as_ref()
+            .expect("A texture used by a pass has no image.")
+    };
+
+    for pass in passes {
+        let transitions = pass_transitions(
+            &mut renderer.resource_states.lock().unwrap(),
+            pass.transitions(),
+            &images,
+        );
+        record_transitions(&renderer.synchronization, command_buffer, &transitions);
+        let pass_textures = GraphPassTextures {
+            pass: pass.name().to_owned(),
+            textures: pass
+                .accesses()
+                .iter()
+                .map(|&(texture, access)| GraphPassTexture {
+                    texture,
+                    name: graph_resources.texture_name(texture).to_owned(),
+                    access,
+                    image: image(texture).image,
+                    view: image(texture).view,
+                })
+                .collect(),
+        };
+
+        if !pass.has_attachments() {
+            encoder.graph_pass_scope(pass_textures, || pass.execute(encoder));
+            continue;
+        }
+
+        let pass_attachment = |attachment: &tr::Attachment| PassAttachment {
+            view: image(attachment.texture).view,
+            format: vk_format(graph_resources.texture_desc(attachment.texture).format),
+            load_op: load_op(attachment.load_op),
+            clear_value: clear_value(attachment.load_op),
+        };
+        let color = pass
+            .color_attachments()
+            .iter()
+            .map(pass_attachment)
+            .collect::<Vec<_>>();
+        let depth = pass.depth_attachment().map(pass_attachment);
+        let first_attachment = pass
+            .color_attachments()
+            .iter()
+            .chain(pass.depth_attachment())
+            .next()
+            .expect("A pass with attachments has no attachment.");
+        let desc = graph_resources.texture_desc(first_attachment.texture);
+        let extent = vk::Extent2D {
+            width: desc.width,
+            height: desc.height,
+        };
+        if renderer.dynamic_rendering {
+            begin_rendering(
+                device,
+                command_buffer,
+                extent,
+                &color,
+                depth,
+                vk::RenderingFlags::empty(),
+            );
+            encoder.graph_pass_scope(pass_textures, || {
+                encoder.render_pass_scope(|| pass.execute(encoder))
+            });
+            unsafe {
+                device.cmd_end_rendering(command_buffer);
+            }
+        } else {
+            let render_pass = create_render_pass(
+                device,
+                &color
+                    .iter()
+                    .map(|attachment| (attachment.format, attachment.load_op))
+                    .collect::<Vec<_>>(),
+                depth.map(|attachment| (attachment.format, attachment.load_op)),
+            )
+            .expect("Create render graph render pass failed.");
+            resources.render_passes.push(render_pass);
+            let views = color
+                .iter()
+                .chain(&depth)
+                .map(|attachment| attachment.view)
+                .collect::<Vec<_>>();
+            let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(&views)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1)
+                .build();
+            let framebuffer = unsafe {
+                device
+                    .create_framebuffer(&framebuffer_create_info, None)
+                    .expect("Create render graph framebuffer failed.")
+            };
+            resources.framebuffers.push(framebuffer);
+            let clear_values = color
+                .iter()
+                .chain(&depth)
+                .map(|attachment| attachment.clear_value)
+                .collect::<Vec<_>>();
+
+            unsafe {
+                let begin_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(render_pass)
+                    .framebuffer(framebuffer)
+                    .render_area(extent.into())
+                    .clear_values(&clear_values)
+                    .build();
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+            }
+            set_viewport(device, command_buffer, extent);
+            encoder.graph_pass_scope(pass_textures, || {
+                encoder.render_pass_scope(|| pass.execute(encoder))
+            });
+            unsafe {
+                device.cmd_end_render_pass(command_buffer);
+            }
+        }
+    }
+
+    if let Some((swapchain, _)) = swapchain {
+        let transition = renderer.resource_states.lock().unwrap().transition(
+            TrackedResource::Image(swapchain.current_image().0),
+            &present_use(),
+        );
+        if let Some(transition) = transition {
+            record_transitions(&renderer.synchronization, command_buffer, &[transition]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+    use crate::vulkan::descriptor::{descriptor_use, DescriptorBinding};
+
+    const DESC: tr::TextureDesc = tr::TextureDesc {
+        width: 64,
+        height: 64,
+        format: tr::TextureFormat::Bgra8Unorm,
+    };
+
+    /// Validates a graph clearing `target` with one pass, after a pass reading it as `access`
+    /// if any.
+    fn validate(
+        target_imported: bool,
+        access: Option<tr::TextureAccess>,
+        backbuffer_desc: tr::TextureDesc,
+    ) -> Result<(), String> {
+        let mut graph = tr::RenderGraph::<()>::new();
+        let target = if target_imported {
+            graph.import_texture("backbuffer", DESC)
+        } else {
+            graph.create_texture("color", DESC)
+        };
+        let clear = tr::LoadOp::Clear(tr::ClearValue::Color([0.0; 4]));
+        graph.add_pass("clear", |pass| pass.color_attachment(target, clear), |_| {});
+        if let Some(access) = access {
+            graph.add_pass(
+                "read",
+                |pass| {
+                    pass.read(target, access);
+                    pass.side_effect();
+                },
+                |_| {},
+            );
+        }
+        validate_backbuffer(&graph.compile().unwrap(), backbuffer_desc, target)
+    }
+
+    #[test]
+    fn accepts_a_backbuffer_rendered_to() {
+        assert_eq!(validate(true, None, DESC), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_backbuffer_that_is_not_imported() {
+        assert_eq!(
+            validate(false, None, DESC),
+            Err("The backbuffer must be imported into the graph.".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_a_backbuffer_not_matching_the_swapchain() {
+        let desc = tr::TextureDesc { width: 32, ..DESC };
+        assert_eq!(
+            validate(true, None, desc),
+            Err("The backbuffer description doesn't match the swapchain.".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_a_backbuffer_used_other_than_as_a_color_attachment() {
+        assert_eq!(
+            validate(true, Some(tr::TextureAccess::Sampled), DESC),
+            Err("The backbuffer can only be a color attachment, not Sampled.".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_imported_textures_without_a_swapchain() {
+        let mut graph = tr::RenderGraph::<()>::new();
+        let target = graph.import_texture("target", DESC);
+        let clear = tr::LoadOp::Clear(tr::ClearValue::Color([0.0; 4]));
+        graph.add_pass("clear", |pass| pass.color_attachment(target, clear), |_| {});
+        assert_eq!(
+            validate_offscreen(&graph.compile().unwrap()),
+            Err("Imported texture target can't be used without a swapchain.".to_owned())
+        );
+    }
+
+    fn request(size: u64, alignment: u64, lifetime: (usize, usize)) -> MemoryRequest {
+        MemoryRequest {
+            size,
+            alignment,
+            memory_type_bits: 0b11,
+            lifetime,
+        }
+    }
+
+    #[test]
+    fn places_textures_with_disjoint_lifetimes_at_the_same_offset() {
+        let requests = [
+            request(4096, 256, (0, 1)),
+            request(1024, 256, (2, 3)),
+            request(8192, 256, (4, 4)),
+        ];
+        let (memories, placements) = place_textures(&requests);
+        assert_eq!(
+            memories,
+            vec![SharedMemory {
+                size: 8192,
+                memory_type_bits: 0b11,
+            }]
+        );
+        assert_eq!(placements, vec![(0, 0), (0, 0), (0, 0)]);
+        assert_eq!(
+            aliased_requests(&requests, &placements),
+            vec![vec![], vec![0], vec![0, 1]]
+        );
+    }
+
+    #[test]
+    fn places_textures_with_overlapping_lifetimes_at_aligned_disjoint_offsets() {
+        let requests = [
+            request(1000, 256, (0, 2)),
+            request(1000, 512, (1, 3)),
+            request(100, 256, (3, 3)),
+            request(500, 256, (3, 4)),
+        ];
+        let (memories, placements) = place_textures(&requests);
+        // The last two textures take the place of the first, next to each other.
+        assert_eq!(placements, vec![(0, 0), (0, 1024), (0, 0), (0, 256)]);
+        assert_eq!(memories[0].size, 2024);
+        assert_eq!(
+            aliased_requests(&requests, &placements),
+            vec![vec![], vec![], vec![0], vec![0]]
+        );
+    }
+
+    #[test]
+    fn places_textures_of_incompatible_memory_types_in_separate_memories() {
+        let requests = [
+            MemoryRequest {
+                memory_type_bits: 0b011,
+                ..request(1024, 256, (0, 0))
+            },
+            MemoryRequest {
+                memory_type_bits: 0b100,
+                ..request(1024, 256, (1, 1))
+            },
+            MemoryRequest {
+                memory_type_bits: 0b1100,
+                ..request(2048, 256, (2, 2))
+            },
+        ];
+        let (memories, placements) = place_textures(&requests);
+        assert_eq!(
+            memories,
+            vec![
+                SharedMemory {
+                    size: 1024,
+                    memory_type_bits: 0b011,
+                },
+                SharedMemory {
+                    size: 2048,
+                    memory_type_bits: 0b100,
+                },
+            ]
+        );
+        assert_eq!(placements, vec![(0, 0), (1, 0), (1, 0)]);
+        assert_eq!(
+            aliased_requests(&requests, &placements),
+            vec![vec![], vec![], vec![1]]
+        );
+    }
+
+    /// Images and views of fake handles for the textures of `graph`, in order.
+    fn fake_images<E: ?Sized>(graph: &tr::CompiledRenderGraph<'_, E>) -> Vec<Option<GraphImage>> {
+        graph
+            .resources()
+            .textures()
+            .map(|texture| {
+                Some(GraphImage {
+                    image: vk::Image::from_raw(texture.index() as u64 + 1),
+                    view: vk::ImageView::from_raw(texture.index() as u64 + 1),
+                    aliased: Vec::new(),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn makes_a_texture_written_by_a_pass_readable_by_the_next() {
+        let mut graph = tr::RenderGraph::<()>::new();
+        let color = graph.create_texture("color", DESC);
+        let clear = tr::LoadOp::Clear(tr::ClearValue::Color([0.0; 4]));
+        graph.add_pass("draw", |pass| pass.color_attachment(color, clear), |_| {});
+        graph.add_pass(
+            "read",
+            |pass| {
+                pass.read(color, tr::TextureAccess::Sampled);
+                pass.side_effect();
+            },
+            |_| {},
+        );
+        let graph = graph.compile().unwrap();
+        let images = fake_images(&graph);
+        let image = images[color.index()].as_ref().unwrap().image;
+        let mut states = ResourceStates::default();
+        states.register_image(
+            image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageAspectFlags::COLOR,
+        );
+        let (_, passes) = graph.into_parts();
+
+        let draw = pass_transitions(&mut states, passes[0].transitions(), &images);
+        assert_eq!(draw.len(), 1);
+        assert_eq!(draw[0].old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(
+            draw[0].new_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+
+        let read = pass_transitions(&mut states, passes[1].transitions(), &images);
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].resource, TrackedResource::Image(image));
+        assert_eq!(
+            read[0].old_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            read[0].new_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert_eq!(
+            read[0].src,
+            texture_use(tr::TextureAccess::ColorAttachment).scope
+        );
+        assert_eq!(read[0].dst, texture_use(tr::TextureAccess::Sampled).scope);
+
+        // The read pass binds the texture as a sampled image, which needs no other barrier.
+        let pass_textures = GraphPassTextures {
+            pass: "read".to_owned(),
+            textures: passes[1]
+                .accesses()
+                .iter()
+                .map(|&(texture, access)| GraphPassTexture {
+                    texture,
+                    name: "color".to_owned(),
+                    access,
+                    image,
+                    view: vk::ImageView::null(),
+                })
+                .collect(),
+        };
+        assert_eq!(
+            pass_textures.image(color),
+            Ok((
+                vk::DescriptorType::SAMPLED_IMAGE,
+                image,
+                vk::ImageView::null()
+            ))
+        );
+        let binding = DescriptorBinding {
+            set: 1,
+            binding: 0,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            element_stride: None,
+        };
+        let (resource, sampled) = descriptor_use(&binding, TrackedResource::Image(image));
+        assert!(states.use_in_render_pass(resource, &sampled));
+    }
+
+    #[test]
+    fn rejects_binding_textures_a_pass_does_not_read_from_shaders() {
+        let mut graph = tr::RenderGraph::<()>::new();
+        let color = graph.create_texture("color", DESC);
+        let other = graph.create_texture("other", DESC);
+        let clear = tr::LoadOp::Clear(tr::ClearValue::Color([0.0; 4]));
+        graph.add_pass("draw", |pass| pass.color_attachment(color, clear), |_| {});
+        let pass_textures = GraphPassTextures {
+            pass: "draw".to_owned(),
+            textures: vec![GraphPassTexture {
+                texture: color,
+                name: "color".to_owned(),
+                access: tr::TextureAccess::ColorAttachment,
+                image: vk::Image::null(),
+                view: vk::ImageView::null(),
+            }],
+        };
+        assert_eq!(
+            pass_textures.image(color),
+            Err(
+                "Pass draw uses texture color as ColorAttachment, which shaders can't access."
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            pass_textures.image(other),
+            Err(format!(
+                "Pass draw doesn't declare texture {} it binds.",
+                other.index()
+            ))
+        );
+    }
+
+    #[test]
+    fn waits_for_the_textures_sharing_memory_before_discarding() {
+        let mut graph = tr::RenderGraph::<()>::new();
+        let first = graph.create_texture("first", DESC);
+        let second = graph.create_texture("second", DESC);
+        let first_image = vk::Image::from_raw(1);
+        let second_image = vk::Image::from_raw(2);
+        let images = [
+            Some(GraphImage {
+                image: first_image,
+                view: vk::ImageView::null(),
+                aliased: Vec::new(),
+            }),
+            Some(GraphImage {
+                image: second_image,
+                view: vk::ImageView::null(),
+                aliased: vec![first_image],
+            }),
+        ];
+        let mut states = ResourceStates::default();
+        for image in [first_image, second_image] {
+            states.register_image(
+                image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageAspectFlags::COLOR,
+            );
+        }
+        let first_use = |texture, access| tr::TextureTransition {
+            texture,
+            before: None,
+            after: access,
+            discard: true,
+        };
+        pass_transitions(
+            &mut states,
+            &[first_use(first, tr::TextureAccess::Storage)],
+            &images,
+        );
+
+        // `second` takes over the memory `first` was written to.
+        let transitions = pass_transitions(
+            &mut states,
+            &[first_use(second, tr::TextureAccess::ColorAttachment)],
+            &images,
+        );
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(
+            transitions[0].resource,
+            TrackedResource::Image(second_image)
+        );
+        assert_eq!(transitions[0].old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(
+            transitions[0].src,
+            texture_use(tr::TextureAccess::Storage).scope
+        );
+    }
+}
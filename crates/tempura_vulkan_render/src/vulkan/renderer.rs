@@ -1,36 +1,155 @@
 use std::{
-    ffi::{c_char, CString},
-    rc::Rc,
+    collections::HashMap,
+    ffi::{c_char, CStr, CString},
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
-use ash::{extensions::ext::DebugUtils, prelude::VkResult, vk, Device, Entry, Instance};
+use ash::{
+    extensions::{ext::DebugUtils, khr::DrawIndirectCount},
+    prelude::VkResult,
+    vk, Device, Entry, Instance,
+};
 use raw_window_handle::RawDisplayHandle;
 
-use super::{Material, Shader, VulkanSwapchain};
+use super::{
+    bindless::{
+        enable_bindless_features, is_bindless_supported, BindlessBinding, BindlessTable,
+        QueueProgress,
+    },
+    descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache},
+    frame::{Frame, FRAMES_IN_FLIGHT},
+    pipeline_cache::{create_pipeline_cache, read_pipeline_cache_file, write_pipeline_cache_file},
+    query::QueryCapabilities,
+    queue::{
+        acquire_waits, record_acquires, record_ownership_transfer, take_acquires, AsyncQueue,
+        PendingAcquire, QueueFamilies,
+    },
+    registry::ResourceRegistry,
+    render_graph::{record_render_graph, validate_backbuffer, validate_offscreen},
+    rendering::create_render_pass,
+    resource_state::{AccessScope, ResourceStates, TrackedResource},
+    synchronization::{is_synchronization2_supported, Synchronization},
+    timeline::SubmissionTimeline,
+    upload::{
+        record_upload_copy, texel_size, StagingBuffer, UploadCopy, UploadDestination,
+        UploadManager, STAGING_RING_SIZE,
+    },
+    AttachmentFormats, BindlessIndex, Buffer, CommandEncoder, ComputePipeline, ComputeShader,
+    GpuTiming, Handle, InstanceBuffer, Material, MemoryLocation, OcclusionResult,
+    PipelineStatistics, QueueSubmission, QueueType, RegisteredResource, ResourceLeak, Sampler,
+    Shader, ShaderCompileOptions, ShaderStage, ShaderVariants, StorageBuffer, StorageImage,
+    UploadHandle, VertexLayout, VulkanSwapchain,
+};
 use tempura_render as tr;
 
+/// Choices made when creating a `Renderer`.
+#[derive(Clone, Debug)]
+pub struct RendererOptions {
+    /// Renders without render pass and framebuffer objects where the device supports Vulkan 1.3
+    /// dynamic rendering. Otherwise render passes are used.
+    pub dynamic_rendering: bool,
+    /// Records barriers and submits command buffers with synchronization2 where the device
+    /// supports it, from Vulkan 1.3 or its extension. Otherwise the original commands are used.
+    pub synchronization2: bool,
+    /// Numbers submissions with timeline semaphores where the device supports Vulkan 1.2 with
+    /// the `timelineSemaphore` feature. Otherwise each submission signals a fence.
+    pub timeline_semaphores: bool,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        RendererOptions {
+            dynamic_rendering: true,
+            synchronization2: true,
+            timeline_semaphores: true,
+        }
+    }
+}
+
 pub struct Renderer {
     pub(crate) entry: Entry,
     pub(crate) instance: Instance,
     pub(crate) physical_device: vk::PhysicalDevice,
-    pub(crate) device: Rc<Device>,
-    pub(crate) surface_loader: Rc<ash::extensions::khr::Surface>,
-    pub(crate) swapchain_loader: Rc<ash::extensions::khr::Swapchain>,
+    pub(crate) device: Arc<Device>,
+    pub(crate) surface_loader: Arc<ash::extensions::khr::Surface>,
+    pub(crate) swapchain_loader: Arc<ash::extensions::khr::Swapchain>,
+    pub(crate) pipeline_cache: vk::PipelineCache,
+    /// Whether the renderer was created without a display, so without swapchain support.
+    headless: bool,
 
     present_queue: vk::Queue,
-    present_semaphore: vk::Semaphore,
-    render_semaphore: vk::Semaphore,
-    _graphics_queue_family_index: u32,
+    graphics_queue_family_index: u32,
+    /// Pool of the frame command buffers.
     command_pool: vk::CommandPool,
-    _setup_command_buffer: vk::CommandBuffer,
-    draw_command_buffer: vk::CommandBuffer,
-    render_fence: vk::Fence,
+    /// Pool of `setup_command_buffer`, so that resources are created while frames record.
+    setup_command_pool: vk::CommandPool,
+    /// Queues of `submit_compute` and `submit_transfer`, on dedicated families if any.
+    compute_queue: AsyncQueue,
+    transfer_queue: AsyncQueue,
+    /// Resources released to another queue, acquired by its next command buffer.
+    pub(crate) pending_acquires: Mutex<Vec<PendingAcquire>>,
+    /// Staging memory and batches of the uploads through the transfer queue.
+    uploads: Mutex<UploadManager>,
+    setup_command_buffer: vk::CommandBuffer,
+    pub(crate) query_capabilities: QueryCapabilities,
+    /// Features the device was created with.
+    pub(crate) enabled_features: vk::PhysicalDeviceFeatures,
+    /// Loaded when the device supports `VK_KHR_draw_indirect_count`.
+    pub(crate) draw_indirect_count_loader: Option<DrawIndirectCount>,
+    /// Whether passes use dynamic rendering instead of render pass objects.
+    pub(crate) dynamic_rendering: bool,
+    /// Global descriptor table, created when the device supports descriptor indexing.
+    pub(crate) bindless: Option<BindlessTable>,
+    /// Set layouts shared by the pipelines declaring the same bindings.
+    pub(crate) descriptor_layouts: Mutex<DescriptorLayoutCache>,
+    /// Pools of the descriptor sets of materials and compute pipelines.
+    pub(crate) descriptor_sets: Mutex<DescriptorAllocator>,
+    /// Render passes that pipelines are created against without dynamic rendering.
+    compatible_render_passes: Mutex<HashMap<AttachmentFormats, vk::RenderPass>>,
+    /// Format of the last swapchain created, which materials render to by default.
+    pub(crate) swapchain_format: Mutex<vk::Format>,
+    /// Layouts and accesses the command encoders insert barriers from.
+    pub(crate) resource_states: Mutex<ResourceStates>,
+    /// Barrier and submission commands, with or without synchronization2.
+    pub(crate) synchronization: Synchronization,
+    /// Values of the submissions of frames and `execute`.
+    pub(crate) timeline: SubmissionTimeline,
+    /// Held while submitting to a queue, as queue types may share the same device queue.
+    queue_lock: Mutex<()>,
+    /// Held while a frame or `execute` is recorded, as they share the frames in flight.
+    frame_lock: Mutex<()>,
+    /// Resources referenced by handles, owned until they are unregistered.
+    registry: Mutex<ResourceRegistry>,
+    frames: Vec<Frame>,
+    frame_index: AtomicUsize,
+    gpu_timings: Mutex<Vec<GpuTiming>>,
+    pipeline_statistics: Mutex<Vec<PipelineStatistics>>,
+    occlusion_results: Mutex<Vec<OcclusionResult>>,
     debug_utils_loader: DebugUtils,
     debug_callback: vk::DebugUtilsMessengerEXT,
 }
 
 impl Renderer {
     pub fn new(display_handle: &RawDisplayHandle) -> Self {
+        Self::with_options(display_handle, &RendererOptions::default())
+    }
+
+    pub fn with_options(display_handle: &RawDisplayHandle, options: &RendererOptions) -> Self {
+        Self::create(Some(display_handle), options)
+    }
+
+    /// Creates a renderer without a display, e.g. to run compute work in tools and tests.
+    /// It records with `execute` and the queue submissions, and can't create swapchains.
+    pub fn headless(options: &RendererOptions) -> Self {
+        Self::create(None, options)
+    }
+
+    fn create(display_handle: Option<&RawDisplayHandle>, options: &RendererOptions) -> Self {
         let entry = unsafe { Entry::load().expect("Load entry error") };
         let instance = create_instance(&entry, display_handle).expect("Create instance error");
         let debug_utils_loader = DebugUtils::new(&entry, &instance);
@@ -54,42 +173,149 @@ impl Renderer {
         };
 
         let physical_device = pick_physical_device(&instance).expect("Not found physical device");
-        let graphics_queue_family_index =
-            get_graphics_queue_family_index(&instance, &physical_device)
-                .expect("Not found graphics queue");
-        let device = create_device(&instance, &physical_device, graphics_queue_family_index)
-            .expect("Create device error");
-        let device = Rc::new(device);
+        let queue_families =
+            get_queue_families(&instance, &physical_device).expect("Not found graphics queue");
+        let graphics_queue_family_index = queue_families.graphics;
+        let query_capabilities =
+            get_query_capabilities(&instance, &physical_device, graphics_queue_family_index);
+        let enabled_features =
+            get_enabled_features(&instance, &physical_device, &query_capabilities);
+        let api_version = unsafe {
+            instance
+                .get_physical_device_properties(physical_device)
+                .api_version
+        };
+        let draw_indirect_count =
+            is_device_extension_supported(&instance, &physical_device, DrawIndirectCount::name());
+        // Below Vulkan 1.3, synchronization2 needs its extension.
+        let synchronization2_extension = api_version < vk::API_VERSION_1_3
+            && is_device_extension_supported(
+                &instance,
+                &physical_device,
+                vk::KhrSynchronization2Fn::name(),
+            );
+        let synchronization2 = options.synchronization2
+            && (api_version >= vk::API_VERSION_1_3 || synchronization2_extension)
+            && is_synchronization2_supported(&instance, &physical_device);
+        let mut device_extensions = Vec::new();
+        if display_handle.is_some() {
+            device_extensions.push(ash::extensions::khr::Swapchain::name());
+        }
+        // Devices supporting the portability subset require it to be enabled.
+        if is_device_extension_supported(
+            &instance,
+            &physical_device,
+            vk::KhrPortabilitySubsetFn::name(),
+        ) {
+            device_extensions.push(vk::KhrPortabilitySubsetFn::name());
+        }
+        if draw_indirect_count {
+            device_extensions.push(DrawIndirectCount::name());
+        }
+        if synchronization2 && synchronization2_extension {
+            device_extensions.push(vk::KhrSynchronization2Fn::name());
+        }
+        let dynamic_rendering = options.dynamic_rendering
+            && is_dynamic_rendering_supported(&instance, &physical_device);
+        // The features of a version can only be chained for devices supporting it.
+        let vulkan13 = (api_version >= vk::API_VERSION_1_3).then(|| {
+            vk::PhysicalDeviceVulkan13Features::builder()
+                .dynamic_rendering(dynamic_rendering)
+                .synchronization2(synchronization2)
+                .build()
+        });
+        let synchronization2_extension =
+            (synchronization2 && synchronization2_extension).then(|| {
+                vk::PhysicalDeviceSynchronization2Features::builder()
+                    .synchronization2(true)
+                    .build()
+            });
+        let vulkan12_supported = api_version >= vk::API_VERSION_1_2;
+        let timeline_semaphore = options.timeline_semaphores
+            && vulkan12_supported
+            && is_timeline_semaphore_supported(&instance, &physical_device);
+        let bindless = vulkan12_supported && is_bindless_supported(&instance, &physical_device);
+        let vulkan12 = vulkan12_supported.then(|| {
+            let vulkan12_features = vk::PhysicalDeviceVulkan12Features::builder()
+                .timeline_semaphore(timeline_semaphore);
+            if bindless {
+                enable_bindless_features(vulkan12_features).build()
+            } else {
+                vulkan12_features.build()
+            }
+        });
+        let device = create_device(
+            &instance,
+            &physical_device,
+            &queue_families,
+            &enabled_features,
+            &device_extensions,
+            ChainedFeatures {
+                vulkan12,
+                vulkan13,
+                synchronization2: synchronization2_extension,
+            },
+        )
+        .expect("Create device error");
+        let synchronization = Synchronization::new(
+            &instance,
+            &device,
+            synchronization2,
+            api_version >= vk::API_VERSION_1_3,
+        );
+        let bindless = bindless.then(|| {
+            BindlessTable::new(&instance, &physical_device, &device)
+                .expect("Create bindless table error")
+        });
+        let timeline =
+            SubmissionTimeline::new(&device, timeline_semaphore).expect("Create timeline error");
+        let compute_queue = AsyncQueue::new(
+            &device,
+            queue_families
+                .compute
+                .unwrap_or(graphics_queue_family_index),
+            timeline_semaphore,
+        )
+        .expect("Create compute queue error");
+        let transfer_queue = AsyncQueue::new(
+            &device,
+            queue_families
+                .transfer
+                .unwrap_or(graphics_queue_family_index),
+            timeline_semaphore,
+        )
+        .expect("Create transfer queue error");
+        let draw_indirect_count_loader =
+            draw_indirect_count.then(|| DrawIndirectCount::new(&instance, &device));
+        let device = Arc::new(device);
         let present_queue = unsafe { device.get_device_queue(graphics_queue_family_index, 0) };
         let command_pool = create_command_pool(&device, graphics_queue_family_index)
             .expect("Create command pool error");
         let command_buffers =
-            create_command_buffers(&device, &command_pool).expect("Create command buffers error");
-        let setup_command_buffer = command_buffers[0];
-        let draw_command_buffer = command_buffers[1];
+            create_command_buffers(&device, &command_pool, FRAMES_IN_FLIGHT as u32)
+                .expect("Create command buffers error");
+        let setup_command_pool = create_command_pool(&device, graphics_queue_family_index)
+            .expect("Create command pool error");
+        let setup_command_buffer = create_command_buffers(&device, &setup_command_pool, 1)
+            .expect("Create command buffers error")[0];
         let surface_loader = ash::extensions::khr::Surface::new(&entry, &instance);
-        let surface_loader = Rc::new(surface_loader);
+        let surface_loader = Arc::new(surface_loader);
         let swapchain_loader = ash::extensions::khr::Swapchain::new(&instance, &device);
-        let swapchain_loader = Rc::new(swapchain_loader);
-        let fence_create_info = vk::FenceCreateInfo::builder()
-            .flags(vk::FenceCreateFlags::SIGNALED)
-            .build();
-        let render_fence = unsafe {
-            device
-                .create_fence(&fence_create_info, None)
-                .expect("Create fence error")
-        };
-        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
-        let present_semaphore = unsafe {
-            device
-                .create_semaphore(&semaphore_create_info, None)
-                .expect("Create semaphore error")
-        };
-        let render_semaphore = unsafe {
-            device
-                .create_semaphore(&semaphore_create_info, None)
-                .expect("Create semaphore error")
+        let swapchain_loader = Arc::new(swapchain_loader);
+        let pipeline_cache =
+            create_pipeline_cache(&device, &[]).expect("Create pipeline cache error");
+        let limits = unsafe {
+            instance
+                .get_physical_device_properties(physical_device)
+                .limits
         };
+        let frames = command_buffers
+            .iter()
+            .map(|&command_buffer| {
+                Frame::new(&device, command_buffer, &query_capabilities, &limits)
+                    .expect("Create frame error")
+            })
+            .collect::<Vec<Frame>>();
 
         Renderer {
             entry,
@@ -100,117 +326,1187 @@ impl Renderer {
             device,
             surface_loader,
             swapchain_loader,
+            pipeline_cache,
+            headless: display_handle.is_none(),
             present_queue,
-            present_semaphore,
-            render_semaphore,
-            _graphics_queue_family_index: graphics_queue_family_index,
+            graphics_queue_family_index,
             command_pool,
-            _setup_command_buffer: setup_command_buffer,
-            draw_command_buffer,
-            render_fence,
+            setup_command_pool,
+            compute_queue,
+            transfer_queue,
+            pending_acquires: Mutex::new(Vec::new()),
+            uploads: Mutex::new(UploadManager::default()),
+            setup_command_buffer,
+            query_capabilities,
+            enabled_features,
+            draw_indirect_count_loader,
+            dynamic_rendering,
+            bindless,
+            descriptor_layouts: Mutex::new(DescriptorLayoutCache::default()),
+            descriptor_sets: Mutex::new(DescriptorAllocator::new(true)),
+            compatible_render_passes: Mutex::new(HashMap::new()),
+            swapchain_format: Mutex::new(vk::Format::B8G8R8A8_UNORM),
+            resource_states: Mutex::new(ResourceStates::default()),
+            synchronization,
+            timeline,
+            queue_lock: Mutex::new(()),
+            frame_lock: Mutex::new(()),
+            registry: Mutex::new(ResourceRegistry::default()),
+            frames,
+            frame_index: AtomicUsize::new(0),
+            gpu_timings: Mutex::new(Vec::new()),
+            pipeline_statistics: Mutex::new(Vec::new()),
+            occlusion_results: Mutex::new(Vec::new()),
         }
     }
-}
 
-impl Drop for Renderer {
-    fn drop(&mut self) {
+    /// Renders a frame to the swapchain, recording additional commands with `record` inside
+    /// the swapchain pass. Use `render_frame_with` to record commands outside of it.
+    pub fn render_with<F>(&self, swapchain: &VulkanSwapchain, record: F)
+    where
+        F: FnOnce(&CommandEncoder),
+    {
+        self.render_frame_with(swapchain, |encoder| encoder.swapchain_pass(record));
+    }
+
+    /// Renders a frame to the swapchain, recording its commands with `record`.
+    /// The encoder starts outside of any render pass, so that compute work and copies can be
+    /// recorded before `CommandEncoder::swapchain_pass`. If `record` doesn't record the
+    /// swapchain pass, an empty one clearing the image is recorded after it.
+    pub fn render_frame_with<F>(&self, swapchain: &VulkanSwapchain, record: F)
+    where
+        F: FnOnce(&CommandEncoder),
+    {
+        let _frame_lock = self.frame_lock.lock().unwrap();
+        let frame = &self.frames[self.frame_index.load(Ordering::Relaxed)];
+        self.timeline
+            .wait(&self.device, frame.submission.load(Ordering::Relaxed));
+        if !swapchain.acquire_next_image(&frame.present_semaphore) {
+            return;
+        };
+
+        let wait_semaphores = self.begin_frame_commands(frame);
+
+        let encoder = CommandEncoder::new(self, frame, Some(swapchain));
+        record(&encoder);
+        if !encoder.swapchain_pass_recorded() {
+            encoder.swapchain_pass(|_| {});
+        }
+        drop(encoder);
+        self.submit_and_present(frame, swapchain, &wait_semaphores);
+    }
+
+    /// Renders a frame to the swapchain by executing `graph`, whose passes are recorded with
+    /// the frame's encoder. `backbuffer` is the swapchain image, imported with the description
+    /// from `VulkanSwapchain::backbuffer_desc`; it is the only imported texture supported.
+    /// Transient textures are allocated per frame in flight and reused by the next graphs;
+    /// textures whose lifetimes don't overlap share memory. Passes bind the textures they
+    /// read or write in shaders with `CommandEncoder::bind_graph_textures`.
+    /// Panics if the graph fails to compile or uses the backbuffer other than as a color
+    /// attachment.
+    pub fn render_graph<'r>(
+        &'r self,
+        swapchain: &'r VulkanSwapchain,
+        graph: tr::RenderGraph<'_, CommandEncoder<'r>>,
+        backbuffer: tr::TextureHandle,
+    ) {
+        let graph = graph
+            .compile()
+            .unwrap_or_else(|e| panic!("Render graph compile failed. {}", e));
+        validate_backbuffer(&graph, swapchain.backbuffer_desc(), backbuffer)
+            .unwrap_or_else(|e| panic!("Render graph compile failed. {}", e));
+        let _frame_lock = self.frame_lock.lock().unwrap();
+        let frame = &self.frames[self.frame_index.load(Ordering::Relaxed)];
+        self.timeline
+            .wait(&self.device, frame.submission.load(Ordering::Relaxed));
+        if !swapchain.acquire_next_image(&frame.present_semaphore) {
+            return;
+        }
+
+        let wait_semaphores = self.begin_frame_commands(frame);
+        let encoder = CommandEncoder::new(self, frame, None);
+        record_render_graph(
+            self,
+            &encoder,
+            &mut frame.graph_resources.lock().unwrap(),
+            Some((swapchain, backbuffer)),
+            graph,
+        );
+        drop(encoder);
+        self.submit_and_present(frame, swapchain, &wait_semaphores);
+    }
+
+    /// Ends the command buffer of a frame recorded to the acquired swapchain image, submits it
+    /// and presents the image. The submission also waits for `wait_semaphores`.
+    fn submit_and_present(
+        &self,
+        frame: &Frame,
+        swapchain: &VulkanSwapchain,
+        wait_semaphores: &[vk::SemaphoreSubmitInfo],
+    ) {
         unsafe {
-            self.device.device_wait_idle().unwrap();
-            self.device.destroy_semaphore(self.present_semaphore, None);
-            self.device.destroy_semaphore(self.render_semaphore, None);
-            self.device.destroy_fence(self.render_fence, None);
-            self.device.destroy_command_pool(self.command_pool, None);
-            self.debug_utils_loader
-                .destroy_debug_utils_messenger(self.debug_callback, None);
-            self.device.destroy_device(None);
-            self.instance.destroy_instance(None);
+            self.device
+                .end_command_buffer(frame.command_buffer)
+                .expect("End commandbuffer failed.");
+
+            // The swapchain only works with binary semaphores.
+            let wait_semaphore = vk::SemaphoreSubmitInfo::builder()
+                .semaphore(frame.present_semaphore)
+                .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .build();
+            let wait_semaphores = [&[wait_semaphore], wait_semaphores].concat();
+            let signal_semaphore = vk::SemaphoreSubmitInfo::builder()
+                .semaphore(frame.render_semaphore)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .build();
+            let _queue_lock = self.queue_lock.lock().unwrap();
+            let submission = self
+                .timeline
+                .submit(
+                    &self.device,
+                    &self.synchronization,
+                    self.present_queue,
+                    frame.command_buffer,
+                    &wait_semaphores,
+                    &[signal_semaphore],
+                )
+                .expect("Queue submit failed.");
+            frame.submission.store(submission, Ordering::Relaxed);
+
+            swapchain
+                .present(&frame.render_semaphore, &self.present_queue)
+                .unwrap();
         }
+
+        self.frame_index.store(
+            (self.frame_index.load(Ordering::Relaxed) + 1) % FRAMES_IN_FLIGHT,
+            Ordering::Relaxed,
+        );
     }
-}
 
-impl tr::Renderer for Renderer {
-    type Swapchain = VulkanSwapchain;
-    type Shader = Shader;
-    type Material = Material;
+    /// Records commands without a swapchain, such as compute work, submits them and waits
+    /// until they complete. The encoder has no swapchain pass.
+    /// It must not be called from the closure of `render_with` or `render_frame_with`.
+    pub fn execute<F>(&self, record: F)
+    where
+        F: FnOnce(&CommandEncoder),
+    {
+        self.execute_frame(|_, encoder| record(encoder));
+    }
+
+    /// Executes `graph` like `execute`, e.g. to render offscreen or run compute passes.
+    /// The graph has no imported textures.
+    /// Panics if the graph fails to compile or imports a texture.
+    pub fn execute_graph<'r>(&'r self, graph: tr::RenderGraph<'_, CommandEncoder<'r>>) {
+        let graph = graph
+            .compile()
+            .unwrap_or_else(|e| panic!("Render graph compile failed. {}", e));
+        validate_offscreen(&graph).unwrap_or_else(|e| panic!("Render graph compile failed. {}", e));
+        self.execute_frame(|frame, encoder| {
+            record_render_graph(
+                self,
+                encoder,
+                &mut frame.graph_resources.lock().unwrap(),
+                None,
+                graph,
+            );
+        });
+    }
+
+    fn execute_frame<'r, F>(&'r self, record: F)
+    where
+        F: FnOnce(&'r Frame, &CommandEncoder<'r>),
+    {
+        let _frame_lock = self.frame_lock.lock().unwrap();
+        let frame = &self.frames[self.frame_index.load(Ordering::Relaxed)];
+        self.timeline
+            .wait(&self.device, frame.submission.load(Ordering::Relaxed));
+        let wait_semaphores = self.begin_frame_commands(frame);
+
+        record(frame, &CommandEncoder::new(self, frame, None));
 
-    fn render(&self, swapchain: &Self::Swapchain) {
         unsafe {
-            if !swapchain.acquire_next_image(&self.present_semaphore) {
-                return;
+            self.device
+                .end_command_buffer(frame.command_buffer)
+                .expect("End commandbuffer failed.");
+        }
+        let submission = {
+            let _queue_lock = self.queue_lock.lock().unwrap();
+            self.timeline
+                .submit(
+                    &self.device,
+                    &self.synchronization,
+                    self.present_queue,
+                    frame.command_buffer,
+                    &wait_semaphores,
+                    &[],
+                )
+                .expect("Queue submit failed.")
+        };
+        frame.submission.store(submission, Ordering::Relaxed);
+        self.timeline.wait(&self.device, submission);
+    }
+
+    /// Records commands for the compute queue and submits them without waiting, e.g. to run
+    /// simulations alongside the frames. Buffers the commands write are handed to the
+    /// graphics queue with `CommandEncoder::release_buffer`.
+    /// Without a dedicated compute family, the commands run on the graphics queue.
+    pub fn submit_compute<F>(&self, record: F) -> QueueSubmission
+    where
+        F: FnOnce(&CommandEncoder),
+    {
+        self.submit_async(QueueType::Compute, record)
+    }
+
+    /// Same as `submit_compute` for the transfer queue, e.g. to upload streaming data from
+    /// staging buffers. The encoder only records copies, fills and releases.
+    /// The closure must not upload with `upload_buffer`, which may flush to the same queue.
+    pub fn submit_transfer<F>(&self, record: F) -> QueueSubmission
+    where
+        F: FnOnce(&CommandEncoder),
+    {
+        self.submit_async(QueueType::Transfer, record)
+    }
+
+    fn submit_async<F>(&self, queue_type: QueueType, record: F) -> QueueSubmission
+    where
+        F: FnOnce(&CommandEncoder),
+    {
+        let queue = self.async_queue(queue_type);
+        let _recording = queue.recording.lock().unwrap();
+        let command_buffer = queue
+            .begin(&self.device)
+            .expect("Begin commandbuffer failed.");
+        let wait_semaphores = self.acquire_released(queue_type, command_buffer);
+        record(&CommandEncoder::new_async(self, command_buffer, queue_type));
+        let value = {
+            let _queue_lock = self.queue_lock.lock().unwrap();
+            queue
+                .submit(
+                    &self.device,
+                    &self.synchronization,
+                    command_buffer,
+                    &wait_semaphores,
+                )
+                .expect("Queue submit failed.")
+        };
+        QueueSubmission {
+            queue: queue_type,
+            value,
+        }
+    }
+
+    /// Uploads `data` to `dst` at `offset` bytes through the staging ring, in a batch of copies
+    /// submitted to the transfer queue by the next frame or `flush_uploads`.
+    /// The buffer is usable by the graphics queue once `is_upload_complete` returns true, from
+    /// the frame that sees the copy complete. The GPU must not be using the range meanwhile.
+    /// With a dedicated transfer family, a buffer the graphics queue used before is released
+    /// to the transfer queue first, and the CPU waits for the release to complete.
+    /// Panics if the buffer lacks `TRANSFER_DST` or the range is out of the buffer.
+    pub fn upload_buffer<T: bytemuck::Pod>(
+        &self,
+        dst: &Buffer,
+        offset: u64,
+        data: &[T],
+    ) -> UploadHandle {
+        let bytes = bytemuck::cast_slice::<T, u8>(data);
+        assert!(
+            dst.usage().contains(vk::BufferUsageFlags::TRANSFER_DST),
+            "The buffer of an upload needs TRANSFER_DST usage."
+        );
+        assert!(
+            offset
+                .checked_add(bytes.len() as u64)
+                .is_some_and(|end| end <= dst.size()),
+            "Range {}..{} is out of the buffer of {} bytes.",
+            offset,
+            offset.saturating_add(bytes.len() as u64),
+            dst.size()
+        );
+        self.stage_upload(
+            bytes,
+            UploadDestination::Buffer {
+                buffer: dst.buffer,
+                offset,
+            },
+        )
+    }
+
+    /// Uploads the texels of the whole `dst`, tightly packed row by row, in the same way as
+    /// `upload_buffer`.
+    /// Panics if the format has no known texel size or `data` doesn't cover the image.
+    pub fn upload_storage_image(&self, dst: &StorageImage, data: &[u8]) -> UploadHandle {
+        let texel_size = texel_size(dst.format())
+            .unwrap_or_else(|| panic!("Uploads to {:?} images are not supported.", dst.format()));
+        let extent = dst.extent();
+        let size = extent.width as u64 * extent.height as u64 * texel_size;
+        assert!(
+            data.len() as u64 == size,
+            "The upload has {} bytes instead of the {} bytes of the image.",
+            data.len(),
+            size
+        );
+        self.stage_upload(
+            data,
+            UploadDestination::Image {
+                image: dst.image,
+                extent,
+            },
+        )
+    }
+
+    /// Writes `data` to staging memory and adds its copy to the pending batch.
+    /// When the ring is full, the pending batch is submitted and the oldest one waited for.
+    fn stage_upload(&self, data: &[u8], dst: UploadDestination) -> UploadHandle {
+        let size = data.len() as u64;
+        if size > STAGING_RING_SIZE {
+            let staging = StagingBuffer::new(self, size, vk::BufferUsageFlags::TRANSFER_SRC)
+                .expect("Create staging buffer failed.");
+            staging.write(0, data);
+            let mut uploads = self.uploads.lock().unwrap();
+            let batch = uploads.pending_batch();
+            batch.copies.push(UploadCopy {
+                staging: staging.buffer,
+                staging_offset: 0,
+                size,
+                dst,
+            });
+            batch.dedicated.push(staging);
+            return UploadHandle(batch.number);
+        }
+
+        let offset = loop {
+            let mut uploads = self.uploads.lock().unwrap();
+            if uploads.ring.is_none() {
+                uploads.ring = Some(
+                    StagingBuffer::new(self, STAGING_RING_SIZE, vk::BufferUsageFlags::TRANSFER_SRC)
+                        .expect("Create staging buffer failed."),
+                );
+            }
+            if let Some(offset) = uploads.allocate(size) {
+                break offset;
+            }
+            drop(uploads);
+            self.flush_uploads();
+            let oldest = self
+                .uploads
+                .lock()
+                .unwrap()
+                .submitted
+                .front()
+                .map(|batch| batch.submission);
+            if let Some(submission) = oldest {
+                self.transfer_queue.timeline.wait(&self.device, submission);
+            }
+            self.uploads.lock().unwrap().release_staging(
+                &self.device,
+                self.transfer_queue.timeline.completed(&self.device),
+            );
+        };
+        let mut uploads = self.uploads.lock().unwrap();
+        let ring = uploads.ring.as_ref().unwrap();
+        ring.write(offset, data);
+        let staging = ring.buffer;
+        let batch = uploads.pending_batch();
+        batch.copies.push(UploadCopy {
+            staging,
+            staging_offset: offset,
+            size,
+            dst,
+        });
+        UploadHandle(batch.number)
+    }
+
+    /// Submits the pending uploads to the transfer queue without waiting for the next frame.
+    pub fn flush_uploads(&self) {
+        let _recording = self.transfer_queue.recording.lock().unwrap();
+        let Some(batch) = self.uploads.lock().unwrap().pending.take() else {
+            return;
+        };
+        let command_buffer = self
+            .transfer_queue
+            .begin(&self.device)
+            .expect("Begin commandbuffer failed.");
+        let mut wait_semaphores = self.acquire_released(QueueType::Transfer, command_buffer);
+        let families = self.has_dedicated_queue(QueueType::Transfer).then_some((
+            self.transfer_queue.family_index,
+            self.graphics_queue_family_index,
+        ));
+        let mut resources = Vec::new();
+        let mut released = Vec::new();
+        let mut used_before = false;
+        {
+            let mut states = self.resource_states.lock().unwrap();
+            for copy in &batch.copies {
+                let resource = copy.dst.resource();
+                if resources.contains(&resource) {
+                    continue;
+                }
+                let src = states.hand_over(resource);
+                if src != AccessScope::default() {
+                    used_before = true;
+                    // The rest of the buffer is only preserved if the graphics queue releases
+                    // it. Images are overwritten whole.
+                    if families.is_some() && matches!(resource, TrackedResource::Buffer(_)) {
+                        released.push((resource, src));
+                    }
+                }
+                resources.push(resource);
+            }
+        }
+        if let Some((transfer_family, graphics_family)) = families.filter(|_| !released.is_empty())
+        {
+            let release_families = (graphics_family, transfer_family);
+            self.submit_setup_commands(|setup_command_buffer| {
+                for &(resource, src) in &released {
+                    record_ownership_transfer(
+                        &self.synchronization,
+                        setup_command_buffer,
+                        resource,
+                        release_families,
+                        src,
+                        AccessScope::default(),
+                    );
+                }
+            });
+            let dst = AccessScope {
+                stages: vk::PipelineStageFlags2::COPY,
+                access: vk::AccessFlags2::TRANSFER_WRITE,
+            };
+            for &(resource, _) in &released {
+                record_ownership_transfer(
+                    &self.synchronization,
+                    command_buffer,
+                    resource,
+                    release_families,
+                    AccessScope::default(),
+                    dst,
+                );
+            }
+        }
+        let mut copied = Vec::new();
+        for copy in &batch.copies {
+            let resource = copy.dst.resource();
+            if copied.contains(&resource) {
+                // Copies to the same resource may overlap, so they stay in order.
+                let barrier = vk::MemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .build();
+                self.synchronization
+                    .pipeline_barrier(command_buffer, &[barrier], &[], &[]);
+            } else {
+                copied.push(resource);
+            }
+            record_upload_copy(&self.device, command_buffer, copy);
+        }
+        if used_before {
+            // The destinations may still be in use by submitted frames.
+            let submission = self.timeline.last_submitted();
+            match self.timeline.wait_info(submission) {
+                Some(wait_info) => wait_semaphores.push(wait_info),
+                None => self.timeline.wait(&self.device, submission),
+            }
+        }
+        if let Some(families) = families {
+            let src = AccessScope {
+                stages: vk::PipelineStageFlags2::COPY,
+                access: vk::AccessFlags2::TRANSFER_WRITE,
             };
+            for &resource in &resources {
+                record_ownership_transfer(
+                    &self.synchronization,
+                    command_buffer,
+                    resource,
+                    families,
+                    src,
+                    AccessScope::default(),
+                );
+            }
+        }
+        let submission = {
+            let _queue_lock = self.queue_lock.lock().unwrap();
+            self.transfer_queue
+                .submit(
+                    &self.device,
+                    &self.synchronization,
+                    command_buffer,
+                    &wait_semaphores,
+                )
+                .expect("Queue submit failed.")
+        };
+        self.uploads
+            .lock()
+            .unwrap()
+            .push_submitted(batch, submission, resources, families);
+    }
 
-            self.device
-                .wait_for_fences(&[self.render_fence], true, std::u64::MAX)
-                .expect("Wait for fence failed.");
-            self.device
-                .reset_fences(&[self.render_fence])
-                .expect("Reset fences failed.");
+    /// Whether the upload of `handle` and the ones before it completed and were acquired by
+    /// the graphics queue, so that the current frame can use their resources.
+    pub fn is_upload_complete(&self, handle: UploadHandle) -> bool {
+        handle.0 <= self.uploads.lock().unwrap().usable
+    }
+
+    /// Hands the resources of completed uploads over to the graphics command buffer being
+    /// begun, then submits the pending uploads.
+    fn update_uploads(&self) {
+        let completed = self.transfer_queue.timeline.completed(&self.device);
+        {
+            let mut uploads = self.uploads.lock().unwrap();
+            uploads.release_staging(&self.device, completed);
+            let mut pending_acquires = self.pending_acquires.lock().unwrap();
+            for batch in std::mem::take(&mut uploads.completed) {
+                pending_acquires.extend(batch.resources.iter().map(|&resource| PendingAcquire {
+                    resource,
+                    src_queue: QueueType::Transfer,
+                    dst_queue: QueueType::Graphics,
+                    submission: batch.submission,
+                    families: batch.families,
+                }));
+                uploads.usable = batch.number;
+            }
+        }
+        self.flush_uploads();
+    }
+
+    /// Forgets the tracked state of a resource being destroyed, after the uploads copying to
+    /// it complete.
+    pub(crate) fn forget_resource(&self, resource: TrackedResource) {
+        let copying = self
+            .uploads
+            .lock()
+            .unwrap()
+            .submitted
+            .iter()
+            .filter(|batch| batch.resources.contains(&resource))
+            .map(|batch| batch.submission)
+            .max();
+        if let Some(submission) = copying {
+            self.transfer_queue.timeline.wait(&self.device, submission);
+        }
+        self.uploads.lock().unwrap().forget(resource);
+        self.pending_acquires
+            .lock()
+            .unwrap()
+            .retain(|acquire| acquire.resource != resource);
+        self.resource_states.lock().unwrap().forget(resource);
+        let handle = match resource {
+            TrackedResource::Buffer(buffer) => vk::Handle::as_raw(buffer),
+            TrackedResource::Image(image) => vk::Handle::as_raw(image),
+        };
+        self.forget_bindless(handle);
+    }
+
+    /// Frees the bindless slots of a resource being destroyed.
+    pub(crate) fn forget_bindless(&self, handle: u64) {
+        if let Some(table) = &self.bindless {
+            table.forget(handle, self.bindless_submitted());
+        }
+    }
+
+    /// Whether the device supports descriptor indexing, which the bindless table needs.
+    pub fn supports_bindless(&self) -> bool {
+        self.bindless.is_some()
+    }
+
+    /// Adds `buffer` to the storage buffers of the bindless table, returning its index.
+    /// A buffer keeps one index until it is dropped or removed with `remove_bindless`.
+    /// Bindless resources aren't tracked by draws and dispatches, so commands writing them
+    /// declare it with `CommandEncoder::use_buffer`.
+    /// Panics if bindless isn't supported, the buffer lacks the storage usage or the binding
+    /// is full.
+    pub fn bindless_buffer(&self, buffer: &Buffer) -> BindlessIndex {
+        assert!(
+            buffer
+                .usage()
+                .contains(vk::BufferUsageFlags::STORAGE_BUFFER),
+            "Bindless buffers need the STORAGE_BUFFER usage."
+        );
+        let buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer.buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+        self.add_bindless(
+            BindlessBinding::StorageBuffer,
+            vk::Handle::as_raw(buffer.buffer),
+            vk::WriteDescriptorSet::builder().buffer_info(&buffer_info),
+        )
+    }
+
+    /// Adds `image` to the sampled images of the bindless table, to be sampled as a texture
+    /// with a bindless sampler. See `bindless_buffer`.
+    pub fn bindless_texture(&self, image: &StorageImage) -> BindlessIndex {
+        self.add_bindless_image(BindlessBinding::SampledImage, image)
+    }
+
+    /// Adds `image` to the storage images of the bindless table. See `bindless_buffer`.
+    pub fn bindless_storage_image(&self, image: &StorageImage) -> BindlessIndex {
+        self.add_bindless_image(BindlessBinding::StorageImage, image)
+    }
+
+    /// Adds `sampler` to the samplers of the bindless table. See `bindless_buffer`.
+    pub fn bindless_sampler(&self, sampler: &Sampler) -> BindlessIndex {
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .sampler(sampler.sampler)
+            .build()];
+        self.add_bindless(
+            BindlessBinding::Sampler,
+            vk::Handle::as_raw(sampler.sampler),
+            vk::WriteDescriptorSet::builder().image_info(&image_info),
+        )
+    }
+
+    /// Frees the slot of `index` before its resource is dropped. The slot is reused once the
+    /// submissions recorded so far complete, so shaders must not index it anymore.
+    pub fn remove_bindless(&self, index: BindlessIndex) {
+        self.bindless_table()
+            .remove(index, self.bindless_submitted());
+    }
+
+    fn add_bindless_image(&self, binding: BindlessBinding, image: &StorageImage) -> BindlessIndex {
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(image.view)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .build()];
+        self.add_bindless(
+            binding,
+            vk::Handle::as_raw(image.image),
+            vk::WriteDescriptorSet::builder().image_info(&image_info),
+        )
+    }
+
+    fn add_bindless(
+        &self,
+        binding: BindlessBinding,
+        handle: u64,
+        write: vk::WriteDescriptorSetBuilder<'_>,
+    ) -> BindlessIndex {
+        let completed = [
+            self.timeline.completed(&self.device),
+            self.compute_queue.timeline.completed(&self.device),
+        ];
+        self.bindless_table()
+            .add(&self.device, binding, handle, completed, write)
+    }
+
+    fn bindless_table(&self) -> &BindlessTable {
+        self.bindless
+            .as_ref()
+            .expect("The device doesn't support bindless descriptors.")
+    }
+
+    /// Submissions that may use the bindless slots freed now.
+    fn bindless_submitted(&self) -> QueueProgress {
+        [
+            self.timeline.last_submitted(),
+            self.compute_queue.timeline.last_submitted(),
+        ]
+    }
+
+    /// Records the acquires of the resources released to `queue` by submitted command buffers,
+    /// and returns the submissions of other queues the command buffer must wait for.
+    /// Without timeline semaphores, the CPU waits for them instead.
+    fn acquire_released(
+        &self,
+        queue: QueueType,
+        command_buffer: vk::CommandBuffer,
+    ) -> Vec<vk::SemaphoreSubmitInfo> {
+        let acquires = take_acquires(
+            &mut self.pending_acquires.lock().unwrap(),
+            queue,
+            |src_queue| self.timeline_of(src_queue).last_submitted(),
+        );
+        record_acquires(&self.synchronization, command_buffer, &acquires);
+        acquire_waits(&acquires)
+            .into_iter()
+            .filter_map(|(src_queue, value)| {
+                let timeline = self.timeline_of(src_queue);
+                let wait_info = timeline.wait_info(value);
+                if wait_info.is_none() {
+                    timeline.wait(&self.device, value);
+                }
+                wait_info
+            })
+            .collect()
+    }
+
+    fn async_queue(&self, queue: QueueType) -> &AsyncQueue {
+        match queue {
+            QueueType::Graphics => panic!("The graphics queue submits frames."),
+            QueueType::Compute => &self.compute_queue,
+            QueueType::Transfer => &self.transfer_queue,
+        }
+    }
+
+    pub(crate) fn timeline_of(&self, queue: QueueType) -> &SubmissionTimeline {
+        match queue {
+            QueueType::Graphics => &self.timeline,
+            _ => &self.async_queue(queue).timeline,
+        }
+    }
+
+    pub(crate) fn queue_family_index(&self, queue: QueueType) -> u32 {
+        match queue {
+            QueueType::Graphics => self.graphics_queue_family_index,
+            _ => self.async_queue(queue).family_index,
+        }
+    }
+
+    /// Whether `queue` has a family of its own, so that its work overlaps the graphics queue.
+    pub fn has_dedicated_queue(&self, queue: QueueType) -> bool {
+        self.queue_family_index(queue) != self.graphics_queue_family_index
+    }
+
+    /// Whether the GPU completed `submission` and every one before it on the same queue.
+    pub fn is_queue_submission_complete(&self, submission: QueueSubmission) -> bool {
+        self.timeline_of(submission.queue).completed(&self.device) >= submission.value
+    }
+
+    /// Blocks until the GPU completes `submission`.
+    pub fn wait_for_queue_submission(&self, submission: QueueSubmission) {
+        let timeline = self.timeline_of(submission.queue);
+        assert!(
+            submission.value <= timeline.last_submitted(),
+            "Submission {:?} hasn't been submitted.",
+            submission
+        );
+        timeline.wait(&self.device, submission.value);
+    }
+
+    /// Value of the last submission to the graphics queue, of a frame, `execute` or the setup
+    /// of a resource. Submissions are numbered from 1 in order, so a resource used by the
+    /// commands of `CommandEncoder::submission` can be reused or read back once that
+    /// submission is complete.
+    pub fn last_submission(&self) -> u64 {
+        self.timeline.last_submitted()
+    }
+
+    /// Whether the GPU completed the submission `value` and every one before it.
+    pub fn is_submission_complete(&self, value: u64) -> bool {
+        self.timeline.completed(&self.device) >= value
+    }
+
+    /// Blocks until the GPU completes the submission `value`.
+    /// Panics if `value` hasn't been submitted yet, as it would never complete.
+    pub fn wait_for_submission(&self, value: u64) {
+        assert!(
+            value <= self.timeline.last_submitted(),
+            "Submission {} hasn't been submitted.",
+            value
+        );
+        self.timeline.wait(&self.device, value);
+    }
+
+    /// Whether barriers and submissions use synchronization2, which needs the option and a
+    /// device supporting it.
+    pub fn uses_synchronization2(&self) -> bool {
+        self.synchronization.is_synchronization2()
+    }
+
+    /// Whether submissions signal a timeline semaphore rather than fences.
+    pub fn uses_timeline_semaphores(&self) -> bool {
+        self.timeline.uses_timeline_semaphore()
+    }
+
+    /// Resets the command buffer of a frame whose submission is complete, collects its query
+    /// results and begins recording with the acquires of buffers released to the graphics
+    /// queue. Returns the semaphores the submission waits for.
+    fn begin_frame_commands(&self, frame: &Frame) -> Vec<vk::SemaphoreSubmitInfo> {
+        unsafe {
+            if let Some(timings) = frame.queries.resolve_timings(&self.device) {
+                *self.gpu_timings.lock().unwrap() = timings;
+            }
+            if let Some(statistics) = frame.queries.resolve_pipeline_statistics(&self.device) {
+                *self.pipeline_statistics.lock().unwrap() = statistics;
+            }
+            if let Some(results) = frame.queries.resolve_occlusion(&self.device) {
+                *self.occlusion_results.lock().unwrap() = results;
+            }
 
             self.device
                 .reset_command_buffer(
-                    self.draw_command_buffer,
+                    frame.command_buffer,
                     vk::CommandBufferResetFlags::RELEASE_RESOURCES,
                 )
                 .expect("Reset command buffer failed.");
+            frame
+                .reset_thread_pools(&self.device)
+                .expect("Reset command pool failed.");
+            frame
+                .transient_descriptors
+                .lock()
+                .unwrap()
+                .reset(&self.device)
+                .expect("Reset descriptor pool failed.");
+            frame.transient_uniforms.lock().unwrap().reset();
 
             let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
                 .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
                 .build();
 
             self.device
-                .begin_command_buffer(self.draw_command_buffer, &command_buffer_begin_info)
+                .begin_command_buffer(frame.command_buffer, &command_buffer_begin_info)
                 .expect("Begin commandbuffer failed.");
 
-            let clear_values = [vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.5, 1.0],
-                },
-            }];
+            frame.queries.reset(&self.device, frame.command_buffer);
+        }
+        self.update_uploads();
+        self.acquire_released(QueueType::Graphics, frame.command_buffer)
+    }
 
-            swapchain.begin_render_pass(&clear_values, &self.draw_command_buffer);
+    /// Records one-off commands, such as layout transitions of new resources, into the setup
+    /// command buffer, submits them to the graphics queue and waits until they complete.
+    pub(crate) fn submit_setup_commands<F>(&self, record: F)
+    where
+        F: FnOnce(vk::CommandBuffer),
+    {
+        let _queue_lock = self.queue_lock.lock().unwrap();
+        unsafe {
+            self.device
+                .reset_command_buffer(
+                    self.setup_command_buffer,
+                    vk::CommandBufferResetFlags::RELEASE_RESOURCES,
+                )
+                .expect("Reset command buffer failed.");
+            let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build();
+            self.device
+                .begin_command_buffer(self.setup_command_buffer, &command_buffer_begin_info)
+                .expect("Begin commandbuffer failed.");
 
-            swapchain.end_render_pass(&self.draw_command_buffer);
+            record(self.setup_command_buffer);
 
             self.device
-                .end_command_buffer(self.draw_command_buffer)
+                .end_command_buffer(self.setup_command_buffer)
                 .expect("End commandbuffer failed.");
+        }
+        let submission = self
+            .timeline
+            .submit(
+                &self.device,
+                &self.synchronization,
+                self.present_queue,
+                self.setup_command_buffer,
+                &[],
+                &[],
+            )
+            .expect("Queue submit failed.");
+        // The setup command buffer is reused once the submission completes.
+        self.timeline.wait(&self.device, submission);
+    }
 
-            let submit_info = vk::SubmitInfo::builder()
-                .wait_semaphores(&[self.present_semaphore])
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                .command_buffers(&[self.draw_command_buffer])
-                .signal_semaphores(&[self.render_semaphore])
-                .build();
+    /// Merges the pipeline cache saved by `save_pipeline_cache` into the renderer's cache.
+    /// Fails with `io::ErrorKind::InvalidData` if the file was written by another device or
+    /// driver version, so that stale caches are never handed to the driver.
+    pub fn load_pipeline_cache<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+        };
+        let data = read_pipeline_cache_file(path.as_ref(), &properties)?;
+        unsafe {
+            let loaded_cache = create_pipeline_cache(&self.device, &data)
+                .map_err(|r| io::Error::new(io::ErrorKind::InvalidData, r))?;
+            let result = self
+                .device
+                .merge_pipeline_caches(self.pipeline_cache, &[loaded_cache]);
+            self.device.destroy_pipeline_cache(loaded_cache, None);
+            result.map_err(io::Error::other)
+        }
+    }
 
-            self.device
-                .queue_submit(self.present_queue, &[submit_info], self.render_fence)
-                .expect("Queue submit failed.");
+    /// Writes the pipelines compiled so far, to be loaded on the next launch.
+    pub fn save_pipeline_cache<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        write_pipeline_cache_file(&self.device, self.pipeline_cache, path.as_ref())
+    }
 
-            swapchain
-                .present(&self.render_semaphore, &self.present_queue)
-                .unwrap();
+    /// Creates a shader from any set of graphics stages, such as vertex, tessellation,
+    /// geometry and fragment. Panics under the conditions of `create_shader`, or if the stages
+    /// don't make a pipeline the device supports.
+    pub fn create_shader_with_stages(
+        self: &Arc<Self>,
+        stages: &[(ShaderStage, tr::ShaderSource)],
+    ) -> Shader {
+        Shader::new(self, stages)
+    }
+
+    /// Declares the variants of a set of shader stage files, compiled on demand with `keywords`.
+    pub fn create_shader_variants(
+        self: &Arc<Self>,
+        stage_paths: &[(ShaderStage, &Path)],
+        keywords: &[&str],
+        options: &ShaderCompileOptions,
+    ) -> ShaderVariants {
+        ShaderVariants::new(self, stage_paths, keywords, options)
+    }
+
+    /// Creates a material reading the vertex buffers of `vertex_layout`, such as per-instance
+    /// bindings, and rendering to attachments of `attachment_formats`.
+    /// Panics under the conditions of `create_material`.
+    pub fn create_material_with_layout(
+        self: &Arc<Self>,
+        shader: &Arc<Shader>,
+        vertex_layout: &VertexLayout,
+        attachment_formats: &AttachmentFormats,
+        specialization_constants: &[(&str, tr::SpecializationValue)],
+    ) -> Material {
+        Material::new(
+            self,
+            shader,
+            Vec::new(),
+            vertex_layout.clone(),
+            attachment_formats.clone(),
+            specialization_constants,
+        )
+    }
+
+    /// Creates a compute shader. Panics if the source fails to translate to SPIR-V.
+    pub fn create_compute_shader(self: &Arc<Self>, source: &tr::ShaderSource) -> ComputeShader {
+        ComputeShader::new(self, source)
+    }
+
+    /// Creates a compute pipeline, with its descriptor sets reflected from `shader`.
+    pub fn create_compute_pipeline(
+        self: &Arc<Self>,
+        shader: &Arc<ComputeShader>,
+        specialization_constants: &[(&str, tr::SpecializationValue)],
+    ) -> ComputePipeline {
+        ComputePipeline::new(self, shader, specialization_constants)
+    }
+
+    /// Creates a sampler for bindless textures, filtering with `filter` when magnifying and
+    /// minifying.
+    pub fn create_sampler(
+        self: &Arc<Self>,
+        filter: vk::Filter,
+        address_mode: vk::SamplerAddressMode,
+    ) -> Sampler {
+        Sampler::new(self, filter, address_mode).expect("Create sampler failed.")
+    }
+
+    pub fn create_buffer(
+        self: &Arc<Self>,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> Buffer {
+        Buffer::new(self, size, usage, location).expect("Create buffer failed.")
+    }
+
+    /// Registers `resource` and returns the handle to reference it by, e.g. from plain data.
+    /// The registry owns the resource until `unregister`. `name` identifies it in the report
+    /// of `unregister_all`.
+    /// Registered resources keep the renderer alive, so they are unregistered before shutdown.
+    pub fn register<T: RegisteredResource>(
+        &self,
+        name: &str,
+        resource: impl Into<Arc<T>>,
+    ) -> Handle<T> {
+        self.registry.lock().unwrap().insert(name, resource.into())
+    }
+
+    /// Resource registered as `handle`, or `None` once it is unregistered.
+    pub fn resource<T: RegisteredResource>(&self, handle: Handle<T>) -> Option<Arc<T>> {
+        self.registry.lock().unwrap().get(handle)
+    }
+
+    /// Removes the resource of `handle` from the registry and returns it, so that it is
+    /// destroyed when the last reference is dropped. Returns `None` for a stale handle.
+    pub fn unregister<T: RegisteredResource>(&self, handle: Handle<T>) -> Option<Arc<T>> {
+        self.registry.lock().unwrap().remove(handle)
+    }
+
+    /// Number of resources currently registered.
+    pub fn registered_resource_count(&self) -> usize {
+        self.registry.lock().unwrap().len()
+    }
+
+    /// Unregisters every resource still registered, e.g. at shutdown, and returns a report of
+    /// each one for the caller to log, as they should have been unregistered by their owners.
+    pub fn unregister_all(&self) -> Vec<ResourceLeak> {
+        let drained = self.registry.lock().unwrap().drain();
+        drained.into_iter().map(|(leak, _resource)| leak).collect()
+    }
+
+    /// Whether passes use dynamic rendering, which needs the option and a Vulkan 1.3 device.
+    pub fn uses_dynamic_rendering(&self) -> bool {
+        self.dynamic_rendering
+    }
+
+    /// Render pass with `formats` to create pipelines against without dynamic rendering.
+    /// It is compatible with any render pass with the same formats.
+    pub(crate) fn compatible_render_pass(
+        &self,
+        formats: &AttachmentFormats,
+    ) -> VkResult<vk::RenderPass> {
+        let mut render_passes = self.compatible_render_passes.lock().unwrap();
+        if let Some(&render_pass) = render_passes.get(formats) {
+            return Ok(render_pass);
         }
+        let render_pass = create_render_pass(
+            &self.device,
+            &formats
+                .color
+                .iter()
+                .map(|&format| (format, vk::AttachmentLoadOp::DONT_CARE))
+                .collect::<Vec<_>>(),
+            formats
+                .depth
+                .map(|format| (format, vk::AttachmentLoadOp::DONT_CARE)),
+        )?;
+        render_passes.insert(formats.clone(), render_pass);
+        Ok(render_pass)
+    }
+
+    /// Formats of the last swapchain created, which materials render to by default.
+    pub fn swapchain_attachment_formats(&self) -> AttachmentFormats {
+        AttachmentFormats::new(vec![*self.swapchain_format.lock().unwrap()], None)
+    }
+
+    /// Whether the encoder can record `draw_indirect_count` and `draw_indexed_indirect_count`.
+    pub fn supports_draw_indirect_count(&self) -> bool {
+        self.draw_indirect_count_loader.is_some()
+    }
+
+    /// Creates a storage buffer of `len` elements of `T`, with `usage` in addition to the
+    /// storage and transfer usages, e.g. `INDIRECT_BUFFER` for GPU-driven draws.
+    pub fn create_storage_buffer<T: bytemuck::Pod>(
+        self: &Arc<Self>,
+        len: usize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> StorageBuffer<T> {
+        StorageBuffer::new(self, len, usage, location)
+    }
+
+    /// Creates a vertex buffer of `len` instances of `T`, bound with
+    /// `CommandEncoder::bind_vertex_buffers` to a per-instance binding.
+    pub fn create_instance_buffer<T: bytemuck::Pod>(
+        self: &Arc<Self>,
+        len: usize,
+        location: MemoryLocation,
+    ) -> InstanceBuffer<T> {
+        InstanceBuffer::new(self, len, location)
+    }
+
+    /// Creates a 2D storage image, ready to be bound in the `GENERAL` layout.
+    pub fn create_storage_image(
+        self: &Arc<Self>,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+    ) -> StorageImage {
+        StorageImage::new(self, width, height, format).expect("Create storage image failed.")
+    }
+
+    /// GPU timings of the latest completed frame that recorded `gpu_timer` scopes.
+    /// They lag behind the frame being recorded by `FRAMES_IN_FLIGHT` frames.
+    pub fn gpu_timings(&self) -> Vec<GpuTiming> {
+        self.gpu_timings.lock().unwrap().clone()
+    }
+
+    /// Pipeline statistics of the latest completed frame that recorded
+    /// `pipeline_statistics_query` scopes. Empty if the device lacks `pipelineStatisticsQuery`.
+    pub fn pipeline_statistics(&self) -> Vec<PipelineStatistics> {
+        self.pipeline_statistics.lock().unwrap().clone()
+    }
+
+    /// Occlusion results of the latest completed frame that recorded `occlusion_query` scopes.
+    pub fn occlusion_results(&self) -> Vec<OcclusionResult> {
+        self.occlusion_results.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+            self.frames
+                .iter()
+                .for_each(|frame| frame.destroy(&self.device));
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device
+                .destroy_command_pool(self.setup_command_pool, None);
+            self.timeline.destroy(&self.device);
+            self.compute_queue.destroy(&self.device);
+            self.transfer_queue.destroy(&self.device);
+            self.uploads.lock().unwrap().destroy(&self.device);
+            if let Some(table) = &self.bindless {
+                table.destroy(&self.device);
+            }
+            self.descriptor_sets.lock().unwrap().destroy(&self.device);
+            self.descriptor_layouts
+                .lock()
+                .unwrap()
+                .destroy(&self.device);
+            self.compatible_render_passes
+                .lock()
+                .unwrap()
+                .values()
+                .for_each(|&render_pass| self.device.destroy_render_pass(render_pass, None));
+            self.device
+                .destroy_pipeline_cache(self.pipeline_cache, None);
+            self.debug_utils_loader
+                .destroy_debug_utils_messenger(self.debug_callback, None);
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+impl tr::Renderer for Renderer {
+    type Swapchain = VulkanSwapchain;
+    type Shader = Shader;
+    type Material = Material;
+
+    fn render(&self, swapchain: &Self::Swapchain) {
+        self.render_with(swapchain, |_| {});
     }
 
     fn create_swapchain(
-        self: &Rc<Self>,
+        self: &Arc<Self>,
         display_handle: &RawDisplayHandle,
         window_handle: &raw_window_handle::RawWindowHandle,
-        window_size_provider: &Rc<dyn tempura_render::WindowSizeProvider>,
+        window_size_provider: &Arc<dyn tempura_render::WindowSizeProvider>,
     ) -> Self::Swapchain {
+        assert!(
+            !self.headless,
+            "A headless renderer can't create swapchains."
+        );
         VulkanSwapchain::new(self, display_handle, window_handle, window_size_provider)
     }
 
     fn create_shader(
-        self: &Rc<Self>,
-        vertex_shader_code: &Vec<u8>,
-        fragment_shader_code: &Vec<u8>,
+        self: &Arc<Self>,
+        vertex_shader: &tr::ShaderSource,
+        fragment_shader: &tr::ShaderSource,
     ) -> Self::Shader {
-        Shader::new(self, vertex_shader_code, fragment_shader_code)
+        Shader::new(
+            self,
+            &[
+                (ShaderStage::Vertex, *vertex_shader),
+                (ShaderStage::Fragment, *fragment_shader),
+            ],
+        )
     }
 
-    fn create_material(self: &Rc<Self>, shader: &Rc<Self::Shader>) -> Self::Material {
-        Material::new(self, shader)
+    fn create_material(
+        self: &Arc<Self>,
+        shader: &Arc<Self::Shader>,
+        specialization_constants: &[(&str, tr::SpecializationValue)],
+    ) -> Self::Material {
+        Material::new(
+            self,
+            shader,
+            Vec::new(),
+            VertexLayout::default(),
+            self.swapchain_attachment_formats(),
+            specialization_constants,
+        )
     }
 }
 
@@ -245,7 +1541,8 @@ unsafe extern "system" fn vulkan_debug_callback(
 
 /// Create Instance.
 /// In case of develop feature, Validation layer etc. will be added.
-fn create_instance(entry: &Entry, display_handle: &RawDisplayHandle) -> VkResult<Instance> {
+/// Without a display, no surface extension is enabled.
+fn create_instance(entry: &Entry, display_handle: Option<&RawDisplayHandle>) -> VkResult<Instance> {
     unsafe {
         let app_name = CString::new("tempura").unwrap();
         let engine_name = CString::new("tempura").unwrap();
@@ -293,9 +1590,12 @@ fn create_instance(entry: &Entry, display_handle: &RawDisplayHandle) -> VkResult
                 }
             })
             .collect::<Vec<*const c_char>>();
-        let mut extension_names = ash_window::enumerate_required_extensions(*display_handle)
-            .expect("enumerate required extensions error")
-            .to_vec();
+        let mut extension_names = match display_handle {
+            Some(display_handle) => ash_window::enumerate_required_extensions(*display_handle)
+                .expect("enumerate required extensions error")
+                .to_vec(),
+            None => Vec::new(),
+        };
         extension_names.push(DebugUtils::name().as_ptr());
         #[cfg(any(target_os = "macos", target_os = "ios"))]
         {
@@ -350,62 +1650,152 @@ fn pick_physical_device(instance: &Instance) -> Option<vk::PhysicalDevice> {
                 .device_type
                 == vk::PhysicalDeviceType::DISCRETE_GPU
         });
-        if let Some(pdevice) = discrete {
-            Some(*pdevice)
-        } else if let Some(pdevice) = filtered.first() {
-            Some(*pdevice)
-        } else {
-            None
+        discrete.or(filtered.first()).copied()
+    }
+}
+
+fn get_queue_families(instance: &Instance, pdevice: &vk::PhysicalDevice) -> Option<QueueFamilies> {
+    let properties = unsafe { instance.get_physical_device_queue_family_properties(*pdevice) };
+    QueueFamilies::select(&properties)
+}
+
+fn get_query_capabilities(
+    instance: &Instance,
+    pdevice: &vk::PhysicalDevice,
+    queue_family_index: u32,
+) -> QueryCapabilities {
+    unsafe {
+        let timestamp_valid_bits = instance.get_physical_device_queue_family_properties(*pdevice)
+            [queue_family_index as usize]
+            .timestamp_valid_bits;
+        let timestamp_period = instance
+            .get_physical_device_properties(*pdevice)
+            .limits
+            .timestamp_period;
+        let features = instance.get_physical_device_features(*pdevice);
+        QueryCapabilities {
+            timestamp_valid_bits,
+            timestamp_period,
+            pipeline_statistics_query: features.pipeline_statistics_query == vk::TRUE,
+            occlusion_query_precise: features.occlusion_query_precise == vk::TRUE,
         }
     }
 }
 
-fn get_graphics_queue_family_index(
+/// Features to enable, the optional ones only if the device supports them.
+fn get_enabled_features(
     instance: &Instance,
     pdevice: &vk::PhysicalDevice,
-) -> Option<u32> {
+    query_capabilities: &QueryCapabilities,
+) -> vk::PhysicalDeviceFeatures {
+    let supported = unsafe { instance.get_physical_device_features(*pdevice) };
+    vk::PhysicalDeviceFeatures {
+        shader_clip_distance: 1,
+        pipeline_statistics_query: query_capabilities.pipeline_statistics_query.into(),
+        occlusion_query_precise: query_capabilities.occlusion_query_precise.into(),
+        tessellation_shader: supported.tessellation_shader,
+        geometry_shader: supported.geometry_shader,
+        multi_draw_indirect: supported.multi_draw_indirect,
+        draw_indirect_first_instance: supported.draw_indirect_first_instance,
+        ..Default::default()
+    }
+}
+
+/// Whether the device supports Vulkan 1.3 with the `dynamicRendering` feature.
+fn is_dynamic_rendering_supported(instance: &Instance, pdevice: &vk::PhysicalDevice) -> bool {
+    unsafe {
+        let api_version = instance
+            .get_physical_device_properties(*pdevice)
+            .api_version;
+        if api_version < vk::API_VERSION_1_3 {
+            return false;
+        }
+        let mut vulkan13_features = vk::PhysicalDeviceVulkan13Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut vulkan13_features)
+            .build();
+        instance.get_physical_device_features2(*pdevice, &mut features);
+        vulkan13_features.dynamic_rendering == vk::TRUE
+    }
+}
+
+/// Whether the device supports the `timelineSemaphore` feature. It must support Vulkan 1.2.
+fn is_timeline_semaphore_supported(instance: &Instance, pdevice: &vk::PhysicalDevice) -> bool {
+    unsafe {
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut vulkan12_features)
+            .build();
+        instance.get_physical_device_features2(*pdevice, &mut features);
+        vulkan12_features.timeline_semaphore == vk::TRUE
+    }
+}
+
+fn is_device_extension_supported(
+    instance: &Instance,
+    pdevice: &vk::PhysicalDevice,
+    name: &CStr,
+) -> bool {
     unsafe {
         instance
-            .get_physical_device_queue_family_properties(*pdevice)
-            .iter()
-            .enumerate()
-            .find_map(|(index, prop)| {
-                if prop.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                    Some(index as u32)
-                } else {
-                    None
-                }
+            .enumerate_device_extension_properties(*pdevice)
+            .map(|properties| {
+                properties
+                    .iter()
+                    .any(|p| CStr::from_ptr(p.extension_name.as_ptr()) == name)
             })
+            .unwrap_or(false)
     }
 }
 
+/// Features to enable chained to the device create info. The optional ones are only chained
+/// for devices supporting their Vulkan version or extension.
+struct ChainedFeatures {
+    vulkan12: Option<vk::PhysicalDeviceVulkan12Features>,
+    vulkan13: Option<vk::PhysicalDeviceVulkan13Features>,
+    /// Feature of `VK_KHR_synchronization2`, for devices below Vulkan 1.3.
+    synchronization2: Option<vk::PhysicalDeviceSynchronization2Features>,
+}
+
+/// Creates the device with `extensions` and the `chained` features.
 fn create_device(
     instance: &Instance,
     pdevice: &vk::PhysicalDevice,
-    graphics_queue_family_index: u32,
+    queue_families: &QueueFamilies,
+    features: &vk::PhysicalDeviceFeatures,
+    extensions: &[&CStr],
+    mut chained: ChainedFeatures,
 ) -> VkResult<Device> {
     unsafe {
-        let extension_names = [
-            ash::extensions::khr::Swapchain::name().as_ptr(),
-            // #[cfg(any(target_os = "macos", target_os = "ios"))]
-            vk::KhrPortabilitySubsetFn::name().as_ptr(),
-        ];
-        let features = vk::PhysicalDeviceFeatures {
-            shader_clip_distance: 1,
-            ..Default::default()
-        };
+        let extension_names = extensions
+            .iter()
+            .map(|name| name.as_ptr())
+            .collect::<Vec<_>>();
         let queue_priorities = [1.0];
-        let queue_info = vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(graphics_queue_family_index)
-            .queue_priorities(&queue_priorities)
-            .build();
-        let queue_infos = [queue_info];
-        let create_info = vk::DeviceCreateInfo::builder()
+        let queue_infos = queue_families
+            .unique()
+            .into_iter()
+            .map(|family_index| {
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(family_index)
+                    .queue_priorities(&queue_priorities)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let mut create_info = vk::DeviceCreateInfo::builder()
             .enabled_extension_names(&extension_names)
-            .enabled_features(&features)
-            .queue_create_infos(&queue_infos)
-            .build();
-        instance.create_device(*pdevice, &create_info, None)
+            .enabled_features(features)
+            .queue_create_infos(&queue_infos);
+        if let Some(vulkan12) = &mut chained.vulkan12 {
+            create_info = create_info.push_next(vulkan12);
+        }
+        if let Some(vulkan13) = &mut chained.vulkan13 {
+            create_info = create_info.push_next(vulkan13);
+        }
+        if let Some(synchronization2) = &mut chained.synchronization2 {
+            create_info = create_info.push_next(synchronization2);
+        }
+        instance.create_device(*pdevice, &create_info.build(), None)
     }
 }
 
@@ -422,13 +1812,226 @@ fn create_command_pool(device: &Device, queue_family_index: u32) -> VkResult<vk:
 fn create_command_buffers(
     device: &Device,
     command_pool: &vk::CommandPool,
+    count: u32,
 ) -> VkResult<Vec<vk::CommandBuffer>> {
     unsafe {
         let allocate_info = vk::CommandBufferAllocateInfo::builder()
-            .command_buffer_count(2)
+            .command_buffer_count(count)
             .command_pool(*command_pool)
             .level(vk::CommandBufferLevel::PRIMARY)
             .build();
         device.allocate_command_buffers(&allocate_info)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vulkan::ResourceAccess;
+
+    /// Tests needing a Vulkan implementation with a graphics queue, such as lavapipe, are
+    /// ignored by default and run with `cargo test -- --ignored`, where they fail without one.
+    fn headless_renderer(options: &RendererOptions) -> Arc<Renderer> {
+        Arc::new(Renderer::headless(options))
+    }
+
+    #[test]
+    fn shares_the_renderer_and_resources_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Renderer>();
+        assert_send_sync::<Buffer>();
+        assert_send_sync::<StorageBuffer<u32>>();
+        assert_send_sync::<InstanceBuffer<u32>>();
+        assert_send_sync::<StorageImage>();
+        assert_send_sync::<Sampler>();
+        assert_send_sync::<Shader>();
+        assert_send_sync::<Material>();
+        assert_send_sync::<ComputeShader>();
+        assert_send_sync::<ComputePipeline>();
+        assert_send_sync::<VulkanSwapchain>();
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn dispatches_compute_and_reads_the_result_back() {
+        // The fallbacks of devices without synchronization2 and timeline semaphores.
+        for (synchronization2, timeline_semaphores) in [(true, true), (false, true), (false, false)]
+        {
+            let options = RendererOptions {
+                synchronization2,
+                timeline_semaphores,
+                ..Default::default()
+            };
+            let renderer = headless_renderer(&options);
+            if !synchronization2 {
+                assert!(!renderer.uses_synchronization2());
+            }
+            if !timeline_semaphores {
+                assert!(!renderer.uses_timeline_semaphores());
+            }
+            dispatch_and_read_back(&renderer);
+        }
+    }
+
+    fn dispatch_and_read_back(renderer: &Arc<Renderer>) {
+        let shader = Arc::new(renderer.create_compute_shader(&tr::ShaderSource::Wgsl {
+            source: "
+                @group(0) @binding(0) var<storage, read_write> values: array<u32>;
+
+                @compute @workgroup_size(64)
+                fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+                    if (id.x < arrayLength(&values)) {
+                        values[id.x] = values[id.x] * 2u;
+                    }
+                }
+            ",
+            entry_point: "main",
+        }));
+        let pipeline = renderer.create_compute_pipeline(&shader, &[]);
+        let values = renderer.create_storage_buffer::<u32>(
+            100,
+            vk::BufferUsageFlags::empty(),
+            MemoryLocation::GpuToCpu,
+        );
+        values.write(0, &(0..100).collect::<Vec<u32>>());
+        pipeline.bind_storage_buffer(0, 0, &values);
+
+        // Submitted twice, so that the second submission waits for the first one.
+        for _ in 0..2 {
+            renderer.execute(|encoder| {
+                encoder.bind_compute_pipeline(&pipeline);
+                encoder.dispatch(2, 1, 1);
+                encoder.use_buffer(values.buffer(), ResourceAccess::HostRead);
+            });
+        }
+
+        assert_eq!(
+            values.read(0, 100),
+            (0..100).map(|value| value * 4).collect::<Vec<u32>>()
+        );
+        assert!(renderer.is_submission_complete(renderer.last_submission()));
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn reads_a_graph_texture_written_by_an_earlier_pass() {
+        for dynamic_rendering in [true, false] {
+            let options = RendererOptions {
+                dynamic_rendering,
+                ..Default::default()
+            };
+            let renderer = headless_renderer(&options);
+            let shader = Arc::new(renderer.create_compute_shader(&tr::ShaderSource::Wgsl {
+                source: "
+                    @group(0) @binding(0) var<storage, read_write> texels: array<u32>;
+                    @group(1) @binding(0) var source: texture_2d<f32>;
+
+                    @compute @workgroup_size(8, 8)
+                    fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+                        if (id.x < 8u && id.y < 8u) {
+                            let texel = textureLoad(source, vec2<i32>(id.xy), 0);
+                            texels[id.y * 8u + id.x] = pack4x8unorm(texel);
+                        }
+                    }
+                ",
+                entry_point: "main",
+            }));
+            let pipeline = renderer.create_compute_pipeline(&shader, &[]);
+            let texels = renderer.create_storage_buffer::<u32>(
+                64,
+                vk::BufferUsageFlags::empty(),
+                MemoryLocation::GpuToCpu,
+            );
+            pipeline.bind_storage_buffer(0, 0, &texels);
+
+            let mut graph = tr::RenderGraph::new();
+            let color = graph.create_texture(
+                "color",
+                tr::TextureDesc {
+                    width: 8,
+                    height: 8,
+                    format: tr::TextureFormat::Rgba8Unorm,
+                },
+            );
+            graph.add_pass(
+                "clear",
+                |pass| {
+                    pass.color_attachment(
+                        color,
+                        tr::LoadOp::Clear(tr::ClearValue::Color([1.0, 0.0, 0.0, 1.0])),
+                    )
+                },
+                |_| {},
+            );
+            graph.add_pass(
+                "read",
+                |pass| {
+                    pass.read(color, tr::TextureAccess::Sampled);
+                    pass.side_effect();
+                },
+                |encoder: &CommandEncoder| {
+                    encoder.bind_compute_pipeline(&pipeline);
+                    encoder.bind_graph_textures(1, &[(0, color)]);
+                    encoder.dispatch(1, 1, 1);
+                    encoder.use_buffer(texels.buffer(), ResourceAccess::HostRead);
+                },
+            );
+            renderer.execute_graph(graph);
+
+            assert_eq!(texels.read(0, 64), vec![0xff0000ff; 64]);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn builds_material_pipelines_with_and_without_dynamic_rendering() {
+        let source = "
+            @vertex
+            fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+                let uv = vec2<f32>(f32(index & 1u), f32(index >> 1u));
+                return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+            }
+
+            @fragment
+            fn fs_main() -> @location(0) vec4<f32> {
+                return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+            }
+        ";
+        for dynamic_rendering in [true, false] {
+            let options = RendererOptions {
+                dynamic_rendering,
+                ..Default::default()
+            };
+            let renderer = headless_renderer(&options);
+            if !dynamic_rendering {
+                assert!(!renderer.uses_dynamic_rendering());
+            }
+            let shader = Arc::new(renderer.create_shader_with_stages(&[
+                (
+                    ShaderStage::Vertex,
+                    tr::ShaderSource::Wgsl {
+                        source,
+                        entry_point: "vs_main",
+                    },
+                ),
+                (
+                    ShaderStage::Fragment,
+                    tr::ShaderSource::Wgsl {
+                        source,
+                        entry_point: "fs_main",
+                    },
+                ),
+            ]));
+            renderer.create_material_with_layout(
+                &shader,
+                &VertexLayout::default(),
+                &AttachmentFormats::new(vec![vk::Format::R8G8B8A8_UNORM], None),
+                &[],
+            );
+            assert_eq!(
+                renderer.compatible_render_passes.lock().unwrap().len(),
+                usize::from(!renderer.uses_dynamic_rendering())
+            );
+        }
+    }
+}
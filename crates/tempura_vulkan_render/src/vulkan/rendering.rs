@@ -0,0 +1,182 @@
+use ash::{prelude::VkResult, vk, Device};
+
+/// Formats of the attachments a material renders to, which its pipeline is created against.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AttachmentFormats {
+    pub color: Vec<vk::Format>,
+    pub depth: Option<vk::Format>,
+}
+
+impl AttachmentFormats {
+    pub fn new(color: Vec<vk::Format>, depth: Option<vk::Format>) -> Self {
+        AttachmentFormats { color, depth }
+    }
+}
+
+/// Attachment of a pass: the view to render to, its format and how it starts.
+#[derive(Clone, Copy)]
+pub(crate) struct PassAttachment {
+    pub(crate) view: vk::ImageView,
+    pub(crate) format: vk::Format,
+    pub(crate) load_op: vk::AttachmentLoadOp,
+    pub(crate) clear_value: vk::ClearValue,
+}
+
+fn attachment_layout(depth: bool) -> vk::ImageLayout {
+    if depth {
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+    } else {
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+    }
+}
+
+/// Render pass with one subpass rendering to color attachments and an optional depth
+/// attachment of `(format, load_op)`. The attachments stay in their attachment layouts, so
+/// that barriers outside of the pass transition them.
+/// Any render pass with the same formats is compatible with the pipelines created with it.
+pub(crate) fn create_render_pass(
+    device: &Device,
+    color: &[(vk::Format, vk::AttachmentLoadOp)],
+    depth: Option<(vk::Format, vk::AttachmentLoadOp)>,
+) -> VkResult<vk::RenderPass> {
+    let attachments = color
+        .iter()
+        .map(|&attachment| (attachment, false))
+        .chain(depth.map(|attachment| (attachment, true)))
+        .collect::<Vec<_>>();
+    let descriptions = attachments
+        .iter()
+        .map(|&((format, load_op), depth)| {
+            vk::AttachmentDescription::builder()
+                .format(format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(load_op)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(attachment_layout(depth))
+                .final_layout(attachment_layout(depth))
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let references = attachments
+        .iter()
+        .enumerate()
+        .map(|(index, &(_, depth))| vk::AttachmentReference {
+            attachment: index as u32,
+            layout: attachment_layout(depth),
+        })
+        .collect::<Vec<_>>();
+    let (color_references, depth_reference) = references.split_at(color.len());
+    let mut subpass = vk::SubpassDescription::builder().color_attachments(color_references);
+    if let Some(depth_reference) = depth_reference.first() {
+        subpass = subpass.depth_stencil_attachment(depth_reference);
+    }
+    let create_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&descriptions)
+        .subpasses(&[subpass.build()])
+        .build();
+    unsafe { device.create_render_pass(&create_info, None) }
+}
+
+/// Begins dynamic rendering to attachments in their attachment layouts, with the viewport and
+/// scissor covering `extent`. `flags` tell whether secondary command buffers record the pass,
+/// in which case they set the viewport themselves.
+pub(crate) fn begin_rendering(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    extent: vk::Extent2D,
+    color: &[PassAttachment],
+    depth: Option<PassAttachment>,
+    flags: vk::RenderingFlags,
+) {
+    let attachment_info = |attachment: &PassAttachment, depth: bool| {
+        vk::RenderingAttachmentInfo::builder()
+            .image_view(attachment.view)
+            .image_layout(attachment_layout(depth))
+            .load_op(attachment.load_op)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(attachment.clear_value)
+            .build()
+    };
+    let color_attachments = color
+        .iter()
+        .map(|attachment| attachment_info(attachment, false))
+        .collect::<Vec<_>>();
+    let depth_attachment = depth.map(|attachment| attachment_info(&attachment, true));
+    let mut rendering_info = vk::RenderingInfo::builder()
+        .flags(flags)
+        .render_area(extent.into())
+        .layer_count(1)
+        .color_attachments(&color_attachments);
+    if let Some(depth_attachment) = &depth_attachment {
+        rendering_info = rendering_info.depth_attachment(depth_attachment);
+    }
+    unsafe {
+        device.cmd_begin_rendering(command_buffer, &rendering_info);
+    }
+    if !flags.contains(vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS) {
+        set_viewport(device, command_buffer, extent);
+    }
+}
+
+/// Pass that secondary command buffers record draws into, without beginning it themselves.
+#[derive(Clone, Debug)]
+pub(crate) struct PassInheritance {
+    /// Null with dynamic rendering, where the formats describe the pass instead.
+    pub(crate) render_pass: vk::RenderPass,
+    pub(crate) framebuffer: vk::Framebuffer,
+    pub(crate) color_formats: Vec<vk::Format>,
+    pub(crate) depth_format: Option<vk::Format>,
+    pub(crate) extent: vk::Extent2D,
+}
+
+/// Begins a secondary command buffer continuing the pass of `inheritance`, with the viewport
+/// and scissor covering it, as secondary command buffers don't inherit dynamic state.
+pub(crate) fn begin_secondary(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    inheritance: &PassInheritance,
+) -> VkResult<()> {
+    let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+        .color_attachment_formats(&inheritance.color_formats)
+        .depth_attachment_format(inheritance.depth_format.unwrap_or(vk::Format::UNDEFINED))
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+        .build();
+    let mut inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+        .render_pass(inheritance.render_pass)
+        .subpass(0)
+        .framebuffer(inheritance.framebuffer);
+    if inheritance.render_pass == vk::RenderPass::null() {
+        inheritance_info = inheritance_info.push_next(&mut rendering_info);
+    }
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(
+            vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+        )
+        .inheritance_info(&inheritance_info);
+    unsafe {
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+    }
+    set_viewport(device, command_buffer, inheritance.extent);
+    Ok(())
+}
+
+/// Sets the viewport and scissor to cover `extent`, as pipelines leave them dynamic.
+pub(crate) fn set_viewport(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    extent: vk::Extent2D,
+) {
+    let viewport = vk::Viewport::builder()
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0)
+        .build();
+    unsafe {
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(command_buffer, 0, &[extent.into()]);
+    }
+}
@@ -0,0 +1,690 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use super::{synchronization::Synchronization, ShaderStage};
+
+/// How a command uses a buffer or a storage image, for `CommandEncoder::use_buffer` and
+/// `CommandEncoder::use_storage_image`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceAccess {
+    TransferRead,
+    TransferWrite,
+    IndirectRead,
+    VertexRead,
+    IndexRead,
+    UniformRead(ShaderStage),
+    StorageRead(ShaderStage),
+    /// Storage writes, which may read too.
+    StorageWrite(ShaderStage),
+    /// Reads by the CPU from mapped memory once the submission completes.
+    HostRead,
+}
+
+impl ResourceAccess {
+    pub(crate) fn resource_use(&self, layout: vk::ImageLayout) -> ResourceUse {
+        let (stages, access, write) = match *self {
+            ResourceAccess::TransferRead => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_READ,
+                false,
+            ),
+            ResourceAccess::TransferWrite => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_WRITE,
+                true,
+            ),
+            ResourceAccess::IndirectRead => (
+                vk::PipelineStageFlags2::DRAW_INDIRECT,
+                vk::AccessFlags2::INDIRECT_COMMAND_READ,
+                false,
+            ),
+            ResourceAccess::VertexRead => (
+                vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+                vk::AccessFlags2::VERTEX_ATTRIBUTE_READ,
+                false,
+            ),
+            ResourceAccess::IndexRead => (
+                vk::PipelineStageFlags2::INDEX_INPUT,
+                vk::AccessFlags2::INDEX_READ,
+                false,
+            ),
+            ResourceAccess::UniformRead(stage) => (
+                shader_stages(stage.vk_flags()),
+                vk::AccessFlags2::UNIFORM_READ,
+                false,
+            ),
+            ResourceAccess::StorageRead(stage) => (
+                shader_stages(stage.vk_flags()),
+                vk::AccessFlags2::SHADER_STORAGE_READ,
+                false,
+            ),
+            ResourceAccess::StorageWrite(stage) => (
+                shader_stages(stage.vk_flags()),
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                true,
+            ),
+            ResourceAccess::HostRead => (
+                vk::PipelineStageFlags2::HOST,
+                vk::AccessFlags2::HOST_READ,
+                false,
+            ),
+        };
+        ResourceUse {
+            layout,
+            scope: AccessScope { stages, access },
+            write,
+        }
+    }
+}
+
+/// Pipeline stages of shaders with `flags`.
+pub(crate) fn shader_stages(flags: vk::ShaderStageFlags) -> vk::PipelineStageFlags2 {
+    [
+        (
+            vk::ShaderStageFlags::VERTEX,
+            vk::PipelineStageFlags2::VERTEX_SHADER,
+        ),
+        (
+            vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            vk::PipelineStageFlags2::TESSELLATION_CONTROL_SHADER,
+        ),
+        (
+            vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            vk::PipelineStageFlags2::TESSELLATION_EVALUATION_SHADER,
+        ),
+        (
+            vk::ShaderStageFlags::GEOMETRY,
+            vk::PipelineStageFlags2::GEOMETRY_SHADER,
+        ),
+        (
+            vk::ShaderStageFlags::FRAGMENT,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        ),
+        (
+            vk::ShaderStageFlags::COMPUTE,
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+        ),
+    ]
+    .iter()
+    .filter(|(stage, _)| flags.contains(*stage))
+    .fold(
+        vk::PipelineStageFlags2::NONE,
+        |stages, (_, pipeline_stage)| stages | *pipeline_stage,
+    )
+}
+
+/// Pipeline stages and memory accesses, on one side of a barrier.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct AccessScope {
+    pub(crate) stages: vk::PipelineStageFlags2,
+    pub(crate) access: vk::AccessFlags2,
+}
+
+impl AccessScope {
+    fn contains(&self, other: &AccessScope) -> bool {
+        self.stages.contains(other.stages) && self.access.contains(other.access)
+    }
+
+    fn union(&self, other: &AccessScope) -> AccessScope {
+        AccessScope {
+            stages: self.stages | other.stages,
+            access: self.access | other.access,
+        }
+    }
+}
+
+/// Reads a global memory barrier makes writes visible to.
+const READ_ACCESSES: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::INDIRECT_COMMAND_READ.as_raw()
+        | vk::AccessFlags2::INDEX_READ.as_raw()
+        | vk::AccessFlags2::VERTEX_ATTRIBUTE_READ.as_raw()
+        | vk::AccessFlags2::UNIFORM_READ.as_raw()
+        | vk::AccessFlags2::SHADER_SAMPLED_READ.as_raw()
+        | vk::AccessFlags2::SHADER_STORAGE_READ.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_READ.as_raw()
+        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ.as_raw()
+        | vk::AccessFlags2::TRANSFER_READ.as_raw()
+        | vk::AccessFlags2::HOST_READ.as_raw()
+        | vk::AccessFlags2::MEMORY_READ.as_raw(),
+);
+
+/// Use of a resource by a command. `layout` is ignored for buffers.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ResourceUse {
+    pub(crate) layout: vk::ImageLayout,
+    pub(crate) scope: AccessScope,
+    pub(crate) write: bool,
+}
+
+/// Resource whose state is tracked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum TrackedResource {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+/// State of a resource after the commands recorded so far.
+#[derive(Clone, Copy, Debug, Default)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    aspect_mask: vk::ImageAspectFlags,
+    /// Last write, which later uses wait for.
+    write: AccessScope,
+    /// Stages that read since the last write, which the next write waits for.
+    read_stages: vk::PipelineStageFlags2,
+    /// Reads the last write was made visible to.
+    visible: AccessScope,
+    /// Destination of the last barrier and the uses it covers, which uses inside a render pass
+    /// can rely on without a barrier of their own.
+    prepared: AccessScope,
+}
+
+/// Barrier bringing a resource from its state to a new use.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Transition {
+    pub(crate) resource: TrackedResource,
+    pub(crate) src: AccessScope,
+    pub(crate) dst: AccessScope,
+    pub(crate) old_layout: vk::ImageLayout,
+    pub(crate) new_layout: vk::ImageLayout,
+    pub(crate) aspect_mask: vk::ImageAspectFlags,
+}
+
+impl ResourceState {
+    /// Accesses the next write or layout transition waits for.
+    fn pending(&self) -> AccessScope {
+        AccessScope {
+            stages: self.write.stages | self.read_stages,
+            access: self.write.access,
+        }
+    }
+
+    /// Moves to `next`, returning the barrier needed before it, if any.
+    fn transition(&mut self, resource: TrackedResource, next: &ResourceUse) -> Option<Transition> {
+        let layout_change =
+            matches!(resource, TrackedResource::Image(_)) && self.layout != next.layout;
+        let src = if next.write || layout_change {
+            // Writes and layout transitions wait for the last write and the reads after it.
+            let src = self.pending();
+            self.write = if next.write {
+                next.scope
+            } else {
+                // The layout transition is the write the next reads wait for.
+                AccessScope {
+                    stages: next.scope.stages,
+                    access: vk::AccessFlags2::NONE,
+                }
+            };
+            self.read_stages = vk::PipelineStageFlags2::NONE;
+            self.visible = if next.write {
+                AccessScope::default()
+            } else {
+                next.scope
+            };
+            self.prepared = next.scope;
+            (layout_change || !src.stages.is_empty()).then_some(src)
+        } else {
+            self.read_stages |= next.scope.stages;
+            if self.write.stages.is_empty() || self.visible.contains(&next.scope) {
+                self.prepared = self.prepared.union(&next.scope);
+                None
+            } else {
+                self.visible = self.visible.union(&next.scope);
+                self.prepared = self.prepared.union(&next.scope);
+                Some(self.write)
+            }
+        };
+        let old_layout = self.layout;
+        self.layout = next.layout;
+        src.map(|src| Transition {
+            resource,
+            src,
+            dst: next.scope,
+            old_layout,
+            new_layout: next.layout,
+            aspect_mask: self.aspect_mask,
+        })
+    }
+}
+
+/// Layout and accesses of buffers and images, shared by the command buffers recorded in
+/// submission order, so that a use can wait for the ones recorded before it.
+/// Buffers start with no accesses, images from the layout they are registered with.
+#[derive(Default)]
+pub(crate) struct ResourceStates {
+    states: HashMap<TrackedResource, ResourceState>,
+}
+
+impl ResourceStates {
+    /// Starts tracking an image created in or transitioned to `layout`, with nothing pending.
+    pub(crate) fn register_image(
+        &mut self,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+        aspect_mask: vk::ImageAspectFlags,
+    ) {
+        self.states.insert(
+            TrackedResource::Image(image),
+            ResourceState {
+                layout,
+                aspect_mask,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Starts tracking a swapchain image acquired with a semaphore waited for at
+    /// `wait_stages`, which its first use waits for. Its contents are thrown away.
+    pub(crate) fn register_acquired_image(
+        &mut self,
+        image: vk::Image,
+        wait_stages: vk::PipelineStageFlags2,
+    ) {
+        self.states.insert(
+            TrackedResource::Image(image),
+            ResourceState {
+                layout: vk::ImageLayout::UNDEFINED,
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                write: AccessScope {
+                    stages: wait_stages,
+                    access: vk::AccessFlags2::NONE,
+                },
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Throws away the contents of `image`, whose memory the `aliased` images used before,
+    /// so that its next use transitions it from `UNDEFINED` after their accesses and its own.
+    pub(crate) fn discard(&mut self, image: vk::Image, aliased: &[vk::Image]) {
+        let pending = aliased.iter().fold(
+            self.state_mut(TrackedResource::Image(image)).pending(),
+            |pending, &aliased| {
+                pending.union(&self.state_mut(TrackedResource::Image(aliased)).pending())
+            },
+        );
+        let state = self.state_mut(TrackedResource::Image(image));
+        *state = ResourceState {
+            layout: vk::ImageLayout::UNDEFINED,
+            aspect_mask: state.aspect_mask,
+            write: pending,
+            ..Default::default()
+        };
+    }
+
+    /// Stops tracking a destroyed resource, whose handle may be reused.
+    pub(crate) fn forget(&mut self, resource: TrackedResource) {
+        self.states.remove(&resource);
+    }
+
+    /// Records `next` and returns the barrier needed before it, if any.
+    /// Panics if the resource is an image that isn't registered.
+    pub(crate) fn transition(
+        &mut self,
+        resource: TrackedResource,
+        next: &ResourceUse,
+    ) -> Option<Transition> {
+        self.state_mut(resource).transition(resource, next)
+    }
+
+    /// Records `next` inside a render pass, where barriers can't be recorded.
+    /// Returns false, leaving the state as it is, if `next` needs a barrier that wasn't
+    /// recorded before the pass.
+    pub(crate) fn use_in_render_pass(
+        &mut self,
+        resource: TrackedResource,
+        next: &ResourceUse,
+    ) -> bool {
+        let state = self.state_mut(resource);
+        // A write also waits for the reads since the barrier.
+        if state.layout == next.layout
+            && state.prepared.contains(&next.scope)
+            && (!next.write || state.read_stages.is_empty())
+        {
+            return true;
+        }
+        let mut moved = *state;
+        if moved.transition(resource, next).is_some() {
+            return false;
+        }
+        *state = moved;
+        true
+    }
+
+    /// Records a global memory barrier from the commands in `src_stages` to those in
+    /// `dst_stages`, after which the reads there need no barrier for the writes it waits for.
+    pub(crate) fn memory_barrier(
+        &mut self,
+        src_stages: vk::PipelineStageFlags2,
+        dst_stages: vk::PipelineStageFlags2,
+    ) {
+        let dst = AccessScope {
+            stages: dst_stages,
+            access: READ_ACCESSES,
+        };
+        for state in self.states.values_mut() {
+            if src_stages.contains(vk::PipelineStageFlags2::ALL_COMMANDS)
+                || src_stages.contains(state.write.stages)
+            {
+                state.visible = state.visible.union(&dst);
+                state.prepared = state.prepared.union(&dst);
+            }
+        }
+    }
+
+    /// Returns the accesses a release of the resource to another queue waits for, and forgets
+    /// them, as the acquire on the other queue covers all later commands.
+    pub(crate) fn hand_over(&mut self, resource: TrackedResource) -> AccessScope {
+        let state = self.state_mut(resource);
+        let src = state.pending();
+        *state = ResourceState {
+            layout: state.layout,
+            aspect_mask: state.aspect_mask,
+            ..Default::default()
+        };
+        src
+    }
+
+    fn state_mut(&mut self, resource: TrackedResource) -> &mut ResourceState {
+        match resource {
+            TrackedResource::Buffer(_) => self.states.entry(resource).or_default(),
+            TrackedResource::Image(_) => self
+                .states
+                .get_mut(&resource)
+                .expect("The image is not tracked."),
+        }
+    }
+}
+
+/// Records the barriers of `transitions` with one pipeline barrier.
+pub(crate) fn record_transitions(
+    synchronization: &Synchronization,
+    command_buffer: vk::CommandBuffer,
+    transitions: &[Transition],
+) {
+    if transitions.is_empty() {
+        return;
+    }
+    let mut buffer_barriers = Vec::new();
+    let mut image_barriers = Vec::new();
+    for transition in transitions {
+        match transition.resource {
+            TrackedResource::Buffer(buffer) => buffer_barriers.push(
+                vk::BufferMemoryBarrier2::builder()
+                    .src_stage_mask(transition.src.stages)
+                    .src_access_mask(transition.src.access)
+                    .dst_stage_mask(transition.dst.stages)
+                    .dst_access_mask(transition.dst.access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            ),
+            TrackedResource::Image(image) => image_barriers.push(
+                vk::ImageMemoryBarrier2::builder()
+                    .src_stage_mask(transition.src.stages)
+                    .src_access_mask(transition.src.access)
+                    .dst_stage_mask(transition.dst.stages)
+                    .dst_access_mask(transition.dst.access)
+                    .old_layout(transition.old_layout)
+                    .new_layout(transition.new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: transition.aspect_mask,
+                        base_mip_level: 0,
+                        level_count: vk::REMAINING_MIP_LEVELS,
+                        base_array_layer: 0,
+                        layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    })
+                    .build(),
+            ),
+        }
+    }
+    synchronization.pipeline_barrier(command_buffer, &[], &buffer_barriers, &image_barriers);
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    fn buffer() -> TrackedResource {
+        TrackedResource::Buffer(vk::Buffer::from_raw(1))
+    }
+
+    fn buffer_use(access: ResourceAccess) -> ResourceUse {
+        access.resource_use(vk::ImageLayout::UNDEFINED)
+    }
+
+    fn image() -> vk::Image {
+        vk::Image::from_raw(2)
+    }
+
+    fn states_with_image(layout: vk::ImageLayout) -> ResourceStates {
+        let mut states = ResourceStates::default();
+        states.register_image(image(), layout, vk::ImageAspectFlags::COLOR);
+        states
+    }
+
+    #[test]
+    fn makes_a_write_visible_to_each_kind_of_read_once() {
+        let mut states = ResourceStates::default();
+        let write = buffer_use(ResourceAccess::StorageWrite(ShaderStage::Compute));
+        let vertex = buffer_use(ResourceAccess::VertexRead);
+        let indirect = buffer_use(ResourceAccess::IndirectRead);
+        // Nothing to wait for before the first write.
+        assert!(states.transition(buffer(), &write).is_none());
+
+        let transition = states.transition(buffer(), &vertex).unwrap();
+        assert_eq!(transition.src, write.scope);
+        assert_eq!(transition.dst, vertex.scope);
+        assert!(states.transition(buffer(), &vertex).is_none());
+        let transition = states.transition(buffer(), &indirect).unwrap();
+        assert_eq!(transition.src, write.scope);
+        assert_eq!(transition.dst, indirect.scope);
+    }
+
+    #[test]
+    fn merges_reads_and_waits_for_them_before_the_next_write() {
+        let mut states = ResourceStates::default();
+        let vertex = buffer_use(ResourceAccess::VertexRead);
+        let uniform = buffer_use(ResourceAccess::UniformRead(ShaderStage::Fragment));
+        let write = buffer_use(ResourceAccess::TransferWrite);
+        // Reads of a buffer nothing wrote, or read before, need no barrier.
+        assert!(states.transition(buffer(), &vertex).is_none());
+        assert!(states.transition(buffer(), &uniform).is_none());
+        assert!(states.transition(buffer(), &vertex).is_none());
+
+        let transition = states.transition(buffer(), &write).unwrap();
+        assert_eq!(
+            transition.src,
+            AccessScope {
+                stages: vertex.scope.stages | uniform.scope.stages,
+                access: vk::AccessFlags2::NONE,
+            }
+        );
+        assert_eq!(transition.dst, write.scope);
+        // A write after a write waits for it.
+        let transition = states.transition(buffer(), &write).unwrap();
+        assert_eq!(transition.src, write.scope);
+    }
+
+    #[test]
+    fn transitions_image_layouts() {
+        let mut states = states_with_image(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let resource = TrackedResource::Image(image());
+        let sampled = ResourceUse {
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            scope: AccessScope {
+                stages: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+            },
+            write: false,
+        };
+        let copy = buffer_use(ResourceAccess::TransferWrite);
+        let copy = ResourceUse {
+            layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ..copy
+        };
+        assert!(states.transition(resource, &sampled).is_none());
+
+        let transition = states.transition(resource, &copy).unwrap();
+        assert_eq!(
+            transition.old_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert_eq!(transition.new_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(
+            transition.src.stages,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(transition.aspect_mask, vk::ImageAspectFlags::COLOR);
+
+        // A read in another layout transitions it, after the write.
+        let transition = states.transition(resource, &sampled).unwrap();
+        assert_eq!(transition.old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(transition.src, copy.scope);
+        assert!(states.transition(resource, &sampled).is_none());
+        // Later reads in yet another layout wait for the layout transition and the reads.
+        let storage = ResourceUse {
+            layout: vk::ImageLayout::GENERAL,
+            ..buffer_use(ResourceAccess::StorageRead(ShaderStage::Compute))
+        };
+        let transition = states.transition(resource, &storage).unwrap();
+        assert_eq!(
+            transition.src,
+            AccessScope {
+                stages: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                access: vk::AccessFlags2::NONE,
+            }
+        );
+    }
+
+    #[test]
+    fn uses_resources_in_render_passes_only_as_prepared() {
+        let mut states = ResourceStates::default();
+        let write = buffer_use(ResourceAccess::StorageWrite(ShaderStage::Compute));
+        let vertex = buffer_use(ResourceAccess::VertexRead);
+        let uniform = buffer_use(ResourceAccess::UniformRead(ShaderStage::Vertex));
+        assert!(states.use_in_render_pass(buffer(), &vertex));
+
+        states.transition(buffer(), &write);
+        assert!(!states.use_in_render_pass(buffer(), &vertex));
+        // The failed use left the state as it was.
+        assert!(states.transition(buffer(), &vertex).is_some());
+        assert!(states.use_in_render_pass(buffer(), &vertex));
+        assert!(!states.use_in_render_pass(buffer(), &uniform));
+        assert!(!states.use_in_render_pass(buffer(), &write));
+    }
+
+    #[test]
+    fn forgets_the_accesses_handed_over_to_another_queue() {
+        let mut states = ResourceStates::default();
+        let write = buffer_use(ResourceAccess::StorageWrite(ShaderStage::Compute));
+        let vertex = buffer_use(ResourceAccess::VertexRead);
+        states.transition(buffer(), &write);
+        states.transition(buffer(), &vertex);
+
+        assert_eq!(
+            states.hand_over(buffer()),
+            AccessScope {
+                stages: write.scope.stages | vertex.scope.stages,
+                access: write.scope.access,
+            }
+        );
+        // The acquire on the other queue covers the uses after it.
+        assert!(states.transition(buffer(), &write).is_none());
+        assert_eq!(states.hand_over(buffer()), write.scope);
+    }
+
+    #[test]
+    #[should_panic(expected = "The image is not tracked.")]
+    fn panics_on_images_it_forgot() {
+        let mut states = states_with_image(vk::ImageLayout::GENERAL);
+        states.forget(TrackedResource::Image(image()));
+        states.transition(
+            TrackedResource::Image(image()),
+            &buffer_use(ResourceAccess::TransferRead),
+        );
+    }
+
+    #[test]
+    fn waits_for_the_acquire_semaphore_before_the_first_use_of_a_swapchain_image() {
+        let mut states = ResourceStates::default();
+        states.register_acquired_image(image(), vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT);
+        let attachment = ResourceUse {
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            scope: AccessScope {
+                stages: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            },
+            write: true,
+        };
+        let transition = states
+            .transition(TrackedResource::Image(image()), &attachment)
+            .unwrap();
+        assert_eq!(transition.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(
+            transition.src,
+            AccessScope {
+                stages: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags2::NONE,
+            }
+        );
+    }
+
+    #[test]
+    fn discards_after_the_accesses_of_the_aliased_images() {
+        let mut states = states_with_image(vk::ImageLayout::UNDEFINED);
+        let aliased = vk::Image::from_raw(3);
+        states.register_image(
+            aliased,
+            vk::ImageLayout::GENERAL,
+            vk::ImageAspectFlags::COLOR,
+        );
+        let storage = ResourceUse {
+            layout: vk::ImageLayout::GENERAL,
+            ..buffer_use(ResourceAccess::StorageWrite(ShaderStage::Compute))
+        };
+        states.transition(TrackedResource::Image(aliased), &storage);
+
+        states.discard(image(), &[aliased]);
+        let copy = ResourceUse {
+            layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ..buffer_use(ResourceAccess::TransferWrite)
+        };
+        let transition = states
+            .transition(TrackedResource::Image(image()), &copy)
+            .unwrap();
+        assert_eq!(transition.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(transition.src, storage.scope);
+    }
+
+    #[test]
+    fn makes_writes_before_a_memory_barrier_visible_after_it() {
+        let mut states = ResourceStates::default();
+        let write = buffer_use(ResourceAccess::StorageWrite(ShaderStage::Compute));
+        let vertex = buffer_use(ResourceAccess::VertexRead);
+        let fragment = buffer_use(ResourceAccess::StorageRead(ShaderStage::Fragment));
+        states.transition(buffer(), &write);
+        // A barrier from other stages doesn't cover the write.
+        states.memory_barrier(
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+        );
+        assert!(!states.use_in_render_pass(buffer(), &vertex));
+
+        states.memory_barrier(
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+        );
+        assert!(states.use_in_render_pass(buffer(), &vertex));
+        assert!(states.transition(buffer(), &fragment).is_some());
+    }
+}
@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use ash::{prelude::VkResult, vk, vk::Handle};
+
+use super::Renderer;
+
+/// Sampler for textures of the bindless table, added with `Renderer::bindless_sampler`.
+pub struct Sampler {
+    renderer: Arc<Renderer>,
+    pub(crate) sampler: vk::Sampler,
+}
+
+impl Sampler {
+    pub(crate) fn new(
+        renderer: &Arc<Renderer>,
+        filter: vk::Filter,
+        address_mode: vk::SamplerAddressMode,
+    ) -> VkResult<Self> {
+        let create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(filter)
+            .min_filter(filter)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(address_mode)
+            .address_mode_v(address_mode)
+            .address_mode_w(address_mode)
+            .max_lod(vk::LOD_CLAMP_NONE)
+            .build();
+        let sampler = unsafe { renderer.device.create_sampler(&create_info, None)? };
+        Ok(Sampler {
+            renderer: renderer.clone(),
+            sampler,
+        })
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.renderer.forget_bindless(self.sampler.as_raw());
+        unsafe {
+            self.renderer.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
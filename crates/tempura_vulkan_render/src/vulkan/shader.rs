@@ -1,73 +1,410 @@
-use std::{io::Cursor, rc::Rc};
+use std::{
+    ffi::CString,
+    io::Cursor,
+    mem,
+    sync::{Arc, Mutex, Weak},
+};
 
 use ash::{util::read_spv, vk};
-use spirv_reflect::ShaderModule;
+use rspirv_reflect::{
+    rspirv::dr::Operand,
+    spirv::{Decoration, ExecutionMode, Op, StorageClass},
+    Reflection,
+};
 use tempura_render as tr;
 
-use super::Renderer;
+use super::{
+    descriptor::{reflect_descriptor_bindings, DescriptorBinding},
+    material::MaterialPipeline,
+    shader_compiler::translate_to_spirv,
+    specialization::{reflect_specialization_constants, SpecializationConstant},
+    Renderer, ShaderStage,
+};
 
+/// Shader module of one stage and what is reflected from it.
+pub(crate) struct ShaderStageModule {
+    pub(crate) stage: ShaderStage,
+    pub(crate) module: vk::ShaderModule,
+    pub(crate) entry_point: CString,
+    pub(crate) specialization_constants: Vec<SpecializationConstant>,
+    /// Bytes of the push constant block the stage reads, if it declares one.
+    pub(crate) push_constant_range: Option<vk::PushConstantRange>,
+    pub(crate) descriptor_bindings: Vec<DescriptorBinding>,
+    /// Patch size implied by the tessellation mode, for tessellation stages declaring one.
+    pub(crate) patch_control_points: Option<u32>,
+    /// Locations of the user-defined inputs, which are vertex attributes for the vertex stage.
+    pub(crate) input_locations: Vec<u32>,
+}
+
+impl ShaderStageModule {
+    /// Creates the module and its reflection from the SPIR-V translated from `source`.
+    pub(crate) fn new(
+        renderer: &Renderer,
+        source: &tr::ShaderSource,
+        stage: ShaderStage,
+    ) -> Result<Self, String> {
+        let code = translate_to_spirv(source, stage)
+            .map_err(|e| format!("{:?} shader translation failed. {}", stage, e))?;
+        let code = read_spv(&mut Cursor::new(code))
+            .map_err(|e| format!("{:?} shader read_spv failed. {}", stage, e))?;
+        let reflect = Reflection::new_from_spirv(bytemuck::cast_slice(&code))
+            .map_err(|e| format!("{:?} shader reflection failed. {}", stage, e))?;
+        let entry_point = reflect_entry_point(&reflect)
+            .ok_or_else(|| format!("{:?} shader has no entry point.", stage))?;
+        let specialization_constants = reflect_specialization_constants(&reflect);
+        let push_constant_range = reflect
+            .get_push_constant_range()
+            .map_err(|e| format!("{:?} shader reflection failed. {}", stage, e))?
+            .map(|info| vk::PushConstantRange {
+                stage_flags: stage.vk_flags(),
+                offset: info.offset,
+                size: info.size,
+            });
+        let descriptor_bindings = reflect_descriptor_bindings(&reflect, stage.vk_flags())
+            .map_err(|e| format!("{:?} shader reflection failed. {}", stage, e))?;
+        let patch_control_points = reflect_patch_control_points(&reflect);
+        let input_locations = reflect_input_locations(&reflect);
+        let create_info = vk::ShaderModuleCreateInfo::builder().code(&code).build();
+        let module = unsafe {
+            renderer
+                .device
+                .create_shader_module(&create_info, None)
+                .map_err(|e| format!("{:?} shader create_shader_module failed. {}", stage, e))?
+        };
+
+        Ok(ShaderStageModule {
+            stage,
+            module,
+            entry_point,
+            specialization_constants,
+            push_constant_range,
+            descriptor_bindings,
+            patch_control_points,
+            input_locations,
+        })
+    }
+
+    pub(crate) fn destroy(&self, renderer: &Renderer) {
+        unsafe {
+            renderer.device.destroy_shader_module(self.module, None);
+        }
+    }
+}
+
+/// Name of the first entry point, the one a pipeline stage uses.
+fn reflect_entry_point(reflect: &Reflection) -> Option<CString> {
+    let entry_point = reflect.0.entry_points.first()?;
+    match entry_point.operands.get(2)? {
+        Operand::LiteralString(name) => CString::new(name.as_str()).ok(),
+        _ => None,
+    }
+}
+
+/// Vertices per patch implied by the tessellation mode a stage declares: 3 for `triangles`,
+/// 4 for `quads` and 2 for `isolines`.
+fn reflect_patch_control_points(reflect: &Reflection) -> Option<u32> {
+    reflect
+        .0
+        .execution_modes
+        .iter()
+        .find_map(|instruction| match instruction.operands.get(1)? {
+            Operand::ExecutionMode(ExecutionMode::Triangles) => Some(3),
+            Operand::ExecutionMode(ExecutionMode::Quads) => Some(4),
+            Operand::ExecutionMode(ExecutionMode::Isolines) => Some(2),
+            _ => None,
+        })
+}
+
+/// Locations of the input variables, leaving out built-ins which have no location.
+fn reflect_input_locations(reflect: &Reflection) -> Vec<u32> {
+    let module = &reflect.0;
+    let location = |target: u32| {
+        module
+            .annotations
+            .iter()
+            .find_map(|instruction| match instruction.operands.as_slice() {
+                [Operand::IdRef(id), Operand::Decoration(Decoration::Location), Operand::LiteralBit32(location)]
+                    if *id == target =>
+                {
+                    Some(*location)
+                }
+                _ => None,
+            })
+    };
+    module
+        .types_global_values
+        .iter()
+        .filter(|instruction| {
+            instruction.class.opcode == Op::Variable
+                && matches!(
+                    instruction.operands.first(),
+                    Some(Operand::StorageClass(StorageClass::Input))
+                )
+        })
+        .filter_map(|instruction| location(instruction.result_id?))
+        .collect()
+}
+
+/// Shader modules of the graphics stages, in pipeline order.
+pub(crate) struct ShaderModules {
+    pub(crate) stages: Vec<ShaderStageModule>,
+}
+
+impl ShaderModules {
+    fn new(
+        renderer: &Renderer,
+        stages: &[(ShaderStage, tr::ShaderSource)],
+    ) -> Result<Self, String> {
+        let stage_kinds = stages.iter().map(|&(stage, _)| stage).collect::<Vec<_>>();
+        validate_stages(&renderer.enabled_features, &stage_kinds)?;
+        let mut modules = ShaderModules { stages: Vec::new() };
+        for stage in ShaderStage::GRAPHICS {
+            let Some((_, source)) = stages.iter().find(|(s, _)| *s == stage) else {
+                continue;
+            };
+            match ShaderStageModule::new(renderer, source, stage) {
+                Ok(module) => modules.stages.push(module),
+                Err(e) => {
+                    modules.destroy(renderer);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(modules)
+    }
+
+    pub(crate) fn stage(&self, stage: ShaderStage) -> Option<&ShaderStageModule> {
+        self.stages.iter().find(|module| module.stage == stage)
+    }
+
+    /// Patch size of the tessellation stages, `None` without tessellation.
+    pub(crate) fn patch_control_points(&self) -> Option<u32> {
+        let control = self.stage(ShaderStage::TessellationControl)?;
+        let evaluation = self.stage(ShaderStage::TessellationEvaluation)?;
+        Some(
+            evaluation
+                .patch_control_points
+                .or(control.patch_control_points)
+                .unwrap_or(3),
+        )
+    }
+
+    /// One range per stage declaring push constants, as Vulkan allows a stage in one range
+    /// only. Updates of partly overlapping blocks are split by `set_push_constants`.
+    pub(crate) fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.stages
+            .iter()
+            .filter_map(|stage| stage.push_constant_range)
+            .collect()
+    }
+
+    fn destroy(&self, renderer: &Renderer) {
+        self.stages.iter().for_each(|stage| stage.destroy(renderer));
+    }
+}
+
+/// Checks that the stages make a graphics pipeline a device with `features` supports.
+fn validate_stages(
+    features: &vk::PhysicalDeviceFeatures,
+    stages: &[ShaderStage],
+) -> Result<(), String> {
+    for (index, stage) in stages.iter().enumerate() {
+        if !ShaderStage::GRAPHICS.contains(stage) {
+            return Err(format!("{:?} is not a graphics stage.", stage));
+        }
+        if stages[..index].contains(stage) {
+            return Err(format!("{:?} stage is given more than once.", stage));
+        }
+    }
+    let has = |stage| stages.contains(&stage);
+    if !has(ShaderStage::Vertex) {
+        return Err("Vertex stage is required.".to_owned());
+    }
+    let tessellation = has(ShaderStage::TessellationControl);
+    if tessellation != has(ShaderStage::TessellationEvaluation) {
+        return Err("Tessellation control and evaluation stages are used together.".to_owned());
+    }
+    if tessellation && features.tessellation_shader != vk::TRUE {
+        return Err("The device doesn't support tessellation shaders.".to_owned());
+    }
+    if has(ShaderStage::Geometry) && features.geometry_shader != vk::TRUE {
+        return Err("The device doesn't support geometry shaders.".to_owned());
+    }
+    Ok(())
+}
+
+/// Graphics shader made of a vertex stage, optional tessellation and geometry stages,
+/// and usually a fragment stage.
 pub struct Shader {
-    renderer: Rc<Renderer>,
-    pub(crate) vertex_shader: vk::ShaderModule,
-    pub(crate) vertex_shader_reflect: spirv_reflect::ShaderModule,
-    pub(crate) fragment_shader: vk::ShaderModule,
-    pub(crate) fragment_shader_reflect: spirv_reflect::ShaderModule,
+    renderer: Arc<Renderer>,
+    pub(crate) modules: Mutex<ShaderModules>,
+    /// Pipelines of the materials created from this shader, rebuilt by `reload`.
+    material_pipelines: Mutex<Vec<Weak<MaterialPipeline>>>,
 }
 
 impl Shader {
+    /// Panics if a stage fails to translate, or if the stages don't make a pipeline the device
+    /// supports: a vertex stage is required, tessellation stages go in pairs, and tessellation
+    /// and geometry stages need the device features.
     pub(crate) fn new(
-        renderer: &std::rc::Rc<Renderer>,
-        vertex_shader_code: &Vec<u8>,
-        fragment_shader_code: &Vec<u8>,
+        renderer: &Arc<Renderer>,
+        stages: &[(ShaderStage, tr::ShaderSource)],
     ) -> Self {
+        let modules = ShaderModules::new(renderer, stages).unwrap_or_else(|e| panic!("{}", e));
+
+        Shader {
+            renderer: renderer.clone(),
+            modules: Mutex::new(modules),
+            material_pipelines: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Stages of the shader, in pipeline order.
+    pub fn stages(&self) -> Vec<ShaderStage> {
+        self.modules
+            .lock()
+            .unwrap()
+            .stages
+            .iter()
+            .map(|module| module.stage)
+            .collect()
+    }
+
+    /// Replaces the stages and rebuilds the pipelines of the dependent materials.
+    /// On failure the shader is left untouched. A material whose pipeline fails to build
+    /// keeps the previous one, which is reported in the returned messages together with the
+    /// material resources that could not be bound to the new stages.
+    /// The device is waited idle, so this should be called between frames.
+    pub fn reload(
+        &self,
+        stages: &[(ShaderStage, tr::ShaderSource)],
+    ) -> Result<Vec<String>, String> {
+        let modules = ShaderModules::new(&self.renderer, stages)?;
         unsafe {
-            let mut vertex_shader_code = Cursor::new(vertex_shader_code.to_owned());
-            let vertex_shader_code = read_spv(&mut vertex_shader_code).expect("read_spv failed.");
-            let vertex_shader_create_info = vk::ShaderModuleCreateInfo::builder()
-                .code(&vertex_shader_code)
-                .build();
-            let vertex_shader = renderer
-                .device
-                .create_shader_module(&vertex_shader_create_info, None)
-                .expect("create_shader_module failed.");
-            let vertex_shader_reflect = ShaderModule::load_u32_data(&vertex_shader_code)
-                .expect("vertex shader reflection failed.");
-
-            let mut fragment_shader_code = Cursor::new(fragment_shader_code.to_owned());
-            let fragment_shader_code =
-                read_spv(&mut fragment_shader_code).expect("read_spv failed.");
-            let fragment_shader_create_info = vk::ShaderModuleCreateInfo::builder()
-                .code(&fragment_shader_code)
-                .build();
-            let fragment_shader = renderer
+            self.renderer
                 .device
-                .create_shader_module(&fragment_shader_create_info, None)
-                .expect("create_shader_module failed.");
-            let fragment_shader_reflect = ShaderModule::load_u32_data(&fragment_shader_code)
-                .expect("fragment shader reflection failed.");
-
-            Shader {
-                renderer: renderer.clone(),
-                vertex_shader,
-                vertex_shader_reflect,
-                fragment_shader,
-                fragment_shader_reflect,
+                .device_wait_idle()
+                .expect("device_wait_idle failed.");
+        }
+        let old_modules = mem::replace(&mut *self.modules.lock().unwrap(), modules);
+
+        self.material_pipelines
+            .lock()
+            .unwrap()
+            .retain(|pipeline| pipeline.strong_count() > 0);
+        let mut messages = Vec::new();
+        for pipeline in self.material_pipelines.lock().unwrap().iter() {
+            if let Some(pipeline) = pipeline.upgrade() {
+                match pipeline.rebuild(self) {
+                    Ok(rebind_errors) => messages.extend(
+                        rebind_errors
+                            .into_iter()
+                            .map(|e| format!("Rebinding material resource failed. {}", e)),
+                    ),
+                    Err(e) => messages.push(format!(
+                        "Rebuilding material pipeline failed. Keep using the old one. {}",
+                        e
+                    )),
+                }
             }
         }
+
+        old_modules.destroy(&self.renderer);
+        Ok(messages)
+    }
+
+    /// Push constant ranges reflected from the stages, one per stage declaring a block.
+    pub fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.modules.lock().unwrap().push_constant_ranges()
+    }
+
+    pub(crate) fn add_material_pipeline(&self, pipeline: &Arc<MaterialPipeline>) {
+        self.material_pipelines
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(pipeline));
     }
 }
 
 impl Drop for Shader {
     fn drop(&mut self) {
-        unsafe {
-            self.renderer
-                .device
-                .destroy_shader_module(self.vertex_shader, None);
-            self.renderer
-                .device
-                .destroy_shader_module(self.fragment_shader, None);
-        }
+        self.modules.lock().unwrap().destroy(&self.renderer);
     }
 }
 
 impl tr::Shader for Shader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_the_stages_of_graphics_pipelines() {
+        use ShaderStage::*;
+        let all_features = vk::PhysicalDeviceFeatures {
+            tessellation_shader: vk::TRUE,
+            geometry_shader: vk::TRUE,
+            ..Default::default()
+        };
+        let no_features = vk::PhysicalDeviceFeatures::default();
+        let cases: &[(
+            &[ShaderStage],
+            &vk::PhysicalDeviceFeatures,
+            Result<(), &str>,
+        )] = &[
+            (&[Vertex, Fragment], &no_features, Ok(())),
+            (&[Vertex], &no_features, Ok(())),
+            (
+                &[
+                    Vertex,
+                    TessellationControl,
+                    TessellationEvaluation,
+                    Geometry,
+                    Fragment,
+                ],
+                &all_features,
+                Ok(()),
+            ),
+            (&[Fragment], &all_features, Err("Vertex stage is required.")),
+            (
+                &[Vertex, Compute],
+                &all_features,
+                Err("Compute is not a graphics stage."),
+            ),
+            (
+                &[Vertex, Fragment, Vertex],
+                &all_features,
+                Err("Vertex stage is given more than once."),
+            ),
+            (
+                &[Vertex, TessellationControl, Fragment],
+                &all_features,
+                Err("Tessellation control and evaluation stages are used together."),
+            ),
+            (
+                &[Vertex, TessellationEvaluation],
+                &all_features,
+                Err("Tessellation control and evaluation stages are used together."),
+            ),
+            (
+                &[Vertex, TessellationControl, TessellationEvaluation],
+                &no_features,
+                Err("The device doesn't support tessellation shaders."),
+            ),
+            (
+                &[Vertex, Geometry, Fragment],
+                &no_features,
+                Err("The device doesn't support geometry shaders."),
+            ),
+        ];
+        for &(stages, features, expected) in cases {
+            assert_eq!(
+                validate_stages(features, stages),
+                expected.map_err(str::to_owned),
+                "{:?}",
+                stages
+            );
+        }
+    }
+}
@@ -0,0 +1,733 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use ash::vk;
+use tempura_render as tr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    TessellationControl,
+    TessellationEvaluation,
+    Geometry,
+    Fragment,
+    Compute,
+}
+
+impl ShaderStage {
+    /// Graphics stages in pipeline order.
+    pub(crate) const GRAPHICS: [ShaderStage; 5] = [
+        ShaderStage::Vertex,
+        ShaderStage::TessellationControl,
+        ShaderStage::TessellationEvaluation,
+        ShaderStage::Geometry,
+        ShaderStage::Fragment,
+    ];
+
+    /// Detects the stage from a file extension such as `vert` or `ps`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "vert" | "vs" => Some(ShaderStage::Vertex),
+            "tesc" | "hs" => Some(ShaderStage::TessellationControl),
+            "tese" | "ds" => Some(ShaderStage::TessellationEvaluation),
+            "geom" | "gs" => Some(ShaderStage::Geometry),
+            "frag" | "ps" => Some(ShaderStage::Fragment),
+            "comp" | "cs" => Some(ShaderStage::Compute),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn vk_flags(&self) -> vk::ShaderStageFlags {
+        match self {
+            ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
+            ShaderStage::TessellationControl => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            ShaderStage::TessellationEvaluation => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            ShaderStage::Geometry => vk::ShaderStageFlags::GEOMETRY,
+            ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+            ShaderStage::Compute => vk::ShaderStageFlags::COMPUTE,
+        }
+    }
+
+    fn hlsl_profile(&self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "vs_6_0",
+            ShaderStage::TessellationControl => "hs_6_0",
+            ShaderStage::TessellationEvaluation => "ds_6_0",
+            ShaderStage::Geometry => "gs_6_0",
+            ShaderStage::Fragment => "ps_6_0",
+            ShaderStage::Compute => "cs_6_0",
+        }
+    }
+
+    /// naga has no tessellation and geometry stages, so they must be HLSL or SPIR-V.
+    fn naga_stage(&self) -> Result<naga::ShaderStage, ShaderCompileError> {
+        match self {
+            ShaderStage::Vertex => Ok(naga::ShaderStage::Vertex),
+            ShaderStage::Fragment => Ok(naga::ShaderStage::Fragment),
+            ShaderStage::Compute => Ok(naga::ShaderStage::Compute),
+            _ => Err(ShaderCompileError::UnsupportedStage(*self)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    Glsl,
+    Hlsl,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderCompileOptions {
+    /// Macros defined before the source, like `#define NAME VALUE`.
+    pub defines: Vec<(String, String)>,
+    /// Directories searched by `#include` after the directory of the including file.
+    pub include_dirs: Vec<PathBuf>,
+    /// Entry point of HLSL sources, and of WGSL files reloaded by `ShaderWatcher`.
+    /// GLSL always uses `main`.
+    pub entry_point: String,
+}
+
+impl Default for ShaderCompileOptions {
+    fn default() -> Self {
+        ShaderCompileOptions {
+            defines: Vec::new(),
+            include_dirs: Vec::new(),
+            entry_point: "main".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderCompileError {
+    Io(PathBuf, io::Error),
+    UnknownStage(PathBuf),
+    IncludeNotFound(String),
+    /// Tessellation and geometry stages given as GLSL or WGSL, which naga can't compile.
+    UnsupportedStage(ShaderStage),
+    UnknownKeyword(String),
+    Compile(String),
+}
+
+impl fmt::Display for ShaderCompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderCompileError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ShaderCompileError::UnknownStage(path) => {
+                write!(f, "{}: shader stage is unknown", path.display())
+            }
+            ShaderCompileError::IncludeNotFound(name) => write!(f, "include not found: {}", name),
+            ShaderCompileError::UnsupportedStage(stage) => write!(
+                f,
+                "{:?} shaders can't be compiled from GLSL or WGSL, only from HLSL or given as SPIR-V",
+                stage
+            ),
+            ShaderCompileError::UnknownKeyword(keyword) => {
+                write!(f, "keyword is not declared: {}", keyword)
+            }
+            ShaderCompileError::Compile(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ShaderCompileError {}
+
+/// Compiles a GLSL or HLSL file to SPIR-V, which can be passed to `create_shader` directly.
+/// The language and stage are detected from the extensions: `triangle.vert` and
+/// `triangle.vert.glsl` are GLSL vertex shaders, `triangle.ps.hlsl` is an HLSL pixel shader.
+pub fn compile_shader_file(
+    path: &Path,
+    options: &ShaderCompileOptions,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    compile_shader_file_with_includes(path, options).map(|(code, _)| code)
+}
+
+/// Same as `compile_shader_file`, but also returns the canonical paths of the file and of
+/// every file it includes.
+pub(crate) fn compile_shader_file_with_includes(
+    path: &Path,
+    options: &ShaderCompileOptions,
+) -> Result<(Vec<u8>, HashSet<PathBuf>), ShaderCompileError> {
+    let (language, stage) = detect_language_and_stage(path)
+        .ok_or_else(|| ShaderCompileError::UnknownStage(path.to_owned()))?;
+    let source =
+        fs::read_to_string(path).map_err(|e| ShaderCompileError::Io(path.to_owned(), e))?;
+    compile_source_with_includes(&source, Some(path), language, stage, options)
+}
+
+/// Compiles GLSL or HLSL source to SPIR-V.
+/// `path` is used to resolve relative includes and in error messages.
+/// HLSL requires the DirectX Shader Compiler library (`dxcompiler`) at runtime.
+pub fn compile_shader_source(
+    source: &str,
+    path: Option<&Path>,
+    language: ShaderLanguage,
+    stage: ShaderStage,
+    options: &ShaderCompileOptions,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    compile_source_with_includes(source, path, language, stage, options).map(|(code, _)| code)
+}
+
+fn compile_source_with_includes(
+    source: &str,
+    path: Option<&Path>,
+    language: ShaderLanguage,
+    stage: ShaderStage,
+    options: &ShaderCompileOptions,
+) -> Result<(Vec<u8>, HashSet<PathBuf>), ShaderCompileError> {
+    if language == ShaderLanguage::Glsl {
+        stage.naga_stage()?;
+    }
+    let source = resolve_includes(source, path, &options.include_dirs, language)?;
+    let code = match language {
+        ShaderLanguage::Glsl => compile_glsl(&source, stage, options),
+        ShaderLanguage::Hlsl => compile_hlsl(&source, stage, options),
+    }?;
+    Ok((code, source.included))
+}
+
+/// Translates a `ShaderSource` to SPIR-V, the only representation the backend consumes.
+pub(crate) fn translate_to_spirv<'a>(
+    source: &tr::ShaderSource<'a>,
+    stage: ShaderStage,
+) -> Result<Cow<'a, [u8]>, ShaderCompileError> {
+    match *source {
+        tr::ShaderSource::SpirV(code) => Ok(Cow::Borrowed(code)),
+        tr::ShaderSource::Wgsl {
+            source,
+            entry_point,
+        } => compile_wgsl(source, stage, entry_point).map(Cow::Owned),
+        tr::ShaderSource::Glsl(source) => compile_glsl(
+            &ExpandedSource::unexpanded(source, ""),
+            stage,
+            &ShaderCompileOptions::default(),
+        )
+        .map(Cow::Owned),
+    }
+}
+
+fn detect_language_and_stage(path: &Path) -> Option<(ShaderLanguage, ShaderStage)> {
+    let (stem, extension) = path.file_name()?.to_str()?.rsplit_once('.')?;
+    let stage_extension = || Some(stem.rsplit_once('.')?.1);
+    match extension {
+        "glsl" => Some((
+            ShaderLanguage::Glsl,
+            ShaderStage::from_extension(stage_extension()?)?,
+        )),
+        "hlsl" => Some((
+            ShaderLanguage::Hlsl,
+            ShaderStage::from_extension(stage_extension()?)?,
+        )),
+        extension => Some((
+            ShaderLanguage::Glsl,
+            ShaderStage::from_extension(extension)?,
+        )),
+    }
+}
+
+/// Source whose includes are expanded, with the origin of each of its lines.
+struct ExpandedSource {
+    text: String,
+    /// Names of the files of the source, the including one first.
+    files: Vec<String>,
+    /// Lines of `text` from which the lines come from a file, as the 0-based line of `text`,
+    /// the index of the file and the 1-based line in the file.
+    segments: Vec<(usize, usize, usize)>,
+    /// Canonical paths of the file and of every file it includes.
+    included: HashSet<PathBuf>,
+}
+
+impl ExpandedSource {
+    /// Source without includes.
+    fn unexpanded(source: &str, name: &str) -> Self {
+        ExpandedSource {
+            text: source.to_owned(),
+            files: vec![name.to_owned()],
+            segments: vec![(0, 0, 1)],
+            included: HashSet::new(),
+        }
+    }
+
+    /// File name and 1-based line in it of the 1-based `line` of the text.
+    fn locate(&self, line: usize) -> (&str, usize) {
+        let line = line.saturating_sub(1);
+        let (start, file, file_line) = self
+            .segments
+            .iter()
+            .rev()
+            .find(|&&(start, ..)| start <= line)
+            .copied()
+            .unwrap_or((0, 0, 1));
+        (&self.files[file], file_line + line - start)
+    }
+
+    /// `message` prefixed by the file, line and column of `location`.
+    fn describe(&self, location: Option<naga::SourceLocation>, message: &str) -> String {
+        let Some(location) = location else {
+            return match self.files[0].as_str() {
+                "" => message.to_owned(),
+                name => format!("{}: {}", name, message),
+            };
+        };
+        let (file, line) = self.locate(location.line_number as usize);
+        let file = if file.is_empty() { "shader" } else { file };
+        format!("{}:{}:{}: {}", file, line, location.line_position, message)
+    }
+}
+
+/// Expands `#include "file"` and `#include <file>` directives.
+///
+/// Each included file is wrapped in a guard, as if it had `#pragma once`, and followed by
+/// `#line` directives, so that the compiler's own preprocessor decides which includes
+/// conditional blocks keep and locates errors in the included files. Includes are expanded
+/// wherever they appear, so `included` also has the files of disabled blocks.
+fn resolve_includes(
+    source: &str,
+    path: Option<&Path>,
+    include_dirs: &[PathBuf],
+    language: ShaderLanguage,
+) -> Result<ExpandedSource, ShaderCompileError> {
+    let name = path
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    let canonical = path.and_then(|path| path.canonicalize().ok());
+    let mut expander = IncludeExpander {
+        include_dirs,
+        language,
+        expanded: ExpandedSource::unexpanded("", &name),
+        file_paths: vec![canonical.clone().unwrap_or_default()],
+        stack: canonical.into_iter().collect(),
+    };
+    expander.expanded.text.reserve(source.len());
+    expander
+        .expanded
+        .included
+        .extend(expander.stack.iter().cloned());
+    expander.expand(source, path, 0)?;
+    Ok(expander.expanded)
+}
+
+struct IncludeExpander<'a> {
+    include_dirs: &'a [PathBuf],
+    language: ShaderLanguage,
+    expanded: ExpandedSource,
+    /// Canonical paths of `expanded.files`, by which included files get their index.
+    file_paths: Vec<PathBuf>,
+    /// Files being expanded, the innermost last.
+    stack: Vec<PathBuf>,
+}
+
+impl IncludeExpander<'_> {
+    fn expand(
+        &mut self,
+        source: &str,
+        path: Option<&Path>,
+        file: usize,
+    ) -> Result<(), ShaderCompileError> {
+        for (index, line) in source.lines().enumerate() {
+            let directive = line.trim_start();
+            // Lines are replaced rather than removed, so that the next ones keep their number.
+            if directive.starts_with("#extension GL_GOOGLE_include_directive") {
+                self.expanded.text.push('\n');
+                continue;
+            }
+            let Some(name) = directive.strip_prefix("#include") else {
+                self.expanded.text.push_str(line);
+                self.expanded.text.push('\n');
+                continue;
+            };
+            let name = name
+                .trim()
+                .trim_matches(|c| c == '"' || c == '<' || c == '>');
+            let include_path = path
+                .and_then(Path::parent)
+                .into_iter()
+                .chain(self.include_dirs.iter().map(PathBuf::as_path))
+                .map(|dir| dir.join(name))
+                .find(|candidate| candidate.is_file())
+                .ok_or_else(|| ShaderCompileError::IncludeNotFound(name.to_owned()))?;
+            let include_path = include_path
+                .canonicalize()
+                .map_err(|e| ShaderCompileError::Io(include_path.clone(), e))?;
+            // A file included again while it is expanded is within its own guard, where it
+            // expands to nothing. This also breaks include cycles.
+            if self.stack.contains(&include_path) {
+                self.expanded.text.push('\n');
+                continue;
+            }
+            let include_file = match self.file_paths.iter().position(|p| *p == include_path) {
+                Some(include_file) => include_file,
+                None => {
+                    self.file_paths.push(include_path.clone());
+                    self.expanded.files.push(include_path.display().to_string());
+                    self.file_paths.len() - 1
+                }
+            };
+            self.expanded.included.insert(include_path.clone());
+            let include_source = fs::read_to_string(&include_path)
+                .map_err(|e| ShaderCompileError::Io(include_path.clone(), e))?;
+
+            let guard = format!("TEMPURA_INCLUDE_{}", include_file);
+            let line_directive = self.line_directive(1, include_file);
+            self.push_lines(
+                &format!("#ifndef {0}\n#define {0}\n{1}\n", guard, line_directive),
+                include_file,
+                1,
+            );
+            self.stack.push(include_path.clone());
+            self.expand(&include_source, Some(&include_path), include_file)?;
+            self.stack.pop();
+            let line_directive = self.line_directive(index + 2, file);
+            self.push_lines(&format!("#endif\n{}\n", line_directive), file, index + 2);
+        }
+        Ok(())
+    }
+
+    /// Pushes generated lines, after which the text continues at `line` of `file`.
+    fn push_lines(&mut self, lines: &str, file: usize, line: usize) {
+        self.expanded.text.push_str(lines);
+        let start = self.expanded.text.matches('\n').count();
+        self.expanded.segments.push((start, file, line));
+    }
+
+    /// Directive numbering the next line `line` of `file`. GLSL identifies files by number.
+    fn line_directive(&self, line: usize, file: usize) -> String {
+        match self.language {
+            ShaderLanguage::Glsl => format!("#line {} {}", line, file),
+            ShaderLanguage::Hlsl => format!(
+                "#line {} \"{}\"",
+                line,
+                self.expanded.files[file].replace('\\', "\\\\")
+            ),
+        }
+    }
+}
+
+fn compile_glsl(
+    source: &ExpandedSource,
+    stage: ShaderStage,
+    options: &ShaderCompileOptions,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    let glsl_options = naga::front::glsl::Options {
+        stage: stage.naga_stage()?,
+        defines: options.defines.iter().cloned().collect(),
+    };
+    let module = naga::front::glsl::Frontend::default()
+        .parse(&glsl_options, &source.text)
+        .map_err(|e| {
+            ShaderCompileError::Compile(
+                e.errors
+                    .iter()
+                    .map(|error| {
+                        source.describe(error.location(&source.text), &error.kind.to_string())
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+        })?;
+    write_spirv(&module, source, stage, "main", false)
+}
+
+fn compile_wgsl(
+    source: &str,
+    stage: ShaderStage,
+    entry_point: &str,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    stage.naga_stage()?;
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| ShaderCompileError::Compile(e.emit_to_string(source)))?;
+    // WGSL clip space is y-up.
+    write_spirv(
+        &module,
+        &ExpandedSource::unexpanded(source, ""),
+        stage,
+        entry_point,
+        true,
+    )
+}
+
+/// Writes a naga module as SPIR-V bytes.
+/// `flip_y` converts from a y-up clip space, which is needed for sources not written for Vulkan.
+fn write_spirv(
+    module: &naga::Module,
+    source: &ExpandedSource,
+    stage: ShaderStage,
+    entry_point: &str,
+    flip_y: bool,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(module)
+    .map_err(|e| {
+        ShaderCompileError::Compile(source.describe(e.location(&source.text), &e.to_string()))
+    })?;
+
+    // SPIR-V 1.3 declares storage buffers with their own storage class.
+    let mut spv_options = naga::back::spv::Options {
+        lang_version: (1, 3),
+        ..Default::default()
+    };
+    spv_options.flags.set(
+        naga::back::spv::WriterFlags::ADJUST_COORDINATE_SPACE,
+        flip_y,
+    );
+    let pipeline_options = naga::back::spv::PipelineOptions {
+        shader_stage: stage.naga_stage()?,
+        entry_point: entry_point.to_owned(),
+    };
+    let words = naga::back::spv::write_vec(module, &info, &spv_options, Some(&pipeline_options))
+        .map_err(|e| ShaderCompileError::Compile(source.describe(None, &e.to_string())))?;
+    Ok(words.iter().flat_map(|word| word.to_le_bytes()).collect())
+}
+
+fn compile_hlsl(
+    source: &ExpandedSource,
+    stage: ShaderStage,
+    options: &ShaderCompileOptions,
+) -> Result<Vec<u8>, ShaderCompileError> {
+    let defines = options
+        .defines
+        .iter()
+        .map(|(name, value)| (name.as_str(), Some(value.as_str())))
+        .collect::<Vec<_>>();
+    // The `#line` directives of the expanded source locate DXC's errors.
+    hassle_rs::compile_hlsl(
+        &source.files[0],
+        &source.text,
+        &options.entry_point,
+        stage.hlsl_profile(),
+        &["-spirv", "-fspv-target-env=vulkan1.3"],
+        &defines,
+    )
+    .map_err(|e| ShaderCompileError::Compile(source.describe(None, &e.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory for the files of one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("tempura_shader_compiler_{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    const COLOR_HEADER: &str = "vec4 color() {\n    return vec4(1.0);\n}\n";
+
+    #[test]
+    fn detects_language_and_stage_from_extensions() {
+        let detect = |name: &str| detect_language_and_stage(Path::new(name));
+        assert_eq!(
+            detect("shaders/triangle.vert"),
+            Some((ShaderLanguage::Glsl, ShaderStage::Vertex))
+        );
+        assert_eq!(
+            detect("triangle.frag.glsl"),
+            Some((ShaderLanguage::Glsl, ShaderStage::Fragment))
+        );
+        assert_eq!(
+            detect("triangle.ps.hlsl"),
+            Some((ShaderLanguage::Hlsl, ShaderStage::Fragment))
+        );
+        assert_eq!(
+            detect("particles.cs.hlsl"),
+            Some((ShaderLanguage::Hlsl, ShaderStage::Compute))
+        );
+        assert_eq!(detect("common.glsl"), None);
+        assert_eq!(detect("triangle.txt"), None);
+        assert_eq!(detect("vert"), None);
+    }
+
+    #[test]
+    fn wraps_includes_in_guards_and_line_directives() {
+        let dir = test_dir("guards");
+        fs::write(dir.join("color.glsl"), COLOR_HEADER).unwrap();
+        let path = dir.join("main.frag");
+        let source = "#version 450\n#include \"color.glsl\"\nvoid main() {}\n";
+        let expanded = resolve_includes(source, Some(&path), &[], ShaderLanguage::Glsl).unwrap();
+
+        assert_eq!(
+            expanded.text,
+            "#version 450\n\
+             #ifndef TEMPURA_INCLUDE_1\n#define TEMPURA_INCLUDE_1\n#line 1 1\n\
+             vec4 color() {\n    return vec4(1.0);\n}\n\
+             #endif\n#line 3 0\n\
+             void main() {}\n"
+        );
+        // Lines of the header and of the including file after it.
+        let header = dir.join("color.glsl").canonicalize().unwrap();
+        assert_eq!(
+            expanded.locate(6),
+            (header.display().to_string().as_str(), 2)
+        );
+        assert_eq!(
+            expanded.locate(10),
+            (path.display().to_string().as_str(), 3)
+        );
+        assert!(expanded.included.contains(&header));
+    }
+
+    #[test]
+    fn names_files_in_hlsl_line_directives() {
+        let dir = test_dir("hlsl");
+        fs::write(dir.join("color.hlsl"), "float4 color() { return 1.0; }\n").unwrap();
+        let path = dir.join("main.ps.hlsl");
+        let expanded = resolve_includes(
+            "#include \"color.hlsl\"\n",
+            Some(&path),
+            &[],
+            ShaderLanguage::Hlsl,
+        )
+        .unwrap();
+
+        let header = dir.join("color.hlsl").canonicalize().unwrap();
+        assert!(expanded
+            .text
+            .contains(&format!("#line 1 \"{}\"\n", header.display())));
+        assert!(expanded
+            .text
+            .contains(&format!("#line 2 \"{}\"\n", path.display())));
+    }
+
+    #[test]
+    fn keeps_includes_of_enabled_blocks_after_disabled_ones() {
+        let dir = test_dir("conditionals");
+        fs::write(dir.join("color.glsl"), COLOR_HEADER).unwrap();
+        let source = "#version 450\n\
+                      #ifdef DISABLED\n#include \"color.glsl\"\n#endif\n\
+                      #include \"color.glsl\"\n\
+                      layout(location = 0) out vec4 out_color;\n\
+                      void main() {\n    out_color = color();\n}\n";
+        let path = dir.join("main.frag");
+        fs::write(&path, source).unwrap();
+
+        let code = compile_shader_file(&path, &ShaderCompileOptions::default());
+        assert!(code.is_ok(), "{}", code.unwrap_err());
+    }
+
+    #[test]
+    fn includes_each_file_once_per_branch_taken() {
+        let dir = test_dir("once");
+        fs::write(dir.join("color.glsl"), COLOR_HEADER).unwrap();
+        let source = "#version 450\n\
+                      #include \"color.glsl\"\n#include \"color.glsl\"\n\
+                      layout(location = 0) out vec4 out_color;\n\
+                      void main() {\n    out_color = color();\n}\n";
+        let path = dir.join("main.frag");
+        fs::write(&path, source).unwrap();
+
+        let code = compile_shader_file(&path, &ShaderCompileOptions::default());
+        assert!(code.is_ok(), "{}", code.unwrap_err());
+    }
+
+    #[test]
+    fn breaks_include_cycles() {
+        let dir = test_dir("cycles");
+        fs::write(
+            dir.join("a.glsl"),
+            "#include \"b.glsl\"\nfloat a() { return 1.0; }\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("b.glsl"),
+            "#include \"a.glsl\"\nfloat b() { return 2.0; }\n",
+        )
+        .unwrap();
+        let path = dir.join("main.frag");
+        let expanded = resolve_includes(
+            "#include \"a.glsl\"\n",
+            Some(&path),
+            &[],
+            ShaderLanguage::Glsl,
+        )
+        .unwrap();
+
+        assert_eq!(expanded.text.matches("float a()").count(), 1);
+        assert_eq!(expanded.text.matches("float b()").count(), 1);
+        assert_eq!(expanded.included.len(), 2);
+    }
+
+    #[test]
+    fn searches_include_dirs_after_the_including_directory() {
+        let dir = test_dir("include_dirs");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib").join("color.glsl"), COLOR_HEADER).unwrap();
+        let path = dir.join("main.frag");
+
+        let missing = resolve_includes(
+            "#include <color.glsl>\n",
+            Some(&path),
+            &[],
+            ShaderLanguage::Glsl,
+        );
+        assert!(
+            matches!(missing, Err(ShaderCompileError::IncludeNotFound(name)) if name == "color.glsl")
+        );
+        let expanded = resolve_includes(
+            "#include <color.glsl>\n",
+            Some(&path),
+            &[dir.join("lib")],
+            ShaderLanguage::Glsl,
+        )
+        .unwrap();
+        assert!(expanded.text.contains("vec4 color()"));
+    }
+
+    #[test]
+    fn locates_errors_in_included_files() {
+        let dir = test_dir("errors");
+        fs::write(
+            dir.join("broken.glsl"),
+            "float ok() { return 1.0; }\nfloat broken( {\n",
+        )
+        .unwrap();
+        let source = "#version 450\n#include \"broken.glsl\"\nvoid main() {}\n";
+        let path = dir.join("main.frag");
+        fs::write(&path, source).unwrap();
+
+        let error = compile_shader_file(&path, &ShaderCompileOptions::default())
+            .unwrap_err()
+            .to_string();
+        let header = dir.join("broken.glsl").canonicalize().unwrap();
+        assert!(
+            error.starts_with(&format!("{}:2:", header.display())),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_stages_naga_cannot_compile() {
+        for stage in [
+            ShaderStage::TessellationControl,
+            ShaderStage::TessellationEvaluation,
+            ShaderStage::Geometry,
+        ] {
+            let glsl = compile_shader_source(
+                "#version 450\nvoid main() {}\n",
+                None,
+                ShaderLanguage::Glsl,
+                stage,
+                &ShaderCompileOptions::default(),
+            );
+            assert!(matches!(glsl, Err(ShaderCompileError::UnsupportedStage(s)) if s == stage));
+            let wgsl = translate_to_spirv(
+                &tr::ShaderSource::Wgsl {
+                    source: "",
+                    entry_point: "main",
+                },
+                stage,
+            );
+            assert!(matches!(wgsl, Err(ShaderCompileError::UnsupportedStage(s)) if s == stage));
+        }
+    }
+}
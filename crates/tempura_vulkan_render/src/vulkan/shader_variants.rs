@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use tempura_render as tr;
+
+use super::{
+    compile_shader_file, AttachmentFormats, Material, Renderer, Shader, ShaderCompileError,
+    ShaderCompileOptions, ShaderStage, VertexLayout,
+};
+
+/// Set of keywords enabled in a variant, one bit per keyword of its `ShaderVariants`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ShaderVariantKey(u64);
+
+/// Permutations of one set of shader stage files, selected by keywords.
+/// An enabled keyword is defined as `1` when compiling, so sources branch with `#ifdef`.
+/// Variants are compiled the first time they are requested, then cached.
+pub struct ShaderVariants {
+    renderer: Arc<Renderer>,
+    stage_paths: Vec<(ShaderStage, PathBuf)>,
+    keywords: Vec<String>,
+    options: ShaderCompileOptions,
+    variants: Mutex<HashMap<ShaderVariantKey, Arc<Shader>>>,
+}
+
+impl ShaderVariants {
+    pub(crate) fn new(
+        renderer: &Arc<Renderer>,
+        stage_paths: &[(ShaderStage, &Path)],
+        keywords: &[&str],
+        options: &ShaderCompileOptions,
+    ) -> Self {
+        assert!(keywords.len() <= 64, "A shader can have up to 64 keywords.");
+
+        ShaderVariants {
+            renderer: renderer.clone(),
+            stage_paths: stage_paths
+                .iter()
+                .map(|&(stage, path)| (stage, path.to_owned()))
+                .collect(),
+            keywords: keywords.iter().map(|&keyword| keyword.to_owned()).collect(),
+            options: options.clone(),
+            variants: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn keywords(&self) -> &[String] {
+        &self.keywords
+    }
+
+    pub fn key(&self, enabled_keywords: &[&str]) -> Result<ShaderVariantKey, ShaderCompileError> {
+        variant_key(&self.keywords, enabled_keywords)
+    }
+
+    /// Returns the variant with `enabled_keywords`, compiling it if it isn't cached yet.
+    pub fn variant(&self, enabled_keywords: &[&str]) -> Result<Arc<Shader>, ShaderCompileError> {
+        let key = self.key(enabled_keywords)?;
+        if let Some(shader) = self.variants.lock().unwrap().get(&key) {
+            return Ok(shader.clone());
+        }
+
+        let options = variant_options(&self.options, &self.keywords, key);
+        let codes = self
+            .stage_paths
+            .iter()
+            .map(|(stage, path)| Ok((*stage, compile_shader_file(path, &options)?)))
+            .collect::<Result<Vec<_>, ShaderCompileError>>()?;
+        let stages = codes
+            .iter()
+            .map(|(stage, code)| (*stage, tr::ShaderSource::SpirV(code)))
+            .collect::<Vec<_>>();
+        let shader = Arc::new(Shader::new(&self.renderer, &stages));
+        self.variants.lock().unwrap().insert(key, shader.clone());
+        Ok(shader)
+    }
+
+    /// Creates a material using the variant with `enabled_keywords`, without vertex buffers,
+    /// rendering to the swapchain.
+    pub fn create_material(
+        &self,
+        enabled_keywords: &[&str],
+        specialization_constants: &[(&str, tr::SpecializationValue)],
+    ) -> Result<Material, ShaderCompileError> {
+        self.create_material_with_layout(
+            enabled_keywords,
+            &VertexLayout::default(),
+            &self.renderer.swapchain_attachment_formats(),
+            specialization_constants,
+        )
+    }
+
+    /// Creates a material using the variant with `enabled_keywords`, reading the vertex
+    /// buffers of `vertex_layout` and rendering to attachments of `attachment_formats`.
+    pub fn create_material_with_layout(
+        &self,
+        enabled_keywords: &[&str],
+        vertex_layout: &VertexLayout,
+        attachment_formats: &AttachmentFormats,
+        specialization_constants: &[(&str, tr::SpecializationValue)],
+    ) -> Result<Material, ShaderCompileError> {
+        let shader = self.variant(enabled_keywords)?;
+        let keywords = enabled_keywords
+            .iter()
+            .map(|&keyword| keyword.to_owned())
+            .collect();
+        Ok(Material::new(
+            &self.renderer,
+            &shader,
+            keywords,
+            vertex_layout.clone(),
+            attachment_formats.clone(),
+            specialization_constants,
+        ))
+    }
+}
+
+/// Key of the variant with `enabled_keywords`, in any order, out of `keywords`.
+fn variant_key(
+    keywords: &[String],
+    enabled_keywords: &[&str],
+) -> Result<ShaderVariantKey, ShaderCompileError> {
+    enabled_keywords
+        .iter()
+        .try_fold(ShaderVariantKey::default(), |key, &keyword| {
+            let index = keywords
+                .iter()
+                .position(|k| k == keyword)
+                .ok_or_else(|| ShaderCompileError::UnknownKeyword(keyword.to_owned()))?;
+            Ok(ShaderVariantKey(key.0 | 1 << index))
+        })
+}
+
+/// `options` with the keywords enabled in `key` defined as `1`.
+fn variant_options(
+    options: &ShaderCompileOptions,
+    keywords: &[String],
+    key: ShaderVariantKey,
+) -> ShaderCompileOptions {
+    let mut options = options.clone();
+    options.defines.extend(
+        keywords
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| key.0 & 1 << index != 0)
+            .map(|(_, keyword)| (keyword.clone(), "1".to_owned())),
+    );
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vulkan::{compile_shader_source, ShaderLanguage};
+
+    fn keywords() -> Vec<String> {
+        ["SHADOWS", "FOG", "SKINNING"]
+            .iter()
+            .map(|&keyword| keyword.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn keys_variants_by_their_set_of_keywords() {
+        let keywords = keywords();
+        let key = |enabled: &[&str]| variant_key(&keywords, enabled).unwrap();
+        assert_eq!(key(&[]), ShaderVariantKey::default());
+        assert_eq!(key(&["SHADOWS", "SKINNING"]), key(&["SKINNING", "SHADOWS"]));
+        assert_eq!(key(&["FOG", "FOG"]), key(&["FOG"]));
+        assert_ne!(key(&["FOG"]), key(&["SHADOWS"]));
+        assert_ne!(key(&["FOG"]), key(&["FOG", "SHADOWS"]));
+    }
+
+    #[test]
+    fn rejects_undeclared_keywords() {
+        let result = variant_key(&keywords(), &["FOG", "BLOOM"]);
+        assert!(matches!(result, Err(ShaderCompileError::UnknownKeyword(k)) if k == "BLOOM"));
+    }
+
+    #[test]
+    fn defines_the_enabled_keywords() {
+        let keywords = keywords();
+        let mut base = ShaderCompileOptions::default();
+        base.defines.push(("QUALITY".to_owned(), "2".to_owned()));
+        let key = variant_key(&keywords, &["SKINNING", "SHADOWS"]).unwrap();
+
+        let options = variant_options(&base, &keywords, key);
+        assert_eq!(
+            options.defines,
+            [("QUALITY", "2"), ("SHADOWS", "1"), ("SKINNING", "1")]
+                .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        );
+        assert_eq!(
+            variant_options(&base, &keywords, ShaderVariantKey::default()).defines,
+            base.defines
+        );
+    }
+
+    #[test]
+    fn compiles_the_branches_of_enabled_keywords() {
+        let keywords = keywords();
+        // `fog` is only declared by the branch of the keyword.
+        let source = "#version 450\n\
+                      #ifdef FOG\nfloat fog() { return 0.5; }\n#endif\n\
+                      layout(location = 0) out vec4 out_color;\n\
+                      void main() {\n    out_color = vec4(fog());\n}\n";
+        let compile = |enabled: &[&str]| {
+            let key = variant_key(&keywords, enabled).unwrap();
+            compile_shader_source(
+                source,
+                None,
+                ShaderLanguage::Glsl,
+                ShaderStage::Fragment,
+                &variant_options(&ShaderCompileOptions::default(), &keywords, key),
+            )
+        };
+        let code = compile(&["FOG"]);
+        assert!(code.is_ok(), "{}", code.unwrap_err());
+        assert!(compile(&["SHADOWS"]).is_err());
+    }
+}
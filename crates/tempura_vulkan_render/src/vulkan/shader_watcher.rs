@@ -0,0 +1,260 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc, Weak,
+    },
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tempura_render as tr;
+
+use super::{
+    shader_compiler::compile_shader_file_with_includes, Shader, ShaderCompileOptions, ShaderStage,
+};
+
+struct WatchedShader {
+    shader: Weak<Shader>,
+    stage_paths: Vec<(ShaderStage, PathBuf)>,
+    options: ShaderCompileOptions,
+    /// Canonical paths of the stage files and their includes.
+    dependencies: HashSet<PathBuf>,
+}
+
+/// Code of a stage file, translated to SPIR-V by the shader like any `ShaderSource`.
+enum StageSource {
+    SpirV(Vec<u8>),
+    Wgsl(String),
+}
+
+/// Sources of the stages and the canonical paths they were loaded from.
+struct LoadedStages {
+    sources: Vec<(ShaderStage, StageSource)>,
+    dependencies: HashSet<PathBuf>,
+}
+
+/// Outcome of reloading a shader whose files changed.
+#[derive(Debug)]
+pub struct ShaderReload {
+    /// Stage files of the shader.
+    pub name: String,
+    /// Messages of the materials that kept their previous pipeline or lost resource bindings,
+    /// or the error for which the shader was kept as it was.
+    pub result: Result<Vec<String>, String>,
+}
+
+/// Reloads shaders when their files change.
+/// Stage files are SPIR-V binaries if their extension is `spv` and WGSL if it is `wgsl`,
+/// otherwise they are compiled like `compile_shader_file`.
+pub struct ShaderWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    watched_dirs: HashSet<PathBuf>,
+    shaders: Vec<WatchedShader>,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let (sender, events) = channel();
+        let watcher = notify::recommended_watcher(sender)?;
+
+        Ok(ShaderWatcher {
+            watcher,
+            events,
+            watched_dirs: HashSet::new(),
+            shaders: Vec::new(),
+        })
+    }
+
+    /// Starts watching the stage files `shader` was created from.
+    pub fn watch(
+        &mut self,
+        shader: &Arc<Shader>,
+        stage_paths: &[(ShaderStage, &Path)],
+        options: &ShaderCompileOptions,
+    ) -> notify::Result<()> {
+        let mut watched = WatchedShader {
+            shader: Arc::downgrade(shader),
+            stage_paths: stage_paths
+                .iter()
+                .map(|&(stage, path)| (stage, path.to_owned()))
+                .collect(),
+            options: options.clone(),
+            dependencies: HashSet::new(),
+        };
+        // The sources are loaded only to find their includes.
+        if let Ok(loaded) = watched.load() {
+            watched.dependencies = loaded.dependencies;
+        }
+        for (_, path) in stage_paths {
+            if let Ok(path) = path.canonicalize() {
+                watched.dependencies.insert(path);
+            }
+        }
+        self.watch_dirs(&watched.dependencies)?;
+        self.shaders.push(watched);
+        Ok(())
+    }
+
+    /// Reloads the shaders whose files changed since the last call, together with the pipelines
+    /// of their materials, and returns how each reload went. Call it between frames, as it
+    /// waits for the device to be idle.
+    /// A shader that fails to compile is kept as it is.
+    pub fn reload_changed_shaders(&mut self) -> Vec<ShaderReload> {
+        let changed = self.changed_paths();
+        if changed.is_empty() {
+            return Vec::new();
+        }
+
+        self.shaders
+            .retain(|watched| watched.shader.strong_count() > 0);
+        let mut reloads = Vec::new();
+        let mut new_dependencies = Vec::new();
+        for watched in self
+            .shaders
+            .iter_mut()
+            .filter(|watched| !watched.dependencies.is_disjoint(&changed))
+        {
+            let Some(shader) = watched.shader.upgrade() else {
+                continue;
+            };
+            let result = watched.load().and_then(|loaded| {
+                let stages = loaded
+                    .sources
+                    .iter()
+                    .map(|(stage, source)| {
+                        let source = match source {
+                            StageSource::SpirV(code) => tr::ShaderSource::SpirV(code),
+                            StageSource::Wgsl(source) => tr::ShaderSource::Wgsl {
+                                source,
+                                entry_point: &watched.options.entry_point,
+                            },
+                        };
+                        (*stage, source)
+                    })
+                    .collect::<Vec<_>>();
+                let messages = shader.reload(&stages)?;
+                Ok((messages, loaded.dependencies))
+            });
+            let result = result.map(|(messages, dependencies)| {
+                watched.dependencies = dependencies;
+                new_dependencies.push((reloads.len(), watched.dependencies.clone()));
+                messages
+            });
+            reloads.push(ShaderReload {
+                name: watched.name(),
+                result,
+            });
+        }
+        // Files newly included by a reloaded shader are watched from now on.
+        for (index, dependencies) in new_dependencies {
+            if let Err(e) = self.watch_dirs(&dependencies) {
+                reloads[index].result = Err(format!("Watching shader includes failed. {}", e));
+            }
+        }
+        reloads
+    }
+
+    fn changed_paths(&self) -> HashSet<PathBuf> {
+        self.events
+            .try_iter()
+            .filter_map(Result::ok)
+            .filter(|event| matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)))
+            .flat_map(|event| event.paths)
+            .map(|path| path.canonicalize().unwrap_or(path))
+            .collect()
+    }
+
+    /// Directories are watched instead of files, because editors often save by replacing them.
+    fn watch_dirs(&mut self, paths: &HashSet<PathBuf>) -> notify::Result<()> {
+        for dir in paths.iter().filter_map(|path| path.parent()) {
+            if self.watched_dirs.insert(dir.to_owned()) {
+                self.watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WatchedShader {
+    fn name(&self) -> String {
+        self.stage_paths
+            .iter()
+            .map(|(_, path)| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn load(&self) -> Result<LoadedStages, String> {
+        let mut sources = Vec::new();
+        let mut dependencies = HashSet::new();
+        for (stage, path) in &self.stage_paths {
+            let (source, stage_dependencies) = load_stage(path, &self.options)?;
+            sources.push((*stage, source));
+            dependencies.extend(stage_dependencies);
+        }
+        Ok(LoadedStages {
+            sources,
+            dependencies,
+        })
+    }
+}
+
+/// Reads a stage file, compiling GLSL and HLSL here because they may include other files.
+fn load_stage(
+    path: &Path,
+    options: &ShaderCompileOptions,
+) -> Result<(StageSource, HashSet<PathBuf>), String> {
+    let read_error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    let source = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("spv") => StageSource::SpirV(fs::read(path).map_err(read_error)?),
+        Some("wgsl") => StageSource::Wgsl(fs::read_to_string(path).map_err(read_error)?),
+        _ => {
+            let (code, dependencies) =
+                compile_shader_file_with_includes(path, options).map_err(|e| e.to_string())?;
+            return Ok((StageSource::SpirV(code), dependencies));
+        }
+    };
+    Ok((source, path.canonicalize().into_iter().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("tempura_shader_watcher_{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn loads_stages_by_extension() {
+        let dir = test_dir("extensions");
+        let options = ShaderCompileOptions::default();
+
+        let wgsl = dir.join("triangle.wgsl");
+        fs::write(&wgsl, "@fragment fn main() {}\n").unwrap();
+        let (source, dependencies) = load_stage(&wgsl, &options).unwrap();
+        assert!(matches!(source, StageSource::Wgsl(source) if source.starts_with("@fragment")));
+        assert_eq!(dependencies, HashSet::from([wgsl.canonicalize().unwrap()]));
+
+        let spv = dir.join("triangle.frag.spv");
+        fs::write(&spv, [3, 2, 35, 7]).unwrap();
+        let (source, _) = load_stage(&spv, &options).unwrap();
+        assert!(matches!(source, StageSource::SpirV(code) if code == [3, 2, 35, 7]));
+
+        let glsl = dir.join("triangle.frag");
+        fs::write(&glsl, "#version 450\nvoid main() {}\n").unwrap();
+        let (source, dependencies) = load_stage(&glsl, &options).unwrap();
+        assert!(matches!(source, StageSource::SpirV(code) if code[..4] == [3, 2, 35, 7]));
+        assert_eq!(dependencies, HashSet::from([glsl.canonicalize().unwrap()]));
+
+        assert!(load_stage(&dir.join("missing.wgsl"), &options).is_err());
+    }
+}
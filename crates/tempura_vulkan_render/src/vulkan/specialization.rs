@@ -0,0 +1,306 @@
+use ash::vk;
+use rspirv_reflect::{
+    rspirv::dr::Operand,
+    spirv::{Decoration, Op},
+    Reflection,
+};
+use tempura_render as tr;
+
+/// Scalar types a specialization constant can be set with.
+/// 64-bit and smaller than 32-bit types are reflected as `Unsupported`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SpecializationConstantType {
+    Bool,
+    Int,
+    UInt,
+    Float,
+    Unsupported,
+}
+
+impl SpecializationConstantType {
+    fn accepts(&self, value: &tr::SpecializationValue) -> bool {
+        use tr::SpecializationValue as Value;
+        matches!(
+            (self, value),
+            (Self::Bool, Value::Bool(_))
+                | (Self::Int, Value::Int(_))
+                | (Self::UInt, Value::UInt(_))
+                | (Self::Float, Value::Float(_))
+        )
+    }
+}
+
+/// Specialization constant declared by a stage.
+#[derive(Clone, Debug)]
+pub(crate) struct SpecializationConstant {
+    pub(crate) id: u32,
+    pub(crate) name: String,
+    pub(crate) ty: SpecializationConstantType,
+}
+
+/// Finds the specialization constants a module declares with a `SpecId`.
+pub(crate) fn reflect_specialization_constants(
+    reflect: &Reflection,
+) -> Vec<SpecializationConstant> {
+    let module = &reflect.0;
+    let spec_id = |target: u32| {
+        module
+            .annotations
+            .iter()
+            .find_map(|instruction| match instruction.operands.as_slice() {
+                [Operand::IdRef(id), Operand::Decoration(Decoration::SpecId), Operand::LiteralBit32(spec_id)]
+                    if *id == target =>
+                {
+                    Some(*spec_id)
+                }
+                _ => None,
+            })
+    };
+    let name = |target: u32| {
+        module
+            .debug_names
+            .iter()
+            .find_map(|instruction| match instruction.operands.as_slice() {
+                [Operand::IdRef(id), Operand::LiteralString(name)]
+                    if instruction.class.opcode == Op::Name && *id == target =>
+                {
+                    Some(name.clone())
+                }
+                _ => None,
+            })
+    };
+    let ty = |target: u32| {
+        let instruction = module
+            .types_global_values
+            .iter()
+            .find(|instruction| instruction.result_id == Some(target))?;
+        Some(
+            match (instruction.class.opcode, instruction.operands.as_slice()) {
+                (Op::TypeBool, _) => SpecializationConstantType::Bool,
+                (Op::TypeInt, [Operand::LiteralBit32(32), Operand::LiteralBit32(0)]) => {
+                    SpecializationConstantType::UInt
+                }
+                (Op::TypeInt, [Operand::LiteralBit32(32), _]) => SpecializationConstantType::Int,
+                (Op::TypeFloat, [Operand::LiteralBit32(32), ..]) => {
+                    SpecializationConstantType::Float
+                }
+                _ => SpecializationConstantType::Unsupported,
+            },
+        )
+    };
+
+    module
+        .types_global_values
+        .iter()
+        .filter(|instruction| {
+            matches!(
+                instruction.class.opcode,
+                Op::SpecConstantTrue | Op::SpecConstantFalse | Op::SpecConstant
+            )
+        })
+        .filter_map(|instruction| {
+            let result_id = instruction.result_id?;
+            Some(SpecializationConstant {
+                id: spec_id(result_id)?,
+                name: name(result_id).unwrap_or_default(),
+                ty: instruction
+                    .result_type
+                    .and_then(ty)
+                    .unwrap_or(SpecializationConstantType::Unsupported),
+            })
+        })
+        .collect()
+}
+
+/// Checks that every value names a constant of one of the stages and matches its type.
+pub(crate) fn validate_specialization_values(
+    stages: &[&[SpecializationConstant]],
+    values: &[(String, tr::SpecializationValue)],
+) -> Result<(), String> {
+    for (name, value) in values {
+        let constant = stages
+            .iter()
+            .flat_map(|constants| constants.iter())
+            .find(|constant| &constant.name == name)
+            .ok_or_else(|| format!("Specialization constant {} is not declared.", name))?;
+        if !constant.ty.accepts(value) {
+            return Err(format!(
+                "Specialization constant {} is {:?}, but {:?} is given.",
+                name, constant.ty, value
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Specialization map and data of one stage.
+/// Values naming constants the stage doesn't declare are skipped.
+pub(crate) struct SpecializationData {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationData {
+    pub(crate) fn new(
+        constants: &[SpecializationConstant],
+        values: &[(String, tr::SpecializationValue)],
+    ) -> Self {
+        let mut entries = Vec::new();
+        let mut data = Vec::new();
+        for (name, value) in values {
+            let Some(constant) = constants.iter().find(|constant| &constant.name == name) else {
+                continue;
+            };
+            let bytes = match *value {
+                tr::SpecializationValue::Bool(value) => vk::Bool32::from(value).to_ne_bytes(),
+                tr::SpecializationValue::Int(value) => value.to_ne_bytes(),
+                tr::SpecializationValue::UInt(value) => value.to_ne_bytes(),
+                tr::SpecializationValue::Float(value) => value.to_ne_bytes(),
+            };
+            entries.push(vk::SpecializationMapEntry {
+                constant_id: constant.id,
+                offset: data.len() as u32,
+                size: bytes.len(),
+            });
+            data.extend_from_slice(&bytes);
+        }
+        SpecializationData { entries, data }
+    }
+
+    /// The returned info points into `self`.
+    pub(crate) fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    /// Null-terminated literal string packed in little-endian words.
+    fn string(value: &str) -> Vec<u32> {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.resize((bytes.len() / 4 + 1) * 4, 0);
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Module declaring `enabled` (bool, SpecId 0), `disabled` (bool, SpecId 1), `count`
+    /// (int, SpecId 2), `mask` (uint, SpecId 3), `scale` (float, SpecId 4) and `wide`
+    /// (64-bit int, SpecId 5), and an `unnamed` constant without a SpecId.
+    fn module() -> Reflection {
+        let names = [
+            (10, "enabled"),
+            (11, "disabled"),
+            (12, "count"),
+            (13, "mask"),
+            (14, "scale"),
+            (15, "wide"),
+            (16, "unnamed"),
+        ];
+        let mut code = vec![0x0723_0203, 0x0001_0300, 0, 17, 0];
+        code.extend(instruction(17, &[1])); // OpCapability Shader
+        code.extend(instruction(17, &[11])); // OpCapability Int64
+        code.extend(instruction(14, &[0, 1])); // OpMemoryModel Logical GLSL450
+        for (id, name) in names {
+            let operands = [vec![id], string(name)].concat();
+            code.extend(instruction(5, &operands)); // OpName
+        }
+        for spec_id in 0..6 {
+            code.extend(instruction(71, &[10 + spec_id, 1, spec_id])); // OpDecorate SpecId
+        }
+        code.extend(instruction(20, &[1])); // OpTypeBool
+        code.extend(instruction(21, &[2, 32, 1])); // OpTypeInt 32 signed
+        code.extend(instruction(21, &[3, 32, 0])); // OpTypeInt 32 unsigned
+        code.extend(instruction(22, &[4, 32])); // OpTypeFloat 32
+        code.extend(instruction(21, &[5, 64, 1])); // OpTypeInt 64 signed
+        code.extend(instruction(48, &[1, 10])); // OpSpecConstantTrue
+        code.extend(instruction(49, &[1, 11])); // OpSpecConstantFalse
+        code.extend(instruction(50, &[2, 12, 8])); // OpSpecConstant
+        code.extend(instruction(50, &[3, 13, 0xff]));
+        code.extend(instruction(50, &[4, 14, 1.0f32.to_bits()]));
+        code.extend(instruction(50, &[5, 15, 0, 0]));
+        code.extend(instruction(50, &[2, 16, 1]));
+        Reflection::new_from_spirv(bytemuck::cast_slice(&code)).unwrap()
+    }
+
+    #[test]
+    fn reflects_spec_constants_with_their_ids_and_types() {
+        let constants = reflect_specialization_constants(&module());
+        let reflected = constants
+            .iter()
+            .map(|constant| (constant.id, constant.name.as_str(), constant.ty))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reflected,
+            [
+                (0, "enabled", SpecializationConstantType::Bool),
+                (1, "disabled", SpecializationConstantType::Bool),
+                (2, "count", SpecializationConstantType::Int),
+                (3, "mask", SpecializationConstantType::UInt),
+                (4, "scale", SpecializationConstantType::Float),
+                (5, "wide", SpecializationConstantType::Unsupported),
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_values_against_the_reflected_types() {
+        use tr::SpecializationValue as Value;
+        let constants = reflect_specialization_constants(&module());
+        let validate = |name: &str, value| {
+            validate_specialization_values(&[&constants], &[(name.to_owned(), value)])
+        };
+
+        assert!(validate("enabled", Value::Bool(false)).is_ok());
+        assert!(validate("count", Value::Int(-1)).is_ok());
+        assert!(validate("mask", Value::UInt(1)).is_ok());
+        assert!(validate("scale", Value::Float(0.5)).is_ok());
+        assert_eq!(
+            validate("count", Value::Float(1.0)),
+            Err("Specialization constant count is Int, but Float(1.0) is given.".to_owned())
+        );
+        assert!(validate("mask", Value::Int(1)).is_err());
+        assert!(validate("wide", Value::Int(1)).is_err());
+        assert_eq!(
+            validate("unnamed", Value::Int(1)),
+            Err("Specialization constant unnamed is not declared.".to_owned())
+        );
+    }
+
+    #[test]
+    fn packs_the_values_a_stage_declares() {
+        use tr::SpecializationValue as Value;
+        let constants = reflect_specialization_constants(&module());
+        let data = SpecializationData::new(
+            &constants,
+            &[
+                ("scale".to_owned(), Value::Float(2.0)),
+                ("other_stage".to_owned(), Value::Int(3)),
+                ("enabled".to_owned(), Value::Bool(true)),
+            ],
+        );
+
+        let entries = data
+            .entries
+            .iter()
+            .map(|entry| (entry.constant_id, entry.offset, entry.size))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, [(4, 0, 4), (0, 4, 4)]);
+        assert_eq!(
+            data.data,
+            [2.0f32.to_ne_bytes(), 1u32.to_ne_bytes()].concat()
+        );
+    }
+}
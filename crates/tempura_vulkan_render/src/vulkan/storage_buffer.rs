@@ -0,0 +1,67 @@
+use std::{marker::PhantomData, mem, sync::Arc};
+
+use ash::vk;
+
+use super::{Buffer, MemoryLocation, Renderer};
+
+/// Storage buffer holding an array of `T`, laid out as the shader's `std430` runtime array.
+/// `T` must match the element stride the shader declares, including padding, which is
+/// checked when the buffer is bound.
+pub struct StorageBuffer<T> {
+    buffer: Arc<Buffer>,
+    len: usize,
+    _element: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> StorageBuffer<T> {
+    /// The buffer has `STORAGE_BUFFER`, `TRANSFER_SRC` and `TRANSFER_DST` usages in addition
+    /// to `usage`.
+    pub(crate) fn new(
+        renderer: &Arc<Renderer>,
+        len: usize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> Self {
+        let usage = usage
+            | vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_SRC
+            | vk::BufferUsageFlags::TRANSFER_DST;
+        // Vulkan buffers can't be empty.
+        let size = (len.max(1) * mem::size_of::<T>()) as u64;
+        let buffer =
+            Buffer::new(renderer, size, usage, location).expect("Create storage buffer failed.");
+
+        StorageBuffer {
+            buffer: Arc::new(buffer),
+            len,
+            _element: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Untyped buffer, for copies and other commands taking a `Buffer`.
+    pub fn buffer(&self) -> &Arc<Buffer> {
+        &self.buffer
+    }
+
+    /// Copies `elements` into the buffer starting at element `first`.
+    /// The buffer must be host visible and the GPU must not be using the written elements.
+    pub fn write(&self, first: usize, elements: &[T]) {
+        self.buffer
+            .write((first * mem::size_of::<T>()) as u64, elements);
+    }
+
+    /// Reads `count` elements starting at element `first`.
+    /// The buffer must be host visible and the GPU must have finished writing them.
+    pub fn read(&self, first: usize, count: usize) -> Vec<T> {
+        self.buffer
+            .read((first * mem::size_of::<T>()) as u64, count)
+    }
+}
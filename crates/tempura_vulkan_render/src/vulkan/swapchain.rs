@@ -1,35 +1,39 @@
-use std::{
-    cell::{Cell, RefCell},
-    rc::Rc,
-};
+use std::sync::{Arc, Mutex};
 
 use ash::{prelude::VkResult, vk};
 use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
-use tempura_render::{Swapchain, WindowSizeProvider};
+use tempura_render::{self as tr, Swapchain, WindowSizeProvider};
 
-use super::{Renderer, VulkanRenderTarget};
+use super::{
+    render_graph::{present_use, texture_format, texture_use},
+    rendering::{begin_rendering, set_viewport, PassAttachment, PassInheritance},
+    resource_state::{record_transitions, ResourceUse, TrackedResource},
+    Renderer, VulkanRenderTarget,
+};
 
 pub struct VulkanSwapchain {
-    renderer: Rc<Renderer>,
-    window_size_provider: Rc<dyn WindowSizeProvider>,
+    renderer: Arc<Renderer>,
+    window_size_provider: Arc<dyn WindowSizeProvider>,
     surface: vk::SurfaceKHR,
 
-    swapchain: Cell<vk::SwapchainKHR>,
-    surface_format: Cell<vk::SurfaceFormatKHR>,
-    surface_resolution: Cell<vk::Extent2D>,
-    present_image_views: RefCell<Vec<vk::ImageView>>,
-    render_pass: Cell<vk::RenderPass>,
-    framebuffers: RefCell<Vec<vk::Framebuffer>>,
+    swapchain: Mutex<vk::SwapchainKHR>,
+    surface_format: Mutex<vk::SurfaceFormatKHR>,
+    surface_resolution: Mutex<vk::Extent2D>,
+    present_images: Mutex<Vec<vk::Image>>,
+    present_image_views: Mutex<Vec<vk::ImageView>>,
+    /// Null, with no framebuffers, when the renderer uses dynamic rendering.
+    render_pass: Mutex<vk::RenderPass>,
+    framebuffers: Mutex<Vec<vk::Framebuffer>>,
 
-    next_image_index: Cell<u32>,
+    next_image_index: Mutex<u32>,
 }
 
 impl VulkanSwapchain {
     pub(crate) fn new(
-        renderer: &Rc<Renderer>,
+        renderer: &Arc<Renderer>,
         display_handle: &RawDisplayHandle,
         window_handle: &RawWindowHandle,
-        window_size_provider: &Rc<dyn WindowSizeProvider>,
+        window_size_provider: &Arc<dyn WindowSizeProvider>,
     ) -> Self {
         unsafe {
             let surface = ash_window::create_surface(
@@ -45,6 +49,7 @@ impl VulkanSwapchain {
                 swapchain,
                 surface_format,
                 surface_resolution,
+                present_images,
                 present_image_views,
                 render_pass,
                 framebuffers,
@@ -55,19 +60,22 @@ impl VulkanSwapchain {
                 &renderer.swapchain_loader,
                 &renderer.surface_loader,
                 &surface,
+                renderer.dynamic_rendering,
             );
+            *renderer.swapchain_format.lock().unwrap() = surface_format.format;
 
             VulkanSwapchain {
                 renderer: renderer.clone(),
                 window_size_provider: window_size_provider.clone(),
                 surface,
-                swapchain: Cell::new(swapchain),
-                surface_format: Cell::new(surface_format),
-                surface_resolution: Cell::new(surface_resolution),
-                present_image_views: RefCell::new(present_image_views),
-                render_pass: Cell::new(render_pass),
-                framebuffers: RefCell::new(framebuffers),
-                next_image_index: Cell::new(0),
+                swapchain: Mutex::new(swapchain),
+                surface_format: Mutex::new(surface_format),
+                surface_resolution: Mutex::new(surface_resolution),
+                present_images: Mutex::new(present_images),
+                present_image_views: Mutex::new(present_image_views),
+                render_pass: Mutex::new(render_pass),
+                framebuffers: Mutex::new(framebuffers),
+                next_image_index: Mutex::new(0),
             }
         }
     }
@@ -75,13 +83,22 @@ impl VulkanSwapchain {
     pub(crate) fn acquire_next_image(&self, semaphore: &vk::Semaphore) -> bool {
         unsafe {
             match self.renderer.swapchain_loader.acquire_next_image(
-                self.swapchain.get(),
-                std::u64::MAX,
+                *self.swapchain.lock().unwrap(),
+                u64::MAX,
                 *semaphore,
                 vk::Fence::null(),
             ) {
                 Ok(r) => {
-                    self.next_image_index.set(r.0);
+                    *self.next_image_index.lock().unwrap() = r.0;
+                    // Whatever the previous frame left in the image is gone.
+                    self.renderer
+                        .resource_states
+                        .lock()
+                        .unwrap()
+                        .register_acquired_image(
+                            self.present_images.lock().unwrap()[r.0 as usize],
+                            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                        );
                     true
                 }
                 Err(r)
@@ -97,39 +114,150 @@ impl VulkanSwapchain {
         }
     }
 
+    /// Description of the swapchain image, to import it into a render graph.
+    /// Panics if the surface format has no render graph texture format.
+    pub fn backbuffer_desc(&self) -> tr::TextureDesc {
+        let extent = *self.surface_resolution.lock().unwrap();
+        let format = self.surface_format.lock().unwrap().format;
+        tr::TextureDesc {
+            width: extent.width,
+            height: extent.height,
+            format: texture_format(format).unwrap_or_else(|| {
+                panic!(
+                    "Swapchain format {:?} is not a render graph format.",
+                    format
+                )
+            }),
+        }
+    }
+
+    /// Image acquired for the current frame and its view.
+    pub(crate) fn current_image(&self) -> (vk::Image, vk::ImageView) {
+        let index = *self.next_image_index.lock().unwrap() as usize;
+        (
+            self.present_images.lock().unwrap()[index],
+            self.present_image_views.lock().unwrap()[index],
+        )
+    }
+
+    /// Begins the pass of the acquired image, recorded inline or by secondary command buffers
+    /// with `pass_inheritance`, as `contents` tell.
     pub(crate) fn begin_render_pass(
         &self,
         clear_values: &[vk::ClearValue],
         command_buffer: &vk::CommandBuffer,
+        contents: vk::SubpassContents,
     ) {
+        let extent = *self.surface_resolution.lock().unwrap();
+        if self.renderer.dynamic_rendering {
+            let (image, view) = self.current_image();
+            self.transition_image(
+                *command_buffer,
+                image,
+                &texture_use(tr::TextureAccess::ColorAttachment),
+            );
+            let attachment = PassAttachment {
+                view,
+                format: self.surface_format.lock().unwrap().format,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                clear_value: clear_values.first().copied().unwrap_or_default(),
+            };
+            let flags = if contents == vk::SubpassContents::SECONDARY_COMMAND_BUFFERS {
+                vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+            } else {
+                vk::RenderingFlags::empty()
+            };
+            begin_rendering(
+                &self.renderer.device,
+                *command_buffer,
+                extent,
+                &[attachment],
+                None,
+                flags,
+            );
+            return;
+        }
         unsafe {
             let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-                .render_pass(self.render_pass.get())
-                .framebuffer(self.framebuffers.borrow()[self.next_image_index.get() as usize])
-                .render_area(self.surface_resolution.get().into())
-                .clear_values(&clear_values)
+                .render_pass(*self.render_pass.lock().unwrap())
+                .framebuffer(
+                    self.framebuffers.lock().unwrap()
+                        [*self.next_image_index.lock().unwrap() as usize],
+                )
+                .render_area((*self.surface_resolution.lock().unwrap()).into())
+                .clear_values(clear_values)
                 .build();
 
             self.renderer.device.cmd_begin_render_pass(
                 *command_buffer,
                 &render_pass_begin_info,
-                vk::SubpassContents::INLINE,
+                contents,
             );
         }
+        if contents == vk::SubpassContents::INLINE {
+            set_viewport(&self.renderer.device, *command_buffer, extent);
+        }
+    }
+
+    /// Pass of the acquired image, for the secondary command buffers recording it.
+    pub(crate) fn pass_inheritance(&self) -> PassInheritance {
+        let framebuffer = if self.renderer.dynamic_rendering {
+            vk::Framebuffer::null()
+        } else {
+            self.framebuffers.lock().unwrap()[*self.next_image_index.lock().unwrap() as usize]
+        };
+        PassInheritance {
+            render_pass: *self.render_pass.lock().unwrap(),
+            framebuffer,
+            color_formats: vec![self.surface_format.lock().unwrap().format],
+            depth_format: None,
+            extent: *self.surface_resolution.lock().unwrap(),
+        }
     }
 
     pub(crate) fn end_render_pass(&self, command_buffer: &vk::CommandBuffer) {
+        if !self.renderer.dynamic_rendering {
+            unsafe {
+                self.renderer.device.cmd_end_render_pass(*command_buffer);
+            }
+            return;
+        }
         unsafe {
-            self.renderer.device.cmd_end_render_pass(*command_buffer);
+            self.renderer.device.cmd_end_rendering(*command_buffer);
+        }
+        let (image, _) = self.current_image();
+        self.transition_image(*command_buffer, image, &present_use());
+    }
+
+    /// Records the barrier the state tracker needs before `next` use of a swapchain image,
+    /// which the render pass does without dynamic rendering.
+    fn transition_image(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        next: &ResourceUse,
+    ) {
+        let transition = self
+            .renderer
+            .resource_states
+            .lock()
+            .unwrap()
+            .transition(TrackedResource::Image(image), next);
+        if let Some(transition) = transition {
+            record_transitions(
+                &self.renderer.synchronization,
+                command_buffer,
+                &[transition],
+            );
         }
     }
 
     pub(crate) fn present(&self, semaphore: &vk::Semaphore, queue: &vk::Queue) -> VkResult<bool> {
         unsafe {
             let present_info = vk::PresentInfoKHR::builder()
-                .swapchains(&[self.swapchain.get()])
+                .swapchains(&[*self.swapchain.lock().unwrap()])
                 .wait_semaphores(&[*semaphore])
-                .image_indices(&[self.next_image_index.get()])
+                .image_indices(&[*self.next_image_index.lock().unwrap()])
                 .build();
 
             match self
@@ -156,17 +284,29 @@ impl VulkanSwapchain {
             self.renderer.device.device_wait_idle().unwrap();
             self.renderer
                 .device
-                .destroy_render_pass(self.render_pass.get(), None);
-            self.framebuffers.borrow().iter().for_each(|&framebuffer| {
-                self.renderer.device.destroy_framebuffer(framebuffer, None)
-            });
+                .destroy_render_pass(*self.render_pass.lock().unwrap(), None);
+            self.framebuffers
+                .lock()
+                .unwrap()
+                .iter()
+                .for_each(|&framebuffer| {
+                    self.renderer.device.destroy_framebuffer(framebuffer, None)
+                });
             self.present_image_views
-                .borrow()
+                .lock()
+                .unwrap()
                 .iter()
                 .for_each(|&view| self.renderer.device.destroy_image_view(view, None));
+            let mut states = self.renderer.resource_states.lock().unwrap();
+            self.present_images
+                .lock()
+                .unwrap()
+                .iter()
+                .for_each(|&image| states.forget(TrackedResource::Image(image)));
+            drop(states);
             self.renderer
                 .swapchain_loader
-                .destroy_swapchain(self.swapchain.get(), None);
+                .destroy_swapchain(*self.swapchain.lock().unwrap(), None);
         }
     }
 
@@ -176,6 +316,7 @@ impl VulkanSwapchain {
             swapchain,
             surface_format,
             surface_resolution,
+            present_images,
             present_image_views,
             render_pass,
             framebuffers,
@@ -186,14 +327,17 @@ impl VulkanSwapchain {
             &self.renderer.swapchain_loader,
             &self.renderer.surface_loader,
             &self.surface,
+            self.renderer.dynamic_rendering,
         );
 
-        self.swapchain.set(swapchain);
-        self.surface_format.set(surface_format);
-        self.surface_resolution.set(surface_resolution);
-        *(self.present_image_views.borrow_mut()) = present_image_views;
-        self.render_pass.set(render_pass);
-        *(self.framebuffers.borrow_mut()) = framebuffers;
+        *self.renderer.swapchain_format.lock().unwrap() = surface_format.format;
+        *self.swapchain.lock().unwrap() = swapchain;
+        *self.surface_format.lock().unwrap() = surface_format;
+        *self.surface_resolution.lock().unwrap() = surface_resolution;
+        *(self.present_images.lock().unwrap()) = present_images;
+        *(self.present_image_views.lock().unwrap()) = present_image_views;
+        *self.render_pass.lock().unwrap() = render_pass;
+        *(self.framebuffers.lock().unwrap()) = framebuffers;
     }
 }
 
@@ -204,10 +348,12 @@ fn create_swapchain_objects(
     swapchain_loader: &ash::extensions::khr::Swapchain,
     surface_loader: &ash::extensions::khr::Surface,
     surface: &vk::SurfaceKHR,
+    dynamic_rendering: bool,
 ) -> (
     vk::SwapchainKHR,
     vk::SurfaceFormatKHR,
     vk::Extent2D,
+    Vec<vk::Image>,
     Vec<vk::ImageView>,
     vk::RenderPass,
     Vec<vk::Framebuffer>,
@@ -226,7 +372,7 @@ fn create_swapchain_objects(
             surface_capabilities.min_image_count + 1,
             surface_capabilities.max_image_count,
         );
-        let surface_resolution = if surface_capabilities.current_extent.width == std::u32::MAX {
+        let surface_resolution = if surface_capabilities.current_extent.width == u32::MAX {
             extent
         } else {
             surface_capabilities.current_extent
@@ -284,6 +430,18 @@ fn create_swapchain_objects(
             })
             .collect::<Vec<vk::ImageView>>();
 
+        if dynamic_rendering {
+            return (
+                swapchain,
+                surface_format,
+                surface_resolution,
+                present_images,
+                present_image_views,
+                vk::RenderPass::null(),
+                Vec::new(),
+            );
+        }
+
         let color_attachment_desc = vk::AttachmentDescription::builder()
             .format(surface_format.format)
             .samples(vk::SampleCountFlags::TYPE_1)
@@ -333,6 +491,7 @@ fn create_swapchain_objects(
             swapchain,
             surface_format,
             surface_resolution,
+            present_images,
             present_image_views,
             render_pass,
             framebuffers,
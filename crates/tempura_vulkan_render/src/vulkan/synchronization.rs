@@ -0,0 +1,335 @@
+use ash::{extensions::khr, prelude::VkResult, vk, Device, Instance};
+
+/// Records barriers and submits command buffers described with the synchronization2
+/// structures, through Vulkan 1.3, `VK_KHR_synchronization2`, or the original commands they
+/// are translated to on devices supporting neither.
+#[derive(Clone)]
+pub(crate) enum Synchronization {
+    Core(Device),
+    Khr(khr::Synchronization2),
+    Legacy(Device),
+}
+
+impl Synchronization {
+    /// Picks the commands of how the device was created: `synchronization2` enabled with
+    /// Vulkan 1.3 (`core`), with the extension, or not at all.
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        synchronization2: bool,
+        core: bool,
+    ) -> Self {
+        match (synchronization2, core) {
+            (true, true) => Synchronization::Core(device.clone()),
+            (true, false) => Synchronization::Khr(khr::Synchronization2::new(instance, device)),
+            (false, _) => Synchronization::Legacy(device.clone()),
+        }
+    }
+
+    pub(crate) fn is_synchronization2(&self) -> bool {
+        !matches!(self, Synchronization::Legacy(_))
+    }
+
+    /// Records the barriers with one `vkCmdPipelineBarrier2`, or `vkCmdPipelineBarrier`
+    /// without synchronization2.
+    pub(crate) fn pipeline_barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        memory_barriers: &[vk::MemoryBarrier2],
+        buffer_barriers: &[vk::BufferMemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) {
+        let dependency_info = vk::DependencyInfo::builder()
+            .memory_barriers(memory_barriers)
+            .buffer_memory_barriers(buffer_barriers)
+            .image_memory_barriers(image_barriers)
+            .build();
+        match self {
+            Synchronization::Core(device) => unsafe {
+                device.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+            },
+            Synchronization::Khr(loader) => unsafe {
+                loader.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+            },
+            Synchronization::Legacy(device) => legacy_pipeline_barrier(
+                device,
+                command_buffer,
+                memory_barriers,
+                buffer_barriers,
+                image_barriers,
+            ),
+        }
+    }
+
+    /// Submits `command_buffer` with one `vkQueueSubmit2`, or `vkQueueSubmit` without
+    /// synchronization2.
+    pub(crate) fn queue_submit(
+        &self,
+        queue: vk::Queue,
+        command_buffer: vk::CommandBuffer,
+        wait_semaphores: &[vk::SemaphoreSubmitInfo],
+        signal_semaphores: &[vk::SemaphoreSubmitInfo],
+        fence: vk::Fence,
+    ) -> VkResult<()> {
+        let command_buffer_infos = [vk::CommandBufferSubmitInfo::builder()
+            .command_buffer(command_buffer)
+            .build()];
+        let submit_info = vk::SubmitInfo2::builder()
+            .wait_semaphore_infos(wait_semaphores)
+            .command_buffer_infos(&command_buffer_infos)
+            .signal_semaphore_infos(signal_semaphores)
+            .build();
+        match self {
+            Synchronization::Core(device) => unsafe {
+                device.queue_submit2(queue, &[submit_info], fence)
+            },
+            Synchronization::Khr(loader) => unsafe {
+                loader.queue_submit2(queue, &[submit_info], fence)
+            },
+            Synchronization::Legacy(device) => legacy_queue_submit(
+                device,
+                queue,
+                command_buffer,
+                wait_semaphores,
+                signal_semaphores,
+                fence,
+            ),
+        }
+    }
+}
+
+/// `vkCmdPipelineBarrier` waiting for the union of the source stages of the barriers before
+/// the union of their destination stages.
+fn legacy_pipeline_barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    memory_barriers: &[vk::MemoryBarrier2],
+    buffer_barriers: &[vk::BufferMemoryBarrier2],
+    image_barriers: &[vk::ImageMemoryBarrier2],
+) {
+    let mut src_stages = vk::PipelineStageFlags2::NONE;
+    let mut dst_stages = vk::PipelineStageFlags2::NONE;
+    let memory_barriers = memory_barriers
+        .iter()
+        .map(|barrier| {
+            src_stages |= barrier.src_stage_mask;
+            dst_stages |= barrier.dst_stage_mask;
+            vk::MemoryBarrier::builder()
+                .src_access_mask(legacy_access(barrier.src_access_mask))
+                .dst_access_mask(legacy_access(barrier.dst_access_mask))
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let buffer_barriers = buffer_barriers
+        .iter()
+        .map(|barrier| {
+            src_stages |= barrier.src_stage_mask;
+            dst_stages |= barrier.dst_stage_mask;
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(legacy_access(barrier.src_access_mask))
+                .dst_access_mask(legacy_access(barrier.dst_access_mask))
+                .src_queue_family_index(barrier.src_queue_family_index)
+                .dst_queue_family_index(barrier.dst_queue_family_index)
+                .buffer(barrier.buffer)
+                .offset(barrier.offset)
+                .size(barrier.size)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let image_barriers = image_barriers
+        .iter()
+        .map(|barrier| {
+            src_stages |= barrier.src_stage_mask;
+            dst_stages |= barrier.dst_stage_mask;
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(legacy_access(barrier.src_access_mask))
+                .dst_access_mask(legacy_access(barrier.dst_access_mask))
+                .old_layout(barrier.old_layout)
+                .new_layout(barrier.new_layout)
+                .src_queue_family_index(barrier.src_queue_family_index)
+                .dst_queue_family_index(barrier.dst_queue_family_index)
+                .image(barrier.image)
+                .subresource_range(barrier.subresource_range)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            legacy_stages(src_stages, vk::PipelineStageFlags::TOP_OF_PIPE),
+            legacy_stages(dst_stages, vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+            vk::DependencyFlags::empty(),
+            &memory_barriers,
+            &buffer_barriers,
+            &image_barriers,
+        );
+    }
+}
+
+/// `vkQueueSubmit` of `command_buffer`. Semaphores with a value of 0 are binary, the others
+/// are timeline semaphores, whose values are only chained if there are any.
+fn legacy_queue_submit(
+    device: &Device,
+    queue: vk::Queue,
+    command_buffer: vk::CommandBuffer,
+    wait_semaphores: &[vk::SemaphoreSubmitInfo],
+    signal_semaphores: &[vk::SemaphoreSubmitInfo],
+    fence: vk::Fence,
+) -> VkResult<()> {
+    let semaphores = |infos: &[vk::SemaphoreSubmitInfo]| {
+        infos.iter().map(|info| info.semaphore).collect::<Vec<_>>()
+    };
+    let values =
+        |infos: &[vk::SemaphoreSubmitInfo]| infos.iter().map(|info| info.value).collect::<Vec<_>>();
+    let wait_stages = wait_semaphores
+        .iter()
+        .map(|info| legacy_stages(info.stage_mask, vk::PipelineStageFlags::TOP_OF_PIPE))
+        .collect::<Vec<_>>();
+    let wait_values = values(wait_semaphores);
+    let signal_values = values(signal_semaphores);
+    let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+        .wait_semaphore_values(&wait_values)
+        .signal_semaphore_values(&signal_values)
+        .build();
+    let wait_semaphores = semaphores(wait_semaphores);
+    let signal_semaphores = semaphores(signal_semaphores);
+    let command_buffers = [command_buffer];
+    let mut submit_info = vk::SubmitInfo::builder()
+        .wait_semaphores(&wait_semaphores)
+        .wait_dst_stage_mask(&wait_stages)
+        .command_buffers(&command_buffers)
+        .signal_semaphores(&signal_semaphores);
+    // Devices without timeline semaphores may not know the structure.
+    if wait_values
+        .iter()
+        .chain(&signal_values)
+        .any(|&value| value != 0)
+    {
+        submit_info = submit_info.push_next(&mut timeline_info);
+    }
+    unsafe { device.queue_submit(queue, &[submit_info.build()], fence) }
+}
+
+/// Stages of `vkCmdPipelineBarrier` and `vkQueueSubmit` covering `stages`, or `none` where
+/// no stage is given, which those commands don't accept.
+fn legacy_stages(
+    stages: vk::PipelineStageFlags2,
+    none: vk::PipelineStageFlags,
+) -> vk::PipelineStageFlags {
+    if stages.is_empty() {
+        return none;
+    }
+    // Stages introduced by synchronization2, and the original stages including them.
+    let split_stages = [
+        (
+            vk::PipelineStageFlags2::COPY
+                | vk::PipelineStageFlags2::RESOLVE
+                | vk::PipelineStageFlags2::BLIT
+                | vk::PipelineStageFlags2::CLEAR,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        (
+            vk::PipelineStageFlags2::INDEX_INPUT | vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+            vk::PipelineStageFlags::VERTEX_INPUT,
+        ),
+        (
+            vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS,
+            vk::PipelineStageFlags::ALL_GRAPHICS,
+        ),
+    ];
+    // The original stages keep their bits in synchronization2.
+    let original = vk::PipelineStageFlags::from_raw(stages.as_raw() as u32);
+    split_stages
+        .iter()
+        .filter(|(split, _)| stages.intersects(*split))
+        .fold(original, |legacy, (_, stage)| legacy | *stage)
+}
+
+/// Accesses of the original barriers covering `access`.
+fn legacy_access(access: vk::AccessFlags2) -> vk::AccessFlags {
+    let split_access = [
+        (
+            vk::AccessFlags2::SHADER_SAMPLED_READ | vk::AccessFlags2::SHADER_STORAGE_READ,
+            vk::AccessFlags::SHADER_READ,
+        ),
+        (
+            vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            vk::AccessFlags::SHADER_WRITE,
+        ),
+    ];
+    let original = vk::AccessFlags::from_raw(access.as_raw() as u32);
+    split_access
+        .iter()
+        .filter(|(split, _)| access.intersects(*split))
+        .fold(original, |legacy, (_, access)| legacy | *access)
+}
+
+/// Whether the device supports the `synchronization2` feature. It must support Vulkan 1.3 or
+/// `VK_KHR_synchronization2`.
+pub(crate) fn is_synchronization2_supported(
+    instance: &Instance,
+    pdevice: &vk::PhysicalDevice,
+) -> bool {
+    unsafe {
+        let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut synchronization2_features)
+            .build();
+        instance.get_physical_device_features2(*pdevice, &mut features);
+        synchronization2_features.synchronization2 == vk::TRUE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_split_stages_to_the_original_ones() {
+        let none = vk::PipelineStageFlags::TOP_OF_PIPE;
+        assert_eq!(
+            legacy_stages(vk::PipelineStageFlags2::NONE, none),
+            vk::PipelineStageFlags::TOP_OF_PIPE
+        );
+        assert_eq!(
+            legacy_stages(
+                vk::PipelineStageFlags2::NONE,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE
+            ),
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE
+        );
+        assert_eq!(
+            legacy_stages(
+                vk::PipelineStageFlags2::COPY | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                none
+            ),
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER
+        );
+        assert_eq!(
+            legacy_stages(vk::PipelineStageFlags2::INDEX_INPUT, none),
+            vk::PipelineStageFlags::VERTEX_INPUT
+        );
+        assert_eq!(
+            legacy_stages(
+                vk::PipelineStageFlags2::ALL_COMMANDS | vk::PipelineStageFlags2::HOST,
+                none
+            ),
+            vk::PipelineStageFlags::ALL_COMMANDS | vk::PipelineStageFlags::HOST
+        );
+    }
+
+    #[test]
+    fn translates_split_accesses_to_the_original_ones() {
+        assert_eq!(legacy_access(vk::AccessFlags2::NONE), vk::AccessFlags::NONE);
+        assert_eq!(
+            legacy_access(
+                vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE
+            ),
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE
+        );
+        assert_eq!(
+            legacy_access(vk::AccessFlags2::TRANSFER_WRITE | vk::AccessFlags2::UNIFORM_READ),
+            vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::UNIFORM_READ
+        );
+    }
+}
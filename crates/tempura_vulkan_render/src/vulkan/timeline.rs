@@ -0,0 +1,322 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ash::{prelude::VkResult, vk, Device};
+
+use super::synchronization::Synchronization;
+
+/// Numbers the submissions of command buffers to the queue from 1, and tells which of them
+/// the GPU completed.
+/// A timeline semaphore is signaled with the value of each submission where supported.
+/// Otherwise each pending submission signals a fence, recycled once it completes.
+pub(crate) struct SubmissionTimeline {
+    semaphore: Option<vk::Semaphore>,
+    pending_fences: Mutex<PendingFences>,
+    free_fences: Mutex<Vec<vk::Fence>>,
+    last_submitted: AtomicU64,
+    /// Highest value known to be completed, so that completed values are answered without
+    /// asking the device.
+    completed: AtomicU64,
+}
+
+impl SubmissionTimeline {
+    pub(crate) fn new(device: &Device, timeline_semaphore: bool) -> VkResult<Self> {
+        let semaphore = if timeline_semaphore {
+            let mut type_create_info = vk::SemaphoreTypeCreateInfo::builder()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0)
+                .build();
+            let create_info = vk::SemaphoreCreateInfo::builder()
+                .push_next(&mut type_create_info)
+                .build();
+            Some(unsafe { device.create_semaphore(&create_info, None)? })
+        } else {
+            None
+        };
+        Ok(SubmissionTimeline {
+            semaphore,
+            pending_fences: Mutex::new(PendingFences::default()),
+            free_fences: Mutex::new(Vec::new()),
+            last_submitted: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+        })
+    }
+
+    pub(crate) fn uses_timeline_semaphore(&self) -> bool {
+        self.semaphore.is_some()
+    }
+
+    /// Value of the last submission, or 0 before the first one.
+    pub(crate) fn last_submitted(&self) -> u64 {
+        self.last_submitted.load(Ordering::Relaxed)
+    }
+
+    /// Wait of another submission for the submission of `value`, or `None` without a timeline
+    /// semaphore, where the CPU waits instead.
+    pub(crate) fn wait_info(&self, value: u64) -> Option<vk::SemaphoreSubmitInfo> {
+        self.semaphore.map(|semaphore| {
+            vk::SemaphoreSubmitInfo::builder()
+                .semaphore(semaphore)
+                .value(value)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .build()
+        })
+    }
+
+    /// Submits `command_buffer` waiting for and signaling binary semaphores, such as the ones
+    /// of the swapchain. Returns the value of the submission.
+    /// Submissions to a queue are externally synchronized by the caller, as for `vkQueueSubmit`.
+    pub(crate) fn submit(
+        &self,
+        device: &Device,
+        synchronization: &Synchronization,
+        queue: vk::Queue,
+        command_buffer: vk::CommandBuffer,
+        wait_semaphores: &[vk::SemaphoreSubmitInfo],
+        signal_semaphores: &[vk::SemaphoreSubmitInfo],
+    ) -> VkResult<u64> {
+        let value = self.last_submitted.load(Ordering::Relaxed) + 1;
+        let mut signal_semaphores = signal_semaphores.to_vec();
+        let fence = match self.semaphore {
+            Some(semaphore) => {
+                signal_semaphores.push(
+                    vk::SemaphoreSubmitInfo::builder()
+                        .semaphore(semaphore)
+                        .value(value)
+                        .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                        .build(),
+                );
+                vk::Fence::null()
+            }
+            None => match self.free_fences.lock().unwrap().pop() {
+                Some(fence) => fence,
+                None => unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None)? },
+            },
+        };
+        let result = synchronization.queue_submit(
+            queue,
+            command_buffer,
+            wait_semaphores,
+            &signal_semaphores,
+            fence,
+        );
+        if let Err(e) = result {
+            if fence != vk::Fence::null() {
+                self.free_fences.lock().unwrap().push(fence);
+            }
+            return Err(e);
+        }
+        if fence != vk::Fence::null() {
+            self.pending_fences.lock().unwrap().push(value, fence);
+        }
+        self.last_submitted.store(value, Ordering::Relaxed);
+        Ok(value)
+    }
+
+    /// Highest value whose submission and every one before it the GPU completed.
+    pub(crate) fn completed(&self, device: &Device) -> u64 {
+        let completed = match self.semaphore {
+            Some(semaphore) => unsafe {
+                device
+                    .get_semaphore_counter_value(semaphore)
+                    .expect("Get semaphore counter value failed.")
+            },
+            None => {
+                let (completed, free) =
+                    self.pending_fences.lock().unwrap().retire(|fence| unsafe {
+                        device
+                            .get_fence_status(fence)
+                            .expect("Get fence status failed.")
+                    });
+                free.into_iter()
+                    .for_each(|fence| self.recycle_fence(device, fence));
+                completed.unwrap_or_else(|| self.completed.load(Ordering::Relaxed))
+            }
+        };
+        self.completed.fetch_max(completed, Ordering::Relaxed);
+        completed
+    }
+
+    /// Blocks until the GPU completes the submission of `value`.
+    pub(crate) fn wait(&self, device: &Device, value: u64) {
+        if value <= self.completed.load(Ordering::Relaxed) {
+            return;
+        }
+        match self.semaphore {
+            Some(semaphore) => unsafe {
+                let wait_info = vk::SemaphoreWaitInfo::builder()
+                    .semaphores(&[semaphore])
+                    .values(&[value])
+                    .build();
+                device
+                    .wait_semaphores(&wait_info, u64::MAX)
+                    .expect("Wait semaphores failed.");
+            },
+            None => {
+                // The fences are shared, so that a concurrent `completed` doesn't recycle them
+                // while they are waited on.
+                let fences = self.pending_fences.lock().unwrap().fences_up_to(value);
+                if !fences.is_empty() {
+                    let handles = fences.iter().map(|fence| **fence).collect::<Vec<_>>();
+                    unsafe {
+                        device
+                            .wait_for_fences(&handles, true, u64::MAX)
+                            .expect("Wait for fence failed.");
+                    }
+                }
+            }
+        }
+        self.completed(device);
+    }
+
+    fn recycle_fence(&self, device: &Device, fence: vk::Fence) {
+        unsafe {
+            device.reset_fences(&[fence]).expect("Reset fences failed.");
+        }
+        self.free_fences.lock().unwrap().push(fence);
+    }
+
+    /// The submissions must be completed.
+    pub(crate) fn destroy(&self, device: &Device) {
+        unsafe {
+            if let Some(semaphore) = self.semaphore {
+                device.destroy_semaphore(semaphore, None);
+            }
+            self.pending_fences
+                .lock()
+                .unwrap()
+                .fences()
+                .chain(self.free_fences.lock().unwrap().iter().copied())
+                .for_each(|fence| device.destroy_fence(fence, None));
+        }
+    }
+}
+
+/// Fences of the pending submissions, in submission order.
+#[derive(Default)]
+struct PendingFences {
+    pending: VecDeque<(u64, Arc<vk::Fence>)>,
+    /// Fences of completed submissions that a wait still uses.
+    retired: Vec<Arc<vk::Fence>>,
+}
+
+impl PendingFences {
+    fn push(&mut self, value: u64, fence: vk::Fence) {
+        self.pending.push_back((value, Arc::new(fence)));
+    }
+
+    /// Fences of the pending submissions up to `value`, kept from recycling while held.
+    fn fences_up_to(&self, value: u64) -> Vec<Arc<vk::Fence>> {
+        self.pending
+            .iter()
+            .take_while(|(pending, _)| *pending <= value)
+            .map(|(_, fence)| fence.clone())
+            .collect()
+    }
+
+    /// Retires the submissions completed in order, as `signaled` tells, and returns the value
+    /// of the last one, if any, with the fences no wait uses anymore, to recycle.
+    fn retire<F>(&mut self, mut signaled: F) -> (Option<u64>, Vec<vk::Fence>)
+    where
+        F: FnMut(vk::Fence) -> bool,
+    {
+        let mut completed = None;
+        while let Some((value, fence)) = self.pending.front() {
+            if !signaled(**fence) {
+                break;
+            }
+            completed = Some(*value);
+            let (_, fence) = self.pending.pop_front().unwrap();
+            self.retired.push(fence);
+        }
+        let mut free = Vec::new();
+        self.retired.retain(|fence| {
+            // Waits only clone pending fences, so a retired fence with no other owner stays
+            // unused.
+            let used = Arc::strong_count(fence) > 1;
+            if !used {
+                free.push(**fence);
+            }
+            used
+        });
+        (completed, free)
+    }
+
+    fn fences(&self) -> impl Iterator<Item = vk::Fence> + '_ {
+        self.pending
+            .iter()
+            .map(|(_, fence)| fence)
+            .chain(&self.retired)
+            .map(|fence| **fence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    fn fence(raw: u64) -> vk::Fence {
+        vk::Fence::from_raw(raw)
+    }
+
+    fn pending(count: u64) -> PendingFences {
+        let mut fences = PendingFences::default();
+        (1..=count).for_each(|value| fences.push(value, fence(value * 10)));
+        fences
+    }
+
+    fn handles(fences: &[Arc<vk::Fence>]) -> Vec<vk::Fence> {
+        fences.iter().map(|fence| **fence).collect()
+    }
+
+    #[test]
+    fn waits_for_the_fences_of_the_submissions_up_to_a_value() {
+        let fences = pending(3);
+        assert_eq!(handles(&fences.fences_up_to(0)), []);
+        assert_eq!(handles(&fences.fences_up_to(2)), [fence(10), fence(20)]);
+        assert_eq!(
+            handles(&fences.fences_up_to(5)),
+            [fence(10), fence(20), fence(30)]
+        );
+    }
+
+    #[test]
+    fn retires_submissions_in_order() {
+        let mut fences = pending(3);
+        // The third submission completing doesn't complete the second one.
+        let (completed, free) = fences.retire(|fence| fence != self::fence(20));
+        assert_eq!(completed, Some(1));
+        assert_eq!(free, [fence(10)]);
+        assert_eq!(handles(&fences.fences_up_to(3)), [fence(20), fence(30)]);
+
+        assert_eq!(fences.retire(|_| false), (None, vec![]));
+        assert_eq!(
+            fences.retire(|_| true),
+            (Some(3), vec![fence(20), fence(30)])
+        );
+        assert_eq!(fences.fences().count(), 0);
+    }
+
+    #[test]
+    fn keeps_fences_being_waited_on_until_the_wait_ends() {
+        let mut fences = pending(2);
+        let waited = fences.fences_up_to(1);
+
+        let (completed, free) = fences.retire(|_| true);
+        assert_eq!(completed, Some(2));
+        assert_eq!(free, [fence(20)]);
+        // Still destroyed with the timeline.
+        assert_eq!(fences.fences().collect::<Vec<_>>(), [fence(10)]);
+
+        drop(waited);
+        assert_eq!(fences.retire(|_| true), (None, vec![fence(10)]));
+        assert_eq!(fences.fences().count(), 0);
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use ash::{prelude::VkResult, vk, Device};
+
+use super::{upload::StagingBuffer, Renderer};
+
+/// Size of each buffer transient uniforms are allocated from. A frame allocates more of them
+/// when one isn't enough.
+const TRANSIENT_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+/// Largest transient uniform, and range of the descriptors they are bound with, unless the
+/// device limit is lower.
+const MAX_TRANSIENT_UNIFORM_SIZE: u64 = 64 * 1024;
+
+/// Constant data written to the transient uniform memory of a frame by
+/// `CommandEncoder::transient_uniform`, bound at `offset` as a dynamic uniform buffer with
+/// `CommandEncoder::bind_transient_uniforms`. It is valid until the frame is reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransientUniform {
+    pub(crate) buffer: vk::Buffer,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Bump allocator of the transient uniforms of one frame, over host visible chunks that stay
+/// mapped. Allocations never start within the last descriptor range of a chunk, so that
+/// every offset can be bound with descriptors of the whole range.
+pub(crate) struct TransientUniforms {
+    chunks: Vec<StagingBuffer>,
+    /// Number of chunks allocated from by the frame, the last one being the current.
+    used: usize,
+    /// Offset after the last allocation in the current chunk.
+    head: u64,
+    alignment: u64,
+    pub(crate) range: u64,
+    /// Descriptor sets of the frame by layout and the chunks at their bindings, so that
+    /// draws whose uniforms are in the same chunks only change the dynamic offsets.
+    pub(crate) sets: HashMap<(vk::DescriptorSetLayout, Vec<(u32, vk::Buffer)>), vk::DescriptorSet>,
+}
+
+impl TransientUniforms {
+    pub(crate) fn new(limits: &vk::PhysicalDeviceLimits) -> Self {
+        TransientUniforms {
+            chunks: Vec::new(),
+            used: 0,
+            head: 0,
+            alignment: limits.min_uniform_buffer_offset_alignment,
+            range: MAX_TRANSIENT_UNIFORM_SIZE.min(limits.max_uniform_buffer_range as u64),
+            sets: HashMap::new(),
+        }
+    }
+
+    /// Copies `data` to the next aligned offset, starting a new chunk when the current one
+    /// is full.
+    /// Panics if `data` is larger than the descriptor range.
+    pub(crate) fn allocate(
+        &mut self,
+        renderer: &Renderer,
+        data: &[u8],
+    ) -> VkResult<TransientUniform> {
+        let size = data.len() as u64;
+        assert!(
+            size <= self.range,
+            "Transient uniforms are at most {} bytes, but {} are given.",
+            self.range,
+            size
+        );
+        let offset = match next_offset(self.used, self.head, self.alignment, self.range) {
+            Some(offset) => offset,
+            None => {
+                if self.used == self.chunks.len() {
+                    self.chunks.push(StagingBuffer::new(
+                        renderer,
+                        TRANSIENT_CHUNK_SIZE,
+                        vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    )?);
+                }
+                self.used += 1;
+                0
+            }
+        };
+        let chunk = &self.chunks[self.used - 1];
+        chunk.write(offset, data);
+        self.head = offset + size;
+        Ok(TransientUniform {
+            buffer: chunk.buffer,
+            offset: offset as u32,
+            size: size as u32,
+        })
+    }
+
+    /// Frees the uniforms of the frame, which the GPU must be done with. The descriptor sets
+    /// are freed with the transient descriptor pools of the frame.
+    pub(crate) fn reset(&mut self) {
+        self.used = 0;
+        self.head = 0;
+        self.sets.clear();
+    }
+
+    pub(crate) fn destroy(&self, device: &Device) {
+        self.chunks.iter().for_each(|chunk| chunk.destroy(device));
+    }
+}
+
+/// Offset of the next allocation in the current chunk, after `head` aligned to `alignment`,
+/// or `None` if it starts a new chunk: when none of the `used` chunks is current yet, or when
+/// a descriptor `range` at the offset would overflow the chunk.
+fn next_offset(used: usize, head: u64, alignment: u64, range: u64) -> Option<u64> {
+    let offset = head.next_multiple_of(alignment);
+    (used > 0 && offset + range <= TRANSIENT_CHUNK_SIZE).then_some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_offsets_to_the_device_limit() {
+        for alignment in [1, 16, 64, 256] {
+            assert_eq!(next_offset(1, 0, alignment, 1024), Some(0));
+            assert_eq!(next_offset(1, 1, alignment, 1024), Some(alignment));
+            assert_eq!(next_offset(1, alignment, alignment, 1024), Some(alignment));
+            assert_eq!(
+                next_offset(1, 3 * alignment + 1, alignment, 1024),
+                Some(4 * alignment)
+            );
+        }
+        assert_eq!(next_offset(1, 100, 256, 1024), Some(256));
+        assert_eq!(next_offset(1, 257, 256, 1024), Some(512));
+    }
+
+    #[test]
+    fn starts_the_first_chunk_of_a_frame() {
+        // After `reset`, no chunk is current, so the frame starts the first one at 0.
+        assert_eq!(next_offset(0, 0, 256, 1024), None);
+        let mut uniforms = TransientUniforms::new(&vk::PhysicalDeviceLimits {
+            min_uniform_buffer_offset_alignment: 256,
+            max_uniform_buffer_range: 16384,
+            ..Default::default()
+        });
+        uniforms.used = 2;
+        uniforms.head = 1000;
+        uniforms.reset();
+        assert_eq!(
+            next_offset(
+                uniforms.used,
+                uniforms.head,
+                uniforms.alignment,
+                uniforms.range
+            ),
+            None
+        );
+        assert_eq!(uniforms.range, 16384);
+    }
+
+    #[test]
+    fn starts_a_new_chunk_before_the_range_overflows() {
+        let range = MAX_TRANSIENT_UNIFORM_SIZE;
+        let last = TRANSIENT_CHUNK_SIZE - range;
+        assert_eq!(next_offset(1, last, 256, range), Some(last));
+        assert_eq!(next_offset(1, last - 255, 256, range), Some(last));
+        assert_eq!(next_offset(1, last + 1, 256, range), None);
+        // A smaller range leaves room for more allocations at the end of the chunk.
+        assert_eq!(next_offset(1, last + 1, 256, 256), Some(last + 256));
+        assert_eq!(next_offset(1, TRANSIENT_CHUNK_SIZE - 255, 256, 256), None);
+    }
+
+    #[test]
+    fn limits_the_range_to_the_device() {
+        let uniforms = TransientUniforms::new(&vk::PhysicalDeviceLimits {
+            min_uniform_buffer_offset_alignment: 64,
+            max_uniform_buffer_range: u32::MAX,
+            ..Default::default()
+        });
+        assert_eq!(uniforms.range, MAX_TRANSIENT_UNIFORM_SIZE);
+        assert_eq!(uniforms.alignment, 64);
+    }
+}
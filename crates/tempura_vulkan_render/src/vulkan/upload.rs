@@ -0,0 +1,376 @@
+use std::{collections::VecDeque, ptr};
+
+use ash::{prelude::VkResult, vk, Device};
+
+use super::{
+    buffer::{allocate_memory, bind_and_map},
+    resource_state::TrackedResource,
+    MemoryLocation, Renderer,
+};
+
+/// Size of the staging ring that uploads go through. Larger uploads get a staging buffer of
+/// their own.
+pub(crate) const STAGING_RING_SIZE: u64 = 32 * 1024 * 1024;
+/// Alignment of the uploads in the ring, a multiple of the texel sizes of uploaded images.
+const STAGING_ALIGNMENT: u64 = 16;
+
+/// Upload returned by `Renderer::upload_buffer` and `Renderer::upload_storage_image`.
+/// Uploads are numbered in order, so a handle also stands for the uploads before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UploadHandle(pub(crate) u64);
+
+/// Host visible buffer the CPU writes uploads or transient uniforms to, which stays mapped.
+pub(crate) struct StagingBuffer {
+    pub(crate) buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+}
+
+// The mapped memory is only accessed by the owner of the staging buffer.
+unsafe impl Send for StagingBuffer {}
+
+impl StagingBuffer {
+    pub(crate) fn new(
+        renderer: &Renderer,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+    ) -> VkResult<Self> {
+        unsafe {
+            let create_info = vk::BufferCreateInfo::builder()
+                .size(size)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build();
+            let buffer = renderer.device.create_buffer(&create_info, None)?;
+            let requirements = renderer.device.get_buffer_memory_requirements(buffer);
+            let memory = match allocate_memory(renderer, &requirements, MemoryLocation::CpuToGpu) {
+                Ok(memory) => memory,
+                Err(e) => {
+                    renderer.device.destroy_buffer(buffer, None);
+                    return Err(e);
+                }
+            };
+            let mapped = match bind_and_map(renderer, buffer, memory, MemoryLocation::CpuToGpu) {
+                Ok(mapped) => mapped as *mut u8,
+                Err(e) => {
+                    renderer.device.destroy_buffer(buffer, None);
+                    renderer.device.free_memory(memory, None);
+                    return Err(e);
+                }
+            };
+            Ok(StagingBuffer {
+                buffer,
+                memory,
+                mapped,
+            })
+        }
+    }
+
+    /// The GPU must not be reading the written range.
+    pub(crate) fn write(&self, offset: u64, data: &[u8]) {
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.mapped.add(offset as usize), data.len());
+        }
+    }
+
+    pub(crate) fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Resource an upload is copied to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum UploadDestination {
+    Buffer {
+        buffer: vk::Buffer,
+        offset: u64,
+    },
+    /// Whole storage image, in the `GENERAL` layout.
+    Image {
+        image: vk::Image,
+        extent: vk::Extent2D,
+    },
+}
+
+impl UploadDestination {
+    pub(crate) fn resource(&self) -> TrackedResource {
+        match *self {
+            UploadDestination::Buffer { buffer, .. } => TrackedResource::Buffer(buffer),
+            UploadDestination::Image { image, .. } => TrackedResource::Image(image),
+        }
+    }
+}
+
+/// Copy of `size` bytes from staging memory to the destination.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct UploadCopy {
+    pub(crate) staging: vk::Buffer,
+    pub(crate) staging_offset: u64,
+    pub(crate) size: u64,
+    pub(crate) dst: UploadDestination,
+}
+
+pub(crate) fn record_upload_copy(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    copy: &UploadCopy,
+) {
+    unsafe {
+        match copy.dst {
+            UploadDestination::Buffer { buffer, offset } => {
+                let region = vk::BufferCopy {
+                    src_offset: copy.staging_offset,
+                    dst_offset: offset,
+                    size: copy.size,
+                };
+                device.cmd_copy_buffer(command_buffer, copy.staging, buffer, &[region]);
+            }
+            UploadDestination::Image { image, extent } => {
+                let region = vk::BufferImageCopy::builder()
+                    .buffer_offset(copy.staging_offset)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image_extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    })
+                    .build();
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    copy.staging,
+                    image,
+                    vk::ImageLayout::GENERAL,
+                    &[region],
+                );
+            }
+        }
+    }
+}
+
+/// Size of a texel of the formats storage images are uploaded with.
+pub(crate) fn texel_size(format: vk::Format) -> Option<u64> {
+    match format {
+        vk::Format::R8_UNORM | vk::Format::R8_UINT => Some(1),
+        vk::Format::R8G8_UNORM | vk::Format::R16_SFLOAT | vk::Format::R16_UINT => Some(2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT
+        | vk::Format::R32_UINT
+        | vk::Format::R32_SINT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32_SFLOAT | vk::Format::R32G32_UINT => {
+            Some(8)
+        }
+        vk::Format::R32G32B32A32_SFLOAT | vk::Format::R32G32B32A32_UINT => Some(16),
+        _ => None,
+    }
+}
+
+/// Uploads recorded into one submission to the transfer queue.
+pub(crate) struct UploadBatch {
+    pub(crate) number: u64,
+    pub(crate) copies: Vec<UploadCopy>,
+    /// Staging buffers of uploads too large for the ring.
+    pub(crate) dedicated: Vec<StagingBuffer>,
+}
+
+/// Submitted batch, whose staging memory is released once it completes.
+pub(crate) struct SubmittedUploads {
+    pub(crate) number: u64,
+    pub(crate) submission: u64,
+    /// Destinations, released to the graphics queue by the submission.
+    pub(crate) resources: Vec<TrackedResource>,
+    pub(crate) families: Option<(u32, u32)>,
+    dedicated: Vec<StagingBuffer>,
+    /// Ring position after the uploads of the batch.
+    ring_end: u64,
+}
+
+/// Staging ring and batches of `Renderer::upload_buffer` and `Renderer::upload_storage_image`.
+///
+/// Ring positions grow monotonically, and the ring offset of a position is its remainder by
+/// the ring size. Uploads are allocated at `head` and freed up to `tail` as batches complete.
+#[derive(Default)]
+pub(crate) struct UploadManager {
+    /// Created by the first upload.
+    pub(crate) ring: Option<StagingBuffer>,
+    head: u64,
+    tail: u64,
+    last_number: u64,
+    /// Uploads waiting for `Renderer::flush_uploads`.
+    pub(crate) pending: Option<UploadBatch>,
+    /// Batches in the order of their submissions.
+    pub(crate) submitted: VecDeque<SubmittedUploads>,
+    /// Completed batches whose destinations the next graphics command buffer acquires.
+    pub(crate) completed: Vec<SubmittedUploads>,
+    /// Number of the last batch acquired by a graphics command buffer.
+    pub(crate) usable: u64,
+}
+
+impl UploadManager {
+    /// Batch the next uploads are added to.
+    pub(crate) fn pending_batch(&mut self) -> &mut UploadBatch {
+        let last_number = &mut self.last_number;
+        self.pending.get_or_insert_with(|| {
+            *last_number += 1;
+            UploadBatch {
+                number: *last_number,
+                copies: Vec::new(),
+                dedicated: Vec::new(),
+            }
+        })
+    }
+
+    /// Ring offset of `size` bytes, or `None` if the ring is too full. An upload never wraps
+    /// around the end of the ring.
+    pub(crate) fn allocate(&mut self, size: u64) -> Option<u64> {
+        if self.head == self.tail {
+            // Restart empty rings at offset 0, so that any upload up to the ring size fits.
+            self.head = self.head.next_multiple_of(STAGING_RING_SIZE);
+            self.tail = self.head;
+        }
+        let mut start = self.head.next_multiple_of(STAGING_ALIGNMENT);
+        let offset = start % STAGING_RING_SIZE;
+        if offset + size > STAGING_RING_SIZE {
+            start += STAGING_RING_SIZE - offset;
+        }
+        if start + size - self.tail > STAGING_RING_SIZE {
+            return None;
+        }
+        self.head = start + size;
+        Some(start % STAGING_RING_SIZE)
+    }
+
+    pub(crate) fn push_submitted(
+        &mut self,
+        batch: UploadBatch,
+        submission: u64,
+        resources: Vec<TrackedResource>,
+        families: Option<(u32, u32)>,
+    ) {
+        let ring_end = self.head;
+        self.submitted.push_back(SubmittedUploads {
+            number: batch.number,
+            submission,
+            resources,
+            families,
+            dedicated: batch.dedicated,
+            ring_end,
+        });
+    }
+
+    /// Frees the staging memory of the batches up to the transfer submission `completed`.
+    pub(crate) fn release_staging(&mut self, device: &Device, completed: u64) {
+        while let Some(mut batch) = self.pop_completed(completed) {
+            batch
+                .dedicated
+                .drain(..)
+                .for_each(|staging| staging.destroy(device));
+            self.completed.push(batch);
+        }
+    }
+
+    /// Oldest batch if it's complete, whose ring space is freed.
+    fn pop_completed(&mut self, completed: u64) -> Option<SubmittedUploads> {
+        if self.submitted.front()?.submission > completed {
+            return None;
+        }
+        let batch = self.submitted.pop_front()?;
+        self.tail = batch.ring_end;
+        Some(batch)
+    }
+
+    /// Forgets the uploads to `resource`, which the GPU must not be copying to anymore.
+    pub(crate) fn forget(&mut self, resource: TrackedResource) {
+        if let Some(batch) = &mut self.pending {
+            batch.copies.retain(|copy| copy.dst.resource() != resource);
+        }
+        self.submitted
+            .iter_mut()
+            .chain(self.completed.iter_mut())
+            .for_each(|batch| batch.resources.retain(|&r| r != resource));
+    }
+
+    /// The GPU must be done with the staging memory.
+    pub(crate) fn destroy(&self, device: &Device) {
+        self.ring
+            .iter()
+            .chain(self.pending.iter().flat_map(|batch| &batch.dedicated))
+            .chain(self.submitted.iter().flat_map(|batch| &batch.dedicated))
+            .for_each(|staging| staging.destroy(device));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submit(uploads: &mut UploadManager, submission: u64) {
+        uploads.pending_batch();
+        let batch = uploads.pending.take().unwrap();
+        uploads.push_submitted(batch, submission, Vec::new(), None);
+    }
+
+    #[test]
+    fn aligns_uploads_in_the_ring() {
+        let mut uploads = UploadManager::default();
+        assert_eq!(uploads.allocate(10), Some(0));
+        assert_eq!(uploads.allocate(4), Some(16));
+        assert_eq!(uploads.allocate(16), Some(32));
+        assert_eq!(uploads.allocate(1), Some(48));
+    }
+
+    #[test]
+    fn rejects_uploads_until_the_full_ring_is_freed() {
+        let mut uploads = UploadManager::default();
+        assert_eq!(uploads.allocate(STAGING_RING_SIZE), Some(0));
+        assert_eq!(uploads.allocate(1), None);
+        submit(&mut uploads, 1);
+        assert_eq!(uploads.allocate(1), None);
+
+        assert!(uploads.pop_completed(0).is_none());
+        assert_eq!(uploads.pop_completed(1).map(|batch| batch.number), Some(1));
+        // The empty ring restarts at offset 0, so that a full ring size fits again.
+        assert_eq!(uploads.allocate(STAGING_RING_SIZE), Some(0));
+    }
+
+    #[test]
+    fn wraps_uploads_around_the_end_of_the_ring() {
+        let mut uploads = UploadManager::default();
+        assert_eq!(uploads.allocate(STAGING_RING_SIZE / 2), Some(0));
+        submit(&mut uploads, 1);
+        assert_eq!(
+            uploads.allocate(STAGING_RING_SIZE / 4),
+            Some(STAGING_RING_SIZE / 2)
+        );
+        submit(&mut uploads, 2);
+        // The upload doesn't fit before the end of the ring, nor at its start, which the
+        // first batch still uses.
+        assert_eq!(uploads.allocate(STAGING_RING_SIZE / 2), None);
+
+        uploads.pop_completed(1).unwrap();
+        assert_eq!(uploads.allocate(STAGING_RING_SIZE / 2), Some(0));
+        // The ring is full up to the second batch.
+        assert_eq!(uploads.allocate(STAGING_ALIGNMENT), None);
+        assert!(uploads.pop_completed(1).is_none());
+
+        // Only the space of the second batch is freed, as the third upload is pending.
+        uploads.pop_completed(2).unwrap();
+        assert_eq!(uploads.allocate(STAGING_RING_SIZE / 2), None);
+        assert_eq!(
+            uploads.allocate(STAGING_RING_SIZE / 4),
+            Some(STAGING_RING_SIZE / 2)
+        );
+    }
+}
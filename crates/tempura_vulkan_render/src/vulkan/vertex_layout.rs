@@ -0,0 +1,231 @@
+use ash::vk;
+
+/// Vertex shader input at `location`, read from `offset` bytes in each element of a binding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: vk::Format,
+    pub offset: u32,
+}
+
+impl VertexAttribute {
+    /// Locations the attribute consumes, two for three and four component 64-bit formats.
+    fn locations(&self) -> std::ops::Range<u32> {
+        let count = match self.format {
+            vk::Format::R64G64B64_UINT
+            | vk::Format::R64G64B64_SINT
+            | vk::Format::R64G64B64_SFLOAT
+            | vk::Format::R64G64B64A64_UINT
+            | vk::Format::R64G64B64A64_SINT
+            | vk::Format::R64G64B64A64_SFLOAT => 2,
+            _ => 1,
+        };
+        self.location..self.location + count
+    }
+}
+
+fn overlap(a: std::ops::Range<u32>, b: std::ops::Range<u32>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Vertex buffer binding whose elements of `stride` bytes advance per vertex or per instance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexBinding {
+    pub binding: u32,
+    pub stride: u32,
+    pub input_rate: vk::VertexInputRate,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+impl VertexBinding {
+    pub fn per_vertex(binding: u32, stride: u32, attributes: Vec<VertexAttribute>) -> Self {
+        VertexBinding {
+            binding,
+            stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+            attributes,
+        }
+    }
+
+    /// Binding advanced once per instance, e.g. for transforms of instanced props.
+    pub fn per_instance(binding: u32, stride: u32, attributes: Vec<VertexAttribute>) -> Self {
+        VertexBinding {
+            binding,
+            stride,
+            input_rate: vk::VertexInputRate::INSTANCE,
+            attributes,
+        }
+    }
+}
+
+/// Vertex buffer bindings a material reads. The default has none, for shaders generating
+/// their vertices from `gl_VertexIndex`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VertexLayout {
+    pub bindings: Vec<VertexBinding>,
+}
+
+impl VertexLayout {
+    pub fn new(bindings: Vec<VertexBinding>) -> Self {
+        VertexLayout { bindings }
+    }
+
+    /// Checks that bindings and locations are unique, including the second location of
+    /// three and four component 64-bit formats, and that every input location of the vertex
+    /// shader is provided by an attribute. Attributes the shader doesn't read are allowed.
+    pub(crate) fn validate(&self, input_locations: &[u32]) -> Result<(), String> {
+        for (index, binding) in self.bindings.iter().enumerate() {
+            if self.bindings[..index]
+                .iter()
+                .any(|b| b.binding == binding.binding)
+            {
+                return Err(format!(
+                    "Vertex binding {} is declared more than once.",
+                    binding.binding
+                ));
+            }
+        }
+        let attributes = self.attributes().collect::<Vec<_>>();
+        for (index, (_, attribute)) in attributes.iter().enumerate() {
+            if let Some((_, other)) = attributes[..index]
+                .iter()
+                .find(|(_, a)| overlap(a.locations(), attribute.locations()))
+            {
+                return Err(format!(
+                    "Vertex attribute location {} overlaps location {}.",
+                    attribute.location, other.location
+                ));
+            }
+        }
+        for &location in input_locations {
+            if !attributes
+                .iter()
+                .any(|(_, a)| a.locations().contains(&location))
+            {
+                return Err(format!(
+                    "Vertex shader input location {} has no vertex attribute.",
+                    location
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn binding_descriptions(&self) -> Vec<vk::VertexInputBindingDescription> {
+        self.bindings
+            .iter()
+            .map(|binding| vk::VertexInputBindingDescription {
+                binding: binding.binding,
+                stride: binding.stride,
+                input_rate: binding.input_rate,
+            })
+            .collect()
+    }
+
+    pub(crate) fn attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.attributes()
+            .map(|(binding, attribute)| vk::VertexInputAttributeDescription {
+                location: attribute.location,
+                binding,
+                format: attribute.format,
+                offset: attribute.offset,
+            })
+            .collect()
+    }
+
+    fn attributes(&self) -> impl Iterator<Item = (u32, &VertexAttribute)> {
+        self.bindings.iter().flat_map(|binding| {
+            binding
+                .attributes
+                .iter()
+                .map(move |attribute| (binding.binding, attribute))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(location: u32, format: vk::Format, offset: u32) -> VertexAttribute {
+        VertexAttribute {
+            location,
+            format,
+            offset,
+        }
+    }
+
+    fn layout() -> VertexLayout {
+        VertexLayout::new(vec![
+            VertexBinding::per_vertex(
+                0,
+                20,
+                vec![
+                    attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+                    attribute(1, vk::Format::R32G32_SFLOAT, 12),
+                ],
+            ),
+            VertexBinding::per_instance(
+                1,
+                16,
+                vec![attribute(2, vk::Format::R32G32B32A32_SFLOAT, 0)],
+            ),
+        ])
+    }
+
+    #[test]
+    fn accepts_inputs_provided_by_attributes() {
+        assert_eq!(layout().validate(&[0, 1, 2]), Ok(()));
+        assert_eq!(layout().validate(&[2, 0]), Ok(()));
+        assert_eq!(VertexLayout::default().validate(&[]), Ok(()));
+    }
+
+    #[test]
+    fn allows_attributes_the_shader_does_not_read() {
+        assert_eq!(layout().validate(&[0]), Ok(()));
+        assert_eq!(layout().validate(&[]), Ok(()));
+    }
+
+    #[test]
+    fn rejects_inputs_without_attributes() {
+        assert_eq!(
+            layout().validate(&[0, 1, 3]),
+            Err("Vertex shader input location 3 has no vertex attribute.".to_owned())
+        );
+        assert_eq!(
+            VertexLayout::default().validate(&[0]),
+            Err("Vertex shader input location 0 has no vertex attribute.".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_overlapping_attributes() {
+        let mut duplicated = layout();
+        duplicated.bindings[1].attributes[0].location = 1;
+        assert_eq!(
+            duplicated.validate(&[0, 1]),
+            Err("Vertex attribute location 1 overlaps location 1.".to_owned())
+        );
+
+        // A dvec4 takes locations 1 and 2.
+        let mut wide = layout();
+        wide.bindings[0].attributes[1] = attribute(1, vk::Format::R64G64B64A64_SFLOAT, 12);
+        wide.bindings[0].stride = 44;
+        assert_eq!(
+            wide.validate(&[0, 1, 2]),
+            Err("Vertex attribute location 2 overlaps location 1.".to_owned())
+        );
+        wide.bindings[1].attributes[0].location = 3;
+        assert_eq!(wide.validate(&[0, 1, 2, 3]), Ok(()));
+    }
+
+    #[test]
+    fn rejects_duplicated_bindings() {
+        let mut layout = layout();
+        layout.bindings[1].binding = 0;
+        assert_eq!(
+            layout.validate(&[0, 1, 2]),
+            Err("Vertex binding 0 is declared more than once.".to_owned())
+        );
+    }
+}
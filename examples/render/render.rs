@@ -1,7 +1,7 @@
-use std::rc::Rc;
+use std::{path::Path, sync::Arc};
 
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use tempura_render::{Renderer, WindowSizeProvider};
+use tempura_render::{ClearValue, LoadOp, RenderGraph, Renderer, ShaderSource, WindowSizeProvider};
 use tempura_vulkan_render::vulkan;
 use winit::{
     dpi::LogicalSize,
@@ -12,7 +12,7 @@ use winit::{
 };
 
 struct WinitWindow {
-    window: Rc<Window>,
+    window: Arc<Window>,
 }
 
 impl WindowSizeProvider for WinitWindow {
@@ -27,7 +27,7 @@ impl WindowSizeProvider for WinitWindow {
 fn main() {
     println!("render example.");
     let mut event_loop = EventLoop::new();
-    let window: Rc<Window> = Rc::new(
+    let window: Arc<Window> = Arc::new(
         WindowBuilder::new()
             .with_title("Tempura Example")
             .with_inner_size(LogicalSize::new(1080.0, 720.0))
@@ -35,8 +35,22 @@ fn main() {
             .unwrap(),
     );
 
-    let renderer = Rc::new(vulkan::Renderer::new(&window.raw_display_handle()));
-    let window_size_provider: Rc<dyn WindowSizeProvider> = Rc::new(WinitWindow {
+    // `--no-dynamic-rendering` renders with render pass objects, as on devices without
+    // Vulkan 1.3.
+    let options = vulkan::RendererOptions {
+        dynamic_rendering: !std::env::args().any(|arg| arg == "--no-dynamic-rendering"),
+        ..Default::default()
+    };
+    let renderer = Arc::new(vulkan::Renderer::with_options(
+        &window.raw_display_handle(),
+        &options,
+    ));
+    println!("dynamic rendering: {}", renderer.uses_dynamic_rendering());
+    let pipeline_cache_path = std::env::temp_dir().join("tempura_pipeline_cache.bin");
+    if let Err(e) = renderer.load_pipeline_cache(&pipeline_cache_path) {
+        println!("pipeline cache is not loaded. {}", e);
+    }
+    let window_size_provider: Arc<dyn WindowSizeProvider> = Arc::new(WinitWindow {
         window: window.clone(),
     });
     let swapchain = renderer.create_swapchain(
@@ -45,10 +59,30 @@ fn main() {
         &window_size_provider,
     );
 
-    let vertex_shader_code = include_bytes!("shaders/triangle.vert.spv").to_vec();
-    let fragment_shader_code = include_bytes!("shaders/triangle.frag.spv").to_vec();
-    let shader = Rc::new(renderer.create_shader(&vertex_shader_code, &fragment_shader_code));
-    // let _material = Rc::new(renderer.create_material(&shader));
+    let shader_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/render/shaders");
+    let shader_compile_options = vulkan::ShaderCompileOptions::default();
+    let vertex_shader_path = shader_dir.join("triangle.vert");
+    let fragment_shader_path = shader_dir.join("triangle.frag");
+    let vertex_shader_code =
+        vulkan::compile_shader_file(&vertex_shader_path, &shader_compile_options).unwrap();
+    let fragment_shader_code =
+        vulkan::compile_shader_file(&fragment_shader_path, &shader_compile_options).unwrap();
+    let shader = Arc::new(renderer.create_shader(
+        &ShaderSource::SpirV(&vertex_shader_code),
+        &ShaderSource::SpirV(&fragment_shader_code),
+    ));
+    let mut shader_watcher = vulkan::ShaderWatcher::new().unwrap();
+    shader_watcher
+        .watch(
+            &shader,
+            &[
+                (vulkan::ShaderStage::Vertex, &vertex_shader_path),
+                (vulkan::ShaderStage::Fragment, &fragment_shader_path),
+            ],
+            &shader_compile_options,
+        )
+        .unwrap();
+    let material = renderer.create_material(&shader, &[]);
 
     event_loop.run_return(|event, _, control_flow| {
         control_flow.set_wait();
@@ -65,10 +99,37 @@ fn main() {
             }
             Event::MainEventsCleared => {
                 //window.request_redraw();
-                renderer.render(&swapchain);
+                for reload in shader_watcher.reload_changed_shaders() {
+                    match reload.result {
+                        Ok(messages) => {
+                            println!("Shader reloaded. {}", reload.name);
+                            messages.iter().for_each(|message| eprintln!("{}", message));
+                        }
+                        Err(e) => eprintln!("Shader reload failed. {}\n{}", reload.name, e),
+                    }
+                }
+                let mut graph = RenderGraph::new();
+                let backbuffer = graph.import_texture("backbuffer", swapchain.backbuffer_desc());
+                graph.add_pass(
+                    "triangle",
+                    |pass| {
+                        pass.color_attachment(
+                            backbuffer,
+                            LoadOp::Clear(ClearValue::Color([0.0, 0.0, 0.5, 1.0])),
+                        )
+                    },
+                    |encoder: &vulkan::CommandEncoder| {
+                        encoder.bind_material(&material);
+                        encoder.draw(3, 0);
+                    },
+                );
+                renderer.render_graph(&swapchain, graph, backbuffer);
             }
             _ => (),
         }
     });
+    if let Err(e) = renderer.save_pipeline_cache(&pipeline_cache_path) {
+        println!("pipeline cache is not saved. {}", e);
+    }
     println!("exit.");
 }